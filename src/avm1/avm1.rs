@@ -123,7 +123,7 @@ pub fn avm1_execute<S : AVM1Syscalls>(ctx: &mut AVM1Context, pc: &mut AVM1Progra
             },
             AVM1Action::If => {
                 let offset = try!(pc.operand_u16());
-                if try!(ctx.stack.data_pop_bool()) {
+                if try!(ctx.stack.data_pop_bool(version)) {
                     pc.increment();
                     pc.offset(offset);
                 }
@@ -180,7 +180,7 @@ pub fn avm1_execute<S : AVM1Syscalls>(ctx: &mut AVM1Context, pc: &mut AVM1Progra
             AVM1Action::StartDrag => {
                 let target = try!(ctx.stack.data_pop_string());
                 let look_centre = try!(ctx.stack.data_pop_f32());
-                let constrain = try!(ctx.stack.data_pop_bool());
+                let constrain = try!(ctx.stack.data_pop_bool(version));
                 if constrain {
                     try!(ctx.stack.data_pop()); // y2
                     try!(ctx.stack.data_pop()); // x2
@@ -248,7 +248,7 @@ pub fn avm1_execute<S : AVM1Syscalls>(ctx: &mut AVM1Context, pc: &mut AVM1Progra
                 ctx.registers[index as usize] = try!(ctx.stack.data_peek()).clone();
             },
 
-            _ => unimplemented!()
+            _ => return Err(AVM1Error::new(AVM1ErrorKind::UnsupportedAction))
         } } else {
             return Err(AVM1Error::new(AVM1ErrorKind::InvalidOpcode))
         }
@@ -79,7 +79,8 @@ pub enum AVM1ErrorKind {
     StackEmpty,
     InvalidOpcode,
     InvalidOperand,
-    TypeMismatch
+    TypeMismatch,
+    UnsupportedAction
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -2,10 +2,12 @@ mod context;
 mod stack;
 mod pc;
 mod avm1;
+mod player;
 
 pub use self::context::{ AVM1Data, AVM1Context, AVM1Syscalls, AVM1SyscallsDefault, AVM1Error, AVM1ErrorKind };
 pub use self::pc::AVM1ProgramCounter;
 pub use self::avm1::avm1_execute;
+pub use self::player::Player;
 
 // AVM1 (AS1/AS2)
 #[derive(Debug, FromPrimitive, Copy, Clone, PartialEq, Eq)]
@@ -0,0 +1,749 @@
+use std::old_io::{ Reader, IoResult };
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::mem;
+use std::num::Float;
+use std::f32::consts::PI;
+use std::ascii::AsciiExt;
+
+use swf::Header;
+use types::Matrix;
+use util::{ Timeline, MovieClip, DisplayObject, RemoveObject, split_frame_target };
+use avm1::{ AVM1Data, AVM1Context, AVM1Syscalls, AVM1Error, AVM1ActionKind, AVM1ProgramCounter, avm1_execute };
+use avm1::stack::{ convert_f32, convert_string };
+
+// Clips made by CloneSprite sit above the timeline's depths, and only those can be removed by RemoveSprite
+const CLONE_DEPTH: i32 = 16384;
+
+// Goto actions queue the actions of the frames they enter, which are run in the same tick up to this many times
+const MAX_ACTION_PASSES: usize = 256;
+
+// Plays a movie headlessly and runs its frame actions. Create it, `execute` the first frame's actions, then
+// `tick` once per frame. Positions and the mouse are in twips
+pub struct Player {
+    version: u8,
+    frame_rate: f64,
+    sprites: HashMap<u16, Rc<Timeline>>,
+    state: RefCell<PlayerState>,
+}
+
+struct PlayerState {
+    root: MovieClip,
+    loaded_frames: usize,
+    ticks: u64,
+
+    // The clip whose actions are running, as depths from the root, and the target it has set
+    current: Vec<u16>,
+    target: String,
+    queue: Vec<(Vec<u16>, Vec<u8>)>,
+    contexts: HashMap<Vec<u16>, AVM1Context>,
+
+    drag: Option<(Vec<u16>, i32, i32)>,
+    mouse: (i32, i32),
+    url: String,
+    high_quality: bool,
+    focus_rect: bool,
+    sound_buffer_time: i32,
+    seed: u32,
+    traces: Vec<String>,
+    urls: Vec<(String, String)>,
+}
+
+impl Player {
+    // `reader` is at the first tag after the header
+    pub fn new<R : Reader>(reader: &mut R, version: u8, header: &Header) -> IoResult<Self> {
+        let (root, sprites) = try!(Timeline::read_main(reader, version, header.frame_count));
+        let root = MovieClip::new(Rc::new(root), &sprites);
+        let loaded_frames = root.frame_count();
+
+        Ok(Player {
+            version: version,
+            frame_rate: header.frame_rate as f64 / 256.0,
+            sprites: sprites,
+            state: RefCell::new(PlayerState {
+                root: root,
+                loaded_frames: loaded_frames,
+                ticks: 0,
+                current: Vec::new(),
+                target: String::new(),
+                queue: Vec::new(),
+                contexts: HashMap::new(),
+                drag: None,
+                mouse: (0, 0),
+                url: String::new(),
+                high_quality: true,
+                focus_rect: true,
+                sound_buffer_time: 5,
+                seed: 1,
+                traces: Vec::new(),
+                urls: Vec::new()
+            })
+        })
+    }
+
+    pub fn with_root<F, T>(&self, f: F) -> T where F : FnOnce(&MovieClip) -> T {
+        f(&self.state.borrow().root)
+    }
+
+    // Frames of the main timeline past this count are reported as not loaded yet to WaitForFrame
+    pub fn set_loaded_frames(&self, frames: usize) {
+        self.state.borrow_mut().loaded_frames = frames;
+    }
+
+    pub fn set_mouse(&self, x: i32, y: i32) {
+        self.state.borrow_mut().mouse = (x, y);
+    }
+
+    // Where the movie was loaded from, as reported by _url
+    pub fn set_url(&self, url: &str) {
+        self.state.borrow_mut().url = url.to_string();
+    }
+
+    pub fn take_traces(&self) -> Vec<String> {
+        mem::replace(&mut self.state.borrow_mut().traces, Vec::new())
+    }
+
+    // GetURL and GetURL2 requests as url and window or target
+    pub fn take_urls(&self) -> Vec<(String, String)> {
+        mem::replace(&mut self.state.borrow_mut().urls, Vec::new())
+    }
+
+    // Moves every playing clip on a frame, follows the mouse with a dragged clip and runs the actions
+    pub fn tick(&self) -> Result<(), AVM1Error> {
+        {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            state.ticks += 1;
+            state.root.advance(&self.sprites);
+
+            if let Some((ref path, x, y)) = state.drag {
+                if let Some(object) = object_mut(&mut state.root, path) {
+                    object.matrix.translate_x = state.mouse.0 + x;
+                    object.matrix.translate_y = state.mouse.1 + y;
+                }
+            }
+        }
+        self.execute()
+    }
+
+    // Runs the actions of the frames entered so far, along with those of frames the actions go to
+    pub fn execute(&self) -> Result<(), AVM1Error> {
+        for _ in 0..MAX_ACTION_PASSES {
+            let queue = {
+                let mut state = self.state.borrow_mut();
+                let state = &mut *state;
+                let mut queue = mem::replace(&mut state.queue, Vec::new());
+                collect_actions(&mut state.root, &mut Vec::new(), &mut queue);
+                queue
+            };
+            if queue.is_empty() {
+                break
+            }
+
+            for (path, data) in queue.into_iter() {
+                try!(self.run(path, &data));
+            }
+        }
+        Ok(())
+    }
+
+    // Each timeline keeps its variables and registers between action lists
+    fn run(&self, path: Vec<u16>, data: &[u8]) -> Result<(), AVM1Error> {
+        let mut ctx = match self.state.borrow_mut().contexts.remove(&path) {
+            Some(ctx) => ctx,
+            None => AVM1Context::new(self.version)
+        };
+        ctx.target = String::new();
+
+        // Actions are run one at a time so the syscalls know the target set by SetTarget
+        let mut pc = AVM1ProgramCounter::new(data);
+        let mut result = Ok(());
+        while pc.is_valid() && pc.action() != Some(AVM1ActionKind::End) {
+            {
+                let mut state = self.state.borrow_mut();
+                state.current = path.clone();
+                state.target = ctx.target.clone();
+            }
+            if let Err(err) = avm1_execute(&mut ctx, &mut pc, self, true) {
+                result = Err(err);
+                break
+            }
+        }
+
+        self.state.borrow_mut().contexts.insert(path, ctx);
+        result
+    }
+
+    fn with_clip<F>(&self, target: &str, f: F) where F : FnOnce(&mut MovieClip, &HashMap<u16, Rc<Timeline>>) {
+        let mut state = self.state.borrow_mut();
+        let path = match state.resolve_target(target) {
+            Some(path) => path,
+            None => return
+        };
+        if let Some(clip) = clip_mut(&mut state.root, &path) {
+            f(clip, &self.sprites);
+        }
+    }
+
+    // A frame of a "path:frame" target, see `LabelTable::resolve_frame`
+    fn find_frame(&self, value: &str, scene_bias: u16) -> Option<(Vec<u16>, usize)> {
+        let state = self.state.borrow();
+        let (target, frame) = split_frame_target(value);
+        let path = match state.resolve_target(target.unwrap_or("")) {
+            Some(path) => path,
+            None => return None
+        };
+        let frame = clip(&state.root, &path).and_then(|clip| clip.timeline.labels.resolve_frame(frame, scene_bias));
+        frame.map(|frame| (path, frame))
+    }
+
+    fn loaded_frames(&self, path: &[u16]) -> usize {
+        let state = self.state.borrow();
+        if path.is_empty() {
+            return state.loaded_frames
+        }
+        clip(&state.root, path).map(|clip| clip.frame_count()).unwrap_or(0)
+    }
+}
+
+#[allow(unused_variables)]
+impl<'a> AVM1Syscalls for &'a Player {
+    // SWF 3
+    fn goto_frame(&self, frame: u16) {
+        self.with_clip("", |clip, sprites| clip.goto_frame(frame as usize, sprites));
+    }
+
+    fn get_url(&self, url: &String, target: &String) {
+        self.state.borrow_mut().urls.push((url.clone(), target.clone()));
+    }
+
+    fn next_frame(&self) {
+        self.with_clip("", |clip, sprites| clip.next_frame(sprites));
+    }
+
+    fn previous_frame(&self) {
+        self.with_clip("", |clip, sprites| clip.previous_frame(sprites));
+    }
+
+    fn play(&self) {
+        self.with_clip("", |clip, _| clip.play());
+    }
+
+    fn stop(&self) {
+        self.with_clip("", |clip, _| clip.stop());
+    }
+
+    fn toggle_quality(&self) {
+        let mut state = self.state.borrow_mut();
+        state.high_quality = !state.high_quality;
+    }
+
+    // Nothing is heard when playing headlessly
+    fn stop_sounds(&self) { }
+
+    fn wait_for_frame(&self, frame: u16) -> bool {
+        let path = match self.state.borrow().resolve_target("") {
+            Some(path) => path,
+            None => return true
+        };
+        (frame as usize) < self.loaded_frames(&path)
+    }
+
+    fn goto_label(&self, label: &String) {
+        self.with_clip("", |clip, sprites| {
+            if let Some(frame) = clip.timeline.label_frame(label) {
+                clip.goto_frame(frame, sprites);
+            }
+        });
+    }
+
+    // SWF 4
+    fn call(&self, target: &String) {
+        if let Some((path, frame)) = self.find_frame(target, 0) {
+            let mut state = self.state.borrow_mut();
+            let actions = match clip(&state.root, &path).and_then(|clip| clip.timeline.frames.get(frame)) {
+                Some(frame) => frame.actions(),
+                None => return
+            };
+            for data in actions.into_iter() {
+                state.queue.push((path.clone(), data));
+            }
+        }
+    }
+
+    fn get_url2(&self, target: &String, url: &String, method: u8, target_sprite: bool, load_variables: bool) {
+        self.state.borrow_mut().urls.push((url.clone(), target.clone()));
+    }
+
+    fn goto_frame2(&self, frame: &String, scene_bias: u16, play: bool) {
+        if let Some((path, frame)) = self.find_frame(frame, scene_bias) {
+            let mut state = self.state.borrow_mut();
+            if let Some(clip) = clip_mut(&mut state.root, &path) {
+                clip.goto_frame(frame, &self.sprites);
+                if play { clip.play() } else { clip.stop() }
+            }
+        }
+    }
+
+    fn get_property(&self, target: &String, index: u32) -> AVM1Data {
+        let state = self.state.borrow();
+        let path = match state.resolve_target(target) {
+            Some(path) => path,
+            None => return AVM1Data::Undefined
+        };
+        let clip = match clip(&state.root, &path) {
+            Some(clip) => clip,
+            None => return AVM1Data::Undefined
+        };
+        let object = object(&state.root, &path);
+        let matrix = object.map(|object| object.matrix).unwrap_or(Matrix::identity());
+        let mouse = local_point(&state.root, &path, state.mouse);
+
+        match index {
+            0 => AVM1Data::Float(matrix.translate_x as f32 / 20.0),
+            1 => AVM1Data::Float(matrix.translate_y as f32 / 20.0),
+            2 => AVM1Data::Float(x_scale(&matrix) * 100.0),
+            3 => AVM1Data::Float(y_scale(&matrix) * 100.0),
+            4 => AVM1Data::Integer(clip.current_frame as i32 + 1),
+            5 => AVM1Data::Integer(clip.timeline.frame_count as i32),
+            6 => AVM1Data::Float(object.map(|object| object.color_transform.a_mult as f32 * 100.0 / 256.0).unwrap_or(100.0)),
+            7 => {
+                let visible = object.map(|object| object.visible).unwrap_or(true);
+                if self.version < 5 { AVM1Data::Integer(visible as i32) } else { AVM1Data::Boolean(visible) }
+            },
+            // Shapes are not kept by the player so nothing can be measured, clips report an empty size
+            8 | 9 => AVM1Data::Float(0.0),
+            10 => AVM1Data::Float(matrix.rotate_skew0.atan2(matrix.scale_x) * 180.0 / PI),
+            11 => AVM1Data::String(Rc::new(target_path(&state.root, &path))),
+            12 => AVM1Data::Integer((if path.is_empty() { state.loaded_frames } else { clip.frame_count() }) as i32),
+            13 => AVM1Data::String(Rc::new(object.and_then(|object| object.name.clone()).unwrap_or(String::new()))),
+            // Without hit testing a dragged clip is never over another one
+            14 => AVM1Data::String(Rc::new(String::new())),
+            15 => AVM1Data::String(Rc::new(state.url.clone())),
+            16 => AVM1Data::Integer(state.high_quality as i32),
+            17 => AVM1Data::Integer(state.focus_rect as i32),
+            18 => AVM1Data::Integer(state.sound_buffer_time),
+            19 => AVM1Data::String(Rc::new((if state.high_quality { "HIGH" } else { "LOW" }).to_string())),
+            20 => AVM1Data::Float(mouse.0 / 20.0),
+            21 => AVM1Data::Float(mouse.1 / 20.0),
+            _ => AVM1Data::Undefined
+        }
+    }
+
+    fn set_property(&self, target: &String, index: u32, value: &AVM1Data) {
+        let mut state = self.state.borrow_mut();
+        // The movie wide properties
+        if index >= 16 && index <= 19 {
+            match index {
+                16 => state.high_quality = convert_f32(value.clone()) != 0.0,
+                17 => state.focus_rect = convert_f32(value.clone()) != 0.0,
+                18 => state.sound_buffer_time = convert_f32(value.clone()) as i32,
+                _ => state.high_quality = convert_string(value.clone()).to_ascii_uppercase() != "LOW"
+            }
+            return
+        }
+
+        let path = match state.resolve_target(target) {
+            Some(path) => path,
+            None => return
+        };
+        let object = match object_mut(&mut state.root, &path) {
+            Some(object) => object,
+            None => return
+        };
+
+        let matrix = object.matrix;
+        let rotation = matrix.rotate_skew0.atan2(matrix.scale_x);
+        match index {
+            0 => object.matrix.translate_x = (convert_f32(value.clone()) * 20.0) as i32,
+            1 => object.matrix.translate_y = (convert_f32(value.clone()) * 20.0) as i32,
+            2 => set_transform(&mut object.matrix, convert_f32(value.clone()) / 100.0, y_scale(&matrix), rotation),
+            3 => set_transform(&mut object.matrix, x_scale(&matrix), convert_f32(value.clone()) / 100.0, rotation),
+            6 => object.color_transform.a_mult = (convert_f32(value.clone()) * 256.0 / 100.0) as i16,
+            7 => object.visible = convert_f32(value.clone()) != 0.0,
+            10 => set_transform(&mut object.matrix, x_scale(&matrix), y_scale(&matrix), convert_f32(value.clone()) * PI / 180.0),
+            13 => object.name = Some(convert_string(value.clone())),
+            _ => ()
+        }
+    }
+
+    fn clone_sprite(&self, source: &String, target: &String, depth: f32) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let path = match state.resolve_target(source) {
+            Some(ref path) if !path.is_empty() => path.clone(),
+            _ => return
+        };
+        let depth = depth as i32 + CLONE_DEPTH;
+        if depth < 0 || depth > 0xffff {
+            return
+        }
+
+        let (parent_path, source_depth) = (&path[..path.len() - 1], path[path.len() - 1]);
+        let mut clone_path = parent_path.to_vec();
+        clone_path.push(depth as u16);
+        state.contexts.remove(&clone_path);
+
+        if let Some(parent) = clip_mut(&mut state.root, parent_path) {
+            let timeline = match parent.child(source_depth) {
+                Some(clip) => clip.timeline.clone(),
+                None => return
+            };
+            let mut object = match parent.display_list.get(source_depth) {
+                Some(object) => object.clone(),
+                None => return
+            };
+            object.depth = depth as u16;
+            object.name = Some(target.clone());
+            parent.display_list.insert(object);
+            parent.insert_child(depth as u16, MovieClip::new(timeline, &self.sprites));
+        }
+    }
+
+    fn remove_sprite(&self, target: &String) {
+        let mut state = self.state.borrow_mut();
+        let path = match state.resolve_target(target) {
+            Some(path) => path,
+            None => return
+        };
+        let depth = match path.last() {
+            Some(&depth) if depth as i32 >= CLONE_DEPTH => depth,
+            _ => return
+        };
+
+        if let Some(parent) = clip_mut(&mut state.root, &path[..path.len() - 1]) {
+            parent.remove_child(depth);
+            parent.display_list.remove(&RemoveObject { depth: depth, character_id: None });
+        }
+        state.contexts.remove(&path);
+    }
+
+    // The constraint rectangle does not reach the syscall, so the drag is not limited
+    fn start_drag(&self, target: &String, lock_centre: f32) {
+        let mut state = self.state.borrow_mut();
+        let path = match state.resolve_target(target) {
+            Some(ref path) if !path.is_empty() => path.clone(),
+            _ => return
+        };
+        let (x, y) = match object(&state.root, &path) {
+            Some(object) if lock_centre == 0.0 => (object.matrix.translate_x - state.mouse.0, object.matrix.translate_y - state.mouse.1),
+            Some(_) => (0, 0),
+            None => return
+        };
+        state.drag = Some((path, x, y));
+    }
+
+    fn end_drag(&self) {
+        self.state.borrow_mut().drag = None;
+    }
+
+    fn wait_for_frame2(&self, target: &String) -> bool {
+        match self.find_frame(target, 0) {
+            Some((path, frame)) => frame < self.loaded_frames(&path),
+            None => true
+        }
+    }
+
+    fn trace(&self, value: &String) {
+        self.state.borrow_mut().traces.push(value.clone());
+    }
+
+    // Milliseconds since the movie started, counted in frames
+    fn get_time(&self) -> u64 {
+        let ticks = self.state.borrow().ticks;
+        if self.frame_rate > 0.0 { (ticks as f64 * 1000.0 / self.frame_rate) as u64 } else { 0 }
+    }
+
+    fn random_number(&self, max: u32) -> u32 {
+        let mut state = self.state.borrow_mut();
+        state.seed = ((state.seed as u64 * 1103515245 + 12345) & 0x7fffffff) as u32;
+        if max == 0 { 0 } else { state.seed % max }
+    }
+}
+
+impl PlayerState {
+    // Targets are relative to the target set by the running actions, which is relative to their clip
+    fn resolve_target(&self, target: &str) -> Option<Vec<u16>> {
+        match resolve(&self.root, &self.current, &self.target) {
+            Some(base) => resolve(&self.root, &base, target),
+            None => None
+        }
+    }
+}
+
+// Slash paths such as "/a/../b" and dot paths such as "_root.a.b", names are looked up without case
+fn resolve(root: &MovieClip, base: &[u16], target: &str) -> Option<Vec<u16>> {
+    let mut path = base.to_vec();
+    let separator = if target.contains("/") { '/' } else { '.' };
+    if target.starts_with("/") {
+        path.clear();
+    }
+
+    for name in target.split(separator) {
+        match name {
+            "" | "this" => (),
+            ".." | "_parent" => if path.pop().is_none() {
+                return None
+            },
+            "_root" | "_level0" => path.clear(),
+            _ => {
+                let parent = match clip(root, &path) {
+                    Some(parent) => parent,
+                    None => return None
+                };
+                let depth = match parent.child_depth(name) {
+                    Some(depth) => depth,
+                    None => match instance_depth(name) {
+                        Some(depth) if parent.child(depth).is_some() => depth,
+                        _ => return None
+                    }
+                };
+                path.push(depth);
+            }
+        }
+    }
+    Some(path)
+}
+
+// Unnamed instances are known by their depth as "instanceN"
+fn instance_depth(name: &str) -> Option<u16> {
+    if name.starts_with("instance") {
+        name["instance".len()..].parse().ok()
+    } else {
+        None
+    }
+}
+
+fn target_path(root: &MovieClip, path: &[u16]) -> String {
+    if path.is_empty() {
+        return "/".to_string()
+    }
+
+    let mut target = String::new();
+    for i in 0..path.len() {
+        target.push('/');
+        match object(root, &path[..i + 1]).and_then(|object| object.name.clone()) {
+            Some(name) => target.push_str(&name),
+            None => target.push_str(&format!("instance{}", path[i]))
+        }
+    }
+    target
+}
+
+fn collect_actions(clip: &mut MovieClip, path: &mut Vec<u16>, queue: &mut Vec<(Vec<u16>, Vec<u8>)>) {
+    for data in clip.take_actions().into_iter() {
+        queue.push((path.clone(), data));
+    }
+
+    let depths: Vec<u16> = clip.children().iter().map(|&(depth, _)| depth).collect();
+    for &depth in depths.iter() {
+        path.push(depth);
+        if let Some(child) = clip.child_mut(depth) {
+            collect_actions(child, path, queue);
+        }
+        path.pop();
+    }
+}
+
+fn clip<'a>(root: &'a MovieClip, path: &[u16]) -> Option<&'a MovieClip> {
+    let mut clip = root;
+    for &depth in path.iter() {
+        clip = match clip.child(depth) {
+            Some(child) => child,
+            None => return None
+        };
+    }
+    Some(clip)
+}
+
+fn clip_mut<'a>(clip: &'a mut MovieClip, path: &[u16]) -> Option<&'a mut MovieClip> {
+    if path.is_empty() {
+        return Some(clip)
+    }
+    match clip.child_mut(path[0]) {
+        Some(child) => clip_mut(child, &path[1..]),
+        None => None
+    }
+}
+
+// The display list entry of a clip in its parent, the root has none
+fn object<'a>(root: &'a MovieClip, path: &[u16]) -> Option<&'a DisplayObject> {
+    match path.last() {
+        Some(&depth) => clip(root, &path[..path.len() - 1]).and_then(|parent| parent.display_list.get(depth)),
+        None => None
+    }
+}
+
+fn object_mut<'a>(root: &'a mut MovieClip, path: &[u16]) -> Option<&'a mut DisplayObject> {
+    match path.last() {
+        Some(&depth) => match clip_mut(root, &path[..path.len() - 1]) {
+            Some(parent) => parent.display_list.get_mut(depth),
+            None => None
+        },
+        None => None
+    }
+}
+
+// A point of the root in the coordinates of a clip, undoing the matrices of the clip and its parents
+fn local_point(root: &MovieClip, path: &[u16], point: (i32, i32)) -> (f32, f32) {
+    let (mut x, mut y) = (point.0 as f32, point.1 as f32);
+    for i in 0..path.len() {
+        let matrix = match object(root, &path[..i + 1]) {
+            Some(object) => object.matrix,
+            None => continue
+        };
+        let determinant = matrix.scale_x * matrix.scale_y - matrix.rotate_skew0 * matrix.rotate_skew1;
+        if determinant == 0.0 {
+            continue
+        }
+        let (dx, dy) = (x - matrix.translate_x as f32, y - matrix.translate_y as f32);
+        x = (matrix.scale_y * dx - matrix.rotate_skew1 * dy) / determinant;
+        y = (matrix.scale_x * dy - matrix.rotate_skew0 * dx) / determinant;
+    }
+    (x, y)
+}
+
+fn x_scale(matrix: &Matrix) -> f32 {
+    (matrix.scale_x * matrix.scale_x + matrix.rotate_skew0 * matrix.rotate_skew0).sqrt()
+}
+
+fn y_scale(matrix: &Matrix) -> f32 {
+    (matrix.rotate_skew1 * matrix.rotate_skew1 + matrix.scale_y * matrix.scale_y).sqrt()
+}
+
+fn set_transform(matrix: &mut Matrix, x_scale: f32, y_scale: f32, rotation: f32) {
+    let (sin, cos) = (rotation.sin(), rotation.cos());
+    matrix.scale_x = x_scale * cos;
+    matrix.rotate_skew0 = x_scale * sin;
+    matrix.rotate_skew1 = -y_scale * sin;
+    matrix.scale_y = y_scale * cos;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::BufReader;
+    use swf::{ Header, Tag, TagKind };
+    use types::Matrix;
+    use avm1::AVM1ErrorKind;
+    use super::Player;
+
+    fn tag(data: &mut Vec<u8>, kind: TagKind, body: &[u8]) {
+        Tag { kind: kind, length: body.len() as u32 }.write(data).unwrap();
+        data.push_all(body);
+    }
+
+    fn push_string(actions: &mut Vec<u8>, value: &str) {
+        let length = value.len() + 2;
+        actions.push_all(&[0x96, length as u8, (length >> 8) as u8, 0x00]);
+        actions.push_all(value.as_bytes());
+        actions.push(0x00);
+    }
+
+    fn push_integer(actions: &mut Vec<u8>, value: i32) {
+        actions.push_all(&[0x96, 0x05, 0x00, 0x07, value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    // Traces a property of a target
+    fn trace_property(actions: &mut Vec<u8>, target: &str, index: i32) {
+        push_string(actions, target);
+        push_integer(actions, index);
+        actions.push_all(&[0x22, 0x26]);
+    }
+
+    fn player(version: u8, tags: &[u8], frame_count: u16) -> Player {
+        let header = Header { rectangle: vec![0x00], frame_rate: 12 << 8, frame_count: frame_count };
+        Player::new(&mut BufReader::new(tags), version, &header).unwrap()
+    }
+
+    // A main timeline of two frames, the second labelled "end", with a two frame sprite named "a" at depth 1
+    // moved to (10, 20). The first frame's actions look at the sprite, clone it and go to the second frame
+    fn movie() -> Vec<u8> {
+        let mut tags = Vec::new();
+        let mut sprite = vec![0x01, 0x00, 0x02, 0x00];
+        tag(&mut sprite, TagKind::ShowFrame, &[]);
+        tag(&mut sprite, TagKind::ShowFrame, &[]);
+        tag(&mut sprite, TagKind::End, &[]);
+        tag(&mut tags, TagKind::DefineSprite, &sprite);
+        let mut place = vec![0x26, 0x01, 0x00, 0x01, 0x00];
+        Matrix::translate(200, 400).write(&mut place).unwrap();
+        place.push_all(b"a\0");
+        tag(&mut tags, TagKind::PlaceObject2, &place);
+
+        let mut actions = Vec::new();
+        trace_property(&mut actions, "/a", 11);
+        trace_property(&mut actions, "_root.a", 0);
+        trace_property(&mut actions, "instance1", 13);
+        trace_property(&mut actions, "a/..", 11);
+
+        // _x = 25
+        push_string(&mut actions, "a");
+        push_integer(&mut actions, 0);
+        push_integer(&mut actions, 25);
+        actions.push(0x23);
+        trace_property(&mut actions, "a", 0);
+
+        // duplicateMovieClip("a", "b", 1), then removeMovieClip("b")
+        push_string(&mut actions, "a");
+        push_string(&mut actions, "b");
+        push_integer(&mut actions, 1);
+        actions.push(0x24);
+        trace_property(&mut actions, "b", 11);
+        trace_property(&mut actions, "b", 0);
+        push_string(&mut actions, "b");
+        actions.push(0x25);
+        trace_property(&mut actions, "b", 11);
+
+        // gotoAndStop("a:2"), then gotoAndStop("end")
+        push_string(&mut actions, "a:2");
+        actions.push_all(&[0x9f, 0x01, 0x00, 0x00]);
+        trace_property(&mut actions, "a", 4);
+        actions.push_all(&[0x8c, 0x04, 0x00, b'e', b'n', b'd', 0x00, 0x07, 0x00]);
+        tag(&mut tags, TagKind::DoAction, &actions);
+        tag(&mut tags, TagKind::ShowFrame, &[]);
+
+        let mut actions = Vec::new();
+        trace_property(&mut actions, "", 4);
+        actions.push(0x00);
+        tag(&mut tags, TagKind::FrameLabel, b"end\0");
+        tag(&mut tags, TagKind::DoAction, &actions);
+        tag(&mut tags, TagKind::ShowFrame, &[]);
+        tag(&mut tags, TagKind::End, &[]);
+        tags
+    }
+
+    #[test]
+    fn run_frame_actions() {
+        let player = player(6, &movie(), 2);
+        player.execute().unwrap();
+        assert_eq!(player.take_traces(), vec!["/a", "10", "a", "/", "25", "/b", "25", "undefined", "2", "2"]);
+        assert!(player.with_root(|root| root.children().len() == 1 && root.child(1).unwrap().current_frame == 1));
+
+        // Both timelines were stopped by their gotos, and the first frame's actions are not run again
+        player.tick().unwrap();
+        player.tick().unwrap();
+        assert!(player.with_root(|root| root.current_frame == 1 && root.child(1).unwrap().current_frame == 1));
+        assert!(player.take_traces().is_empty());
+    }
+
+    #[test]
+    fn unsupported_action() {
+        // CallFunction is not implemented yet, the actions before it still run
+        let mut tags = Vec::new();
+        let mut actions = Vec::new();
+        push_string(&mut actions, "before");
+        actions.push_all(&[0x26, 0x3d, 0x00]);
+        tag(&mut tags, TagKind::DoAction, &actions);
+        tag(&mut tags, TagKind::ShowFrame, &[]);
+
+        let player = player(6, &tags, 1);
+        match player.execute() {
+            Err(err) => match err.kind {
+                AVM1ErrorKind::UnsupportedAction => (),
+                kind => panic!("{:?}", kind)
+            },
+            Ok(()) => panic!("CallFunction ran")
+        }
+        assert_eq!(player.take_traces(), vec!["before"]);
+    }
+}
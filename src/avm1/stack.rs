@@ -1,3 +1,4 @@
+use std::num::Float;
 use avm1::{ AVM1Data, AVM1Error, AVM1ErrorKind };
 
 pub trait AVM1Stack {
@@ -17,8 +18,8 @@ pub trait AVM1Stack {
         Ok(convert_i32(try!(self.data_pop())))
     }
 
-    fn data_pop_bool(&mut self) -> Result<bool, AVM1Error> {
-        Ok(convert_bool(try!(self.data_pop())))
+    fn data_pop_bool(&mut self, version: u8) -> Result<bool, AVM1Error> {
+        Ok(convert_bool(try!(self.data_pop()), version))
     }
 
     fn data_operator_binary_f32<F : Fn(f32, f32) -> AVM1Data>(&mut self, f: F) -> Result<(), AVM1Error> {
@@ -73,7 +74,7 @@ impl AVM1Stack for Vec<AVM1Data> {
     }
 }
 
-fn convert_f32(d: AVM1Data) -> f32 {
+pub fn convert_f32(d: AVM1Data) -> f32 {
     match d {
         AVM1Data::Boolean(v) => if v { 1.0 } else { 0.0 },
         AVM1Data::Integer(v) => v as f32,
@@ -95,18 +96,24 @@ fn convert_i32(d: AVM1Data) -> i32 {
     }
 }
 
-fn convert_bool(d: AVM1Data) -> bool {
+// Strings are numbers up to SWF 6, where those that do not read as one are false, and from SWF 7 only the
+// empty string is false
+fn convert_bool(d: AVM1Data, version: u8) -> bool {
     match d {
         AVM1Data::Boolean(v) => v,
         AVM1Data::Integer(v) => if v == 0 { false } else { true },
         AVM1Data::Float(v) => if v == 0.0 { false } else { true },
         AVM1Data::Double(v) => if v == 0.0 { false } else { true },
-        AVM1Data::String(_) => unimplemented!(),
+        AVM1Data::String(ref v) if version >= 7 => !v.is_empty(),
+        AVM1Data::String(v) => match v.trim().parse::<f64>() {
+            Ok(number) => number != 0.0 && !number.is_nan(),
+            Err(_) => false
+        },
         _ => false
     }
 }
 
-fn convert_string(d: AVM1Data) -> String {
+pub fn convert_string(d: AVM1Data) -> String {
     match d {
         AVM1Data::Boolean(v) => v.to_string(),
         AVM1Data::Integer(v) => v.to_string(),
@@ -118,3 +125,24 @@ fn convert_string(d: AVM1Data) -> String {
         _ => String::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use avm1::AVM1Data;
+    use super::convert_bool;
+
+    fn string(value: &str) -> AVM1Data {
+        AVM1Data::String(Rc::new(String::from_str(value)))
+    }
+
+    #[test]
+    fn string_to_bool() {
+        assert!(convert_bool(string("2"), 6) && convert_bool(string(" -0.5"), 6));
+        assert!(!convert_bool(string("0"), 6) && !convert_bool(string("abc"), 6) && !convert_bool(string(""), 6));
+        assert!(!convert_bool(string("NaN"), 6));
+
+        assert!(convert_bool(string("0"), 7) && convert_bool(string("abc"), 7));
+        assert!(!convert_bool(string(""), 7));
+    }
+}
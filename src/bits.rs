@@ -0,0 +1,133 @@
+use std::old_io::{ Reader, Writer, IoResult };
+use std::num::Int;
+
+pub struct BitReader<R> {
+    reader: R,
+    byte: u8,
+    bits: u8,
+}
+
+pub struct BitWriter<W> {
+    writer: W,
+    byte: u8,
+    bits: u8,
+}
+
+impl<R : Reader> BitReader<R> {
+    pub fn new(reader: R) -> Self {
+        BitReader {
+            reader: reader,
+            byte: 0,
+            bits: 0
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    pub fn inner(&mut self) -> &mut R {
+        self.align();
+        &mut self.reader
+    }
+
+    pub fn align(&mut self) {
+        self.bits = 0;
+    }
+
+    pub fn read_bit(&mut self) -> IoResult<bool> {
+        if self.bits == 0 {
+            self.byte = try!(self.reader.read_u8());
+            self.bits = 8;
+        }
+
+        self.bits -= 1;
+        Ok(self.byte & (1 << self.bits) != 0)
+    }
+
+    pub fn read_ubits(&mut self, count: u8) -> IoResult<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            value = value << 1 | if try!(self.read_bit()) { 1 } else { 0 };
+        }
+        Ok(value)
+    }
+
+    pub fn read_sbits(&mut self, count: u8) -> IoResult<i32> {
+        let value = try!(self.read_ubits(count));
+        Ok(match count {
+            0 => 0,
+            32 => value as i32,
+            _ if value & (1 << (count - 1)) != 0 => (value | !0u32 << count) as i32,
+            _ => value as i32
+        })
+    }
+
+    pub fn read_fbits(&mut self, count: u8) -> IoResult<f32> {
+        Ok(try!(self.read_sbits(count)) as f32 / 65536.0)
+    }
+}
+
+impl<W : Writer> BitWriter<W> {
+    pub fn new(writer: W) -> Self {
+        BitWriter {
+            writer: writer,
+            byte: 0,
+            bits: 0
+        }
+    }
+
+    pub fn into_inner(self) -> IoResult<W> {
+        let mut this = self;
+        try!(this.flush());
+        Ok(this.writer)
+    }
+
+    pub fn flush(&mut self) -> IoResult<()> {
+        if self.bits > 0 {
+            let byte = self.byte << (8 - self.bits);
+            self.byte = 0;
+            self.bits = 0;
+            try!(self.writer.write_u8(byte));
+        }
+
+        Ok(())
+    }
+
+    pub fn write_bit(&mut self, value: bool) -> IoResult<()> {
+        self.byte = self.byte << 1 | if value { 1 } else { 0 };
+        self.bits += 1;
+
+        if self.bits == 8 {
+            let byte = self.byte;
+            self.byte = 0;
+            self.bits = 0;
+            try!(self.writer.write_u8(byte));
+        }
+
+        Ok(())
+    }
+
+    pub fn write_ubits(&mut self, count: u8, value: u32) -> IoResult<()> {
+        for i in (0..count).rev() {
+            try!(self.write_bit(value & (1 << i) != 0));
+        }
+        Ok(())
+    }
+
+    pub fn write_sbits(&mut self, count: u8, value: i32) -> IoResult<()> {
+        self.write_ubits(count, value as u32)
+    }
+
+    pub fn write_fbits(&mut self, count: u8, value: f32) -> IoResult<()> {
+        self.write_sbits(count, (value * 65536.0) as i32)
+    }
+}
+
+pub fn count_ubits(value: u32) -> u8 {
+    (32 - value.leading_zeros()) as u8
+}
+
+pub fn count_sbits(value: i32) -> u8 {
+    if value < 0 { count_ubits(!value as u32) + 1 } else if value == 0 { 0 } else { count_ubits(value as u32) + 1 }
+}
@@ -6,5 +6,16 @@ extern crate flate2;
 mod swf;
 pub use self::swf::{ Compression, Header, Magic, Tag, TagKind };
 
+mod bits;
+pub use self::bits::{ BitReader, BitWriter };
+
+mod types;
+pub use self::types::{ Rect, Rgba, Matrix, ColorTransform };
+
+mod shape;
+pub use self::shape::{ Shape, ShapeRecord, PathCommand };
+
+mod zlib;
+
 pub mod util;
 pub mod avm1;
@@ -0,0 +1,242 @@
+use std::old_io::{ Reader, Writer, ByRefReader, IoResult, IoError, InvalidInput };
+use std::cmp::max;
+use bits::{ BitReader, BitWriter, count_ubits, count_sbits };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeRecord {
+    StyleChange {
+        move_to: Option<(i32, i32)>,
+        fill_style0: Option<u32>,
+        fill_style1: Option<u32>,
+        line_style: Option<u32>,
+    },
+    StraightEdge {
+        dx: i32,
+        dy: i32,
+    },
+    CurvedEdge {
+        control_dx: i32,
+        control_dy: i32,
+        anchor_dx: i32,
+        anchor_dy: i32,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PathCommand {
+    MoveTo(i32, i32),
+    LineTo(i32, i32),
+    CurveTo(i32, i32, i32, i32),
+}
+
+// Style-less SHAPE as used by glyphs, style indices refer to an implicit fill style 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shape {
+    pub records: Vec<ShapeRecord>,
+}
+
+impl Shape {
+    pub fn new() -> Self {
+        Shape {
+            records: Vec::new()
+        }
+    }
+
+    pub fn read<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        let mut bits = BitReader::new(reader.by_ref());
+        let fill_bits = try!(bits.read_ubits(4)) as u8;
+        let line_bits = try!(bits.read_ubits(4)) as u8;
+        let mut records = Vec::new();
+
+        loop {
+            if try!(bits.read_bit()) {
+                let straight = try!(bits.read_bit());
+                let nbits = try!(bits.read_ubits(4)) as u8 + 2;
+                records.push(if straight {
+                    if try!(bits.read_bit()) {
+                        ShapeRecord::StraightEdge {
+                            dx: try!(bits.read_sbits(nbits)),
+                            dy: try!(bits.read_sbits(nbits))
+                        }
+                    } else if try!(bits.read_bit()) {
+                        ShapeRecord::StraightEdge { dx: 0, dy: try!(bits.read_sbits(nbits)) }
+                    } else {
+                        ShapeRecord::StraightEdge { dx: try!(bits.read_sbits(nbits)), dy: 0 }
+                    }
+                } else {
+                    ShapeRecord::CurvedEdge {
+                        control_dx: try!(bits.read_sbits(nbits)),
+                        control_dy: try!(bits.read_sbits(nbits)),
+                        anchor_dx: try!(bits.read_sbits(nbits)),
+                        anchor_dy: try!(bits.read_sbits(nbits))
+                    }
+                });
+            } else {
+                let flags = try!(bits.read_ubits(5));
+                if flags == 0 {
+                    break
+                }
+
+                let move_to = if flags & 0x01 != 0 {
+                    let nbits = try!(bits.read_ubits(5)) as u8;
+                    let x = try!(bits.read_sbits(nbits));
+                    let y = try!(bits.read_sbits(nbits));
+                    Some((x, y))
+                } else {
+                    None
+                };
+                let fill_style0 = if flags & 0x02 != 0 { Some(try!(bits.read_ubits(fill_bits))) } else { None };
+                let fill_style1 = if flags & 0x04 != 0 { Some(try!(bits.read_ubits(fill_bits))) } else { None };
+                let line_style = if flags & 0x08 != 0 { Some(try!(bits.read_ubits(line_bits))) } else { None };
+                if flags & 0x10 != 0 {
+                    return Err(IoError { kind: InvalidInput, desc: "shape styles unsupported in glyph shapes", detail: None })
+                }
+
+                records.push(ShapeRecord::StyleChange {
+                    move_to: move_to,
+                    fill_style0: fill_style0,
+                    fill_style1: fill_style1,
+                    line_style: line_style
+                });
+            }
+        }
+
+        Ok(Shape {
+            records: records
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        let mut fill_bits = 0;
+        let mut line_bits = 0;
+        for record in self.records.iter() {
+            if let &ShapeRecord::StyleChange { fill_style0, fill_style1, line_style, .. } = record {
+                fill_bits = max(fill_bits, count_ubits(fill_style0.unwrap_or(0)));
+                fill_bits = max(fill_bits, count_ubits(fill_style1.unwrap_or(0)));
+                line_bits = max(line_bits, count_ubits(line_style.unwrap_or(0)));
+            }
+        }
+
+        let mut bits = BitWriter::new(writer);
+        try!(bits.write_ubits(4, fill_bits as u32));
+        try!(bits.write_ubits(4, line_bits as u32));
+
+        for record in self.records.iter() {
+            match *record {
+                // Without any change its flags would be all zero, which is the end of shape
+                ShapeRecord::StyleChange { move_to: None, fill_style0: None, fill_style1: None, line_style: None } => (),
+                ShapeRecord::StyleChange { move_to, fill_style0, fill_style1, line_style } => {
+                    try!(bits.write_bit(false));
+                    try!(bits.write_bit(false)); // new styles
+                    try!(bits.write_bit(line_style.is_some()));
+                    try!(bits.write_bit(fill_style1.is_some()));
+                    try!(bits.write_bit(fill_style0.is_some()));
+                    try!(bits.write_bit(move_to.is_some()));
+                    if let Some((x, y)) = move_to {
+                        let nbits = max(count_sbits(x), count_sbits(y));
+                        try!(bits.write_ubits(5, nbits as u32));
+                        try!(bits.write_sbits(nbits, x));
+                        try!(bits.write_sbits(nbits, y));
+                    }
+                    if let Some(v) = fill_style0 {
+                        try!(bits.write_ubits(fill_bits, v));
+                    }
+                    if let Some(v) = fill_style1 {
+                        try!(bits.write_ubits(fill_bits, v));
+                    }
+                    if let Some(v) = line_style {
+                        try!(bits.write_ubits(line_bits, v));
+                    }
+                },
+                ShapeRecord::StraightEdge { dx, dy } => {
+                    let nbits = max(2, max(count_sbits(dx), count_sbits(dy)));
+                    try!(bits.write_bit(true));
+                    try!(bits.write_bit(true));
+                    try!(bits.write_ubits(4, nbits as u32 - 2));
+                    if dx != 0 && dy != 0 {
+                        try!(bits.write_bit(true));
+                        try!(bits.write_sbits(nbits, dx));
+                        try!(bits.write_sbits(nbits, dy));
+                    } else {
+                        try!(bits.write_bit(false));
+                        try!(bits.write_bit(dx == 0));
+                        try!(bits.write_sbits(nbits, if dx == 0 { dy } else { dx }));
+                    }
+                },
+                ShapeRecord::CurvedEdge { control_dx, control_dy, anchor_dx, anchor_dy } => {
+                    let nbits = [control_dx, control_dy, anchor_dx, anchor_dy].iter().fold(2, |n, &v| max(n, count_sbits(v)));
+                    try!(bits.write_bit(true));
+                    try!(bits.write_bit(false));
+                    try!(bits.write_ubits(4, nbits as u32 - 2));
+                    try!(bits.write_sbits(nbits, control_dx));
+                    try!(bits.write_sbits(nbits, control_dy));
+                    try!(bits.write_sbits(nbits, anchor_dx));
+                    try!(bits.write_sbits(nbits, anchor_dy));
+                }
+            }
+        }
+
+        try!(bits.write_ubits(6, 0)); // end of shape
+        bits.flush()
+    }
+
+    // Absolute outline of the shape, every contour starts with a MoveTo
+    pub fn outline(&self) -> Vec<PathCommand> {
+        let mut path = Vec::new();
+        let (mut x, mut y) = (0, 0);
+        let mut open = false;
+
+        for record in self.records.iter() {
+            match *record {
+                ShapeRecord::StyleChange { move_to, .. } => if let Some((mx, my)) = move_to {
+                    x = mx;
+                    y = my;
+                    open = false;
+                },
+                ShapeRecord::StraightEdge { dx, dy } => {
+                    if !open {
+                        path.push(PathCommand::MoveTo(x, y));
+                        open = true;
+                    }
+                    x += dx;
+                    y += dy;
+                    path.push(PathCommand::LineTo(x, y));
+                },
+                ShapeRecord::CurvedEdge { control_dx, control_dy, anchor_dx, anchor_dy } => {
+                    if !open {
+                        path.push(PathCommand::MoveTo(x, y));
+                        open = true;
+                    }
+                    let (cx, cy) = (x + control_dx, y + control_dy);
+                    x = cx + anchor_dx;
+                    y = cy + anchor_dy;
+                    path.push(PathCommand::CurveTo(cx, cy, x, y));
+                }
+            }
+        }
+
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Shape, ShapeRecord };
+
+    #[test]
+    fn skips_empty_style_changes() {
+        let mut shape = Shape::new();
+        shape.records.push(ShapeRecord::StyleChange { move_to: Some((-20, 40)), fill_style0: None, fill_style1: Some(1),
+            line_style: None });
+        shape.records.push(ShapeRecord::StraightEdge { dx: 100, dy: 0 });
+        shape.records.push(ShapeRecord::StyleChange { move_to: None, fill_style0: None, fill_style1: None, line_style: None });
+        shape.records.push(ShapeRecord::CurvedEdge { control_dx: -3, control_dy: 50, anchor_dx: -97, anchor_dy: -90 });
+
+        let mut data = Vec::new();
+        shape.write(&mut data).unwrap();
+        let read = Shape::read(&mut &data[..]).unwrap();
+
+        shape.records.remove(2);
+        assert_eq!(read, shape);
+    }
+}
@@ -1,4 +1,4 @@
-use std::old_io::{ Reader, IoResult, IoError, InvalidInput };
+use std::old_io::{ Reader, Writer, ByRefReader, ByRef, IoResult, IoError, InvalidInput };
 use std::old_io::util::LimitReader;
 use std::num;
 
@@ -14,6 +14,7 @@ pub struct Magic {
 
 #[derive(Debug, Clone)]
 pub struct Header {
+    // The frame size RECT as stored
     pub rectangle: Vec<u8>,
     pub frame_rate: u16,
     pub frame_count: u16,
@@ -47,6 +48,13 @@ impl Magic {
         8
     }
 
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(writer.write_u8(self.compression as u8));
+        try!(writer.write_all(b"WS"));
+        try!(writer.write_u8(self.version));
+        writer.write_le_u32(self.size)
+    }
+
     pub fn reader<'a, R : Reader + 'a>(&self, reader: R) -> Box<Reader + 'a> {
         let len = self.size as usize - self.len();
         match self.compression {
@@ -65,8 +73,11 @@ impl Magic {
 
 impl Header {
     pub fn read<R : Reader>(reader: &mut R) -> IoResult<Self> {
-        let rect_size = (4 * (try!(reader.read_u8()) >> 3) as usize - 3 + 7) / 8;
-        let rectangle = try!(reader.read_exact(rect_size));
+        // The raw RECT, starting with the byte that holds its field size, which is all of it when that size is 0
+        let first = try!(reader.read_u8());
+        let rect_size = (5 + 4 * (first >> 3) as usize + 7) / 8 - 1;
+        let mut rectangle = vec![first];
+        rectangle.push_all(&try!(reader.read_exact(rect_size)));
         let frame_rate = try!(reader.read_le_u16());
         let frame_count = try!(reader.read_le_u16());
 
@@ -78,7 +89,13 @@ impl Header {
     }
 
     pub fn len(&self) -> usize {
-        5 + self.rectangle.len()
+        4 + self.rectangle.len()
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(writer.write_all(&self.rectangle));
+        try!(writer.write_le_u16(self.frame_rate));
+        writer.write_le_u16(self.frame_count)
     }
 }
 
@@ -108,6 +125,10 @@ impl Tag {
         })
     }
 
+    pub fn reader<'a, R : Reader>(&self, reader: &'a mut R) -> LimitReader<ByRef<'a, R>> {
+        LimitReader::new(reader.by_ref(), self.length as usize)
+    }
+
     pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
         let kind = (self.kind as u16) << 6;
         match self.length {
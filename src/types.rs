@@ -0,0 +1,385 @@
+use std::old_io::{ Reader, Writer, ByRefReader, IoResult };
+use std::cmp::{ min, max };
+use std::mem::transmute;
+use std::f32;
+use bits::{ BitReader, BitWriter, count_sbits };
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x_min: i32,
+    pub x_max: i32,
+    pub y_min: i32,
+    pub y_max: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix {
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub rotate_skew0: f32,
+    pub rotate_skew1: f32,
+    pub translate_x: i32,
+    pub translate_y: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ColorTransform {
+    pub r_mult: i16,
+    pub g_mult: i16,
+    pub b_mult: i16,
+    pub a_mult: i16,
+    pub r_add: i16,
+    pub g_add: i16,
+    pub b_add: i16,
+    pub a_add: i16,
+}
+
+impl Rect {
+    pub fn read<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        let mut bits = BitReader::new(reader.by_ref());
+        let nbits = try!(bits.read_ubits(5)) as u8;
+
+        Ok(Rect {
+            x_min: try!(bits.read_sbits(nbits)),
+            x_max: try!(bits.read_sbits(nbits)),
+            y_min: try!(bits.read_sbits(nbits)),
+            y_max: try!(bits.read_sbits(nbits))
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        let nbits = [self.x_min, self.x_max, self.y_min, self.y_max].iter().fold(0, |n, &v| max(n, count_sbits(v)));
+
+        let mut bits = BitWriter::new(writer);
+        try!(bits.write_ubits(5, nbits as u32));
+        try!(bits.write_sbits(nbits, self.x_min));
+        try!(bits.write_sbits(nbits, self.x_max));
+        try!(bits.write_sbits(nbits, self.y_min));
+        try!(bits.write_sbits(nbits, self.y_max));
+        bits.flush()
+    }
+
+    pub fn width(&self) -> i32 {
+        self.x_max - self.x_min
+    }
+
+    pub fn height(&self) -> i32 {
+        self.y_max - self.y_min
+    }
+}
+
+impl Rgba {
+    pub fn read_rgb<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        Ok(Rgba {
+            r: try!(reader.read_u8()),
+            g: try!(reader.read_u8()),
+            b: try!(reader.read_u8()),
+            a: 0xff
+        })
+    }
+
+    pub fn read_rgba<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        Ok(Rgba {
+            r: try!(reader.read_u8()),
+            g: try!(reader.read_u8()),
+            b: try!(reader.read_u8()),
+            a: try!(reader.read_u8())
+        })
+    }
+
+    pub fn write_rgb<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        writer.write_all(&[self.r, self.g, self.b])
+    }
+
+    pub fn write_rgba<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        writer.write_all(&[self.r, self.g, self.b, self.a])
+    }
+}
+
+impl Matrix {
+    pub fn identity() -> Self {
+        Matrix {
+            scale_x: 1.0,
+            scale_y: 1.0,
+            rotate_skew0: 0.0,
+            rotate_skew1: 0.0,
+            translate_x: 0,
+            translate_y: 0
+        }
+    }
+
+    pub fn translate(x: i32, y: i32) -> Self {
+        Matrix { translate_x: x, translate_y: y, .. Matrix::identity() }
+    }
+
+    pub fn read<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        let mut bits = BitReader::new(reader.by_ref());
+        let mut matrix = Matrix::identity();
+
+        if try!(bits.read_bit()) {
+            let nbits = try!(bits.read_ubits(5)) as u8;
+            matrix.scale_x = try!(bits.read_fbits(nbits));
+            matrix.scale_y = try!(bits.read_fbits(nbits));
+        }
+
+        if try!(bits.read_bit()) {
+            let nbits = try!(bits.read_ubits(5)) as u8;
+            matrix.rotate_skew0 = try!(bits.read_fbits(nbits));
+            matrix.rotate_skew1 = try!(bits.read_fbits(nbits));
+        }
+
+        let nbits = try!(bits.read_ubits(5)) as u8;
+        matrix.translate_x = try!(bits.read_sbits(nbits));
+        matrix.translate_y = try!(bits.read_sbits(nbits));
+
+        Ok(matrix)
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        let mut bits = BitWriter::new(writer);
+
+        let has_scale = self.scale_x != 1.0 || self.scale_y != 1.0;
+        try!(bits.write_bit(has_scale));
+        if has_scale {
+            let (x, y) = (fixed(self.scale_x), fixed(self.scale_y));
+            let nbits = max(count_sbits(x), count_sbits(y));
+            try!(bits.write_ubits(5, nbits as u32));
+            try!(bits.write_sbits(nbits, x));
+            try!(bits.write_sbits(nbits, y));
+        }
+
+        let has_rotate = self.rotate_skew0 != 0.0 || self.rotate_skew1 != 0.0;
+        try!(bits.write_bit(has_rotate));
+        if has_rotate {
+            let (r0, r1) = (fixed(self.rotate_skew0), fixed(self.rotate_skew1));
+            let nbits = max(count_sbits(r0), count_sbits(r1));
+            try!(bits.write_ubits(5, nbits as u32));
+            try!(bits.write_sbits(nbits, r0));
+            try!(bits.write_sbits(nbits, r1));
+        }
+
+        let nbits = max(count_sbits(self.translate_x), count_sbits(self.translate_y));
+        try!(bits.write_ubits(5, nbits as u32));
+        try!(bits.write_sbits(nbits, self.translate_x));
+        try!(bits.write_sbits(nbits, self.translate_y));
+        bits.flush()
+    }
+
+    pub fn transform(&self, x: f32, y: f32) -> (f32, f32) {
+        (x * self.scale_x + y * self.rotate_skew1 + self.translate_x as f32,
+         x * self.rotate_skew0 + y * self.scale_y + self.translate_y as f32)
+    }
+
+    // Applies `self' after `inner'
+    pub fn concat(&self, inner: &Matrix) -> Matrix {
+        let (tx, ty) = self.transform(inner.translate_x as f32, inner.translate_y as f32);
+
+        Matrix {
+            scale_x: self.scale_x * inner.scale_x + self.rotate_skew1 * inner.rotate_skew0,
+            rotate_skew0: self.rotate_skew0 * inner.scale_x + self.scale_y * inner.rotate_skew0,
+            rotate_skew1: self.scale_x * inner.rotate_skew1 + self.rotate_skew1 * inner.scale_y,
+            scale_y: self.rotate_skew0 * inner.rotate_skew1 + self.scale_y * inner.scale_y,
+            translate_x: tx as i32,
+            translate_y: ty as i32
+        }
+    }
+}
+
+impl ColorTransform {
+    pub fn identity() -> Self {
+        ColorTransform {
+            r_mult: 256,
+            g_mult: 256,
+            b_mult: 256,
+            a_mult: 256,
+            r_add: 0,
+            g_add: 0,
+            b_add: 0,
+            a_add: 0
+        }
+    }
+
+    pub fn read<R : Reader>(reader: &mut R, alpha: bool) -> IoResult<Self> {
+        let mut bits = BitReader::new(reader.by_ref());
+        let mut cxform = ColorTransform::identity();
+
+        let has_add = try!(bits.read_bit());
+        let has_mult = try!(bits.read_bit());
+        let nbits = try!(bits.read_ubits(4)) as u8;
+
+        if has_mult {
+            cxform.r_mult = try!(bits.read_sbits(nbits)) as i16;
+            cxform.g_mult = try!(bits.read_sbits(nbits)) as i16;
+            cxform.b_mult = try!(bits.read_sbits(nbits)) as i16;
+            if alpha {
+                cxform.a_mult = try!(bits.read_sbits(nbits)) as i16;
+            }
+        }
+
+        if has_add {
+            cxform.r_add = try!(bits.read_sbits(nbits)) as i16;
+            cxform.g_add = try!(bits.read_sbits(nbits)) as i16;
+            cxform.b_add = try!(bits.read_sbits(nbits)) as i16;
+            if alpha {
+                cxform.a_add = try!(bits.read_sbits(nbits)) as i16;
+            }
+        }
+
+        Ok(cxform)
+    }
+
+    // The field size has 4 bits, so terms are clamped to the 15 signed bits that allows
+    pub fn write<W : Writer>(&self, writer: &mut W, alpha: bool) -> IoResult<()> {
+        fn clamp(v: i16) -> i32 {
+            max(-0x4000, min(0x3fff, v as i32))
+        }
+        let mult = [clamp(self.r_mult), clamp(self.g_mult), clamp(self.b_mult), clamp(self.a_mult)];
+        let add = [clamp(self.r_add), clamp(self.g_add), clamp(self.b_add), clamp(self.a_add)];
+        let terms = if alpha { 4 } else { 3 };
+
+        let has_mult = mult[..terms].iter().any(|&v| v != 256);
+        let has_add = add[..terms].iter().any(|&v| v != 0);
+        let mut nbits = 0;
+        if has_mult {
+            nbits = mult[..terms].iter().fold(nbits, |n, &v| max(n, count_sbits(v)));
+        }
+        if has_add {
+            nbits = add[..terms].iter().fold(nbits, |n, &v| max(n, count_sbits(v)));
+        }
+
+        let mut bits = BitWriter::new(writer);
+        try!(bits.write_bit(has_add));
+        try!(bits.write_bit(has_mult));
+        try!(bits.write_ubits(4, nbits as u32));
+
+        if has_mult {
+            for &v in mult[..terms].iter() {
+                try!(bits.write_sbits(nbits, v));
+            }
+        }
+
+        if has_add {
+            for &v in add[..terms].iter() {
+                try!(bits.write_sbits(nbits, v));
+            }
+        }
+
+        bits.flush()
+    }
+
+    pub fn apply(&self, color: Rgba) -> Rgba {
+        fn channel(c: u8, mult: i16, add: i16) -> u8 {
+            let v = (c as i32 * mult as i32 >> 8) + add as i32;
+            if v < 0 { 0 } else if v > 0xff { 0xff } else { v as u8 }
+        }
+
+        Rgba {
+            r: channel(color.r, self.r_mult, self.r_add),
+            g: channel(color.g, self.g_mult, self.g_add),
+            b: channel(color.b, self.b_mult, self.b_add),
+            a: channel(color.a, self.a_mult, self.a_add)
+        }
+    }
+}
+
+pub fn read_string<R : Reader>(reader: &mut R) -> IoResult<Vec<u8>> {
+    let mut value = Vec::new();
+    loop {
+        match try!(reader.read_u8()) {
+            0 => return Ok(value),
+            c => value.push(c)
+        }
+    }
+}
+
+pub fn write_string<W : Writer>(writer: &mut W, value: &str) -> IoResult<()> {
+    try!(writer.write_str(value));
+    writer.write_u8(0)
+}
+
+// SWF 6 and later store strings as UTF-8, earlier versions use the locale's encoding
+pub fn decode_string(value: Vec<u8>, version: u8) -> String {
+    match version {
+        0...5 => value.into_iter().map(|c| c as char).collect(),
+        _ => String::from_utf8_lossy(&value).into_owned()
+    }
+}
+
+pub fn encode_string(value: &str, version: u8) -> Vec<u8> {
+    match version {
+        0...5 => value.chars().map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' }).collect(),
+        _ => value.as_bytes().to_vec()
+    }
+}
+
+// IEEE 754 half precision, used by DefineFontAlignZones
+pub fn f16_to_f32(v: u16) -> f32 {
+    let sign = if v & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((v >> 10) & 0x1f) as i32;
+    let mantissa = (v & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa / 1024.0 / 16384.0,
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * pow2(exponent - 15)
+    }
+}
+
+pub fn f32_to_f16(v: f32) -> u16 {
+    let bits: u32 = unsafe { transmute(v) };
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+
+    sign | match exponent {
+        0xff => 0x7c00 | if mantissa != 0 { 0x200 } else { 0 },
+        _ if exponent - 127 > 15 => 0x7c00,
+        _ if exponent - 127 < -24 => 0,
+        _ if exponent - 127 < -14 => ((mantissa | 0x800000) >> (126 - exponent)) as u16,
+        _ => (((exponent - 127 + 15) as u32) << 10 | mantissa >> 13) as u16
+    }
+}
+
+fn pow2(exponent: i32) -> f32 {
+    let mut v = 1.0;
+    if exponent >= 0 {
+        for _ in 0..exponent { v *= 2.0 }
+    } else {
+        for _ in exponent..0 { v /= 2.0 }
+    }
+    v
+}
+
+fn fixed(v: f32) -> i32 {
+    (v * 65536.0) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColorTransform;
+
+    #[test]
+    fn color_transform_round_trip() {
+        let cxform = ColorTransform { r_mult: -256, g_mult: 128, b_mult: 256, a_mult: 16383, r_add: -16384, g_add: 0,
+            b_add: 255, a_add: -1 };
+        let mut data = Vec::new();
+        cxform.write(&mut data, true).unwrap();
+        assert_eq!(ColorTransform::read(&mut &data[..], true).unwrap(), cxform);
+
+        // Terms past 15 signed bits are clamped rather than wrapped
+        let cxform = ColorTransform { r_add: -32768, g_add: 32767, .. ColorTransform::identity() };
+        let mut data = Vec::new();
+        cxform.write(&mut data, false).unwrap();
+        let read = ColorTransform::read(&mut &data[..], false).unwrap();
+        assert_eq!((read.r_add, read.g_add, read.b_add), (-16384, 16383, 0));
+    }
+}
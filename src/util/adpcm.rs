@@ -0,0 +1,255 @@
+use std::old_io::{ BufReader, IoResult, IoError, InvalidInput };
+use bits::{ BitReader, BitWriter };
+use util::sound_stream::{ SoundFormat, CodecKind };
+
+const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45,
+    50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307,
+    337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899,
+    15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767
+];
+
+const INDEX_TABLE2: [i32; 2] = [-1, 2];
+const INDEX_TABLE3: [i32; 4] = [-1, -1, 2, 4];
+const INDEX_TABLE4: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+const INDEX_TABLE5: [i32; 16] = [-1, -1, -1, -1, -1, -1, -1, -1, 1, 2, 4, 6, 8, 10, 13, 16];
+
+// Each packet holds its header sample followed by this many coded samples per channel
+const PACKET_SAMPLES: usize = 4095;
+
+// Flash ADPCM, as found in DefineSound bodies and in each SoundStreamBlock
+#[derive(Debug, Copy, Clone)]
+pub struct AdpcmDecoder {
+    channels: usize,
+}
+
+// Encodes 16-bit samples with 4-bit codes, the size the Flash authoring tool uses by default
+#[derive(Debug, Copy, Clone)]
+pub struct AdpcmEncoder {
+    channels: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Channel {
+    sample: i32,
+    index: i32,
+}
+
+impl AdpcmDecoder {
+    pub fn new(format: &SoundFormat) -> IoResult<Self> {
+        if format.codec() != CodecKind::ADPCM {
+            return Err(IoError { kind: InvalidInput, desc: "ADPCM sound expected", detail: None })
+        }
+
+        Ok(AdpcmDecoder {
+            channels: format.channels() as usize
+        })
+    }
+
+    // Decodes a complete ADPCM stream to interleaved 16-bit samples
+    pub fn decode(&self, data: &[u8]) -> IoResult<Vec<i16>> {
+        let mut bits = BitReader::new(BufReader::new(data));
+        let mut remaining = data.len() * 8;
+        let mut samples = Vec::new();
+
+        if remaining < 2 {
+            return Ok(samples)
+        }
+        let code_bits = try!(bits.read_ubits(2)) as u8 + 2;
+        remaining -= 2;
+
+        let index_table: &[i32] = match code_bits {
+            2 => &INDEX_TABLE2[..],
+            3 => &INDEX_TABLE3[..],
+            4 => &INDEX_TABLE4[..],
+            _ => &INDEX_TABLE5[..]
+        };
+        let sign = 1 << (code_bits - 1);
+
+        let header_bits = 22 * self.channels;
+        let frame_bits = code_bits as usize * self.channels;
+        let mut channels = [Channel { sample: 0, index: 0 }; 2];
+
+        while remaining >= header_bits {
+            for channel in channels[..self.channels].iter_mut() {
+                channel.sample = try!(bits.read_sbits(16));
+                channel.index = try!(bits.read_ubits(6)) as i32;
+                samples.push(channel.sample as i16);
+            }
+            remaining -= header_bits;
+
+            let mut count = 0;
+            while count < PACKET_SAMPLES && remaining >= frame_bits {
+                for channel in channels[..self.channels].iter_mut() {
+                    let code = try!(bits.read_ubits(code_bits)) as i32;
+                    samples.push(decode_sample(channel, code, code_bits, sign, index_table));
+                }
+                remaining -= frame_bits;
+                count += 1;
+            }
+        }
+
+        Ok(samples)
+    }
+}
+
+impl AdpcmEncoder {
+    pub fn new(channels: u16) -> IoResult<Self> {
+        if channels != 1 && channels != 2 {
+            return Err(IoError { kind: InvalidInput, desc: "ADPCM supports mono and stereo only", detail: None })
+        }
+
+        Ok(AdpcmEncoder {
+            channels: channels as usize
+        })
+    }
+
+    // Encodes interleaved samples into a complete ADPCM stream
+    pub fn encode(&self, samples: &[i16]) -> IoResult<Vec<u8>> {
+        let code_bits = 4;
+        let sign = 1 << (code_bits - 1);
+        let mut bits = BitWriter::new(Vec::new());
+        let mut channels = [Channel { sample: 0, index: 0 }; 2];
+
+        try!(bits.write_ubits(2, code_bits as u32 - 2));
+        for packet in samples.chunks((PACKET_SAMPLES + 1) * self.channels) {
+            let mut frames = packet.chunks(self.channels).filter(|f| f.len() == self.channels);
+
+            // The step index carries over from the previous packet
+            if let Some(first) = frames.next() {
+                for (channel, &sample) in channels.iter_mut().zip(first.iter()) {
+                    channel.sample = sample as i32;
+                    try!(bits.write_sbits(16, channel.sample));
+                    try!(bits.write_ubits(6, channel.index as u32));
+                }
+            }
+
+            for frame in frames {
+                for (channel, &sample) in channels.iter_mut().zip(frame.iter()) {
+                    let code = encode_sample(channel, sample, code_bits, sign, &INDEX_TABLE4);
+                    try!(bits.write_ubits(code_bits, code as u32));
+                }
+            }
+        }
+
+        bits.into_inner()
+    }
+}
+
+// Quantizes the step towards `sample` like the IMA reference encoder, updating the state as the decoder will
+fn encode_sample(channel: &mut Channel, sample: i16, code_bits: u8, sign: i32, index_table: &[i32]) -> i32 {
+    let mut difference = sample as i32 - channel.sample;
+    let mut code = 0;
+    if difference < 0 {
+        code = sign;
+        difference = -difference;
+    }
+
+    let mut step = STEP_TABLE[channel.index as usize];
+    let mut mask = 1 << (code_bits - 2);
+    while mask != 0 {
+        if difference >= step {
+            code |= mask;
+            difference -= step;
+        }
+        step >>= 1;
+        mask >>= 1;
+    }
+
+    decode_sample(channel, code, code_bits, sign, index_table);
+    code
+}
+
+fn decode_sample(channel: &mut Channel, code: i32, code_bits: u8, sign: i32, index_table: &[i32]) -> i16 {
+    let mut step = STEP_TABLE[channel.index as usize];
+
+    // Equivalent to (magnitude + 0.5) * step / 2^(code_bits - 2)
+    let mut difference = 0;
+    let mut mask = 1 << (code_bits - 2);
+    while mask != 0 {
+        if code & mask != 0 {
+            difference += step;
+        }
+        step >>= 1;
+        mask >>= 1;
+    }
+    difference += step;
+
+    channel.sample += if code & sign != 0 { -difference } else { difference };
+    if channel.sample > 32767 {
+        channel.sample = 32767;
+    } else if channel.sample < -32768 {
+        channel.sample = -32768;
+    }
+
+    channel.index += index_table[(code & !sign) as usize];
+    if channel.index < 0 {
+        channel.index = 0;
+    } else if channel.index > 88 {
+        channel.index = 88;
+    }
+
+    channel.sample as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ AdpcmDecoder, AdpcmEncoder };
+    use util::sound_stream::{ SoundFormat, CodecKind };
+
+    fn decoder(stereo: bool) -> AdpcmDecoder {
+        AdpcmDecoder::new(&SoundFormat::from_parts(CodecKind::ADPCM, 22050, true, stereo).unwrap()).unwrap()
+    }
+
+    fn samples(data: &[u8]) -> Vec<i16> {
+        data.chunks(2).map(|c| (c[0] as u16 | (c[1] as u16) << 8) as i16).collect()
+    }
+
+    #[test]
+    fn decode_matches_reference() {
+        // FFmpeg's encoding of a stereo sine mix, every FLV audio tag is a complete ADPCM stream of 1024 samples.
+        // The reference is FFmpeg's decoding, which matches to the bit
+        let flv = include_bytes!("../../tests/fixtures/adpcm_22050_stereo.flv");
+        let reference = samples(include_bytes!("../../tests/fixtures/adpcm_22050_stereo.pcm"));
+        let decoder = decoder(true);
+
+        let mut decoded = Vec::new();
+        let mut pos = 13;
+        while pos < flv.len() {
+            let size = (flv[pos + 1] as usize) << 16 | (flv[pos + 2] as usize) << 8 | flv[pos + 3] as usize;
+            if flv[pos] == 8 {
+                decoded.push_all(&decoder.decode(&flv[pos + 12..pos + 11 + size]).unwrap());
+            }
+            pos += 11 + size + 4;
+        }
+
+        assert_eq!(decoded.len(), reference.len());
+        assert!(decoded == reference);
+    }
+
+    #[test]
+    fn decode_two_bit_codes() {
+        // 2-bit codes, a header of 1000 at step index 10, then the codes 01, 11, 00 and 10
+        let data = [0x00, 0xfa, 0x0a, 0x72];
+        assert_eq!(decoder(false).decode(&data).unwrap(), vec![1000, 1028, 994, 1008, 996]);
+        assert_eq!(decoder(false).decode(&data[..1]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        // Packets of 4096 frames, the last one partial
+        let input: Vec<i16> = (0..10000).map(|i| ((i as f32 * 0.05).sin() * 12000.0) as i16).collect();
+        let encoded = AdpcmEncoder::new(1).unwrap().encode(&input).unwrap();
+        assert_eq!(encoded.len(), (2 + 3 * 22 + (10000 - 3) * 4 + 7) / 8);
+
+        let decoded = decoder(false).decode(&encoded).unwrap();
+        assert_eq!(decoded.len(), input.len());
+        // The step size starts at its smallest and needs a few samples to catch up with the signal
+        for (i, (&sample, &expected)) in decoded.iter().zip(input.iter()).enumerate().skip(16) {
+            assert!((sample as i32 - expected as i32).abs() <= 128, "sample {}: {} != {}", i, sample, expected);
+        }
+
+        assert!(AdpcmEncoder::new(3).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::old_io::{ Reader, Writer, IoResult, IoError, InvalidInput };
+use std::old_io::util::{ NullWriter, copy };
+use std::iter::repeat;
+use swf::{ Tag, TagKind };
+use zlib;
+
+const FORMAT_ARGB: u8 = 5;
+
+#[derive(Debug, Clone)]
+pub struct BitmapLossless {
+    pub id: u16,
+    pub width: u16,
+    pub height: u16,
+    pub argb: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BitmapJPEG {
+    pub id: u16,
+    pub width: u16,
+    pub height: u16,
+    pub jpeg: Vec<u8>,
+    pub alpha: Vec<u8>,
+}
+
+impl BitmapLossless {
+    pub fn from_rgba(id: u16, width: u16, height: u16, rgba: &[u8]) -> IoResult<Self> {
+        if rgba.len() != width as usize * height as usize * 4 {
+            return Err(IoError { kind: InvalidInput, desc: "RGBA buffer does not match bitmap size", detail: None })
+        }
+
+        let mut argb = Vec::with_capacity(rgba.len());
+        for pixel in rgba.chunks(4) {
+            let a = pixel[3];
+            argb.push(a);
+            argb.push(premultiply(pixel[0], a));
+            argb.push(premultiply(pixel[1], a));
+            argb.push(premultiply(pixel[2], a));
+        }
+
+        Ok(BitmapLossless {
+            id: id,
+            width: width,
+            height: height,
+            argb: argb
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        let data = try!(zlib::compress(&self.argb));
+
+        try!(Tag { kind: TagKind::DefineBitsLossless2, length: 7 + data.len() as u32 }.write(writer));
+        try!(writer.write_le_u16(self.id));
+        try!(writer.write_u8(FORMAT_ARGB));
+        try!(writer.write_le_u16(self.width));
+        try!(writer.write_le_u16(self.height));
+        writer.write_all(&data)
+    }
+}
+
+impl BitmapJPEG {
+    pub fn new(id: u16, jpeg: Vec<u8>, alpha: Option<Vec<u8>>) -> IoResult<Self> {
+        let (width, height) = try!(jpeg_dimensions(&jpeg).ok_or(
+            IoError { kind: InvalidInput, desc: "JPEG frame header not found", detail: None }
+        ));
+
+        let size = width as usize * height as usize;
+        let alpha = match alpha {
+            Some(alpha) => alpha,
+            None => repeat(0xff).take(size).collect()
+        };
+
+        if alpha.len() != size {
+            return Err(IoError { kind: InvalidInput, desc: "alpha plane does not match JPEG size", detail: None })
+        }
+
+        Ok(BitmapJPEG {
+            id: id,
+            width: width,
+            height: height,
+            jpeg: jpeg,
+            alpha: alpha
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        let alpha = try!(zlib::compress(&self.alpha));
+
+        try!(Tag { kind: TagKind::DefineBitsJPEG3, length: 6 + (self.jpeg.len() + alpha.len()) as u32 }.write(writer));
+        try!(writer.write_le_u16(self.id));
+        try!(writer.write_le_u32(self.jpeg.len() as u32));
+        try!(writer.write_all(&self.jpeg));
+        writer.write_all(&alpha)
+    }
+}
+
+pub fn bitmap_id<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<u16> {
+    match tag.kind {
+        TagKind::DefineBits | TagKind::DefineButsLossless | TagKind::DefineBitsJPEG2 |
+        TagKind::DefineBitsJPEG3 | TagKind::DefineBitsLossless2 | TagKind::DefineBitsJPEG4 => (),
+        _ => return Err(IoError { kind: InvalidInput, desc: "bitmap tag expected", detail: None })
+    }
+
+    let mut reader = tag.reader(reader);
+    let id = try!(reader.read_le_u16());
+    try!(copy(&mut reader, &mut NullWriter));
+
+    Ok(id)
+}
+
+fn premultiply(c: u8, a: u8) -> u8 {
+    ((c as u32 * a as u32 + 127) / 255) as u8
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u16, u16)> {
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        if data[i] != 0xff {
+            return None
+        }
+
+        let marker = data[i + 1];
+        match marker {
+            0xd8 | 0xd9 | 0x01 | 0xd0...0xd7 => { i += 2; continue }, // SOI, EOI, TEM, RSTn
+            0xff => { i += 1; continue }, // fill byte
+            _ => ()
+        }
+
+        let len = (data[i + 2] as usize) << 8 | data[i + 3] as usize;
+        match marker {
+            0xc0...0xc3 | 0xc5...0xc7 | 0xc9...0xcb | 0xcd...0xcf if i + 9 <= data.len() => {
+                let height = (data[i + 5] as u16) << 8 | data[i + 6] as u16;
+                let width = (data[i + 7] as u16) << 8 | data[i + 8] as u16;
+                return Some((width, height))
+            },
+            _ => i += 2 + len
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ BitmapLossless, BitmapJPEG };
+
+    #[test]
+    fn premultiplies_alpha() {
+        let rgba = [255, 128, 0, 255, 255, 128, 0, 128, 200, 100, 50, 0];
+        let bitmap = BitmapLossless::from_rgba(1, 3, 1, &rgba).unwrap();
+        assert_eq!(bitmap.argb, vec![255, 255, 128, 0, 128, 128, 64, 0, 0, 0, 0, 0]);
+        assert!(BitmapLossless::from_rgba(1, 2, 1, &rgba).is_err());
+    }
+
+    #[test]
+    fn jpeg_size_from_frame_header() {
+        // SOI, an APP0 segment to skip, then a baseline frame header of 300x200
+        let jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x4a, 0x46, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x00, 0xc8, 0x01,
+            0x2c, 0x03];
+        let bitmap = BitmapJPEG::new(1, jpeg.clone(), None).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (300, 200));
+        assert_eq!(bitmap.alpha.len(), 300 * 200);
+        assert!(bitmap.alpha.iter().all(|&a| a == 0xff));
+
+        assert!(BitmapJPEG::new(1, jpeg, Some(vec![0; 10])).is_err());
+        assert!(BitmapJPEG::new(1, vec![0xff, 0xd8, 0xff, 0xd9], None).is_err());
+    }
+
+    #[cfg(not(feature = "zlib"))]
+    #[test]
+    fn write_needs_zlib() {
+        let bitmap = BitmapLossless::from_rgba(1, 1, 1, &[0, 0, 0, 255]).unwrap();
+        assert!(bitmap.write(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::old_io::{ Reader, Writer, IoResult, IoError, InvalidInput, EndOfFile };
+use std::old_io::util::{ NullWriter, copy };
+use swf::{ Tag, TagKind };
+use types::{ f16_to_f32, f32_to_f16 };
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GridFit {
+    None     = 0,
+    Pixel    = 1,
+    SubPixel = 2
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CSMTableHint {
+    Thin   = 0,
+    Medium = 1,
+    Thick  = 2
+}
+
+// Advanced anti-aliasing settings for a DefineText or DefineEditText character
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CSMTextSettings {
+    pub id: u16,
+    pub use_flash_type: bool,
+    pub grid_fit: GridFit,
+    pub thickness: f32,
+    pub sharpness: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ZoneData {
+    pub alignment: f32,
+    pub range: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneRecord {
+    pub zones: Vec<ZoneData>,
+    pub mask_x: bool,
+    pub mask_y: bool,
+}
+
+// Per glyph alignment zones of a DefineFont3 character
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontAlignZones {
+    pub id: u16,
+    pub hint: CSMTableHint,
+    pub records: Vec<ZoneRecord>,
+}
+
+impl CSMTextSettings {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::CSMTextSettings {
+            return Err(IoError { kind: InvalidInput, desc: "CSMTextSettings tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let flags = try!(reader.read_u8());
+        let grid_fit = match (flags >> 3) & 0x07 {
+            0 => GridFit::None,
+            1 => GridFit::Pixel,
+            2 => GridFit::SubPixel,
+            _ => return Err(IoError { kind: InvalidInput, desc: "unknown grid fit", detail: None })
+        };
+        let thickness = try!(reader.read_le_f32());
+        let sharpness = try!(reader.read_le_f32());
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(CSMTextSettings {
+            id: id,
+            use_flash_type: (flags >> 6) & 0x03 == 1,
+            grid_fit: grid_fit,
+            thickness: thickness,
+            sharpness: sharpness
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(Tag { kind: TagKind::CSMTextSettings, length: 12 }.write(writer));
+        try!(writer.write_le_u16(self.id));
+        try!(writer.write_u8((if self.use_flash_type { 1 } else { 0 }) << 6 | (self.grid_fit as u8) << 3));
+        try!(writer.write_le_f32(self.thickness));
+        try!(writer.write_le_f32(self.sharpness));
+        writer.write_u8(0)
+    }
+}
+
+impl FontAlignZones {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineFontAlignZones {
+            return Err(IoError { kind: InvalidInput, desc: "DefineFontAlignZones tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let hint = match try!(reader.read_u8()) >> 6 {
+            0 => CSMTableHint::Thin,
+            1 => CSMTableHint::Medium,
+            2 => CSMTableHint::Thick,
+            _ => return Err(IoError { kind: InvalidInput, desc: "unknown CSM table hint", detail: None })
+        };
+
+        // The zone table has one record per glyph of the font and runs to the end of the tag
+        let mut records = Vec::new();
+        loop {
+            let count = match reader.read_u8() {
+                Ok(count) => count,
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            };
+
+            let mut zones = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                zones.push(ZoneData {
+                    alignment: f16_to_f32(try!(reader.read_le_u16())),
+                    range: f16_to_f32(try!(reader.read_le_u16()))
+                });
+            }
+            let mask = try!(reader.read_u8());
+
+            records.push(ZoneRecord {
+                zones: zones,
+                mask_x: mask & 0x01 != 0,
+                mask_y: mask & 0x02 != 0
+            });
+        }
+
+        Ok(FontAlignZones {
+            id: id,
+            hint: hint,
+            records: records
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        let length = self.records.iter().fold(3, |len, record| len + 2 + record.zones.len() * 4);
+
+        try!(Tag { kind: TagKind::DefineFontAlignZones, length: length as u32 }.write(writer));
+        try!(writer.write_le_u16(self.id));
+        try!(writer.write_u8((self.hint as u8) << 6));
+        for record in self.records.iter() {
+            try!(writer.write_u8(record.zones.len() as u8));
+            for zone in record.zones.iter() {
+                try!(writer.write_le_u16(f32_to_f16(zone.alignment)));
+                try!(writer.write_le_u16(f32_to_f16(zone.range)));
+            }
+            try!(writer.write_u8((if record.mask_y { 0x02 } else { 0 }) | (if record.mask_x { 0x01 } else { 0 })));
+        }
+
+        Ok(())
+    }
+}
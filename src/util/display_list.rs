@@ -0,0 +1,729 @@
+use std::old_io::{ Reader, IoResult, IoError, InvalidInput, EndOfFile };
+use std::old_io::util::{ NullWriter, copy };
+use std::collections::BTreeMap;
+use std::collections::btree_map::Values;
+use std::num;
+use swf::{ Tag, TagKind };
+use types::{ Rgba, Matrix, ColorTransform, read_string, decode_string };
+
+#[derive(Debug, FromPrimitive, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal     = 1,
+    Layer      = 2,
+    Multiply   = 3,
+    Screen     = 4,
+    Lighten    = 5,
+    Darken     = 6,
+    Difference = 7,
+    Add        = 8,
+    Subtract   = 9,
+    Invert     = 10,
+    Alpha      = 11,
+    Erase      = 12,
+    Overlay    = 13,
+    HardLight  = 14
+}
+
+// Angles are in radians and blur sizes in pixels. Gradient filters have up to 15 colors with a ratio each,
+// the convolution matrix is stored by rows
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    DropShadow {
+        color: Rgba,
+        blur_x: f32,
+        blur_y: f32,
+        angle: f32,
+        distance: f32,
+        strength: f32,
+        inner: bool,
+        knockout: bool,
+        passes: u8,
+    },
+    Blur {
+        blur_x: f32,
+        blur_y: f32,
+        passes: u8,
+    },
+    Glow {
+        color: Rgba,
+        blur_x: f32,
+        blur_y: f32,
+        strength: f32,
+        inner: bool,
+        knockout: bool,
+        passes: u8,
+    },
+    Bevel {
+        shadow_color: Rgba,
+        highlight_color: Rgba,
+        blur_x: f32,
+        blur_y: f32,
+        angle: f32,
+        distance: f32,
+        strength: f32,
+        inner: bool,
+        knockout: bool,
+        on_top: bool,
+        passes: u8,
+    },
+    GradientGlow {
+        colors: Vec<Rgba>,
+        ratios: Vec<u8>,
+        blur_x: f32,
+        blur_y: f32,
+        angle: f32,
+        distance: f32,
+        strength: f32,
+        inner: bool,
+        knockout: bool,
+        on_top: bool,
+        passes: u8,
+    },
+    Convolution {
+        columns: u8,
+        rows: u8,
+        divisor: f32,
+        bias: f32,
+        matrix: Vec<f32>,
+        default_color: Rgba,
+        clamp: bool,
+        preserve_alpha: bool,
+    },
+    ColorMatrix(Vec<f32>),
+    GradientBevel {
+        colors: Vec<Rgba>,
+        ratios: Vec<u8>,
+        blur_x: f32,
+        blur_y: f32,
+        angle: f32,
+        distance: f32,
+        strength: f32,
+        inner: bool,
+        knockout: bool,
+        on_top: bool,
+        passes: u8,
+    },
+}
+
+// PlaceObject, PlaceObject2 or PlaceObject3, fields that are None leave the object at the depth unchanged.
+// Clip actions are kept as the raw CLIPACTIONS record
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceObject {
+    pub depth: u16,
+    pub is_move: bool,
+    pub character_id: Option<u16>,
+    pub class_name: Option<String>,
+    pub matrix: Option<Matrix>,
+    pub color_transform: Option<ColorTransform>,
+    pub ratio: Option<u16>,
+    pub name: Option<String>,
+    pub clip_depth: Option<u16>,
+    pub filters: Option<Vec<Filter>>,
+    pub blend_mode: Option<BlendMode>,
+    pub cache_as_bitmap: Option<bool>,
+    pub visible: Option<bool>,
+    pub background_color: Option<Rgba>,
+    pub clip_actions: Option<Vec<u8>>,
+}
+
+// RemoveObject names the character at the depth as well, RemoveObject2 only the depth
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RemoveObject {
+    pub depth: u16,
+    pub character_id: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayObject {
+    pub depth: u16,
+    pub character_id: u16,
+    pub class_name: Option<String>,
+    pub matrix: Matrix,
+    pub color_transform: ColorTransform,
+    pub ratio: u16,
+    pub name: Option<String>,
+    pub clip_depth: Option<u16>,
+    pub filters: Vec<Filter>,
+    pub blend_mode: BlendMode,
+    pub cache_as_bitmap: bool,
+    pub visible: bool,
+    pub background_color: Option<Rgba>,
+}
+
+// The objects of a timeline by depth, as they are after a frame's tags
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayList {
+    objects: BTreeMap<u16, DisplayObject>,
+}
+
+impl Filter {
+    pub fn read<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        let kind = try!(reader.read_u8());
+        Ok(match kind {
+            0 => {
+                let color = try!(Rgba::read_rgba(reader));
+                let blur_x = try!(read_fixed(reader));
+                let blur_y = try!(read_fixed(reader));
+                let angle = try!(read_fixed(reader));
+                let distance = try!(read_fixed(reader));
+                let strength = try!(read_fixed8(reader));
+                let flags = try!(reader.read_u8());
+                Filter::DropShadow {
+                    color: color,
+                    blur_x: blur_x,
+                    blur_y: blur_y,
+                    angle: angle,
+                    distance: distance,
+                    strength: strength,
+                    inner: flags & 0x80 != 0,
+                    knockout: flags & 0x40 != 0,
+                    passes: flags & 0x1f
+                }
+            },
+            1 => {
+                let blur_x = try!(read_fixed(reader));
+                let blur_y = try!(read_fixed(reader));
+                let flags = try!(reader.read_u8());
+                Filter::Blur { blur_x: blur_x, blur_y: blur_y, passes: flags >> 3 }
+            },
+            2 => {
+                let color = try!(Rgba::read_rgba(reader));
+                let blur_x = try!(read_fixed(reader));
+                let blur_y = try!(read_fixed(reader));
+                let strength = try!(read_fixed8(reader));
+                let flags = try!(reader.read_u8());
+                Filter::Glow {
+                    color: color,
+                    blur_x: blur_x,
+                    blur_y: blur_y,
+                    strength: strength,
+                    inner: flags & 0x80 != 0,
+                    knockout: flags & 0x40 != 0,
+                    passes: flags & 0x1f
+                }
+            },
+            3 => {
+                let highlight_color = try!(Rgba::read_rgba(reader));
+                let shadow_color = try!(Rgba::read_rgba(reader));
+                let blur_x = try!(read_fixed(reader));
+                let blur_y = try!(read_fixed(reader));
+                let angle = try!(read_fixed(reader));
+                let distance = try!(read_fixed(reader));
+                let strength = try!(read_fixed8(reader));
+                let flags = try!(reader.read_u8());
+                Filter::Bevel {
+                    shadow_color: shadow_color,
+                    highlight_color: highlight_color,
+                    blur_x: blur_x,
+                    blur_y: blur_y,
+                    angle: angle,
+                    distance: distance,
+                    strength: strength,
+                    inner: flags & 0x80 != 0,
+                    knockout: flags & 0x40 != 0,
+                    on_top: flags & 0x10 != 0,
+                    passes: flags & 0x0f
+                }
+            },
+            4 | 7 => {
+                let count = try!(reader.read_u8()) as usize;
+                let mut colors = Vec::with_capacity(count);
+                for _ in 0..count {
+                    colors.push(try!(Rgba::read_rgba(reader)));
+                }
+                let ratios = try!(reader.read_exact(count));
+                let blur_x = try!(read_fixed(reader));
+                let blur_y = try!(read_fixed(reader));
+                let angle = try!(read_fixed(reader));
+                let distance = try!(read_fixed(reader));
+                let strength = try!(read_fixed8(reader));
+                let flags = try!(reader.read_u8());
+                let (inner, knockout, on_top, passes) = (flags & 0x80 != 0, flags & 0x40 != 0, flags & 0x10 != 0, flags & 0x0f);
+                if kind == 4 {
+                    Filter::GradientGlow {
+                        colors: colors, ratios: ratios, blur_x: blur_x, blur_y: blur_y, angle: angle, distance: distance,
+                        strength: strength, inner: inner, knockout: knockout, on_top: on_top, passes: passes
+                    }
+                } else {
+                    Filter::GradientBevel {
+                        colors: colors, ratios: ratios, blur_x: blur_x, blur_y: blur_y, angle: angle, distance: distance,
+                        strength: strength, inner: inner, knockout: knockout, on_top: on_top, passes: passes
+                    }
+                }
+            },
+            5 => {
+                let columns = try!(reader.read_u8());
+                let rows = try!(reader.read_u8());
+                let divisor = try!(reader.read_le_f32());
+                let bias = try!(reader.read_le_f32());
+                let mut matrix = Vec::with_capacity(columns as usize * rows as usize);
+                for _ in 0..columns as usize * rows as usize {
+                    matrix.push(try!(reader.read_le_f32()));
+                }
+                let default_color = try!(Rgba::read_rgba(reader));
+                let flags = try!(reader.read_u8());
+                Filter::Convolution {
+                    columns: columns,
+                    rows: rows,
+                    divisor: divisor,
+                    bias: bias,
+                    matrix: matrix,
+                    default_color: default_color,
+                    clamp: flags & 0x2 != 0,
+                    preserve_alpha: flags & 0x1 != 0
+                }
+            },
+            6 => {
+                let mut matrix = Vec::with_capacity(20);
+                for _ in 0..20 {
+                    matrix.push(try!(reader.read_le_f32()));
+                }
+                Filter::ColorMatrix(matrix)
+            },
+            _ => return Err(IoError { kind: InvalidInput, desc: "unknown filter", detail: None })
+        })
+    }
+
+    pub fn read_list<R : Reader>(reader: &mut R) -> IoResult<Vec<Self>> {
+        let count = try!(reader.read_u8());
+        let mut filters = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            filters.push(try!(Filter::read(reader)));
+        }
+        Ok(filters)
+    }
+}
+
+impl PlaceObject {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        let mut place = PlaceObject {
+            depth: 0,
+            is_move: false,
+            character_id: None,
+            class_name: None,
+            matrix: None,
+            color_transform: None,
+            ratio: None,
+            name: None,
+            clip_depth: None,
+            filters: None,
+            blend_mode: None,
+            cache_as_bitmap: None,
+            visible: None,
+            background_color: None,
+            clip_actions: None
+        };
+
+        let mut reader = tag.reader(reader);
+        match tag.kind {
+            TagKind::PlaceObject => {
+                place.character_id = Some(try!(reader.read_le_u16()));
+                place.depth = try!(reader.read_le_u16());
+                place.matrix = Some(try!(Matrix::read(&mut reader)));
+
+                // The color transform is optional and only known to be there by the tag's length
+                if reader.limit() > 0 {
+                    place.color_transform = Some(try!(ColorTransform::read(&mut reader, false)));
+                }
+            },
+            TagKind::PlaceObject2 | TagKind::PlaceObject3 => {
+                let flags = try!(reader.read_u8());
+                let flags3 = if tag.kind == TagKind::PlaceObject3 { try!(reader.read_u8()) } else { 0 };
+                place.is_move = flags & 0x01 != 0;
+                place.depth = try!(reader.read_le_u16());
+
+                // PlaceObject3 with an image names a bitmap class for the character
+                if flags3 & 0x08 != 0 || (flags3 & 0x10 != 0 && flags & 0x02 != 0) {
+                    place.class_name = Some(decode_string(try!(read_string(&mut reader)), version));
+                }
+                if flags & 0x02 != 0 {
+                    place.character_id = Some(try!(reader.read_le_u16()));
+                }
+                if flags & 0x04 != 0 {
+                    place.matrix = Some(try!(Matrix::read(&mut reader)));
+                }
+                if flags & 0x08 != 0 {
+                    place.color_transform = Some(try!(ColorTransform::read(&mut reader, true)));
+                }
+                if flags & 0x10 != 0 {
+                    place.ratio = Some(try!(reader.read_le_u16()));
+                }
+                if flags & 0x20 != 0 {
+                    place.name = Some(decode_string(try!(read_string(&mut reader)), version));
+                }
+                if flags & 0x40 != 0 {
+                    place.clip_depth = Some(try!(reader.read_le_u16()));
+                }
+                if flags3 & 0x01 != 0 {
+                    place.filters = Some(try!(Filter::read_list(&mut reader)));
+                }
+                if flags3 & 0x02 != 0 {
+                    // 0 and unknown modes draw as normal
+                    let mode = try!(reader.read_u8());
+                    place.blend_mode = Some(num::from_u8(mode).unwrap_or(BlendMode::Normal));
+                }
+                if flags3 & 0x04 != 0 {
+                    place.cache_as_bitmap = Some(try!(reader.read_u8()) != 0);
+                }
+                if flags3 & 0x20 != 0 {
+                    place.visible = Some(try!(reader.read_u8()) != 0);
+                }
+                if flags3 & 0x40 != 0 {
+                    place.background_color = Some(try!(Rgba::read_rgba(&mut reader)));
+                }
+                if flags & 0x80 != 0 {
+                    place.clip_actions = Some(try!(reader.read_to_end()));
+                }
+            },
+            _ => return Err(IoError { kind: InvalidInput, desc: "PlaceObject tag expected", detail: None })
+        }
+
+        try!(copy(&mut reader, &mut NullWriter));
+        Ok(place)
+    }
+}
+
+impl RemoveObject {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        let mut reader = tag.reader(reader);
+        let remove = match tag.kind {
+            TagKind::RemoveObject => {
+                let character_id = try!(reader.read_le_u16());
+                RemoveObject { depth: try!(reader.read_le_u16()), character_id: Some(character_id) }
+            },
+            TagKind::RemoveObject2 => RemoveObject { depth: try!(reader.read_le_u16()), character_id: None },
+            _ => return Err(IoError { kind: InvalidInput, desc: "RemoveObject tag expected", detail: None })
+        };
+
+        try!(copy(&mut reader, &mut NullWriter));
+        Ok(remove)
+    }
+}
+
+impl DisplayObject {
+    pub fn new(depth: u16, character_id: u16) -> Self {
+        DisplayObject {
+            depth: depth,
+            character_id: character_id,
+            class_name: None,
+            matrix: Matrix::identity(),
+            color_transform: ColorTransform::identity(),
+            ratio: 0,
+            name: None,
+            clip_depth: None,
+            filters: Vec::new(),
+            blend_mode: BlendMode::Normal,
+            cache_as_bitmap: false,
+            visible: true,
+            background_color: None
+        }
+    }
+
+    fn update(&mut self, place: &PlaceObject) {
+        if let Some(id) = place.character_id {
+            self.character_id = id;
+        }
+        if let Some(ref class_name) = place.class_name {
+            self.class_name = Some(class_name.clone());
+        }
+        if let Some(matrix) = place.matrix {
+            self.matrix = matrix;
+        }
+        if let Some(cxform) = place.color_transform {
+            self.color_transform = cxform;
+        }
+        if let Some(ratio) = place.ratio {
+            self.ratio = ratio;
+        }
+        if let Some(ref name) = place.name {
+            self.name = Some(name.clone());
+        }
+        if let Some(clip_depth) = place.clip_depth {
+            self.clip_depth = Some(clip_depth);
+        }
+        if let Some(ref filters) = place.filters {
+            self.filters = filters.clone();
+        }
+        if let Some(mode) = place.blend_mode {
+            self.blend_mode = mode;
+        }
+        if let Some(cache) = place.cache_as_bitmap {
+            self.cache_as_bitmap = cache;
+        }
+        if let Some(visible) = place.visible {
+            self.visible = visible;
+        }
+        if let Some(color) = place.background_color {
+            self.background_color = Some(color);
+        }
+    }
+}
+
+impl DisplayList {
+    pub fn new() -> Self {
+        DisplayList { objects: BTreeMap::new() }
+    }
+
+    pub fn get(&self, depth: u16) -> Option<&DisplayObject> {
+        self.objects.get(&depth)
+    }
+
+    pub fn get_mut(&mut self, depth: u16) -> Option<&mut DisplayObject> {
+        self.objects.get_mut(&depth)
+    }
+
+    // Places an object at its depth outside of any tag, replacing whatever was there
+    pub fn insert(&mut self, object: DisplayObject) {
+        self.objects.insert(object.depth, object);
+    }
+
+    // Objects from the lowest depth up, in drawing order
+    pub fn iter(&self) -> Values<u16, DisplayObject> {
+        self.objects.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // A move changes the object at the depth and a new character there keeps its other properties,
+    // otherwise a character is placed afresh. A move to an empty depth does nothing
+    pub fn place(&mut self, place: &PlaceObject) {
+        if place.is_move {
+            if let Some(object) = self.objects.get_mut(&place.depth) {
+                object.update(place);
+            }
+            return
+        }
+
+        match place.character_id {
+            Some(id) => {
+                let mut object = DisplayObject::new(place.depth, id);
+                object.update(place);
+                self.objects.insert(place.depth, object);
+            },
+            None => if let Some(object) = self.objects.get_mut(&place.depth) {
+                object.update(place);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, remove: &RemoveObject) {
+        self.objects.remove(&remove.depth);
+    }
+
+    pub fn clear(&mut self) {
+        self.objects.clear();
+    }
+
+    // Applies a PlaceObject or RemoveObject tag, other tags are left unread and return false
+    pub fn apply<R : Reader>(&mut self, tag: Tag, reader: &mut R, version: u8) -> IoResult<bool> {
+        match tag.kind {
+            TagKind::PlaceObject | TagKind::PlaceObject2 | TagKind::PlaceObject3 => {
+                let place = try!(PlaceObject::read(tag, reader, version));
+                self.place(&place);
+            },
+            TagKind::RemoveObject | TagKind::RemoveObject2 => {
+                let remove = try!(RemoveObject::read(tag, reader));
+                self.remove(&remove);
+            },
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    // The display list of each frame of the main timeline, `reader` is at the first tag after the header
+    pub fn read_frames<R : Reader>(reader: &mut R, version: u8) -> IoResult<Vec<DisplayList>> {
+        let mut list = DisplayList::new();
+        let mut frames = Vec::new();
+        loop {
+            let tag = match Tag::read(reader) {
+                Ok(tag) => tag,
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            };
+
+            match tag.kind {
+                TagKind::End => break,
+                TagKind::ShowFrame => frames.push(list.clone()),
+                _ => if !try!(list.apply(tag, reader, version)) {
+                    try!(copy(&mut tag.reader(reader), &mut NullWriter));
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    // The display list shown on a frame of the main timeline, counting from 0
+    pub fn at_frame<R : Reader>(reader: &mut R, version: u8, frame: usize) -> IoResult<DisplayList> {
+        let mut list = DisplayList::new();
+        let mut current = 0;
+        loop {
+            let tag = match Tag::read(reader) {
+                Ok(tag) => tag,
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            };
+
+            match tag.kind {
+                TagKind::End => break,
+                TagKind::ShowFrame => {
+                    if current == frame {
+                        return Ok(list)
+                    }
+                    current += 1;
+                },
+                _ => if !try!(list.apply(tag, reader, version)) {
+                    try!(copy(&mut tag.reader(reader), &mut NullWriter));
+                }
+            }
+        }
+        Err(IoError { kind: InvalidInput, desc: "frame out of range", detail: None })
+    }
+}
+
+fn read_fixed<R : Reader>(reader: &mut R) -> IoResult<f32> {
+    Ok(try!(reader.read_le_i32()) as f32 / 65536.0)
+}
+
+fn read_fixed8<R : Reader>(reader: &mut R) -> IoResult<f32> {
+    Ok(try!(reader.read_le_i16()) as f32 / 256.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::BufReader;
+    use swf::{ Tag, TagKind };
+    use types::{ Rgba, Matrix, ColorTransform };
+    use super::{ DisplayList, PlaceObject, RemoveObject, Filter, BlendMode };
+
+    fn tag(data: &mut Vec<u8>, kind: TagKind, body: &[u8]) {
+        Tag { kind: kind, length: body.len() as u32 }.write(data).unwrap();
+        data.push_all(body);
+    }
+
+    // PlaceObject2 of `id` at `depth`, moved when `id` is None, with an optional translation
+    fn place2(data: &mut Vec<u8>, depth: u16, id: Option<u16>, translate: Option<(i32, i32)>) {
+        let mut body = vec![if id.is_some() { 0x02 } else { 0x01 } | if translate.is_some() { 0x04 } else { 0x00 },
+                            depth as u8, (depth >> 8) as u8];
+        if let Some(id) = id {
+            body.push_all(&[id as u8, (id >> 8) as u8]);
+        }
+        if let Some((x, y)) = translate {
+            Matrix::translate(x, y).write(&mut body).unwrap();
+        }
+        tag(data, TagKind::PlaceObject2, &body);
+    }
+
+    fn place(depth: u16, is_move: bool, character_id: Option<u16>) -> PlaceObject {
+        PlaceObject { depth: depth, is_move: is_move, character_id: character_id, class_name: None, matrix: None,
+            color_transform: None, ratio: None, name: None, clip_depth: None, filters: None, blend_mode: None,
+            cache_as_bitmap: None, visible: None, background_color: None, clip_actions: None }
+    }
+
+    #[test]
+    fn read_place_object3() {
+        let cxform = ColorTransform { a_mult: 128, r_add: -16, .. ColorTransform::identity() };
+        let mut body = vec![0x7e, 0x6f, 0x03, 0x00];
+        body.push_all(b"Bitmap\0");
+        body.push_all(&[0x07, 0x00]);
+        Matrix::translate(20, -40).write(&mut body).unwrap();
+        cxform.write(&mut body, true).unwrap();
+        body.push_all(&[0x00, 0x80]);
+        body.push_all(b"clip\0");
+        body.push_all(&[0x05, 0x00]);
+
+        // A bevel filter, its highlight comes before its shadow
+        body.push_all(&[0x01, 0x03, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x80]);
+        body.push_all(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x80, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        body.push_all(&[0x80, 0x01, 0x92]);
+
+        // An unknown blend mode is normal
+        body.push_all(&[0x14, 0x01, 0x00, 0x10, 0x20, 0x30, 0x40]);
+
+        let tag = Tag { kind: TagKind::PlaceObject3, length: body.len() as u32 };
+        let place = PlaceObject::read(tag, &mut BufReader::new(&body[..]), 10).unwrap();
+        assert_eq!((place.depth, place.is_move, place.character_id), (3, false, Some(7)));
+        assert_eq!(place.class_name, Some(String::from_str("Bitmap")));
+        assert_eq!((place.matrix, place.color_transform), (Some(Matrix::translate(20, -40)), Some(cxform)));
+        assert_eq!((place.ratio, place.name, place.clip_depth), (Some(0x8000), Some(String::from_str("clip")), Some(5)));
+        assert_eq!(place.filters, Some(vec![Filter::Bevel {
+            shadow_color: Rgba { r: 0, g: 0, b: 0, a: 0x80 },
+            highlight_color: Rgba { r: 0xff, g: 0xff, b: 0xff, a: 0xff },
+            blur_x: 4.0, blur_y: 2.5, angle: 0.0, distance: 1.0, strength: 1.5,
+            inner: true, knockout: false, on_top: true, passes: 2
+        }]));
+        assert_eq!((place.blend_mode, place.cache_as_bitmap, place.visible), (Some(BlendMode::Normal), Some(true), Some(false)));
+        assert_eq!(place.background_color, Some(Rgba { r: 0x10, g: 0x20, b: 0x30, a: 0x40 }));
+        assert_eq!(place.clip_actions, None);
+    }
+
+    #[test]
+    fn read_place_object2() {
+        // Only the flagged fields are there, clip actions take the rest of the tag
+        let mut body = vec![0x91, 0x02, 0x00, 0x03, 0x00];
+        body.push_all(&[0x00, 0x00, 0xaa, 0xbb]);
+        let tag = Tag { kind: TagKind::PlaceObject2, length: body.len() as u32 };
+        let place = PlaceObject::read(tag, &mut BufReader::new(&body[..]), 6).unwrap();
+        assert_eq!((place.depth, place.is_move, place.character_id, place.ratio), (2, true, None, Some(3)));
+        assert_eq!((place.matrix, place.name, place.blend_mode), (None, None, None));
+        assert_eq!(place.clip_actions, Some(vec![0x00, 0x00, 0xaa, 0xbb]));
+    }
+
+    #[test]
+    fn move_and_replace() {
+        let mut list = DisplayList::new();
+        let mut first = place(1, false, Some(1));
+        first.name = Some(String::from_str("a"));
+        first.matrix = Some(Matrix::translate(10, 10));
+        list.place(&first);
+
+        // A move changes only what it names, a new character keeps the other properties
+        let mut moved = place(1, true, None);
+        moved.matrix = Some(Matrix::translate(20, 20));
+        list.place(&moved);
+        list.place(&place(1, true, Some(2)));
+        let object = list.get(1).unwrap();
+        assert_eq!((object.character_id, &object.name, object.matrix), (2, &Some(String::from_str("a")), Matrix::translate(20, 20)));
+
+        // Placing without a move starts afresh, and a move to an empty depth does nothing
+        list.place(&place(1, false, Some(3)));
+        list.place(&place(2, true, Some(4)));
+        let object = list.get(1).unwrap();
+        assert_eq!((object.character_id, &object.name, object.matrix), (3, &None, Matrix::identity()));
+        assert_eq!(list.len(), 1);
+
+        list.remove(&RemoveObject { depth: 1, character_id: None });
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn frames() {
+        let mut data = Vec::new();
+        place2(&mut data, 1, Some(1), None);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        place2(&mut data, 1, None, Some((40, 0)));
+        place2(&mut data, 2, Some(2), None);
+        tag(&mut data, TagKind::DoAction, &[0x00]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::RemoveObject2, &[0x01, 0x00]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::End, &[]);
+
+        let frames = DisplayList::read_frames(&mut BufReader::new(&data[..]), 6).unwrap();
+        assert_eq!(frames.len(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            assert!(DisplayList::at_frame(&mut BufReader::new(&data[..]), 6, i).unwrap() == *frame);
+        }
+        assert!(DisplayList::at_frame(&mut BufReader::new(&data[..]), 6, 3).is_err());
+
+        assert_eq!(frames[0].iter().map(|o| (o.depth, o.matrix)).collect::<Vec<_>>(), vec![(1, Matrix::identity())]);
+        assert_eq!(frames[1].iter().map(|o| (o.depth, o.matrix)).collect::<Vec<_>>(),
+                   vec![(1, Matrix::translate(40, 0)), (2, Matrix::identity())]);
+        assert_eq!(frames[2].iter().map(|o| o.character_id).collect::<Vec<_>>(), vec![2]);
+    }
+}
@@ -0,0 +1,283 @@
+use std::old_io::{ Reader, Writer, IoResult, IoError, InvalidInput };
+use std::old_io::util::{ NullWriter, copy };
+use swf::{ Tag, TagKind };
+use types::{ Rect, Rgba, read_string, decode_string, encode_string };
+use util::csm::CSMTextSettings;
+use util::html::{ TextAlign, TextFormat, TextSpan, parse_html };
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EditTextFlags {
+    pub word_wrap: bool,
+    pub multiline: bool,
+    pub password: bool,
+    pub read_only: bool,
+    pub auto_size: bool,
+    pub no_select: bool,
+    pub border: bool,
+    pub was_static: bool,
+    pub html: bool,
+    pub use_outlines: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EditTextLayout {
+    pub align: TextAlign,
+    pub left_margin: u16,
+    pub right_margin: u16,
+    pub indent: u16,
+    pub leading: i16,
+}
+
+#[derive(Debug, Clone)]
+pub struct EditText {
+    pub id: u16,
+    pub bounds: Rect,
+    pub flags: EditTextFlags,
+    pub font_id: Option<u16>,
+    pub font_class: Option<String>,
+    pub height: Option<u16>,
+    pub color: Option<Rgba>,
+    pub max_length: Option<u16>,
+    pub layout: Option<EditTextLayout>,
+    pub variable_name: String,
+    pub initial_text: Option<String>,
+    pub csm_settings: Option<CSMTextSettings>,
+}
+
+impl EditText {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineEditText {
+            return Err(IoError { kind: InvalidInput, desc: "DefineEditText tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let bounds = try!(Rect::read(&mut reader));
+        let flags1 = try!(reader.read_u8());
+        let flags2 = try!(reader.read_u8());
+
+        let font_id = if flags1 & 0x01 != 0 { Some(try!(reader.read_le_u16())) } else { None };
+        let font_class = if flags2 & 0x80 != 0 {
+            Some(decode_string(try!(read_string(&mut reader)), version))
+        } else {
+            None
+        };
+        let height = if font_id.is_some() || font_class.is_some() { Some(try!(reader.read_le_u16())) } else { None };
+        let color = if flags1 & 0x04 != 0 { Some(try!(Rgba::read_rgba(&mut reader))) } else { None };
+        let max_length = if flags1 & 0x02 != 0 { Some(try!(reader.read_le_u16())) } else { None };
+        let layout = if flags2 & 0x20 != 0 {
+            Some(EditTextLayout {
+                align: match try!(reader.read_u8()) {
+                    0 => TextAlign::Left,
+                    1 => TextAlign::Right,
+                    2 => TextAlign::Center,
+                    3 => TextAlign::Justify,
+                    _ => return Err(IoError { kind: InvalidInput, desc: "unknown text alignment", detail: None })
+                },
+                left_margin: try!(reader.read_le_u16()),
+                right_margin: try!(reader.read_le_u16()),
+                indent: try!(reader.read_le_u16()),
+                leading: try!(reader.read_le_i16())
+            })
+        } else {
+            None
+        };
+        let variable_name = decode_string(try!(read_string(&mut reader)), version);
+        let initial_text = if flags1 & 0x80 != 0 {
+            Some(decode_string(try!(read_string(&mut reader)), version))
+        } else {
+            None
+        };
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(EditText {
+            id: id,
+            bounds: bounds,
+            flags: EditTextFlags {
+                word_wrap: flags1 & 0x40 != 0,
+                multiline: flags1 & 0x20 != 0,
+                password: flags1 & 0x10 != 0,
+                read_only: flags1 & 0x08 != 0,
+                auto_size: flags2 & 0x40 != 0,
+                no_select: flags2 & 0x10 != 0,
+                border: flags2 & 0x08 != 0,
+                was_static: flags2 & 0x04 != 0,
+                html: flags2 & 0x02 != 0,
+                use_outlines: flags2 & 0x01 != 0
+            },
+            font_id: font_id,
+            font_class: font_class,
+            height: height,
+            color: color,
+            max_length: max_length,
+            layout: layout,
+            variable_name: variable_name,
+            initial_text: initial_text,
+            csm_settings: None
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W, version: u8) -> IoResult<()> {
+        let mut body = Vec::new();
+        try!(body.write_le_u16(self.id));
+        try!(self.bounds.write(&mut body));
+
+        let flags = &self.flags;
+        let bit = |v: bool, mask: u8| if v { mask } else { 0 };
+        try!(body.write_u8(
+            bit(self.initial_text.is_some(), 0x80) | bit(flags.word_wrap, 0x40) | bit(flags.multiline, 0x20) |
+            bit(flags.password, 0x10) | bit(flags.read_only, 0x08) | bit(self.color.is_some(), 0x04) |
+            bit(self.max_length.is_some(), 0x02) | bit(self.font_id.is_some(), 0x01)
+        ));
+        try!(body.write_u8(
+            bit(self.font_class.is_some(), 0x80) | bit(flags.auto_size, 0x40) | bit(self.layout.is_some(), 0x20) |
+            bit(flags.no_select, 0x10) | bit(flags.border, 0x08) | bit(flags.was_static, 0x04) |
+            bit(flags.html, 0x02) | bit(flags.use_outlines, 0x01)
+        ));
+
+        if let Some(id) = self.font_id {
+            try!(body.write_le_u16(id));
+        }
+        if let Some(ref class) = self.font_class {
+            try!(body.write_all(&encode_string(class, version)));
+            try!(body.write_u8(0));
+        }
+        if self.font_id.is_some() || self.font_class.is_some() {
+            try!(body.write_le_u16(self.height.unwrap_or(0)));
+        }
+        if let Some(color) = self.color {
+            try!(color.write_rgba(&mut body));
+        }
+        if let Some(max_length) = self.max_length {
+            try!(body.write_le_u16(max_length));
+        }
+        if let Some(layout) = self.layout {
+            try!(body.write_u8(match layout.align {
+                TextAlign::Left => 0,
+                TextAlign::Right => 1,
+                TextAlign::Center => 2,
+                TextAlign::Justify => 3
+            }));
+            try!(body.write_le_u16(layout.left_margin));
+            try!(body.write_le_u16(layout.right_margin));
+            try!(body.write_le_u16(layout.indent));
+            try!(body.write_le_i16(layout.leading));
+        }
+        try!(body.write_all(&encode_string(&self.variable_name, version)));
+        try!(body.write_u8(0));
+        if let Some(ref text) = self.initial_text {
+            try!(body.write_all(&encode_string(text, version)));
+            try!(body.write_u8(0));
+        }
+
+        try!(Tag { kind: TagKind::DefineEditText, length: body.len() as u32 }.write(writer));
+        writer.write_all(&body)
+    }
+
+    // Input fields are editable, dynamic fields are only changed by script
+    pub fn is_input(&self) -> bool {
+        !self.flags.read_only
+    }
+
+    // Base format of the field, HTML text may override it per run
+    pub fn format(&self) -> TextFormat {
+        let mut format = TextFormat::new();
+        format.font = self.font_class.clone();
+        format.size = self.height.map(|h| h as f32 / 20.0);
+        format.color = self.color;
+        if let Some(layout) = self.layout {
+            format.align = Some(layout.align);
+            format.left_margin = Some(layout.left_margin as f32 / 20.0);
+            format.right_margin = Some(layout.right_margin as f32 / 20.0);
+            format.indent = Some(layout.indent as f32 / 20.0);
+            format.leading = Some(layout.leading as f32 / 20.0);
+        }
+        format
+    }
+
+    pub fn spans(&self) -> Vec<TextSpan> {
+        let text = match self.initial_text {
+            Some(ref text) => text,
+            None => return Vec::new()
+        };
+
+        if self.flags.html {
+            parse_html(text, &self.format())
+        } else {
+            vec![TextSpan { text: text.replace("\r", "\n"), format: self.format() }]
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.spans().into_iter().fold(String::new(), |mut text, span| {
+            text.push_str(&span.text);
+            text
+        })
+    }
+
+    pub fn apply_csm_settings(&mut self, settings: &CSMTextSettings) {
+        self.csm_settings = Some(*settings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swf::{ Tag, TagKind };
+    use types::Rgba;
+    use util::html::TextAlign;
+    use super::EditText;
+
+    // An HTML field with a font, colour, layout and initial text
+    fn define_edit_text() -> Vec<u8> {
+        let mut data = vec![
+            0x03, 0x00, 0x00, 0xc5, 0x22, 0x02, 0x00, 0xf0, 0x00, 0x00, 0x00, 0xff, 0xff,
+            0x02, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00,
+            0x76, 0x00
+        ];
+        data.push_all(b"<b>Hi</b> &amp; bye\0");
+        data
+    }
+
+    #[test]
+    fn read_fields() {
+        let data = define_edit_text();
+        let tag = Tag { kind: TagKind::DefineEditText, length: data.len() as u32 };
+        let text = EditText::read(tag, &mut &data[..], 6).unwrap();
+        assert_eq!((text.id, text.font_id, text.height, text.max_length), (3, Some(2), Some(240), None));
+        assert_eq!(text.color, Some(Rgba { r: 0, g: 0, b: 0xff, a: 0xff }));
+        assert!(text.flags.word_wrap && text.flags.html && !text.flags.multiline && !text.flags.border);
+        assert!(text.is_input());
+
+        let layout = text.layout.unwrap();
+        assert_eq!((layout.align, layout.left_margin, layout.leading), (TextAlign::Center, 20, 40));
+        assert_eq!(text.variable_name, "v");
+
+        let spans = text.spans();
+        assert_eq!(spans.len(), 2);
+        assert!(spans[0].format.bold && !spans[1].format.bold);
+        assert_eq!((spans[0].format.size, spans[0].format.left_margin), (Some(12.0), Some(1.0)));
+        assert_eq!(text.text(), "Hi & bye");
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let data = define_edit_text();
+        let tag = Tag { kind: TagKind::DefineEditText, length: data.len() as u32 };
+        let mut text = EditText::read(tag, &mut &data[..], 6).unwrap();
+        text.flags.html = false;
+        text.max_length = Some(8);
+        text.font_class = Some(String::from_str("Font"));
+
+        let mut out = Vec::new();
+        text.write(&mut out, 6).unwrap();
+        let reader = &mut &out[..];
+        let tag = Tag::read(reader).unwrap();
+        let read = EditText::read(tag, reader, 6).unwrap();
+        assert!(reader.is_empty());
+
+        assert_eq!((read.id, read.flags, read.font_id, read.height), (text.id, text.flags, text.font_id, text.height));
+        assert_eq!((read.color, read.max_length, read.layout), (text.color, text.max_length, text.layout));
+        assert_eq!(read.text(), "<b>Hi</b> &amp; bye");
+        assert_eq!((read.font_class, read.variable_name), (text.font_class, text.variable_name));
+    }
+}
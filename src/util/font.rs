@@ -0,0 +1,427 @@
+use std::old_io::{ Reader, Writer, IoResult, IoError, InvalidInput, BufReader };
+use std::old_io::util::{ NullWriter, copy };
+use std::num;
+use std::char;
+use swf::{ Tag, TagKind };
+use types::{ Rect, read_string, decode_string };
+use shape::Shape;
+use util::csm::{ FontAlignZones, ZoneRecord };
+
+#[derive(Debug, FromPrimitive, Copy, Clone, PartialEq, Eq)]
+pub enum LanguageCode {
+    None               = 0,
+    Latin              = 1,
+    Japanese           = 2,
+    Korean             = 3,
+    SimplifiedChinese  = 4,
+    TraditionalChinese = 5
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FontFlags {
+    pub small_text: bool,
+    pub shift_jis: bool,
+    pub ansi: bool,
+    pub italic: bool,
+    pub bold: bool,
+    pub wide_codes: bool,
+    pub utf8: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KerningRecord {
+    pub left: u16,
+    pub right: u16,
+    pub adjustment: i16,
+}
+
+#[derive(Debug, Clone)]
+pub struct FontLayout {
+    pub ascent: u16,
+    pub descent: u16,
+    pub leading: i16,
+    pub advances: Vec<i16>,
+    pub bounds: Vec<Rect>,
+    pub kerning: Vec<KerningRecord>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Font {
+    pub kind: TagKind,
+    pub id: u16,
+    pub name: String,
+    pub copyright: String,
+    pub flags: FontFlags,
+    pub language: LanguageCode,
+    pub glyphs: Vec<Shape>,
+    pub codes: Vec<u16>,
+    pub layout: Option<FontLayout>,
+    pub align_zones: Option<FontAlignZones>,
+}
+
+// DefineFontInfo and DefineFontInfo2
+#[derive(Debug, Clone)]
+pub struct FontInfo {
+    pub id: u16,
+    pub name: String,
+    pub flags: FontFlags,
+    pub language: LanguageCode,
+    pub codes: Vec<u16>,
+}
+
+// DefineFont4 embeds a complete CFF based OpenType font
+#[derive(Debug, Clone)]
+pub struct Font4 {
+    pub id: u16,
+    pub name: String,
+    pub italic: bool,
+    pub bold: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FontName {
+    pub id: u16,
+    pub name: String,
+    pub copyright: String,
+}
+
+impl FontFlags {
+    fn new(flags: u8, version: u8) -> Self {
+        FontFlags {
+            small_text: flags & 0x20 != 0,
+            shift_jis: flags & 0x10 != 0,
+            ansi: flags & 0x08 != 0,
+            italic: flags & 0x04 != 0,
+            bold: flags & 0x02 != 0,
+            wide_codes: flags & 0x01 != 0,
+            utf8: version >= 6
+        }
+    }
+}
+
+impl Font {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        let mut reader = tag.reader(reader);
+        let font = match tag.kind {
+            TagKind::DefineFont => try!(Font::read_font(&mut reader, version)),
+            TagKind::DefineFont2 | TagKind::DefineFont3 => try!(Font::read_font2(tag.kind, &mut reader, version)),
+            _ => return Err(IoError { kind: InvalidInput, desc: "font tag expected", detail: None })
+        };
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(font)
+    }
+
+    fn read_font<R : Reader>(reader: &mut R, version: u8) -> IoResult<Self> {
+        let id = try!(reader.read_le_u16());
+        let data = try!(reader.read_to_end());
+
+        let glyphs = if data.len() >= 2 {
+            let count = (data[0] as usize | (data[1] as usize) << 8) / 2;
+            let mut offsets = Vec::with_capacity(count);
+            let mut table = BufReader::new(&data);
+            for _ in 0..count {
+                offsets.push(try!(table.read_le_u16()) as usize);
+            }
+            try!(read_glyphs(&data, &offsets))
+        } else {
+            Vec::new()
+        };
+
+        Ok(Font {
+            kind: TagKind::DefineFont,
+            id: id,
+            name: String::new(),
+            copyright: String::new(),
+            flags: FontFlags::new(0, version),
+            language: LanguageCode::None,
+            glyphs: glyphs,
+            codes: Vec::new(),
+            layout: None,
+            align_zones: None
+        })
+    }
+
+    fn read_font2<R : Reader>(kind: TagKind, reader: &mut R, version: u8) -> IoResult<Self> {
+        let id = try!(reader.read_le_u16());
+        let flags = try!(reader.read_u8());
+        let has_layout = flags & 0x80 != 0;
+        let wide_offsets = flags & 0x08 != 0;
+        let flags = FontFlags {
+            shift_jis: flags & 0x40 != 0,
+            small_text: flags & 0x20 != 0,
+            ansi: flags & 0x10 != 0,
+            wide_codes: flags & 0x04 != 0,
+            italic: flags & 0x02 != 0,
+            bold: flags & 0x01 != 0,
+            utf8: version >= 6
+        };
+        let language = try!(num::from_u8(try!(reader.read_u8())).ok_or(
+            IoError { kind: InvalidInput, desc: "unknown font language code", detail: None }
+        ));
+        let name_len = try!(reader.read_u8()) as usize;
+        let name = trim_name(try!(reader.read_exact(name_len)), version);
+        let count = try!(reader.read_le_u16()) as usize;
+        let data = try!(reader.read_to_end());
+        let mut reader = BufReader::new(&data);
+
+        let offset_size = if wide_offsets { 4 } else { 2 };
+        let mut offsets = Vec::with_capacity(count);
+        for _ in 0..count {
+            offsets.push(try!(read_offset(&mut reader, wide_offsets)));
+        }
+
+        // Fonts without glyphs may omit the code table offset, in which case the layout follows directly
+        let has_offset = count > 0 || if has_layout {
+            has_code_table_offset(&data, offset_size, flags.wide_codes)
+        } else {
+            data.len() >= offset_size
+        };
+        let code_offset = if has_offset { try!(read_offset(&mut reader, wide_offsets)) } else { 0 };
+
+        if code_offset > data.len() {
+            return Err(IoError { kind: InvalidInput, desc: "font code table offset out of range", detail: None })
+        }
+
+        let glyphs = try!(read_glyphs(&data[..code_offset], &offsets));
+
+        let mut reader = BufReader::new(&data[code_offset..]);
+        let mut codes = Vec::with_capacity(count);
+        for _ in 0..count {
+            codes.push(try!(read_code(&mut reader, flags.wide_codes)));
+        }
+
+        let layout = if has_layout {
+            let ascent = try!(reader.read_le_u16());
+            let descent = try!(reader.read_le_u16());
+            let leading = try!(reader.read_le_i16());
+
+            let mut advances = Vec::with_capacity(count);
+            for _ in 0..count {
+                advances.push(try!(reader.read_le_i16()));
+            }
+
+            let mut bounds = Vec::with_capacity(count);
+            for _ in 0..count {
+                bounds.push(try!(Rect::read(&mut reader)));
+            }
+
+            let kerning_count = try!(reader.read_le_u16()) as usize;
+            let mut kerning = Vec::with_capacity(kerning_count);
+            for _ in 0..kerning_count {
+                kerning.push(KerningRecord {
+                    left: try!(read_code(&mut reader, flags.wide_codes)),
+                    right: try!(read_code(&mut reader, flags.wide_codes)),
+                    adjustment: try!(reader.read_le_i16())
+                });
+            }
+
+            Some(FontLayout {
+                ascent: ascent,
+                descent: descent,
+                leading: leading,
+                advances: advances,
+                bounds: bounds,
+                kerning: kerning
+            })
+        } else {
+            None
+        };
+
+        Ok(Font {
+            kind: kind,
+            id: id,
+            name: name,
+            copyright: String::new(),
+            flags: flags,
+            language: language,
+            glyphs: glyphs,
+            codes: codes,
+            layout: layout,
+            align_zones: None
+        })
+    }
+
+    // Glyph outlines are defined on a 1024 unit EM square, DefineFont3 uses 20 times the resolution
+    pub fn em_square(&self) -> u32 {
+        match self.kind {
+            TagKind::DefineFont3 => 1024 * 20,
+            _ => 1024
+        }
+    }
+
+    pub fn has_glyphs(&self) -> bool {
+        !self.glyphs.is_empty()
+    }
+
+    pub fn glyph_for_code(&self, code: u16) -> Option<usize> {
+        self.codes.iter().position(|&c| c == code)
+    }
+
+    // SWF 6 and later always use UCS-2 codes, earlier fonts may use single byte ANSI codes
+    pub fn char_for_glyph(&self, glyph: usize) -> Option<char> {
+        self.codes.get(glyph).and_then(|&code| char::from_u32(code as u32))
+    }
+
+    pub fn advance(&self, glyph: usize) -> Option<i16> {
+        self.layout.as_ref().and_then(|layout| layout.advances.get(glyph).map(|&v| v))
+    }
+
+    pub fn kerning(&self, left: u16, right: u16) -> i16 {
+        self.layout.as_ref().and_then(|layout| layout.kerning.iter()
+            .find(|k| k.left == left && k.right == right)
+            .map(|k| k.adjustment)
+        ).unwrap_or(0)
+    }
+
+    pub fn apply_info(&mut self, info: &FontInfo) {
+        self.name = info.name.clone();
+        self.flags = info.flags;
+        self.language = info.language;
+        self.codes = info.codes.clone();
+    }
+
+    pub fn apply_name(&mut self, name: &FontName) {
+        self.name = name.name.clone();
+        self.copyright = name.copyright.clone();
+    }
+
+    // The alignment zones of a glyph once DefineFontAlignZones has been applied
+    pub fn zones(&self, index: usize) -> Option<&ZoneRecord> {
+        self.align_zones.as_ref().and_then(|zones| zones.records.get(index))
+    }
+
+    pub fn apply_align_zones(&mut self, zones: &FontAlignZones) {
+        self.align_zones = Some(zones.clone());
+    }
+}
+
+impl FontInfo {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        let info2 = match tag.kind {
+            TagKind::DefineFontInfo => false,
+            TagKind::DefineFontInfo2 => true,
+            _ => return Err(IoError { kind: InvalidInput, desc: "font info tag expected", detail: None })
+        };
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let name_len = try!(reader.read_u8()) as usize;
+        let name = trim_name(try!(reader.read_exact(name_len)), version);
+        let flags = FontFlags::new(try!(reader.read_u8()), version);
+        let language = if info2 {
+            try!(num::from_u8(try!(reader.read_u8())).ok_or(
+                IoError { kind: InvalidInput, desc: "unknown font language code", detail: None }
+            ))
+        } else {
+            LanguageCode::None
+        };
+
+        let data = try!(reader.read_to_end());
+        let codes = if flags.wide_codes {
+            data.chunks(2).filter(|c| c.len() == 2).map(|c| c[0] as u16 | (c[1] as u16) << 8).collect()
+        } else {
+            data.iter().map(|&c| c as u16).collect()
+        };
+
+        Ok(FontInfo {
+            id: id,
+            name: name,
+            flags: flags,
+            language: language,
+            codes: codes
+        })
+    }
+}
+
+impl Font4 {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineFont4 {
+            return Err(IoError { kind: InvalidInput, desc: "DefineFont4 tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let flags = try!(reader.read_u8());
+        let name = decode_string(try!(read_string(&mut reader)), 10);
+        let data = if flags & 0x04 != 0 { try!(reader.read_to_end()) } else { Vec::new() };
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(Font4 {
+            id: id,
+            name: name,
+            italic: flags & 0x02 != 0,
+            bold: flags & 0x01 != 0,
+            data: data
+        })
+    }
+
+    pub fn has_font_data(&self) -> bool {
+        !self.data.is_empty()
+    }
+
+    pub fn write_font<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        writer.write_all(&self.data)
+    }
+}
+
+impl FontName {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineFontName {
+            return Err(IoError { kind: InvalidInput, desc: "font name tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let name = decode_string(try!(read_string(&mut reader)), 6);
+        let copyright = decode_string(try!(read_string(&mut reader)), 6);
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(FontName {
+            id: id,
+            name: name,
+            copyright: copyright
+        })
+    }
+}
+
+fn read_glyphs(data: &[u8], offsets: &[usize]) -> IoResult<Vec<Shape>> {
+    let mut glyphs = Vec::with_capacity(offsets.len());
+    for &offset in offsets.iter() {
+        if offset > data.len() {
+            return Err(IoError { kind: InvalidInput, desc: "glyph offset out of range", detail: None })
+        }
+        glyphs.push(try!(Shape::read(&mut BufReader::new(&data[offset..]))));
+    }
+
+    Ok(glyphs)
+}
+
+fn read_offset<R : Reader>(reader: &mut R, wide: bool) -> IoResult<usize> {
+    Ok(if wide { try!(reader.read_le_u32()) as usize } else { try!(reader.read_le_u16()) as usize })
+}
+
+fn read_code<R : Reader>(reader: &mut R, wide: bool) -> IoResult<u16> {
+    Ok(if wide { try!(reader.read_le_u16()) } else { try!(reader.read_u8()) as u16 })
+}
+
+// Layout is ascent, descent, leading, then the kerning count and table
+fn has_code_table_offset(data: &[u8], offset_size: usize, wide_codes: bool) -> bool {
+    let record_size = if wide_codes { 6 } else { 4 };
+    let kerning_size = |offset: usize| if data.len() >= offset + 8 {
+        Some(8 + (data[offset + 6] as usize | (data[offset + 7] as usize) << 8) * record_size)
+    } else {
+        None
+    };
+
+    kerning_size(0) != Some(data.len()) && kerning_size(offset_size).map(|size| size + offset_size) == Some(data.len())
+}
+
+// Some authoring tools include the terminating null in the name length
+fn trim_name(mut name: Vec<u8>, version: u8) -> String {
+    while name.last() == Some(&0) {
+        name.pop();
+    }
+    decode_string(name, version)
+}
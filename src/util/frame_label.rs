@@ -0,0 +1,209 @@
+use std::old_io::{ Reader, IoResult, IoError, InvalidInput };
+use std::old_io::util::{ NullWriter, copy };
+use std::ascii::AsciiExt;
+use std::num::Float;
+use swf::{ Tag, TagKind };
+use types::{ read_string, decode_string };
+
+// FrameLabel, SWF 6 and later may flag the label as a named anchor for the browser's history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameLabel {
+    pub name: String,
+    pub anchor: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scene {
+    pub offset: usize,
+    pub name: String,
+}
+
+// DefineSceneAndFrameLabelData, the scenes and labels of the main timeline with frames counted from 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SceneAndFrameLabelData {
+    pub scenes: Vec<Scene>,
+    pub labels: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub frame: usize,
+    pub name: String,
+    pub anchor: bool,
+}
+
+// The labels and scenes of a timeline in frame order. Lookups follow AVM1: names are compared without ASCII
+// case and the first match wins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelTable {
+    pub labels: Vec<Label>,
+    pub scenes: Vec<Scene>,
+}
+
+impl FrameLabel {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        if tag.kind != TagKind::FrameLabel {
+            return Err(IoError { kind: InvalidInput, desc: "FrameLabel tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let name = decode_string(try!(read_string(&mut reader)), version);
+        let anchor = reader.limit() > 0 && try!(reader.read_u8()) == 1;
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(FrameLabel { name: name, anchor: anchor })
+    }
+}
+
+impl SceneAndFrameLabelData {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineSceneAndFrameLabelData {
+            return Err(IoError { kind: InvalidInput, desc: "DefineSceneAndFrameLabelData tag expected", detail: None })
+        }
+
+        // The tag only appears in SWF 9 and later, so its strings are always UTF-8
+        let mut reader = tag.reader(reader);
+        let count = try!(read_encoded_u32(&mut reader));
+        let mut scenes = Vec::new();
+        for _ in 0..count {
+            let offset = try!(read_encoded_u32(&mut reader)) as usize;
+            scenes.push(Scene { offset: offset, name: decode_string(try!(read_string(&mut reader)), 9) });
+        }
+
+        let count = try!(read_encoded_u32(&mut reader));
+        let mut labels = Vec::new();
+        for _ in 0..count {
+            let frame = try!(read_encoded_u32(&mut reader)) as usize;
+            labels.push((frame, decode_string(try!(read_string(&mut reader)), 9)));
+        }
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(SceneAndFrameLabelData { scenes: scenes, labels: labels })
+    }
+}
+
+impl LabelTable {
+    pub fn new() -> Self {
+        LabelTable { labels: Vec::new(), scenes: Vec::new() }
+    }
+
+    pub fn add_label(&mut self, frame: usize, label: FrameLabel) {
+        let index = self.labels.iter().position(|l| l.frame > frame).unwrap_or(self.labels.len());
+        self.labels.insert(index, Label { frame: frame, name: label.name, anchor: label.anchor });
+    }
+
+    // Labels already given by FrameLabel tags are kept as they are
+    pub fn add_scene_data(&mut self, data: SceneAndFrameLabelData) {
+        for (frame, name) in data.labels.into_iter() {
+            if !self.labels.iter().any(|l| l.frame == frame && l.name == name) {
+                self.add_label(frame, FrameLabel { name: name, anchor: false });
+            }
+        }
+        self.scenes = data.scenes;
+        self.scenes.sort_by(|a, b| a.offset.cmp(&b.offset));
+    }
+
+    pub fn label_frame(&self, name: &str) -> Option<usize> {
+        self.labels.iter().find(|l| l.name.eq_ignore_ascii_case(name)).map(|l| l.frame)
+    }
+
+    // The frame a GotoFrame2 value names: a string that reads as a number is a 1-based frame, with the fraction
+    // dropped and the scene bias added, anything else is a label. Frames before the first name nothing
+    pub fn resolve_frame(&self, value: &str, scene_bias: u16) -> Option<usize> {
+        match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => {
+                let frame = number.floor() + scene_bias as f64;
+                if frame >= 1.0 && frame <= 0xffffffffu32 as f64 { Some(frame as usize - 1) } else { None }
+            },
+            Ok(_) => None,
+            Err(_) => self.label_frame(value)
+        }
+    }
+}
+
+// Splits a "path:frame" target as used by GotoFrame2, Call and WaitForFrame2 into its clip and frame.
+// The path is whatever comes before the last colon, in slash or dot syntax
+pub fn split_frame_target(value: &str) -> (Option<&str>, &str) {
+    match value.rfind(':') {
+        Some(index) => (Some(&value[..index]), &value[index + 1..]),
+        None => (None, value)
+    }
+}
+
+// EncodedU32, 7 bits a byte from the lowest with the top bit set while more follow
+fn read_encoded_u32<R : Reader>(reader: &mut R) -> IoResult<u32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = try!(reader.read_u8());
+        value |= ((byte & 0x7f) as u32) << (i * 7);
+        if byte & 0x80 == 0 {
+            break
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::{ Reader, BufReader };
+    use swf::{ Tag, TagKind };
+    use super::{ LabelTable, FrameLabel, SceneAndFrameLabelData, Scene, split_frame_target, read_encoded_u32 };
+
+    fn label(name: &str) -> FrameLabel {
+        FrameLabel { name: String::from_str(name), anchor: false }
+    }
+
+    #[test]
+    fn resolve_frame() {
+        let mut labels = LabelTable::new();
+        labels.add_label(4, label("End"));
+        labels.add_label(1, label("intro"));
+        labels.add_label(6, label("end"));
+
+        // Numbers are 1-based frames, the fraction is dropped and the scene bias added
+        assert_eq!((labels.resolve_frame("3", 0), labels.resolve_frame("3.9", 0), labels.resolve_frame("2", 4)), (Some(2), Some(2), Some(5)));
+        assert_eq!((labels.resolve_frame("0", 0), labels.resolve_frame("-1", 2), labels.resolve_frame("0.5", 0)), (None, Some(0), None));
+        assert_eq!(labels.resolve_frame("inf", 0), None);
+
+        // Labels are matched without case, the first in frame order wins
+        assert_eq!((labels.resolve_frame("END", 0), labels.resolve_frame("Intro", 3)), (Some(4), Some(1)));
+        assert_eq!(labels.resolve_frame("outro", 0), None);
+    }
+
+    #[test]
+    fn split_targets() {
+        assert_eq!(split_frame_target("/a/b:3"), (Some("/a/b"), "3"));
+        assert_eq!(split_frame_target("_root.a:end"), (Some("_root.a"), "end"));
+        assert_eq!(split_frame_target(":2"), (Some(""), "2"));
+        assert_eq!(split_frame_target("a:b:c"), (Some("a:b"), "c"));
+        assert_eq!(split_frame_target("5"), (None, "5"));
+    }
+
+    #[test]
+    fn encoded_u32() {
+        let read = |data: &[u8]| read_encoded_u32(&mut BufReader::new(data)).ok();
+        assert_eq!((read(&[0x05]), read(&[0x80, 0x01]), read(&[0xe5, 0x8e, 0x26])), (Some(5), Some(128), Some(624485)));
+        assert_eq!(read(&[0xff, 0xff, 0xff, 0xff, 0x0f]), Some(0xffffffff));
+        assert_eq!(read(&[0x80]), None);
+
+        // No more than five bytes are read
+        let mut reader = BufReader::new(&[0x81, 0x80, 0x80, 0x80, 0x80, 0x02][..]);
+        assert_eq!(read_encoded_u32(&mut reader).ok(), Some(1));
+        assert_eq!(reader.read_u8().ok(), Some(0x02));
+    }
+
+    #[test]
+    fn scene_and_frame_label_data() {
+        let data = [0x02, 0x00, b'A', 0x00, 0x83, 0x01, b'B', 0x00, 0x02, 0x02, b'x', 0x00, 0x05, b'y', 0x00];
+        let tag = Tag { kind: TagKind::DefineSceneAndFrameLabelData, length: data.len() as u32 };
+        let read = SceneAndFrameLabelData::read(tag, &mut BufReader::new(&data[..])).unwrap();
+        assert_eq!(read.labels, vec![(2, String::from_str("x")), (5, String::from_str("y"))]);
+
+        // Scenes are kept by offset, labels already there from FrameLabel are not added twice
+        let mut labels = LabelTable::new();
+        labels.add_label(2, FrameLabel { name: String::from_str("x"), anchor: true });
+        labels.add_scene_data(read);
+        assert_eq!(labels.scenes, vec![Scene { offset: 0, name: String::from_str("A") }, Scene { offset: 131, name: String::from_str("B") }]);
+        assert_eq!(labels.labels.iter().map(|l| (l.frame, &l.name[..], l.anchor)).collect::<Vec<_>>(), vec![(2, "x", true), (5, "y", false)]);
+    }
+}
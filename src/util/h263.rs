@@ -0,0 +1,686 @@
+use std::old_io::{ BufReader, IoResult, IoError, InvalidInput };
+use std::cmp::{ min, max };
+use std::iter::repeat;
+use std::num::Float;
+use std::f32::consts::PI;
+use bits::BitReader;
+use util::video::{ VideoStream, VideoFrame, VideoCodec, YuvFrame };
+
+// Sorenson Spark, the H.263 baseline variant used by video codec 2
+
+type Bits<'a> = BitReader<BufReader<'a>>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PictureType {
+    Intra,
+    Inter,
+    Disposable
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PictureHeader {
+    version: u32,
+    width: usize,
+    height: usize,
+    kind: PictureType,
+    deblocking: bool,
+    quant: i32,
+}
+
+// Planes padded to whole macroblocks
+#[derive(Clone)]
+struct Picture {
+    mb_width: usize,
+    mb_height: usize,
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+}
+
+// Inter frames predict from the last frame that was not disposable, so frames must be decoded in order
+pub struct H263Decoder {
+    deblocking: u8,
+    reference: Option<Picture>,
+    idct: Vec<f32>,
+}
+
+impl H263Decoder {
+    pub fn new(stream: &VideoStream) -> IoResult<Self> {
+        if stream.codec != VideoCodec::SorensonH263 {
+            return Err(IoError { kind: InvalidInput, desc: "Sorenson H.263 stream expected", detail: None })
+        }
+
+        let mut idct = Vec::with_capacity(64);
+        for u in 0..8 {
+            let scale = if u == 0 { 0.5 / 2.0f32.sqrt() } else { 0.5 };
+            for x in 0..8 {
+                idct.push(scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos());
+            }
+        }
+
+        Ok(H263Decoder {
+            deblocking: stream.deblocking,
+            reference: None,
+            idct: idct
+        })
+    }
+
+    // Forgets the reference frame, the next frame must be an intra frame
+    pub fn reset(&mut self) {
+        self.reference = None;
+    }
+
+    pub fn decode(&mut self, frame: &VideoFrame) -> IoResult<YuvFrame> {
+        let mut bits = BitReader::new(BufReader::new(&frame.data[..]));
+        let header = try!(read_header(&mut bits));
+        let mb_width = (header.width + 15) / 16;
+        let mb_height = (header.height + 15) / 16;
+
+        if header.kind != PictureType::Intra {
+            match self.reference {
+                Some(ref picture) if picture.mb_width == mb_width && picture.mb_height == mb_height => (),
+                _ => return Err(IoError { kind: InvalidInput, desc: "inter frame without a matching reference frame", detail: None })
+            }
+        }
+
+        let size = mb_width * mb_height * 256;
+        let mut picture = Picture {
+            mb_width: mb_width,
+            mb_height: mb_height,
+            y: repeat(0).take(size).collect(),
+            u: repeat(0).take(size / 4).collect(),
+            v: repeat(0).take(size / 4).collect()
+        };
+
+        // Motion vectors in half pixels for each 8x8 luma block, and the quantizer of each coded macroblock
+        let mut vectors: Vec<(i32, i32)> = repeat((0, 0)).take(size / 64).collect();
+        let mut quants = Vec::with_capacity(mb_width * mb_height);
+        let mut quant = header.quant;
+        for mb_y in 0..mb_height {
+            for mb_x in 0..mb_width {
+                let coded = try!(self.decode_macroblock(&mut bits, &header, &mut quant, &mut picture, &mut vectors, mb_x, mb_y));
+                quants.push(if coded { quant } else { 0 });
+            }
+        }
+
+        let deblocking = match self.deblocking {
+            0 => header.deblocking,
+            1 => false,
+            _ => true
+        };
+        let frame = if deblocking {
+            let mut filtered = picture.clone();
+            deblock(&mut filtered.y, mb_width * 16, 16, &quants);
+            deblock(&mut filtered.u, mb_width * 8, 8, &quants);
+            deblock(&mut filtered.v, mb_width * 8, 8, &quants);
+            crop(&filtered, header.width, header.height)
+        } else {
+            crop(&picture, header.width, header.height)
+        };
+
+        if header.kind != PictureType::Disposable {
+            self.reference = Some(picture);
+        }
+        Ok(frame)
+    }
+
+    // Returns false for a skipped macroblock
+    fn decode_macroblock(&self, bits: &mut Bits, header: &PictureHeader, quant: &mut i32, picture: &mut Picture,
+        vectors: &mut [(i32, i32)], mb_x: usize, mb_y: usize) -> IoResult<bool>
+    {
+        let grid = picture.mb_width * 2;
+        let blocks = [
+            (mb_y * 2) * grid + mb_x * 2, (mb_y * 2) * grid + mb_x * 2 + 1,
+            (mb_y * 2 + 1) * grid + mb_x * 2, (mb_y * 2 + 1) * grid + mb_x * 2 + 1
+        ];
+
+        let intra;
+        let dquant;
+        let mut cbpc;
+        if header.kind == PictureType::Intra {
+            loop {
+                cbpc = try!(read_vlc(bits, &INTRA_MCBPC));
+                if cbpc != INTRA_MCBPC_STUFFING {
+                    break
+                }
+            }
+            intra = true;
+            dquant = cbpc & 4 != 0;
+        } else {
+            loop {
+                if try!(bits.read_bit()) {
+                    let reference = self.reference.as_ref().unwrap();
+                    predict_macroblock(reference, picture, mb_x, mb_y, &[(0, 0); 4], false);
+                    return Ok(false)
+                }
+                cbpc = try!(read_vlc(bits, &INTER_MCBPC));
+                if cbpc != INTER_MCBPC_STUFFING {
+                    break
+                }
+            }
+            intra = cbpc & 4 != 0;
+            dquant = cbpc & 8 != 0;
+        }
+
+        let cbpy = try!(read_vlc(bits, &CBPY));
+        let cbp = (cbpc & 3) | (if intra { cbpy } else { cbpy ^ 0xf }) << 2;
+        if dquant {
+            *quant = max(1, min(31, *quant + DQUANT[try!(bits.read_ubits(2)) as usize]));
+        }
+
+        if !intra {
+            let four = cbpc & 16 != 0;
+            let mut motion = [(0, 0); 4];
+            for k in 0..(if four { 4 } else { 1 }) {
+                let (x, y) = predict_vector(vectors, grid, mb_x, mb_y, k);
+                motion[k] = (try!(read_motion(bits, x)), try!(read_motion(bits, y)));
+                vectors[blocks[k]] = motion[k];
+            }
+            if !four {
+                for k in 1..4 {
+                    motion[k] = motion[0];
+                    vectors[blocks[k]] = motion[0];
+                }
+            }
+
+            let reference = self.reference.as_ref().unwrap();
+            predict_macroblock(reference, picture, mb_x, mb_y, &motion, four);
+        }
+
+        let stride = picture.mb_width * 16;
+        let mut block = [0i32; 64];
+        let mut residual = [0i32; 64];
+        for k in 0..6 {
+            let coded = cbp & (32 >> k) != 0;
+            if !intra && !coded {
+                continue
+            }
+            try!(read_block(bits, &mut block, intra, coded, *quant, header.version));
+            self.inverse_dct(&block, &mut residual);
+
+            let (plane, stride, x, y) = match k {
+                0...3 => (&mut picture.y, stride, mb_x * 16 + (k & 1) * 8, mb_y * 16 + (k >> 1) * 8),
+                4 => (&mut picture.u, stride / 2, mb_x * 8, mb_y * 8),
+                _ => (&mut picture.v, stride / 2, mb_x * 8, mb_y * 8)
+            };
+            for j in 0..8 {
+                for i in 0..8 {
+                    let pixel = &mut plane[(y + j) * stride + x + i];
+                    let base = if intra { 0 } else { *pixel as i32 };
+                    *pixel = max(0, min(255, base + residual[j * 8 + i])) as u8;
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn inverse_dct(&self, block: &[i32; 64], output: &mut [i32; 64]) {
+        let mut rows = [0.0f32; 64];
+        for y in 0..8 {
+            for x in 0..8 {
+                let mut sum = 0.0;
+                for u in 0..8 {
+                    sum += block[y * 8 + u] as f32 * self.idct[u * 8 + x];
+                }
+                rows[y * 8 + x] = sum;
+            }
+        }
+        for x in 0..8 {
+            for y in 0..8 {
+                let mut sum = 0.0;
+                for v in 0..8 {
+                    sum += rows[v * 8 + x] * self.idct[v * 8 + y];
+                }
+                output[y * 8 + x] = max(-256, min(255, sum.round() as i32));
+            }
+        }
+    }
+}
+
+fn read_header(bits: &mut Bits) -> IoResult<PictureHeader> {
+    if try!(bits.read_ubits(17)) != 1 {
+        return Err(IoError { kind: InvalidInput, desc: "invalid H.263 picture start code", detail: None })
+    }
+    let version = try!(bits.read_ubits(5));
+    if version > 1 {
+        return Err(IoError { kind: InvalidInput, desc: "unsupported Sorenson H.263 version", detail: None })
+    }
+    try!(bits.read_ubits(8)); // temporal reference
+
+    let (width, height) = match try!(bits.read_ubits(3)) {
+        0 => (try!(bits.read_ubits(8)), try!(bits.read_ubits(8))),
+        1 => (try!(bits.read_ubits(16)), try!(bits.read_ubits(16))),
+        2 => (352, 288),
+        3 => (176, 144),
+        4 => (128, 96),
+        5 => (320, 240),
+        6 => (160, 120),
+        _ => (0, 0)
+    };
+    if width == 0 || height == 0 {
+        return Err(IoError { kind: InvalidInput, desc: "invalid H.263 picture size", detail: None })
+    }
+
+    let kind = match try!(bits.read_ubits(2)) {
+        0 => PictureType::Intra,
+        1 => PictureType::Inter,
+        _ => PictureType::Disposable
+    };
+    let deblocking = try!(bits.read_bit());
+    let quant = try!(bits.read_ubits(5)) as i32;
+    if quant == 0 {
+        return Err(IoError { kind: InvalidInput, desc: "invalid H.263 quantizer", detail: None })
+    }
+
+    // Extra information bytes, which have no meaning
+    while try!(bits.read_bit()) {
+        try!(bits.read_ubits(8));
+    }
+
+    Ok(PictureHeader {
+        version: version,
+        width: width as usize,
+        height: height as usize,
+        kind: kind,
+        deblocking: deblocking,
+        quant: quant
+    })
+}
+
+// Reads one code of a table of (code, length) pairs, returning its index
+fn read_vlc(bits: &mut Bits, table: &[(u16, u8)]) -> IoResult<usize> {
+    let mut code = 0;
+    for length in 1..14 {
+        code = code << 1 | try!(bits.read_ubits(1)) as u16;
+        if let Some(index) = table.iter().position(|&(c, l)| l == length && c == code) {
+            return Ok(index)
+        }
+    }
+    Err(IoError { kind: InvalidInput, desc: "invalid H.263 variable length code", detail: None })
+}
+
+fn read_motion(bits: &mut Bits, predictor: i32) -> IoResult<i32> {
+    let code = try!(read_vlc(bits, &MVD)) as i32;
+    if code == 0 {
+        return Ok(predictor)
+    }
+    let value = if try!(bits.read_bit()) { predictor - code } else { predictor + code };
+
+    // Vectors wrap around to stay within -16 to 15.5 pixels
+    Ok(((value + 32) & 63) - 32)
+}
+
+// The median of the vectors to the left, above and above right of luma block `k`, vectors outside the picture
+// are zero, and on the top row the left vector is used alone
+fn predict_vector(vectors: &[(i32, i32)], grid: usize, mb_x: usize, mb_y: usize, k: usize) -> (i32, i32) {
+    let x = (mb_x * 2 + (k & 1)) as i32;
+    let y = (mb_y * 2 + (k >> 1)) as i32;
+    let at = |x: i32, y: i32| if x < 0 || y < 0 || x >= grid as i32 { (0, 0) } else { vectors[y as usize * grid + x as usize] };
+
+    let a = at(x - 1, y);
+    if y == 0 {
+        return a
+    }
+    let b = at(x, y - 1);
+    let c = at(x + [2, 1, 1, -1][k], y - 1);
+    (median(a.0, b.0, c.0), median(a.1, b.1, c.1))
+}
+
+fn median(a: i32, b: i32, c: i32) -> i32 {
+    max(min(a, b), min(max(a, b), c))
+}
+
+fn predict_macroblock(reference: &Picture, picture: &mut Picture, mb_x: usize, mb_y: usize, motion: &[(i32, i32); 4], four: bool) {
+    let stride = picture.mb_width * 16;
+    let rows = picture.mb_height * 16;
+    for k in 0..4 {
+        let (x, y) = motion[k];
+        predict(&reference.y, &mut picture.y, stride, rows, mb_x * 16 + (k & 1) * 8, mb_y * 16 + (k >> 1) * 8, x, y);
+    }
+
+    // Chroma vectors are halved, rounding quarter pixel positions to the half pixel
+    let (x, y) = if four {
+        let sum = motion.iter().fold((0, 0), |s, m| (s.0 + m.0, s.1 + m.1));
+        (round_chroma(sum.0), round_chroma(sum.1))
+    } else {
+        let half = |v: i32| (v >> 2) * 2 + if v & 3 != 0 { 1 } else { 0 };
+        (half(motion[0].0), half(motion[0].1))
+    };
+    predict(&reference.u, &mut picture.u, stride / 2, rows / 2, mb_x * 8, mb_y * 8, x, y);
+    predict(&reference.v, &mut picture.v, stride / 2, rows / 2, mb_x * 8, mb_y * 8, x, y);
+}
+
+// The chroma vector of four luma vectors from their sum, in half pixels
+fn round_chroma(sum: i32) -> i32 {
+    let round = |v: i32| CHROMA_ROUND[(v & 0xf) as usize] + ((v >> 3) & !1);
+    if sum >= 0 { round(sum) } else { -round(-sum) }
+}
+
+// Half pixel prediction of the 8x8 block at (x, y), pixels outside the reference repeat its edges
+fn predict(reference: &[u8], plane: &mut [u8], stride: usize, rows: usize, x: usize, y: usize, mx: i32, my: i32) {
+    let at = |x: i32, y: i32| {
+        let x = max(0, min(stride as i32 - 1, x)) as usize;
+        let y = max(0, min(rows as i32 - 1, y)) as usize;
+        reference[y * stride + x] as i32
+    };
+
+    let left = x as i32 + (mx >> 1);
+    let top = y as i32 + (my >> 1);
+    for j in 0..8 {
+        for i in 0..8 {
+            let (sx, sy) = (left + i as i32, top + j as i32);
+            let value = match (mx & 1, my & 1) {
+                (0, 0) => at(sx, sy),
+                (1, 0) => (at(sx, sy) + at(sx + 1, sy) + 1) >> 1,
+                (0, _) => (at(sx, sy) + at(sx, sy + 1) + 1) >> 1,
+                _ => (at(sx, sy) + at(sx + 1, sy) + at(sx, sy + 1) + at(sx + 1, sy + 1) + 2) >> 2
+            };
+            plane[(y + j) * stride + x + i] = value as u8;
+        }
+    }
+}
+
+// Reads and dequantizes the coefficients of a block, an intra block always has its DC coefficient
+fn read_block(bits: &mut Bits, block: &mut [i32; 64], intra: bool, coded: bool, quant: i32, version: u32) -> IoResult<()> {
+    for coefficient in block.iter_mut() {
+        *coefficient = 0;
+    }
+
+    let mut i = 0;
+    if intra {
+        let dc = try!(bits.read_ubits(8)) as i32;
+        if dc & 0x7f == 0 {
+            return Err(IoError { kind: InvalidInput, desc: "invalid H.263 intra DC coefficient", detail: None })
+        }
+        block[0] = if dc == 255 { 128 } else { dc } * 8;
+        i = 1;
+    }
+    if !coded {
+        return Ok(())
+    }
+
+    let (scale, offset) = (quant * 2, (quant - 1) | 1);
+    loop {
+        let code = try!(read_vlc(bits, &TCOEF));
+        let (last, run, level) = if code == TCOEF.len() - 1 {
+            // Version 1 streams escape to 7 or 11 bit levels, version 0 uses plain H.263 8 bit levels
+            if version == 1 {
+                let long = try!(bits.read_bit());
+                let last = try!(bits.read_bit());
+                let run = try!(bits.read_ubits(6)) as usize;
+                (last, run, try!(bits.read_sbits(if long { 11 } else { 7 })))
+            } else {
+                let last = try!(bits.read_bit());
+                let run = try!(bits.read_ubits(6)) as usize;
+                let level = match try!(bits.read_sbits(8)) {
+                    -128 => try!(bits.read_ubits(5)) as i32 | try!(bits.read_sbits(6)) << 5,
+                    level => level
+                };
+                (last, run, level)
+            }
+        } else {
+            let level = TCOEF_LEVEL[code] as i32;
+            (code >= TCOEF_LAST, TCOEF_RUN[code] as usize, if try!(bits.read_bit()) { -level } else { level })
+        };
+
+        i += run;
+        if i >= 64 {
+            return Err(IoError { kind: InvalidInput, desc: "too many H.263 coefficients", detail: None })
+        }
+        let value = if level > 0 { level * scale + offset } else if level < 0 { level * scale - offset } else { 0 };
+        block[ZIGZAG[i]] = max(-2048, min(2047, value));
+        i += 1;
+
+        if last {
+            return Ok(())
+        }
+    }
+}
+
+fn crop(picture: &Picture, width: usize, height: usize) -> YuvFrame {
+    let copy = |plane: &[u8], stride: usize, width: usize, height: usize| {
+        let mut data = Vec::with_capacity(width * height);
+        for row in 0..height {
+            data.push_all(&plane[row * stride..row * stride + width]);
+        }
+        data
+    };
+
+    let stride = picture.mb_width * 16;
+    let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+    YuvFrame {
+        width: width as u16,
+        height: height as u16,
+        y: copy(&picture.y, stride, width, height),
+        u: copy(&picture.u, stride / 2, chroma_width, chroma_height),
+        v: copy(&picture.v, stride / 2, chroma_width, chroma_height),
+        alpha: None
+    }
+}
+
+// The H.263 annex J filter across 8x8 block edges, with the strength of the macroblock below or right of
+// the edge; edges between two skipped macroblocks are left alone
+fn deblock(plane: &mut [u8], stride: usize, mb_size: usize, quants: &[i32]) {
+    let mb_width = stride / mb_size;
+    let rows = plane.len() / stride;
+    let quant = |x: usize, y: usize| quants[(y / mb_size) * mb_width + x / mb_size];
+    let strength = |q: i32, other: i32| if q != 0 { STRENGTH[q as usize] } else { STRENGTH[other as usize] };
+
+    for edge in 1..rows / 8 {
+        let y = edge * 8;
+        for x in 0..stride {
+            let (q, other) = (quant(x, y), quant(x, y - 1));
+            if q != 0 || other != 0 {
+                filter_edge(plane, (y - 2) * stride + x, stride, strength(q, other));
+            }
+        }
+    }
+    for edge in 1..stride / 8 {
+        let x = edge * 8;
+        for y in 0..rows {
+            let (q, other) = (quant(x, y), quant(x - 1, y));
+            if q != 0 || other != 0 {
+                filter_edge(plane, y * stride + x - 2, 1, strength(q, other));
+            }
+        }
+    }
+}
+
+// Filters the pixels A B | C D starting at `start`, `step` apart
+fn filter_edge(plane: &mut [u8], start: usize, step: usize, strength: i32) {
+    let abs = |v: i32| if v < 0 { -v } else { v };
+    let clip = |v: i32| max(0, min(255, v)) as u8;
+
+    let a = plane[start] as i32;
+    let b = plane[start + step] as i32;
+    let c = plane[start + step * 2] as i32;
+    let d = plane[start + step * 3] as i32;
+
+    let d0 = (a - 4 * b + 4 * c - d) / 8;
+    let ramp = max(0, abs(d0) - max(0, 2 * (abs(d0) - strength)));
+    let d1 = if d0 < 0 { -ramp } else { ramp };
+    let limit = abs(d1 / 2);
+    let d2 = max(-limit, min(limit, (a - d) / 4));
+
+    plane[start] = clip(a - d2);
+    plane[start + step] = clip(b + d1);
+    plane[start + step * 2] = clip(c - d1);
+    plane[start + step * 3] = clip(d + d2);
+}
+
+static DQUANT: [i32; 4] = [-1, -2, 1, 2];
+
+static CHROMA_ROUND: [i32; 16] = [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2];
+
+// Indexed by quantizer
+static STRENGTH: [i32; 32] = [
+    0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5, 5, 6, 6, 7,
+    7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12
+];
+
+static ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63
+];
+
+// Variable length codes as (code, length), the index of each code is its value
+
+// Bits 0 and 1 are the chroma coded flags, 4 is quantizer change
+static INTRA_MCBPC: [(u16, u8); 9] = [
+    (1, 1), (1, 3), (2, 3), (3, 3), (1, 4), (1, 6), (2, 6), (3, 6), (1, 9)
+];
+const INTRA_MCBPC_STUFFING: usize = 8;
+
+// Bits 0 and 1 are the chroma coded flags, 4 is intra, 8 is quantizer change and 16 is four motion vectors
+static INTER_MCBPC: [(u16, u8); 28] = [
+    (1, 1), (3, 4), (2, 4), (5, 6),
+    (3, 5), (4, 8), (3, 8), (3, 7),
+    (3, 3), (7, 7), (6, 7), (5, 9),
+    (4, 6), (4, 9), (3, 9), (2, 9),
+    (2, 3), (5, 7), (4, 7), (5, 8),
+    (1, 9), (0, 0), (0, 0), (0, 0),
+    (2, 11), (12, 13), (14, 13), (15, 13)
+];
+const INTER_MCBPC_STUFFING: usize = 20;
+
+// The luma coded flags of an intra macroblock, inverted for inter macroblocks
+static CBPY: [(u16, u8); 16] = [
+    (3, 4), (5, 5), (4, 5), (9, 4), (3, 5), (7, 4), (2, 6), (11, 4),
+    (2, 5), (3, 6), (5, 4), (10, 4), (4, 4), (8, 4), (6, 4), (3, 2)
+];
+
+// Motion vector differences in half pixels, followed by a sign bit when not zero
+static MVD: [(u16, u8); 33] = [
+    (1, 1), (1, 2), (1, 3), (1, 4), (3, 6), (5, 7), (4, 7), (3, 7),
+    (11, 9), (10, 9), (9, 9), (17, 10), (16, 10), (15, 10), (14, 10), (13, 10),
+    (12, 10), (11, 10), (10, 10), (9, 10), (8, 10), (7, 10), (6, 10), (5, 10),
+    (4, 10), (7, 11), (6, 11), (5, 11), (4, 11), (3, 11), (2, 11), (3, 12),
+    (2, 12)
+];
+
+// Coefficient runs and levels, codes from TCOEF_LAST end the block and the final code is the escape
+static TCOEF: [(u16, u8); 103] = [
+    (0x2, 2), (0xf, 4), (0x15, 6), (0x17, 7), (0x1f, 8), (0x25, 9), (0x24, 9), (0x21, 10),
+    (0x20, 10), (0x7, 11), (0x6, 11), (0x20, 11), (0x6, 3), (0x14, 6), (0x1e, 8), (0xf, 10),
+    (0x21, 11), (0x50, 12), (0xe, 4), (0x1d, 8), (0xe, 10), (0x51, 12), (0xd, 5), (0x23, 9),
+    (0xd, 10), (0xc, 5), (0x22, 9), (0x52, 12), (0xb, 5), (0xc, 10), (0x53, 12), (0x13, 6),
+    (0xb, 10), (0x54, 12), (0x12, 6), (0xa, 10), (0x11, 6), (0x9, 10), (0x10, 6), (0x8, 10),
+    (0x16, 7), (0x55, 12), (0x15, 7), (0x14, 7), (0x1c, 8), (0x1b, 8), (0x21, 9), (0x20, 9),
+    (0x1f, 9), (0x1e, 9), (0x1d, 9), (0x1c, 9), (0x1b, 9), (0x1a, 9), (0x22, 11), (0x23, 11),
+    (0x56, 12), (0x57, 12), (0x7, 4), (0x19, 9), (0x5, 11), (0xf, 6), (0x4, 11), (0xe, 6),
+    (0xd, 6), (0xc, 6), (0x13, 7), (0x12, 7), (0x11, 7), (0x10, 7), (0x1a, 8), (0x19, 8),
+    (0x18, 8), (0x17, 8), (0x16, 8), (0x15, 8), (0x14, 8), (0x13, 8), (0x18, 9), (0x17, 9),
+    (0x16, 9), (0x15, 9), (0x14, 9), (0x13, 9), (0x12, 9), (0x11, 9), (0x7, 10), (0x6, 10),
+    (0x5, 10), (0x4, 10), (0x24, 11), (0x25, 11), (0x26, 11), (0x27, 11), (0x58, 12), (0x59, 12),
+    (0x5a, 12), (0x5b, 12), (0x5c, 12), (0x5d, 12), (0x5e, 12), (0x5f, 12), (0x3, 7)
+];
+const TCOEF_LAST: usize = 58;
+
+static TCOEF_RUN: [u8; 102] = [
+     0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  1,  1,  1,  1,
+     1,  1,  2,  2,  2,  2,  3,  3,  3,  4,  4,  4,  5,  5,  5,  6,
+     6,  6,  7,  7,  8,  8,  9,  9, 10, 10, 11, 12, 13, 14, 15, 16,
+    17, 18, 19, 20, 21, 22, 23, 24, 25, 26,  0,  0,  0,  1,  1,  2,
+     3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+    19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34,
+    35, 36, 37, 38, 39, 40
+];
+
+static TCOEF_LEVEL: [u8; 102] = [
+     1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12,  1,  2,  3,  4,
+     5,  6,  1,  2,  3,  4,  1,  2,  3,  1,  2,  3,  1,  2,  3,  1,
+     2,  3,  1,  2,  1,  2,  1,  2,  1,  2,  1,  1,  1,  1,  1,  1,
+     1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  2,  3,  1,  2,  1,
+     1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,
+     1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,
+     1,  1,  1,  1,  1,  1
+];
+
+#[cfg(test)]
+mod tests {
+    use super::H263Decoder;
+    use util::video::{ VideoStream, VideoFrame, VideoCodec, YuvFrame };
+
+    // FFmpeg's encoding of a moving pattern in groups of an intra frame and three inter frames, with the
+    // second inter frame of each group patched to be disposable. FFmpeg's decoding is the reference, its
+    // integer IDCT rounds differently
+    const TOLERANCE: i32 = 1;
+
+    fn stream(deblocking: u8) -> VideoStream {
+        VideoStream { id: 1, frame_count: 8, width: 44, height: 30, deblocking: deblocking, smoothing: false,
+            codec: VideoCodec::SorensonH263 }
+    }
+
+    // FLV video tags hold the codec byte before the frame
+    fn frames() -> Vec<VideoFrame> {
+        let flv = include_bytes!("../../tests/fixtures/h263_44x30.flv");
+        let mut frames = Vec::new();
+        let mut pos = 13;
+        while pos < flv.len() {
+            let size = (flv[pos + 1] as usize) << 16 | (flv[pos + 2] as usize) << 8 | flv[pos + 3] as usize;
+            if flv[pos] == 9 {
+                let data = flv[pos + 12..pos + 11 + size].to_vec();
+                frames.push(VideoFrame { stream_id: 1, frame_num: frames.len() as u16, data: data });
+            }
+            pos += 11 + size + 4;
+        }
+        frames
+    }
+
+    fn decode(deblocking: u8) -> Vec<YuvFrame> {
+        let mut decoder = H263Decoder::new(&stream(deblocking)).unwrap();
+        frames().iter().map(|frame| decoder.decode(frame).unwrap()).collect()
+    }
+
+    #[test]
+    fn decode_matches_reference() {
+        let reference = include_bytes!("../../tests/fixtures/h263_44x30.yuv");
+        let mut decoded = Vec::new();
+        for picture in decode(1).iter() {
+            assert_eq!((picture.width, picture.height, picture.u.len()), (44, 30, 22 * 15));
+            decoded.push_all(&picture.y);
+            decoded.push_all(&picture.u);
+            decoded.push_all(&picture.v);
+        }
+
+        assert_eq!(decoded.len(), reference.len());
+        for (i, (&a, &b)) in decoded.iter().zip(reference.iter()).enumerate() {
+            assert!((a as i32 - b as i32).abs() <= TOLERANCE, "byte {}: {} != {}", i, a, b);
+        }
+    }
+
+    #[test]
+    fn deblocking() {
+        // FFmpeg sets the deblocking flag of every frame but does not filter, so the filter is only checked
+        // to follow the flag and the stream setting and to stay next to block edges
+        let (filtered, plain) = (decode(0), decode(1));
+        assert!(decode(2) == filtered);
+
+        let near_edge = |v: usize| v % 8 <= 1 || v % 8 >= 6;
+        for (filtered, plain) in filtered.iter().zip(plain.iter()) {
+            assert!(filtered.y != plain.y);
+            for (i, (&a, &b)) in filtered.y.iter().zip(plain.y.iter()).enumerate() {
+                let (x, y) = (i % 44, i / 44);
+                assert!(a == b || near_edge(x) || near_edge(y), "pixel {}, {}: {} != {}", x, y, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inter_frame_needs_reference() {
+        let mut decoder = H263Decoder::new(&stream(1)).unwrap();
+        let frames = frames();
+        assert!(decoder.decode(&frames[1]).is_err());
+        decoder.decode(&frames[0]).unwrap();
+        decoder.decode(&frames[1]).unwrap();
+        decoder.reset();
+        assert!(decoder.decode(&frames[1]).is_err());
+    }
+}
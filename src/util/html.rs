@@ -0,0 +1,415 @@
+use std::char;
+use std::ascii::AsciiExt;
+use types::Rgba;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextFormat {
+    pub font: Option<String>,
+    pub size: Option<f32>,
+    pub color: Option<Rgba>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub url: Option<String>,
+    pub target: Option<String>,
+    pub align: Option<TextAlign>,
+    pub left_margin: Option<f32>,
+    pub right_margin: Option<f32>,
+    pub indent: Option<f32>,
+    pub block_indent: Option<f32>,
+    pub leading: Option<f32>,
+    pub letter_spacing: Option<f32>,
+    pub kerning: bool,
+    pub bullet: bool,
+    pub class: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSpan {
+    pub text: String,
+    pub format: TextFormat,
+}
+
+impl TextFormat {
+    pub fn new() -> Self {
+        TextFormat {
+            font: None,
+            size: None,
+            color: None,
+            bold: false,
+            italic: false,
+            underline: false,
+            url: None,
+            target: None,
+            align: None,
+            left_margin: None,
+            right_margin: None,
+            indent: None,
+            block_indent: None,
+            leading: None,
+            letter_spacing: None,
+            kerning: false,
+            bullet: false,
+            class: None
+        }
+    }
+}
+
+struct HtmlTag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    closing: bool,
+    empty: bool,
+}
+
+// Parses the HTML subset understood by Flash text fields into styled runs
+pub fn parse_html(html: &str, base: &TextFormat) -> Vec<TextSpan> {
+    let html: Vec<char> = html.chars().collect();
+    let mut spans = Vec::new();
+    let mut stack = vec![(String::new(), base.clone())];
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < html.len() {
+        let c = html[i];
+        i += 1;
+
+        match c {
+            '<' => {
+                let start = i;
+                if html[start..].starts_with(&['!', '-', '-'][..]) {
+                    // Comments may contain '>' and only end at "-->"
+                    i += 3;
+                    while i < html.len() && !html[i - 2..].starts_with(&['-', '-', '>'][..]) {
+                        i += 1;
+                    }
+                    i += 1;
+                    continue
+                }
+                while i < html.len() && html[i] != '>' {
+                    i += 1;
+                }
+                let source: String = html[start..i].iter().map(|&c| c).collect();
+                i += 1;
+
+                let tag = match parse_tag(&source) {
+                    Some(tag) => tag,
+                    None => continue
+                };
+
+                let format = stack[stack.len() - 1].1.clone();
+                push_span(&mut spans, &mut text, &format);
+
+                if tag.closing {
+                    let index = stack.iter().rposition(|&(ref name, _)| *name == tag.name);
+                    if let Some(index) = index {
+                        if index > 0 {
+                            stack.truncate(index);
+                        }
+                    }
+                    if tag.name == "p" || tag.name == "li" {
+                        text.push('\n');
+                        push_span(&mut spans, &mut text, &format);
+                    }
+                    continue
+                }
+
+                if tag.name == "br" || tag.name == "sbr" {
+                    text.push('\n');
+                    push_span(&mut spans, &mut text, &format);
+                    continue
+                }
+
+                let mut format = format;
+                apply_tag(&mut format, &tag);
+                if !tag.empty {
+                    stack.push((tag.name, format));
+                }
+            },
+            '&' => {
+                let start = i;
+                while i < html.len() && html[i] != ';' && i - start < 8 {
+                    i += 1;
+                }
+                let entity: String = html[start..i].iter().map(|&c| c).collect();
+                match decode_entity(&entity) {
+                    Some(c) if i < html.len() && html[i] == ';' => {
+                        text.push(c);
+                        i += 1;
+                    },
+                    _ => {
+                        text.push('&');
+                        i = start;
+                    }
+                }
+            },
+            '\r' => text.push('\n'),
+            c => text.push(c)
+        }
+    }
+
+    let format = stack[stack.len() - 1].1.clone();
+    push_span(&mut spans, &mut text, &format);
+
+    spans
+}
+
+pub fn strip_html(html: &str) -> String {
+    parse_html(html, &TextFormat::new()).into_iter().fold(String::new(), |mut text, span| {
+        text.push_str(&span.text);
+        text
+    })
+}
+
+fn push_span(spans: &mut Vec<TextSpan>, text: &mut String, format: &TextFormat) {
+    if text.is_empty() {
+        return
+    }
+
+    let value = text.clone();
+    text.clear();
+
+    if let Some(last) = spans.last_mut() {
+        if last.format == *format {
+            last.text.push_str(&value);
+            return
+        }
+    }
+
+    spans.push(TextSpan {
+        text: value,
+        format: format.clone()
+    });
+}
+
+fn parse_tag(source: &str) -> Option<HtmlTag> {
+    let source: Vec<char> = source.trim().chars().collect();
+    let mut i = 0;
+    let mut end = source.len();
+
+    let closing = end > 0 && source[0] == '/';
+    if closing {
+        i += 1;
+    }
+    let empty = end > i && source[end - 1] == '/';
+    if empty {
+        end -= 1;
+    }
+
+    let start = i;
+    while i < end && !source[i].is_whitespace() {
+        i += 1;
+    }
+    let name: String = source[start..i].iter().map(|c| c.to_ascii_lowercase()).collect();
+    if name.is_empty() || name.starts_with("!") || name.starts_with("?") {
+        return None
+    }
+
+    let mut attributes = Vec::new();
+    loop {
+        while i < end && source[i].is_whitespace() {
+            i += 1;
+        }
+
+        let start = i;
+        while i < end && source[i] != '=' && !source[i].is_whitespace() {
+            i += 1;
+        }
+        if start == i {
+            break
+        }
+        let key: String = source[start..i].iter().map(|c| c.to_ascii_lowercase()).collect();
+
+        while i < end && source[i].is_whitespace() {
+            i += 1;
+        }
+
+        let mut value = String::new();
+        if i < end && source[i] == '=' {
+            i += 1;
+            while i < end && source[i].is_whitespace() {
+                i += 1;
+            }
+
+            if i < end && (source[i] == '"' || source[i] == '\'') {
+                let quote = source[i];
+                i += 1;
+                while i < end && source[i] != quote {
+                    value.push(source[i]);
+                    i += 1;
+                }
+                i += 1;
+            } else {
+                while i < end && !source[i].is_whitespace() {
+                    value.push(source[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        attributes.push((key, unescape(&value)));
+    }
+
+    Some(HtmlTag {
+        name: name,
+        attributes: attributes,
+        closing: closing,
+        empty: empty
+    })
+}
+
+fn apply_tag(format: &mut TextFormat, tag: &HtmlTag) {
+    match &tag.name[..] {
+        "b" => format.bold = true,
+        "i" => format.italic = true,
+        "u" => format.underline = true,
+        "li" => format.bullet = true,
+        _ => ()
+    }
+
+    for &(ref key, ref value) in tag.attributes.iter() {
+        match (&tag.name[..], &key[..]) {
+            ("p", "align") => format.align = match &value.to_ascii_lowercase()[..] {
+                "left" => Some(TextAlign::Left),
+                "right" => Some(TextAlign::Right),
+                "center" => Some(TextAlign::Center),
+                "justify" => Some(TextAlign::Justify),
+                _ => format.align
+            },
+            ("p", "class") | ("span", "class") => format.class = Some(value.clone()),
+            ("font", "face") => format.font = Some(value.clone()),
+            ("font", "size") => format.size = match value.chars().next() {
+                // Relative sizes adjust the current size
+                Some('+') | Some('-') => match (format.size, parse_number(&value[1..])) {
+                    (Some(size), Some(delta)) => Some(if value.starts_with("-") { size - delta } else { size + delta }),
+                    _ => format.size
+                },
+                _ => parse_number(value).or(format.size)
+            },
+            ("font", "color") => format.color = parse_color(value).or(format.color),
+            ("font", "letterspacing") => format.letter_spacing = parse_number(value),
+            ("font", "kerning") => format.kerning = value != "0",
+            ("a", "href") => format.url = Some(value.clone()),
+            ("a", "target") => format.target = Some(value.clone()),
+            ("textformat", "leftmargin") => format.left_margin = parse_number(value),
+            ("textformat", "rightmargin") => format.right_margin = parse_number(value),
+            ("textformat", "indent") => format.indent = parse_number(value),
+            ("textformat", "blockindent") => format.block_indent = parse_number(value),
+            ("textformat", "leading") => format.leading = parse_number(value),
+            _ => ()
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Option<f32> {
+    value.trim().parse().ok()
+}
+
+fn parse_color(value: &str) -> Option<Rgba> {
+    let value = value.trim();
+    let value = if value.starts_with("#") { &value[1..] } else if value.starts_with("0x") { &value[2..] } else { value };
+    parse_hex(value).map(|rgb| Rgba {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+        a: 0xff
+    })
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ if entity.starts_with("#x") || entity.starts_with("#X") => parse_hex(&entity[2..]).and_then(char::from_u32),
+        _ if entity.starts_with("#") => entity[1..].parse().ok().and_then(char::from_u32),
+        _ => None
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = match rest.find(';') {
+            Some(end) => decode_entity(&rest[1..end]).map(|c| (end, c)),
+            None => None
+        };
+        match entity {
+            Some((end, c)) => {
+                text.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    if value.is_empty() || value.len() > 8 {
+        return None
+    }
+
+    value.chars().fold(Some(0), |v, c| match (v, c.to_digit(16)) {
+        (Some(v), Some(d)) => Some(v << 4 | d),
+        _ => None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use types::Rgba;
+    use super::{ TextAlign, TextFormat, parse_html, strip_html };
+
+    #[test]
+    fn nested_formats() {
+        let html = "<p align=\"center\"><font face=\"Arial\" size=\"12\" color=\"#ff0000\">Red <b>bold</b></font></p>plain";
+        let spans = parse_html(html, &TextFormat::new());
+        assert_eq!(spans.iter().map(|s| &s.text[..]).collect::<Vec<_>>(), vec!["Red ", "bold", "\n", "plain"]);
+
+        let format = &spans[0].format;
+        assert_eq!(format.font, Some(String::from_str("Arial")));
+        assert_eq!((format.size, format.align), (Some(12.0), Some(TextAlign::Center)));
+        assert_eq!(format.color, Some(Rgba { r: 0xff, g: 0, b: 0, a: 0xff }));
+        assert!(!format.bold && spans[1].format.bold);
+        assert_eq!(spans[1].format.font, format.font);
+        assert_eq!((spans[2].format.align, spans[2].format.font.is_none()), (Some(TextAlign::Center), true));
+        assert_eq!(spans[3].format, TextFormat::new());
+    }
+
+    #[test]
+    fn attributes_and_entities() {
+        let html = "<a href=\"x?a=1&amp;b=2\" target='_blank'><font size=\"10\"><font size=\"+2\">\
+            a&amp;b&lt;&#65;&#x42;&bogus;</font></font></a>";
+        let spans = parse_html(html, &TextFormat::new());
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "a&b<AB&bogus;");
+        assert_eq!(spans[0].format.size, Some(12.0));
+        assert_eq!(spans[0].format.url, Some(String::from_str("x?a=1&b=2")));
+        assert_eq!(spans[0].format.target, Some(String::from_str("_blank")));
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(strip_html("a<!-- x > y -->b<br/>c<!---->d"), "ab\ncd");
+        assert_eq!(strip_html("<?xml version=\"1.0\"?>a<!DOCTYPE html>b<!-- unterminated > c"), "ab");
+    }
+}
@@ -0,0 +1,471 @@
+use std::old_io::{ IoResult, IoError, InvalidInput };
+use std::collections::HashMap;
+use std::num::Float;
+use types::{ Rgba, Matrix };
+use shape::Shape;
+use util::font::Font;
+use util::text::Text;
+use util::edit_text::EditText;
+use util::html::{ TextAlign, TextFormat };
+use util::csm::{ CSMTextSettings, GridFit, ZoneRecord, ZoneData };
+
+// Text fields keep a 2 pixel gutter between their bounds and the text
+const GUTTER: f32 = 40.0;
+
+#[derive(Debug, Clone)]
+pub struct LayoutGlyph {
+    pub character: char,
+    pub shape: Shape,
+    pub matrix: Matrix,
+    pub color: Rgba,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FontMetrics {
+    pub em_square: u32,
+    pub ascent: i32,
+    pub descent: i32,
+    pub leading: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceGlyph {
+    pub shape: Shape,
+    pub advance: i32,
+}
+
+// Supplies outlines for text fields that use device fonts, units are on the font's EM square
+pub trait FontProvider {
+    fn metrics(&self, name: &str, bold: bool, italic: bool) -> Option<FontMetrics>;
+    fn glyph(&self, name: &str, bold: bool, italic: bool, character: char) -> Option<DeviceGlyph>;
+}
+
+pub struct FontProviderDefault;
+
+#[allow(unused_variables)]
+impl FontProvider for FontProviderDefault {
+    fn metrics(&self, name: &str, bold: bool, italic: bool) -> Option<FontMetrics> { None }
+    fn glyph(&self, name: &str, bold: bool, italic: bool, character: char) -> Option<DeviceGlyph> { None }
+}
+
+enum LayoutFont<'a> {
+    Embedded(&'a Font),
+    Device(String, bool, bool),
+}
+
+struct Item {
+    character: char,
+    shape: Option<Shape>,
+    scale: f32,
+    advance: f32,
+    ascent: f32,
+    descent: f32,
+    leading: f32,
+    color: Rgba,
+    zones: Option<ZoneRecord>,
+}
+
+struct Line {
+    items: Vec<Item>,
+    format: TextFormat,
+    first: bool,
+    last: bool,
+}
+
+// Positions the glyphs of a DefineText/DefineText2 character in its own coordinate space
+pub fn layout_text(text: &Text, fonts: &HashMap<u16, Font>) -> IoResult<Vec<LayoutGlyph>> {
+    let grid = grid_fit(&text.csm_settings);
+    let mut glyphs = Vec::new();
+    let mut font_id = None;
+    let mut color = Rgba { r: 0, g: 0, b: 0, a: 0xff };
+    let mut height = 0;
+    let (mut x, mut y) = (0, 0);
+
+    for record in text.records.iter() {
+        if record.font_id.is_some() {
+            font_id = record.font_id;
+        }
+        color = record.color.unwrap_or(color);
+        height = record.height.unwrap_or(height);
+        x = record.x_offset.map(|v| v as i32).unwrap_or(x);
+        y = record.y_offset.map(|v| v as i32).unwrap_or(y);
+
+        let id = try!(font_id.ok_or(IoError { kind: InvalidInput, desc: "text record without font", detail: None }));
+        let font = try!(fonts.get(&id).ok_or(IoError { kind: InvalidInput, desc: "text refers to unknown font", detail: None }));
+        let scale = height as f32 / font.em_square() as f32;
+
+        for entry in record.glyphs.iter() {
+            let index = entry.index as usize;
+            let shape = try!(font.glyphs.get(index).ok_or(
+                IoError { kind: InvalidInput, desc: "glyph index out of range", detail: None }
+            ));
+
+            glyphs.push(LayoutGlyph {
+                character: font.char_for_glyph(index).unwrap_or('\u{fffd}'),
+                shape: shape.clone(),
+                matrix: fit_matrix(text.matrix.concat(&glyph_matrix(x as f32, y as f32, scale)), grid, font.zones(index)),
+                color: color
+            });
+            x += entry.advance;
+        }
+    }
+
+    Ok(glyphs)
+}
+
+// Lays out the text of a DefineEditText field within its bounds
+pub fn layout_edit_text<P : FontProvider>(text: &EditText, fonts: &HashMap<u16, Font>, provider: &P) -> IoResult<Vec<LayoutGlyph>> {
+    let base_font = text.font_id.and_then(|id| fonts.get(&id));
+    let multiline = text.flags.multiline;
+    let wrap = text.flags.word_wrap;
+
+    // Split styled runs into paragraphs of measured glyphs
+    let mut paragraphs: Vec<(TextFormat, Vec<Item>)> = Vec::new();
+    let mut items = Vec::new();
+    let mut paragraph_format = None;
+    for span in text.spans().into_iter() {
+        let format = span.format;
+        let font = resolve_font(text, &format, base_font, fonts);
+        let size = format.size.unwrap_or(12.0) * 20.0;
+        let metrics = match font {
+            LayoutFont::Embedded(font) => Some(embedded_metrics(font)),
+            LayoutFont::Device(ref name, bold, italic) => provider.metrics(name, bold, italic)
+        };
+        let metrics = match metrics {
+            Some(metrics) => metrics,
+            None => FontMetrics { em_square: 1024, ascent: 922, descent: 205, leading: 0 }
+        };
+        let scale = size / metrics.em_square as f32;
+        let letter_spacing = format.letter_spacing.unwrap_or(0.0) * 20.0;
+        let color = format.color.unwrap_or(Rgba { r: 0, g: 0, b: 0, a: 0xff });
+
+        if paragraph_format.is_none() {
+            paragraph_format = Some(format.clone());
+        }
+
+        for c in span.text.chars() {
+            let c = if text.flags.password && c != '\n' { '*' } else { c };
+            if c == '\n' {
+                if multiline {
+                    paragraphs.push((paragraph_format.take().unwrap_or_else(|| format.clone()), items));
+                    items = Vec::new();
+                    continue
+                }
+            }
+            let c = if c == '\n' { ' ' } else { c };
+
+            let (shape, advance, zones) = match font {
+                LayoutFont::Embedded(font) => match font.glyph_for_code(c as u32 as u16) {
+                    Some(index) if c as u32 <= 0xffff => {
+                        let advance = font.advance(index).map(|v| v as i32).unwrap_or_else(|| {
+                            font.layout.as_ref().and_then(|l| l.bounds.get(index)).map(|b| b.x_max).unwrap_or(0)
+                        });
+                        (font.glyphs.get(index).map(|s| s.clone()), advance, font.zones(index).map(|z| z.clone()))
+                    },
+                    _ => (None, if c == ' ' { metrics.em_square as i32 / 4 } else { 0 }, None)
+                },
+                LayoutFont::Device(ref name, bold, italic) => match provider.glyph(name, bold, italic, c) {
+                    Some(glyph) => (Some(glyph.shape), glyph.advance, None),
+                    None => (None, if c == ' ' { metrics.em_square as i32 / 4 } else { 0 }, None)
+                }
+            };
+
+            // Kerning adjusts the advance of the preceding glyph
+            if format.kerning {
+                if let (&LayoutFont::Embedded(font), Some(previous)) = (&font, items.last_mut()) {
+                    if previous.scale == scale {
+                        previous.advance += font.kerning(previous.character as u32 as u16, c as u32 as u16) as f32 * scale;
+                    }
+                }
+            }
+
+            items.push(Item {
+                character: c,
+                shape: shape,
+                scale: scale,
+                advance: advance as f32 * scale + letter_spacing,
+                ascent: metrics.ascent as f32 * scale,
+                descent: metrics.descent as f32 * scale,
+                leading: format.leading.map(|v| v * 20.0).unwrap_or(metrics.leading as f32 * scale),
+                color: color,
+                zones: zones
+            });
+        }
+    }
+    if !items.is_empty() || paragraph_format.is_some() {
+        paragraphs.push((paragraph_format.unwrap_or_else(|| text.format()), items));
+    }
+
+    // Break paragraphs into lines
+    let bounds = text.bounds;
+    let mut lines = Vec::new();
+    for (format, items) in paragraphs.into_iter() {
+        let (left, right) = line_extent(bounds.x_min as f32, bounds.x_max as f32, &format);
+        let indent = format.indent.unwrap_or(0.0) * 20.0;
+
+        let mut line = Vec::new();
+        let mut width = indent;
+        let mut first = true;
+        for item in items.into_iter() {
+            if wrap && multiline && !line.is_empty() && item.character != ' ' && width + item.advance > right - left {
+                let split = match line.iter().rposition(|i: &Item| i.character == ' ') {
+                    Some(space) => space + 1,
+                    None => line.len()
+                };
+                let rest = line.split_off(split);
+                lines.push(Line { items: line, format: format.clone(), first: first, last: false });
+                first = false;
+                width = rest.iter().fold(0.0, |w, i| w + i.advance);
+                line = rest;
+            }
+            width += item.advance;
+            line.push(item);
+        }
+        lines.push(Line { items: line, format: format.clone(), first: first, last: true });
+    }
+
+    // Position lines from the top of the field
+    let grid = grid_fit(&text.csm_settings);
+    let mut glyphs = Vec::new();
+    let mut y = bounds.y_min as f32 + GUTTER;
+    let default_ascent = text.height.unwrap_or(240) as f32 * 0.9;
+    for line in lines.iter() {
+        let ascent = line.items.iter().fold(0.0, |v, i| if i.ascent > v { i.ascent } else { v });
+        let descent = line.items.iter().fold(0.0, |v, i| if i.descent > v { i.descent } else { v });
+        let leading = line.items.iter().fold(0.0, |v, i| if i.leading > v { i.leading } else { v });
+        let ascent = if line.items.is_empty() { default_ascent } else { ascent };
+
+        let (left, right) = line_extent(bounds.x_min as f32, bounds.x_max as f32, &line.format);
+        let left = left + if line.first { line.format.indent.unwrap_or(0.0) * 20.0 } else { 0.0 };
+        let visible = line.items.len() - line.items.iter().rev().take_while(|i| i.character == ' ').count();
+        let width = line.items[..visible].iter().fold(0.0, |w, i| w + i.advance);
+        let spaces = line.items[..visible].iter().filter(|i| i.character == ' ').count();
+
+        let mut x = match line.format.align.unwrap_or(TextAlign::Left) {
+            TextAlign::Left | TextAlign::Justify => left,
+            TextAlign::Right => right - width,
+            TextAlign::Center => left + (right - left - width) / 2.0
+        };
+        let justify = match line.format.align {
+            Some(TextAlign::Justify) if !line.last && spaces > 0 => (right - left - width) / spaces as f32,
+            _ => 0.0
+        };
+
+        y += ascent;
+        for item in line.items.iter() {
+            if let Some(ref shape) = item.shape {
+                glyphs.push(LayoutGlyph {
+                    character: item.character,
+                    shape: shape.clone(),
+                    matrix: fit_matrix(glyph_matrix(x, y, item.scale), grid, item.zones.as_ref()),
+                    color: item.color
+                });
+            }
+            x += item.advance;
+            if item.character == ' ' {
+                x += justify;
+            }
+        }
+        y += descent + leading;
+    }
+
+    Ok(glyphs)
+}
+
+fn resolve_font<'a>(text: &EditText, format: &TextFormat, base: Option<&'a Font>, fonts: &'a HashMap<u16, Font>) -> LayoutFont<'a> {
+    let base_name = base.map(|f| f.name.clone()).or(text.font_class.clone());
+    let name = format.font.clone().or(base_name.clone()).unwrap_or(String::from_str("_sans"));
+
+    if text.flags.use_outlines {
+        if Some(&name) == base_name.as_ref() {
+            if let Some(font) = base {
+                if font.has_glyphs() {
+                    return LayoutFont::Embedded(font)
+                }
+            }
+        }
+
+        let mut found = None;
+        for font in fonts.values().filter(|f| f.has_glyphs() && f.name == name) {
+            if font.flags.bold == format.bold && font.flags.italic == format.italic {
+                return LayoutFont::Embedded(font)
+            }
+            found = found.or(Some(font));
+        }
+        if let Some(font) = found {
+            return LayoutFont::Embedded(font)
+        }
+    }
+
+    LayoutFont::Device(name, format.bold, format.italic)
+}
+
+fn embedded_metrics(font: &Font) -> FontMetrics {
+    let em = font.em_square();
+    match font.layout {
+        Some(ref layout) => FontMetrics {
+            em_square: em,
+            ascent: layout.ascent as i32,
+            descent: layout.descent as i32,
+            leading: layout.leading as i32
+        },
+        None => FontMetrics {
+            em_square: em,
+            ascent: em as i32 * 9 / 10,
+            descent: em as i32 / 5,
+            leading: 0
+        }
+    }
+}
+
+fn line_extent(x_min: f32, x_max: f32, format: &TextFormat) -> (f32, f32) {
+    let left = x_min + GUTTER + (format.left_margin.unwrap_or(0.0) + format.block_indent.unwrap_or(0.0)) * 20.0;
+    let right = x_max - GUTTER - format.right_margin.unwrap_or(0.0) * 20.0;
+    (left, if right > left { right } else { left })
+}
+
+fn glyph_matrix(x: f32, y: f32, scale: f32) -> Matrix {
+    Matrix {
+        scale_x: scale,
+        scale_y: scale,
+        rotate_skew0: 0.0,
+        rotate_skew1: 0.0,
+        translate_x: x as i32,
+        translate_y: y as i32
+    }
+}
+
+// Grid fitting only applies to text drawn with advanced anti-aliasing
+fn grid_fit(settings: &Option<CSMTextSettings>) -> GridFit {
+    match *settings {
+        Some(ref settings) if settings.use_flash_type => settings.grid_fit,
+        _ => GridFit::None
+    }
+}
+
+// Snaps a glyph to the pixel grid of the text's own coordinate space, or horizontally to thirds of a pixel
+// for sub-pixel fitting. A glyph's alignment zones, the first horizontal and the second vertical, are moved
+// and scaled so both of their edges land on the grid, otherwise only the origin is snapped
+fn fit_matrix(matrix: Matrix, grid: GridFit, zones: Option<&ZoneRecord>) -> Matrix {
+    let step_x = match grid {
+        GridFit::None => return matrix,
+        GridFit::Pixel => 20.0,
+        GridFit::SubPixel => 20.0 / 3.0
+    };
+    if matrix.rotate_skew0 != 0.0 || matrix.rotate_skew1 != 0.0 {
+        return matrix
+    }
+
+    let zone = |index: usize, used: bool| if used { zones.and_then(|z| z.zones.get(index)) } else { None };
+    let (scale_x, translate_x) = fit_axis(matrix.scale_x, matrix.translate_x, step_x, zone(0, zones.map(|z| z.mask_x).unwrap_or(false)));
+    let (scale_y, translate_y) = fit_axis(matrix.scale_y, matrix.translate_y, 20.0, zone(1, zones.map(|z| z.mask_y).unwrap_or(false)));
+    Matrix { scale_x: scale_x, scale_y: scale_y, translate_x: translate_x, translate_y: translate_y, .. matrix }
+}
+
+fn fit_axis(scale: f32, translate: i32, step: f32, zone: Option<&ZoneData>) -> (f32, i32) {
+    let snap = |v: f32| (v / step).round() * step;
+    let zone = match zone {
+        Some(zone) if zone.range != 0.0 => zone,
+        _ => return (scale, snap(translate as f32) as i32)
+    };
+
+    let low = translate as f32 + scale * zone.alignment;
+    let high = translate as f32 + scale * (zone.alignment + zone.range);
+    let (fit_low, mut fit_high) = (snap(low), snap(high));
+    if fit_high == fit_low {
+        fit_high = fit_low + if high < low { -step } else { step };
+    }
+
+    let scale = (fit_high - fit_low) / zone.range;
+    (scale, (fit_low - scale * zone.alignment).round() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use swf::TagKind;
+    use types::{ Rect, Rgba, Matrix };
+    use shape::Shape;
+    use util::font::{ Font, FontFlags, FontLayout, KerningRecord, LanguageCode };
+    use util::text::{ Text, TextRecord, GlyphEntry };
+    use util::edit_text::{ EditText, EditTextFlags, EditTextLayout };
+    use util::html::TextAlign;
+    use util::csm::{ CSMTextSettings, GridFit };
+    use super::{ LayoutGlyph, FontProviderDefault, layout_text, layout_edit_text };
+
+    // Glyphs for "ab " with advances of 500, 600 and 250 and a kerning pair for "ab"
+    fn fonts() -> HashMap<u16, Font> {
+        let flags = FontFlags { small_text: false, shift_jis: false, ansi: false, italic: false, bold: false, wide_codes: true,
+            utf8: true };
+        let layout = FontLayout { ascent: 800, descent: 200, leading: 24, advances: vec![500, 600, 250], bounds: Vec::new(),
+            kerning: vec![KerningRecord { left: 'a' as u16, right: 'b' as u16, adjustment: -100 }] };
+        let font = Font { kind: TagKind::DefineFont2, id: 1, name: String::from_str("Test"), copyright: String::new(),
+            flags: flags, language: LanguageCode::Latin, glyphs: vec![Shape::new(), Shape::new(), Shape::new()],
+            codes: vec!['a' as u16, 'b' as u16, ' ' as u16], layout: Some(layout), align_zones: None };
+
+        let mut fonts = HashMap::new();
+        fonts.insert(1, font);
+        fonts
+    }
+
+    fn edit_text(width: i32, html: bool, align: TextAlign, text: &str) -> EditText {
+        let flags = EditTextFlags { word_wrap: true, multiline: true, password: false, read_only: true, auto_size: false,
+            no_select: false, border: false, was_static: false, html: html, use_outlines: true };
+        let layout = EditTextLayout { align: align, left_margin: 0, right_margin: 0, indent: 0, leading: 0 };
+        EditText { id: 2, bounds: Rect { x_min: 0, x_max: width, y_min: 0, y_max: 2000 }, flags: flags, font_id: Some(1),
+            font_class: None, height: Some(512), color: None, max_length: None, layout: Some(layout),
+            variable_name: String::new(), initial_text: Some(String::from_str(text)), csm_settings: None }
+    }
+
+    fn positions(glyphs: &[LayoutGlyph]) -> Vec<(char, i32, i32)> {
+        glyphs.iter().map(|g| (g.character, g.matrix.translate_x, g.matrix.translate_y)).collect()
+    }
+
+    fn text() -> Text {
+        let record = TextRecord { font_id: Some(1), color: Some(Rgba { r: 0xff, g: 0, b: 0, a: 0xff }), x_offset: Some(25),
+            y_offset: Some(400), height: Some(512),
+            glyphs: vec![GlyphEntry { index: 0, advance: 250 }, GlyphEntry { index: 1, advance: 300 }] };
+        let next = TextRecord { font_id: None, color: None, x_offset: None, y_offset: Some(800), height: None,
+            glyphs: vec![GlyphEntry { index: 1, advance: 0 }] };
+        Text { kind: TagKind::DefineText2, id: 2, bounds: Rect { x_min: 0, x_max: 0, y_min: 0, y_max: 0 },
+            matrix: Matrix::translate(100, 0), records: vec![record, next], csm_settings: None }
+    }
+
+    #[test]
+    fn static_text() {
+        let glyphs = layout_text(&text(), &fonts()).unwrap();
+        assert_eq!(positions(&glyphs), vec![('a', 125, 400), ('b', 375, 400), ('b', 675, 800)]);
+        assert!(glyphs.iter().all(|g| g.matrix.scale_x == 0.5 && g.matrix.scale_y == 0.5));
+        assert_eq!(glyphs[2].color, Rgba { r: 0xff, g: 0, b: 0, a: 0xff });
+    }
+
+    #[test]
+    fn static_text_grid_fit() {
+        let mut text = text();
+        text.csm_settings = Some(CSMTextSettings { id: 2, use_flash_type: true, grid_fit: GridFit::Pixel, thickness: 0.0,
+            sharpness: 0.0 });
+        let glyphs = layout_text(&text, &fonts()).unwrap();
+        assert_eq!(positions(&glyphs), vec![('a', 120, 400), ('b', 380, 400), ('b', 680, 800)]);
+    }
+
+    #[test]
+    fn edit_text_lines() {
+        // Lines start inside the gutter, one ascent down, and advance by ascent, descent and the field's leading
+        let text = edit_text(4000, false, TextAlign::Left, "ab ab\rb");
+        let glyphs = layout_edit_text(&text, &fonts(), &FontProviderDefault).unwrap();
+        assert_eq!(positions(&glyphs), vec![('a', 40, 440), ('b', 290, 440), (' ', 590, 440), ('a', 715, 440), ('b', 965, 440),
+            ('b', 40, 940)]);
+    }
+
+    #[test]
+    fn edit_text_wrap_and_align() {
+        // The field is 600 twips wide inside its gutters, so the second word wraps, and kerning narrows each "ab"
+        let text = edit_text(680, true, TextAlign::Right, "<font kerning=\"1\">ab ab</font>");
+        let glyphs = layout_edit_text(&text, &fonts(), &FontProviderDefault).unwrap();
+        assert_eq!(positions(&glyphs), vec![('a', 140, 440), ('b', 340, 440), (' ', 640, 440), ('a', 140, 940), ('b', 340, 940)]);
+
+        let text = edit_text(680, false, TextAlign::Center, "ab");
+        let glyphs = layout_edit_text(&text, &fonts(), &FontProviderDefault).unwrap();
+        assert_eq!(positions(&glyphs), vec![('a', 65, 440), ('b', 315, 440)]);
+    }
+}
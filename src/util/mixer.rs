@@ -0,0 +1,248 @@
+use std::old_io::{ Reader, IoResult, IoError, InvalidInput, EndOfFile };
+use std::collections::HashMap;
+use std::iter::repeat;
+use util::sound_stream::{ SoundStream, SoundFormat, CodecKind };
+use util::sound_info::SoundInfo;
+use util::pcm::decode_pcm;
+
+// Stereo samples at the mixer's rate
+struct Sound {
+    samples: Vec<f32>,
+}
+
+// Event sounds are kept apart until rendering since a later SyncStop may cut them short
+struct Instance {
+    id: u16,
+    start: usize,
+    end: usize,
+    samples: Vec<f32>,
+}
+
+// Renders event and streaming sounds of a timeline into one stereo track
+pub struct Mixer {
+    rate: u32,
+    frame_rate: f32,
+    sounds: HashMap<u16, Sound>,
+    instances: Vec<Instance>,
+    output: Vec<f32>,
+}
+
+impl Mixer {
+    pub fn new(rate: u32, frame_rate: f32) -> Self {
+        Mixer {
+            rate: rate,
+            frame_rate: frame_rate,
+            sounds: HashMap::new(),
+            instances: Vec::new(),
+            output: Vec::new()
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn frame_sample(&self, frame: usize) -> usize {
+        (frame as f64 * self.rate as f64 / self.frame_rate as f64) as usize
+    }
+
+    // Registers a DefineSound for later StartSound tags, dropping its encoder delay
+    pub fn add_sound<R : Reader>(&mut self, sound: &mut SoundStream<R>) -> IoResult<()> {
+        let format = sound.format;
+        let mut data = Vec::new();
+        loop {
+            match sound.read_block() {
+                Ok(block) => data.push_all(&block),
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            }
+        }
+
+        let samples = try!(decode_pcm(&format, &data));
+        let skip = sound.latency() as usize * format.channels() as usize;
+        let samples = if skip < samples.len() { &samples[skip..] } else { &[][..] };
+        self.add_samples(sound.id, &format, samples);
+        Ok(())
+    }
+
+    pub fn add_samples(&mut self, id: u16, format: &SoundFormat, samples: &[i16]) {
+        let sound = Sound {
+            samples: self.resample(format, samples)
+        };
+        self.sounds.insert(id, sound);
+    }
+
+    // Plays a registered sound as a StartSound at `frame`, tags must be given in timeline order
+    pub fn start_sound(&mut self, frame: usize, id: u16, info: &SoundInfo) -> IoResult<()> {
+        let start = self.frame_sample(frame);
+        let playing = self.instances.iter().any(|i| i.id == id && i.start <= start && start < i.end);
+
+        if info.sync_stop {
+            for instance in self.instances.iter_mut().filter(|i| i.id == id && i.end > start) {
+                instance.end = start;
+            }
+            return Ok(())
+        }
+        if info.sync_no_multiple && playing {
+            return Ok(())
+        }
+
+        let rendered = {
+            let sound = try!(self.sounds.get(&id).ok_or(
+                IoError { kind: InvalidInput, desc: "StartSound refers to unknown sound", detail: None }
+            ));
+            self.render_instance(sound, info)
+        };
+
+        self.instances.push(Instance {
+            id: id,
+            start: start,
+            end: start + rendered.len() / 2,
+            samples: rendered
+        });
+        Ok(())
+    }
+
+    // Mixes a streaming sound whose head is at `frame` of the timeline
+    pub fn add_stream<R : Reader>(&mut self, frame: usize, stream: &mut SoundStream<R>) -> IoResult<()> {
+        let format = stream.format;
+        let mut samples = Vec::new();
+        let mut data = Vec::new();
+        loop {
+            match stream.read_block() {
+                // ADPCM restarts with every block, while MP3 frames may use the bit reservoir of earlier
+                // blocks and Nellymoser blocks overlap
+                Ok(block) => if format.codec() == CodecKind::ADPCM {
+                    samples.extend(try!(decode_pcm(&format, &block)).into_iter());
+                } else {
+                    data.push_all(&block);
+                },
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            }
+        }
+        if !data.is_empty() {
+            samples = try!(decode_pcm(&format, &data));
+        }
+
+        let skip = stream.latency() as usize * format.channels() as usize;
+        let samples = if skip < samples.len() { &samples[skip..] } else { &[][..] };
+        let rendered = self.resample(&format, samples);
+        let start = self.frame_sample(frame + stream.start_frame);
+        mix(&mut self.output, start, &rendered);
+        Ok(())
+    }
+
+    // Interleaved stereo 16-bit output
+    pub fn render(&self) -> Vec<i16> {
+        let mut output = self.output.clone();
+        for instance in self.instances.iter() {
+            mix(&mut output, instance.start, &instance.samples[..(instance.end - instance.start) * 2]);
+        }
+
+        output.iter().map(|&v| {
+            let v = v * 32768.0;
+            if v > 32767.0 { 32767 } else if v < -32768.0 { -32768 } else { v as i16 }
+        }).collect()
+    }
+
+    // Converts to stereo at the mixer's rate with linear interpolation
+    fn resample(&self, format: &SoundFormat, samples: &[i16]) -> Vec<f32> {
+        let channels = format.channels() as usize;
+        let frames = samples.len() / channels;
+        if frames == 0 {
+            return Vec::new()
+        }
+
+        let sample = |frame: usize, channel: usize| {
+            samples[frame * channels + if channel < channels { channel } else { 0 }] as f32 / 32768.0
+        };
+
+        let step = format.sample_rate() as f64 / self.rate as f64;
+        let length = (frames as f64 / step) as usize;
+        let mut output = Vec::with_capacity(length * 2);
+        for i in 0..length {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let t = (position - index as f64) as f32;
+            let next = if index + 1 < frames { index + 1 } else { index };
+            for channel in 0..2 {
+                let (a, b) = (sample(index, channel), sample(next, channel));
+                output.push(a + (b - a) * t);
+            }
+        }
+        output
+    }
+
+    // Applies the in and out points, loops and envelope of a SOUNDINFO
+    fn render_instance(&self, sound: &Sound, info: &SoundInfo) -> Vec<f32> {
+        let frames = sound.samples.len() / 2;
+        let to_output = |position: u32| (position as f64 * self.rate as f64 / 44100.0) as usize;
+        let start = info.in_point.map(|p| to_output(p)).unwrap_or(0);
+        let end = info.out_point.map(|p| to_output(p)).unwrap_or(frames);
+        let end = if end > frames { frames } else { end };
+        if start >= end {
+            return Vec::new()
+        }
+
+        let part = &sound.samples[start * 2..end * 2];
+        let mut output = Vec::with_capacity(part.len() * info.loop_count() as usize);
+        for _ in 0..info.loop_count() {
+            output.push_all(part);
+        }
+
+        if info.envelope.is_some() {
+            for i in 0..output.len() / 2 {
+                let (left, right) = info.envelope_level((i as f64 * 44100.0 / self.rate as f64) as u32);
+                output[i * 2] *= left;
+                output[i * 2 + 1] *= right;
+            }
+        }
+
+        output
+    }
+}
+
+fn mix(output: &mut Vec<f32>, start: usize, samples: &[f32]) {
+    let end = start * 2 + samples.len();
+    if output.len() < end {
+        let extra = end - output.len();
+        output.extend(repeat(0.0).take(extra));
+    }
+    for (out, &sample) in output[start * 2..end].iter_mut().zip(samples.iter()) {
+        *out += sample;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mixer;
+    use util::sound_info::{ SoundInfo, EnvelopePoint };
+    use util::sound_stream::{ SoundFormat, CodecKind };
+
+    #[test]
+    fn start_sound_envelope() {
+        let format = SoundFormat::from_parts(CodecKind::Uncompressed, 44100, true, false).unwrap();
+        let mut mixer = Mixer::new(44100, 10.0);
+        mixer.add_samples(1, &format, &[16384; 6]);
+
+        // Skips the first sample, plays the rest twice and pans from left to right over four samples
+        let info = SoundInfo { sync_stop: false, sync_no_multiple: false, in_point: Some(1), out_point: None, loops: Some(2),
+            envelope: Some(vec![
+                EnvelopePoint { position: 0, left: 32768, right: 0 },
+                EnvelopePoint { position: 4, left: 0, right: 32768 }
+            ]) };
+        mixer.start_sound(0, 1, &info).unwrap();
+        assert_eq!(mixer.render(), vec![
+            16384, 0, 12288, 4096, 8192, 8192, 4096, 12288, 0, 16384,
+            0, 16384, 0, 16384, 0, 16384, 0, 16384, 0, 16384
+        ]);
+
+        // A sync stop cuts the sound short at its frame, 4410 samples per frame at 10 fps
+        let stop = SoundInfo { sync_stop: true, envelope: None, .. info.clone() };
+        mixer.start_sound(1, 1, &info).unwrap();
+        mixer.start_sound(1, 1, &stop).unwrap();
+        assert_eq!(mixer.render().len(), 4410 * 2);
+        assert!(mixer.start_sound(2, 9, &info).is_err());
+    }
+}
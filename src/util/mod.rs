@@ -1,3 +1,53 @@
 mod sound_stream;
+mod pcm;
+mod adpcm;
+mod nellymoser;
+mod wav;
+mod mp3;
+#[cfg(feature = "mp3")]
+mod mp3_decoder;
+mod sound_info;
+mod mixer;
+mod sound_import;
+mod bitmap;
+mod font;
+mod truetype;
+mod text;
+mod html;
+mod edit_text;
+mod csm;
+mod layout;
+mod video;
+mod h263;
+mod screen_video;
+mod vp6;
+mod display_list;
+mod timeline;
+mod frame_label;
 
-pub use self::sound_stream::SoundStream;
+pub use self::sound_stream::{ SoundStream, StreamSound, SoundFormat, SoundBlock, CodecKind };
+pub use self::pcm::decode_pcm;
+pub use self::adpcm::{ AdpcmDecoder, AdpcmEncoder };
+pub use self::nellymoser::NellymoserDecoder;
+pub use self::wav::{ write_wav, write_mp3 };
+pub use self::mp3::{ Mp3Frame, MpegVersion, split_frames };
+#[cfg(feature = "mp3")]
+pub use self::mp3_decoder::Mp3Decoder;
+pub use self::bitmap::{ BitmapLossless, BitmapJPEG, bitmap_id };
+pub use self::font::{ Font, Font4, FontFlags, FontLayout, FontInfo, FontName, KerningRecord, LanguageCode };
+pub use self::truetype::{ TrueTypeWarning, write_truetype };
+pub use self::text::{ Text, TextRecord, TextRun, GlyphEntry };
+pub use self::html::{ TextAlign, TextFormat, TextSpan, parse_html, strip_html };
+pub use self::edit_text::{ EditText, EditTextFlags, EditTextLayout };
+pub use self::csm::{ CSMTextSettings, CSMTableHint, GridFit, FontAlignZones, ZoneRecord, ZoneData };
+pub use self::layout::{ LayoutGlyph, FontMetrics, DeviceGlyph, FontProvider, FontProviderDefault, layout_text, layout_edit_text };
+pub use self::sound_info::{ SoundInfo, EnvelopePoint, StartSound, ButtonSound };
+pub use self::mixer::Mixer;
+pub use self::sound_import::{ SoundImport, SoundReplacer };
+pub use self::video::{ VideoStream, VideoFrame, VideoCodec, YuvFrame, write_flv };
+pub use self::h263::H263Decoder;
+pub use self::screen_video::ScreenVideoDecoder;
+pub use self::vp6::{ Vp6Header, Vp6Decoder, split_vp6_alpha };
+pub use self::display_list::{ DisplayList, DisplayObject, PlaceObject, RemoveObject, Filter, BlendMode };
+pub use self::timeline::{ Timeline, Frame, ControlTag, MovieClip };
+pub use self::frame_label::{ FrameLabel, Scene, SceneAndFrameLabelData, Label, LabelTable, split_frame_target };
@@ -0,0 +1,109 @@
+const BITRATES_MPEG1: [u16; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const BITRATES_MPEG2: [u16; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+// A layer III frame within a block of sound data
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mp3Frame {
+    pub version: MpegVersion,
+    pub bitrate: u16,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channels: u16,
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Mp3Frame {
+    pub fn parse(data: &[u8], offset: usize) -> Option<Self> {
+        if data.len() < offset + 4 {
+            return None
+        }
+        let header = &data[offset..offset + 4];
+        if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+            return None
+        }
+
+        let version = match (header[1] >> 3) & 0x03 {
+            0 => MpegVersion::Mpeg25,
+            2 => MpegVersion::Mpeg2,
+            3 => MpegVersion::Mpeg1,
+            _ => return None
+        };
+        if (header[1] >> 1) & 0x03 != 1 {
+            return None // not layer III
+        }
+
+        let bitrate = match header[2] >> 4 {
+            0 | 15 => return None, // free format and invalid bitrates
+            index if version == MpegVersion::Mpeg1 => BITRATES_MPEG1[index as usize],
+            index => BITRATES_MPEG2[index as usize]
+        };
+        let sample_rate = match (header[2] >> 2) & 0x03 {
+            3 => return None,
+            index => SAMPLE_RATES[index as usize] >> match version {
+                MpegVersion::Mpeg1 => 0,
+                MpegVersion::Mpeg2 => 1,
+                MpegVersion::Mpeg25 => 2
+            }
+        };
+        let padding = header[2] & 0x02 != 0;
+
+        let frame = Mp3Frame {
+            version: version,
+            bitrate: bitrate,
+            sample_rate: sample_rate,
+            padding: padding,
+            channels: if header[3] >> 6 == 3 { 1 } else { 2 },
+            offset: offset,
+            length: 0
+        };
+        let length = frame.samples() as usize / 8 * bitrate as usize * 1000 / sample_rate as usize + if padding { 1 } else { 0 };
+
+        Some(Mp3Frame { length: length, ..frame })
+    }
+
+    pub fn samples(&self) -> u32 {
+        match self.version {
+            MpegVersion::Mpeg1 => 1152,
+            _ => 576
+        }
+    }
+
+    pub fn data<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.offset..self.offset + self.length]
+    }
+}
+
+// Splits MP3 data into frames, skipping a leading ID3v2 tag and a trailing ID3v1 tag. Like the Flash player
+// it stops at the first invalid header or truncated frame and keeps the frames before it
+pub fn split_frames(data: &[u8]) -> Vec<Mp3Frame> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    let mut end = data.len();
+
+    if data.len() >= 10 && &data[..3] == &b"ID3"[..] {
+        let size = data[6..10].iter().fold(0, |size, &b| size << 7 | (b & 0x7f) as usize);
+        offset = 10 + size + if data[5] & 0x10 != 0 { 10 } else { 0 };
+    }
+    if end >= offset + 128 && &data[end - 128..end - 125] == &b"TAG"[..] {
+        end -= 128;
+    }
+
+    while let Some(frame) = Mp3Frame::parse(&data[..end], offset) {
+        if offset + frame.length > end {
+            break
+        }
+        offset += frame.length;
+        frames.push(frame);
+    }
+
+    frames
+}
@@ -0,0 +1,1420 @@
+use std::old_io::{ IoResult, IoError, InvalidInput };
+use std::cmp::{ min, max };
+use std::iter::repeat;
+use util::sound_stream::{ SoundFormat, CodecKind };
+use util::mp3::split_frames;
+
+// MPEG-1, 2 and 2.5 layer III decoding, ported from minimp3 by Lion (lieff), released to the public domain
+
+const MAX_RESERVOIR_BYTES: usize = 511;
+const SHORT_BLOCK_TYPE: u8 = 2;
+const STOP_BLOCK_TYPE: u8 = 3;
+const BITS_DEQUANTIZER_OUT: i32 = -1;
+const MAX_SCF: i32 = 255 + BITS_DEQUANTIZER_OUT * 4 - 210;
+const MAX_SCFI: i32 = (MAX_SCF + 3) & !3;
+
+// The bit reservoir spans frames, so one decoder must be used for a whole sound
+pub struct Mp3Decoder {
+    sample_rate: u32,
+    channels: u16,
+    mdct_overlap: Vec<f32>,
+    qmf_state: Vec<f32>,
+    reservoir: Vec<u8>,
+    header: Option<[u8; 4]>,
+}
+
+#[derive(Copy, Clone)]
+struct GranuleInfo {
+    sfbtab: &'static [u8],
+    part_23_length: u16,
+    big_values: u16,
+    scalefac_compress: u16,
+    global_gain: u8,
+    block_type: u8,
+    mixed_block_flag: u8,
+    n_long_sfb: u8,
+    n_short_sfb: u8,
+    table_select: [u8; 3],
+    region_count: [u8; 3],
+    subblock_gain: [u8; 3],
+    preflag: u8,
+    scalefac_scale: u8,
+    count1_table: u8,
+    scfsi: u8,
+}
+
+struct BitStream<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    limit: usize,
+}
+
+// Bit cache used by the Huffman decoder, reads past the end of the buffer return zeros
+struct BitCache<'a> {
+    buf: &'a [u8],
+    next: usize,
+    cache: u32,
+    shift: i32,
+}
+
+impl Mp3Decoder {
+    pub fn new(format: &SoundFormat) -> IoResult<Self> {
+        if format.codec() != CodecKind::MP3 {
+            return Err(IoError { kind: InvalidInput, desc: "MP3 sound expected", detail: None })
+        }
+
+        Ok(Mp3Decoder {
+            sample_rate: format.sample_rate(),
+            channels: format.channels(),
+            mdct_overlap: repeat(0.0).take(2 * 9 * 32).collect(),
+            qmf_state: repeat(0.0).take(15 * 64).collect(),
+            reservoir: Vec::new(),
+            header: None
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Forgets the bit reservoir and filter state, e.g. after seeking
+    pub fn reset(&mut self) {
+        for v in self.mdct_overlap.iter_mut().chain(self.qmf_state.iter_mut()) {
+            *v = 0.0;
+        }
+        self.reservoir.clear();
+        self.header = None;
+    }
+
+    // Decodes whole frames, such as a DefineSound body or successive SoundStreamBlocks,
+    // to interleaved samples with the channel count of the sound format
+    pub fn decode(&mut self, data: &[u8]) -> IoResult<Vec<i16>> {
+        let mut samples = Vec::new();
+        for frame in split_frames(data).iter() {
+            if frame.sample_rate != self.sample_rate {
+                return Err(IoError { kind: InvalidInput, desc: "MP3 frame sample rate does not match the sound format", detail: None })
+            }
+
+            let pcm = self.decode_frame(frame.data(data));
+            if pcm.is_empty() {
+                // Keep the timing of stream blocks and seek positions
+                samples.extend(repeat(0).take(frame.samples() as usize * self.channels as usize));
+                continue
+            }
+
+            match (frame.channels, self.channels) {
+                (1, 2) => for &sample in pcm.iter() {
+                    samples.push(sample);
+                    samples.push(sample);
+                },
+                (2, 1) => for pair in pcm.chunks(2) {
+                    samples.push(((pair[0] as i32 + pair[1] as i32) / 2) as i16);
+                },
+                _ => samples.push_all(&pcm)
+            }
+        }
+        Ok(samples)
+    }
+
+    // Decodes one complete frame to interleaved samples, frames that depend on
+    // bit reservoir data that was never seen produce no samples
+    fn decode_frame(&mut self, frame: &[u8]) -> Vec<i16> {
+        if frame.len() < 4 || !header_valid(frame) {
+            return Vec::new()
+        }
+        let hdr = [frame[0], frame[1], frame[2], frame[3]];
+
+        let compatible = match self.header {
+            Some(ref previous) => header_compare(previous, &hdr),
+            None => true
+        };
+        if !compatible {
+            self.reset();
+        }
+        self.header = Some(hdr);
+        let hdr = &hdr[..];
+
+        let channels = if is_mono(hdr) { 1 } else { 2 };
+        let mut bs = BitStream::new(&frame[4..]);
+        if is_crc(hdr) {
+            bs.get_bits(16);
+        }
+
+        let mut gr_info = [GranuleInfo::new(); 4];
+        let main_data_begin = read_side_info(&mut bs, &mut gr_info, hdr);
+        if main_data_begin < 0 || bs.pos > bs.limit {
+            self.reset();
+            return Vec::new()
+        }
+        let main_data_begin = main_data_begin as usize;
+
+        // Prepend the bytes of previous frames the side info refers to
+        let have = min(self.reservoir.len(), main_data_begin);
+        let success = self.reservoir.len() >= main_data_begin;
+        let mut main_data = Vec::with_capacity(have + bs.buf.len());
+        main_data.push_all(&self.reservoir[self.reservoir.len() - have..]);
+        main_data.push_all(&bs.buf[bs.pos / 8..bs.limit / 8]);
+
+        let granules = if test_mpeg1(hdr) { 2 } else { 1 };
+        let mut pcm = Vec::new();
+        let mut main_bs = BitStream::new(&main_data);
+        if success {
+            pcm = repeat(0).take(576 * granules * channels).collect();
+            let mut grbuf: Vec<f32> = repeat(0.0).take(576 * 2).collect();
+            let mut syn: Vec<f32> = repeat(0.0).take(33 * 64).collect();
+            let mut scf = [0.0f32; 40];
+            let mut ist_pos = [[0u8; 39]; 2];
+
+            for granule in 0..granules {
+                for v in grbuf.iter_mut() {
+                    *v = 0.0;
+                }
+                let gr = &gr_info[granule * channels..granule * channels + channels];
+                self.decode_granule(hdr, &mut main_bs, gr, &mut grbuf, &mut scf, &mut ist_pos, &mut syn);
+                synth_granule(&mut self.qmf_state, &mut grbuf, 18, channels, &mut pcm, granule * 576 * channels, &mut syn);
+            }
+        }
+
+        // Keep the unused tail of the main data for the next frame
+        let pos = (main_bs.pos + 7) / 8;
+        let end = main_bs.limit / 8;
+        let pos = if end > pos + MAX_RESERVOIR_BYTES { end - MAX_RESERVOIR_BYTES } else { pos };
+        self.reservoir = if pos < end { main_data[pos..end].to_vec() } else { Vec::new() };
+
+        pcm
+    }
+
+    fn decode_granule(&mut self, hdr: &[u8], bs: &mut BitStream, gr: &[GranuleInfo], grbuf: &mut [f32],
+        scf: &mut [f32; 40], ist_pos: &mut [[u8; 39]; 2], syn: &mut [f32])
+    {
+        let channels = gr.len();
+        for ch in 0..channels {
+            let limit = bs.pos + gr[ch].part_23_length as usize;
+            decode_scalefactors(hdr, &mut ist_pos[ch], bs, &gr[ch], scf, ch);
+            huffman(&mut grbuf[576 * ch..576 * ch + 576], bs, &gr[ch], scf, limit);
+        }
+
+        if test_i_stereo(hdr) {
+            intensity_stereo(grbuf, &mut ist_pos[1], gr, hdr);
+        } else if is_ms_stereo(hdr) {
+            midside_stereo(grbuf, 0, 576);
+        }
+
+        for ch in 0..channels {
+            let info = &gr[ch];
+            let grbuf = &mut grbuf[576 * ch..576 * ch + 576];
+            let mut aa_bands = 31;
+            let n_long_bands = (if info.mixed_block_flag != 0 { 2 } else { 0 }) << if my_sample_rate(hdr) == 2 { 1 } else { 0 };
+
+            if info.n_short_sfb != 0 {
+                aa_bands = n_long_bands as i32 - 1;
+                reorder(grbuf, n_long_bands * 18, syn, &info.sfbtab[info.n_long_sfb as usize..]);
+            }
+
+            antialias(grbuf, aa_bands);
+            imdct_granule(grbuf, &mut self.mdct_overlap[288 * ch..288 * ch + 288], info.block_type, n_long_bands);
+            change_sign(grbuf);
+        }
+    }
+}
+
+impl GranuleInfo {
+    fn new() -> Self {
+        GranuleInfo {
+            sfbtab: &SCF_LONG[0],
+            part_23_length: 0,
+            big_values: 0,
+            scalefac_compress: 0,
+            global_gain: 0,
+            block_type: 0,
+            mixed_block_flag: 0,
+            n_long_sfb: 0,
+            n_short_sfb: 0,
+            table_select: [0; 3],
+            region_count: [0; 3],
+            subblock_gain: [0; 3],
+            preflag: 0,
+            scalefac_scale: 0,
+            count1_table: 0,
+            scfsi: 0
+        }
+    }
+}
+
+impl<'a> BitStream<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        BitStream {
+            buf: buf,
+            pos: 0,
+            limit: buf.len() * 8
+        }
+    }
+
+    fn byte(&self, index: usize) -> u32 {
+        if index < self.buf.len() { self.buf[index] as u32 } else { 0 }
+    }
+
+    fn get_bits(&mut self, n: u32) -> u32 {
+        let s = self.pos & 7;
+        let mut shl = n as i32 + s as i32;
+        let mut p = self.pos >> 3;
+        self.pos += n as usize;
+        if self.pos > self.limit {
+            return 0
+        }
+
+        let mut next = self.byte(p) & (255 >> s);
+        p += 1;
+        let mut cache = 0;
+        loop {
+            shl -= 8;
+            if shl <= 0 {
+                break
+            }
+            cache |= next << shl as u32;
+            next = self.byte(p);
+            p += 1;
+        }
+        cache | (next >> (-shl) as u32)
+    }
+}
+
+impl<'a> BitCache<'a> {
+    fn new(bs: &BitStream<'a>) -> Self {
+        let p = bs.pos / 8;
+        let cache = ((bs.byte(p) * 256 + bs.byte(p + 1)) * 256 + bs.byte(p + 2)) * 256 + bs.byte(p + 3);
+        BitCache {
+            buf: bs.buf,
+            next: p + 4,
+            cache: cache << (bs.pos & 7) as u32,
+            shift: (bs.pos & 7) as i32 - 8
+        }
+    }
+
+    fn peek(&self, n: u32) -> u32 {
+        self.cache >> (32 - n)
+    }
+
+    fn flush(&mut self, n: u32) {
+        self.cache <<= n;
+        self.shift += n as i32;
+    }
+
+    fn check(&mut self) {
+        while self.shift >= 0 {
+            let byte = if self.next < self.buf.len() { self.buf[self.next] as u32 } else { 0 };
+            self.cache |= byte << self.shift as u32;
+            self.next += 1;
+            self.shift -= 8;
+        }
+    }
+
+    fn sign(&self) -> bool {
+        (self.cache as i32) < 0
+    }
+
+    fn position(&self) -> i32 {
+        self.next as i32 * 8 - 24 + self.shift
+    }
+}
+
+fn header_valid(h: &[u8]) -> bool {
+    h[0] == 0xff &&
+        ((h[1] & 0xf0) == 0xf0 || (h[1] & 0xfe) == 0xe2) &&
+        layer(h) != 0 &&
+        (h[2] >> 4) != 15 &&
+        ((h[2] >> 2) & 3) != 3
+}
+
+fn header_compare(h1: &[u8], h2: &[u8]) -> bool {
+    header_valid(h2) &&
+        ((h1[1] ^ h2[1]) & 0xfe) == 0 &&
+        ((h1[2] ^ h2[2]) & 0x0c) == 0 &&
+        ((h1[2] & 0xf0) == 0) == ((h2[2] & 0xf0) == 0)
+}
+
+fn layer(h: &[u8]) -> u8 { (h[1] >> 1) & 3 }
+fn is_mono(h: &[u8]) -> bool { (h[3] & 0xc0) == 0xc0 }
+fn is_ms_stereo(h: &[u8]) -> bool { (h[3] & 0xe0) == 0x60 }
+fn is_crc(h: &[u8]) -> bool { (h[1] & 1) == 0 }
+fn test_mpeg1(h: &[u8]) -> bool { (h[1] & 0x08) != 0 }
+fn test_i_stereo(h: &[u8]) -> bool { (h[3] & 0x10) != 0 }
+fn test_ms_stereo(h: &[u8]) -> bool { (h[3] & 0x20) != 0 }
+fn my_sample_rate(h: &[u8]) -> usize {
+    ((h[2] >> 2) & 3) as usize + (((h[1] >> 3) & 1) + ((h[1] >> 4) & 1)) as usize * 3
+}
+
+fn read_side_info(bs: &mut BitStream, gr: &mut [GranuleInfo; 4], hdr: &[u8]) -> i32 {
+    let mut scfsi = 0u32;
+    let mut part_23_sum = 0usize;
+    let mut sr_idx = my_sample_rate(hdr);
+    if sr_idx != 0 {
+        sr_idx -= 1;
+    }
+    let mut gr_count = if is_mono(hdr) { 1 } else { 2 };
+
+    let main_data_begin = if test_mpeg1(hdr) {
+        gr_count *= 2;
+        let main_data_begin = bs.get_bits(9);
+        scfsi = bs.get_bits(7 + gr_count);
+        main_data_begin
+    } else {
+        bs.get_bits(8 + gr_count) >> gr_count
+    };
+
+    for gr in gr[..gr_count as usize].iter_mut() {
+        if is_mono(hdr) {
+            scfsi <<= 4;
+        }
+        gr.part_23_length = bs.get_bits(12) as u16;
+        part_23_sum += gr.part_23_length as usize;
+        gr.big_values = bs.get_bits(9) as u16;
+        if gr.big_values > 288 {
+            return -1
+        }
+        gr.global_gain = bs.get_bits(8) as u8;
+        gr.scalefac_compress = bs.get_bits(if test_mpeg1(hdr) { 4 } else { 9 }) as u16;
+        gr.sfbtab = &SCF_LONG[sr_idx];
+        gr.n_long_sfb = 22;
+        gr.n_short_sfb = 0;
+
+        let tables;
+        if bs.get_bits(1) != 0 {
+            gr.block_type = bs.get_bits(2) as u8;
+            if gr.block_type == 0 {
+                return -1
+            }
+            gr.mixed_block_flag = bs.get_bits(1) as u8;
+            gr.region_count[0] = 7;
+            gr.region_count[1] = 255;
+            if gr.block_type == SHORT_BLOCK_TYPE {
+                scfsi &= 0x0f0f;
+                if gr.mixed_block_flag == 0 {
+                    gr.region_count[0] = 8;
+                    gr.sfbtab = &SCF_SHORT[sr_idx];
+                    gr.n_long_sfb = 0;
+                    gr.n_short_sfb = 39;
+                } else {
+                    gr.sfbtab = &SCF_MIXED[sr_idx];
+                    gr.n_long_sfb = if test_mpeg1(hdr) { 8 } else { 6 };
+                    gr.n_short_sfb = 30;
+                }
+            }
+            tables = bs.get_bits(10) << 5;
+            gr.subblock_gain[0] = bs.get_bits(3) as u8;
+            gr.subblock_gain[1] = bs.get_bits(3) as u8;
+            gr.subblock_gain[2] = bs.get_bits(3) as u8;
+        } else {
+            gr.block_type = 0;
+            gr.mixed_block_flag = 0;
+            tables = bs.get_bits(15);
+            gr.region_count[0] = bs.get_bits(4) as u8;
+            gr.region_count[1] = bs.get_bits(3) as u8;
+            gr.region_count[2] = 255;
+        }
+        gr.table_select[0] = (tables >> 10) as u8;
+        gr.table_select[1] = ((tables >> 5) & 31) as u8;
+        gr.table_select[2] = (tables & 31) as u8;
+        gr.preflag = if test_mpeg1(hdr) {
+            bs.get_bits(1) as u8
+        } else {
+            if gr.scalefac_compress >= 500 { 1 } else { 0 }
+        };
+        gr.scalefac_scale = bs.get_bits(1) as u8;
+        gr.count1_table = bs.get_bits(1) as u8;
+        gr.scfsi = ((scfsi >> 12) & 15) as u8;
+        scfsi <<= 4;
+    }
+
+    if part_23_sum + bs.pos > bs.limit + main_data_begin as usize * 8 {
+        return -1
+    }
+
+    main_data_begin as i32
+}
+
+fn read_scalefactors(scf: &mut [u8], ist_pos: &mut [u8], scf_size: &[u8], scf_count: &[u8], bs: &mut BitStream, scfsi: i32) {
+    let mut scfsi = scfsi;
+    let mut offset = 0;
+    let mut i = 0;
+    while i < 4 && scf_count[i] != 0 {
+        let count = scf_count[i] as usize;
+        if scfsi & 8 != 0 {
+            for k in 0..count {
+                scf[offset + k] = ist_pos[offset + k];
+            }
+        } else {
+            let bits = scf_size[i] as u32;
+            if bits == 0 {
+                for k in 0..count {
+                    scf[offset + k] = 0;
+                    ist_pos[offset + k] = 0;
+                }
+            } else {
+                let max_scf = if scfsi < 0 { (1 << bits) - 1 } else { -1 };
+                for k in 0..count {
+                    let s = bs.get_bits(bits) as i32;
+                    ist_pos[offset + k] = if s == max_scf { 0xff } else { s as u8 };
+                    scf[offset + k] = s as u8;
+                }
+            }
+        }
+        offset += count;
+        i += 1;
+        scfsi *= 2;
+    }
+    scf[offset] = 0;
+    scf[offset + 1] = 0;
+    scf[offset + 2] = 0;
+}
+
+fn ldexp_q2(y: f32, exp_q2: i32) -> f32 {
+    let mut y = y;
+    let mut exp_q2 = exp_q2;
+    loop {
+        let e = min(30 * 4, exp_q2);
+        y *= EXPFRAC[(e & 3) as usize] * ((1 << 30 >> (e >> 2)) as f32);
+        exp_q2 -= e;
+        if exp_q2 <= 0 {
+            return y
+        }
+    }
+}
+
+fn decode_scalefactors(hdr: &[u8], ist_pos: &mut [u8], bs: &mut BitStream, gr: &GranuleInfo, scf: &mut [f32], ch: usize) {
+    let row = (if gr.n_short_sfb != 0 { 1 } else { 0 }) + (if gr.n_long_sfb == 0 { 1 } else { 0 });
+    let mut scf_partition = &SCF_PARTITIONS[row][..];
+    let mut scf_size = [0u8; 4];
+    let mut iscf = [0u8; 40];
+    let scf_shift = gr.scalefac_scale as u32 + 1;
+    let mut scfsi = gr.scfsi as i32;
+
+    if test_mpeg1(hdr) {
+        let part = SCFC_DECODE[gr.scalefac_compress as usize];
+        scf_size[0] = part >> 2;
+        scf_size[1] = part >> 2;
+        scf_size[2] = part & 3;
+        scf_size[3] = part & 3;
+    } else {
+        let ist = if test_i_stereo(hdr) && ch != 0 { 1 } else { 0 };
+        let mut sfc = (gr.scalefac_compress >> ist) as i32;
+        let mut k = ist * 3 * 4;
+        while sfc >= 0 {
+            let mut modprod = 1;
+            for i in (0..4).rev() {
+                scf_size[i] = (sfc / modprod % SCF_MOD[k + i]) as u8;
+                modprod *= SCF_MOD[k + i];
+            }
+            sfc -= modprod;
+            k += 4;
+        }
+        scf_partition = &scf_partition[k..];
+        scfsi = -16;
+    }
+    read_scalefactors(&mut iscf, ist_pos, &scf_size, scf_partition, bs, scfsi);
+
+    let n_long = gr.n_long_sfb as usize;
+    if gr.n_short_sfb != 0 {
+        let sh = 3 - scf_shift;
+        let mut i = 0;
+        while i < gr.n_short_sfb as usize {
+            iscf[n_long + i] += gr.subblock_gain[0] << sh;
+            iscf[n_long + i + 1] += gr.subblock_gain[1] << sh;
+            iscf[n_long + i + 2] += gr.subblock_gain[2] << sh;
+            i += 3;
+        }
+    } else if gr.preflag != 0 {
+        for i in 0..10 {
+            iscf[11 + i] += PREAMP[i];
+        }
+    }
+
+    let gain_exp = gr.global_gain as i32 + BITS_DEQUANTIZER_OUT * 4 - 210 - if is_ms_stereo(hdr) { 2 } else { 0 };
+    let gain = ldexp_q2((1 << (MAX_SCFI / 4)) as f32, MAX_SCFI - gain_exp);
+    for i in 0..n_long + gr.n_short_sfb as usize {
+        scf[i] = ldexp_q2(gain, (iscf[i] as i32) << scf_shift);
+    }
+}
+
+fn pow_43(x: i32) -> f32 {
+    if x < 129 {
+        return POW43[(16 + x) as usize]
+    }
+
+    let mut x = x;
+    let mut mult = 256.0;
+    if x < 1024 {
+        mult = 16.0;
+        x <<= 3;
+    }
+
+    let sign = 2 * x & 64;
+    let frac = ((x & 63) - sign) as f32 / ((x & !63) + sign) as f32;
+    POW43[(16 + ((x + sign) >> 6)) as usize] * (1.0 + frac * ((4.0 / 3.0) + frac * (2.0 / 9.0))) * mult
+}
+
+fn huffman(dst: &mut [f32], bs: &mut BitStream, gr: &GranuleInfo, scf: &[f32], limit: usize) {
+    let mut one = 0.0;
+    let mut ireg = 0;
+    let mut big_val_cnt = gr.big_values as i32;
+    let mut sfb = 0;
+    let mut scf_index = 0;
+    let mut d = 0;
+    let mut bits = BitCache::new(bs);
+
+    while big_val_cnt > 0 {
+        let tab_num = gr.table_select[ireg] as usize;
+        let mut sfb_cnt = gr.region_count[ireg] as i32;
+        ireg += 1;
+        let codebook = &HUFFMAN_TABLES[HUFFMAN_INDEX[tab_num] as usize..];
+        let linbits = LINBITS[tab_num] as u32;
+
+        loop {
+            let np = (gr.sfbtab[sfb] / 2) as i32;
+            sfb += 1;
+            let mut pairs = min(big_val_cnt, np);
+            one = scf[scf_index];
+            scf_index += 1;
+
+            while pairs > 0 {
+                let mut w = 5;
+                let mut leaf = codebook[bits.peek(w) as usize] as i32;
+                while leaf < 0 {
+                    bits.flush(w);
+                    w = (leaf & 7) as u32;
+                    leaf = codebook[(bits.peek(w) as i32 - (leaf >> 3)) as usize] as i32;
+                }
+                bits.flush((leaf >> 8) as u32);
+
+                for _ in 0..2 {
+                    let mut lsb = leaf & 0x0f;
+                    if linbits != 0 && lsb == 15 {
+                        lsb += bits.peek(linbits) as i32;
+                        bits.flush(linbits);
+                        bits.check();
+                        dst[d] = one * pow_43(lsb) * if bits.sign() { -1.0 } else { 1.0 };
+                    } else {
+                        dst[d] = POW43[(16 + lsb - if bits.sign() { 16 } else { 0 }) as usize] * one;
+                    }
+                    bits.flush(if lsb != 0 { 1 } else { 0 });
+                    d += 1;
+                    leaf >>= 4;
+                }
+                bits.check();
+                pairs -= 1;
+            }
+
+            big_val_cnt -= np;
+            sfb_cnt -= 1;
+            if big_val_cnt <= 0 || sfb_cnt < 0 {
+                break
+            }
+        }
+    }
+
+    let codebook_count1 = if gr.count1_table != 0 { &COUNT1_TABLE_B[..] } else { &COUNT1_TABLE_A[..] };
+    let mut np = 1 - big_val_cnt;
+    'count1: while d < dst.len() {
+        let mut leaf = codebook_count1[bits.peek(4) as usize] as i32;
+        if leaf & 8 == 0 {
+            let index = (leaf >> 3) as u32 + (bits.cache << 4 >> (32 - (leaf & 3) as u32));
+            leaf = codebook_count1[index as usize] as i32;
+        }
+        bits.flush((leaf & 7) as u32);
+        if bits.position() > limit as i32 {
+            break
+        }
+
+        for s in 0..4 {
+            if s % 2 == 0 {
+                np -= 1;
+                if np == 0 {
+                    np = (gr.sfbtab[sfb] / 2) as i32;
+                    sfb += 1;
+                    if np == 0 {
+                        break 'count1
+                    }
+                    one = scf[scf_index];
+                    scf_index += 1;
+                }
+            }
+            if leaf & (128 >> s) != 0 {
+                // Malformed granules may run over the end, the sign bits are still consumed
+                if d + s < dst.len() {
+                    dst[d + s] = if bits.sign() { -one } else { one };
+                }
+                bits.flush(1);
+            }
+        }
+        bits.check();
+        d += 4;
+    }
+
+    bs.pos = limit;
+}
+
+fn midside_stereo(buf: &mut [f32], offset: usize, n: usize) {
+    for i in offset..offset + n {
+        let (a, b) = (buf[i], buf[i + 576]);
+        buf[i] = a + b;
+        buf[i + 576] = a - b;
+    }
+}
+
+fn intensity_stereo_band(buf: &mut [f32], offset: usize, n: usize, kl: f32, kr: f32) {
+    for i in offset..offset + n {
+        buf[i + 576] = buf[i] * kr;
+        buf[i] = buf[i] * kl;
+    }
+}
+
+fn stereo_top_band(buf: &[f32], sfb: &[u8], nbands: usize, max_band: &mut [i32; 3]) {
+    *max_band = [-1, -1, -1];
+
+    let mut right = 576;
+    for i in 0..nbands {
+        let mut k = 0;
+        while k < sfb[i] as usize {
+            if buf[right + k] != 0.0 || buf[right + k + 1] != 0.0 {
+                max_band[i % 3] = i as i32;
+                break
+            }
+            k += 2;
+        }
+        right += sfb[i] as usize;
+    }
+}
+
+fn stereo_process(buf: &mut [f32], ist_pos: &[u8], sfb: &[u8], hdr: &[u8], max_band: &[i32; 3], mpeg2_sh: u32) {
+    let max_pos = if test_mpeg1(hdr) { 7 } else { 64 };
+
+    let mut offset = 0;
+    let mut i = 0;
+    while sfb[i] != 0 {
+        let ipos = ist_pos[i] as u32;
+        let n = sfb[i] as usize;
+        if i as i32 > max_band[i % 3] && ipos < max_pos {
+            let s = if test_ms_stereo(hdr) { 1.41421356 } else { 1.0 };
+            let (kl, kr) = if test_mpeg1(hdr) {
+                (PAN[2 * ipos as usize], PAN[2 * ipos as usize + 1])
+            } else {
+                let k = ldexp_q2(1.0, ((ipos + 1) >> 1 << mpeg2_sh) as i32);
+                if ipos & 1 != 0 { (k, 1.0) } else { (1.0, k) }
+            };
+            intensity_stereo_band(buf, offset, n, kl * s, kr * s);
+        } else if test_ms_stereo(hdr) {
+            midside_stereo(buf, offset, n);
+        }
+        offset += n;
+        i += 1;
+    }
+}
+
+fn intensity_stereo(buf: &mut [f32], ist_pos: &mut [u8], gr: &[GranuleInfo], hdr: &[u8]) {
+    let mut max_band = [0; 3];
+    let n_sfb = gr[0].n_long_sfb as usize + gr[0].n_short_sfb as usize;
+    let max_blocks = if gr[0].n_short_sfb != 0 { 3 } else { 1 };
+
+    stereo_top_band(buf, gr[0].sfbtab, n_sfb, &mut max_band);
+    if gr[0].n_long_sfb != 0 {
+        let band = max(max(max_band[0], max_band[1]), max_band[2]);
+        max_band = [band, band, band];
+    }
+    for i in 0..max_blocks {
+        let default_pos = if test_mpeg1(hdr) { 3 } else { 0 };
+        let itop = n_sfb - max_blocks + i;
+        let prev = itop - max_blocks;
+        ist_pos[itop] = if max_band[i] >= prev as i32 { default_pos } else { ist_pos[prev] };
+    }
+    stereo_process(buf, ist_pos, gr[0].sfbtab, hdr, &max_band, (gr[1].scalefac_compress & 1) as u32);
+}
+
+fn reorder(grbuf: &mut [f32], offset: usize, scratch: &mut [f32], sfb: &[u8]) {
+    let mut src = offset;
+    let mut dst = 0;
+    let mut band = 0;
+    while sfb[band] != 0 {
+        let len = sfb[band] as usize;
+        for i in 0..len {
+            scratch[dst] = grbuf[src + i];
+            scratch[dst + 1] = grbuf[src + i + len];
+            scratch[dst + 2] = grbuf[src + i + 2 * len];
+            dst += 3;
+        }
+        src += 3 * len;
+        band += 3;
+    }
+    for i in 0..dst {
+        grbuf[offset + i] = scratch[i];
+    }
+}
+
+fn antialias(grbuf: &mut [f32], nbands: i32) {
+    let mut offset = 0;
+    for _ in 0..max(nbands, 0) {
+        for i in 0..8 {
+            let u = grbuf[offset + 18 + i];
+            let d = grbuf[offset + 17 - i];
+            grbuf[offset + 18 + i] = u * ANTIALIAS[0][i] - d * ANTIALIAS[1][i];
+            grbuf[offset + 17 - i] = u * ANTIALIAS[1][i] + d * ANTIALIAS[0][i];
+        }
+        offset += 18;
+    }
+}
+
+fn dct3_9(y: &mut [f32; 9]) {
+    let mut s0 = y[0];
+    let mut s2 = y[2];
+    let mut s4 = y[4];
+    let mut s6 = y[6];
+    let mut s8 = y[8];
+    let mut t0 = s0 + s6 * 0.5;
+    s0 -= s6;
+    let mut t4 = (s4 + s2) * 0.93969262;
+    let mut t2 = (s8 + s2) * 0.76604444;
+    s6 = (s4 - s8) * 0.17364818;
+    s4 += s8 - s2;
+
+    s2 = s0 - s4 * 0.5;
+    y[4] = s4 + s0;
+    s8 = t0 - t2 + s6;
+    s0 = t0 - t4 + t2;
+    s4 = t0 + t4 - s6;
+
+    let mut s1 = y[1];
+    let mut s3 = y[3];
+    let mut s5 = y[5];
+    let mut s7 = y[7];
+
+    s3 *= 0.86602540;
+    t0 = (s5 + s1) * 0.98480775;
+    t4 = (s5 - s7) * 0.34202014;
+    t2 = (s1 + s7) * 0.64278761;
+    s1 = (s1 - s5 - s7) * 0.86602540;
+
+    s5 = t0 - s3 - t2;
+    s7 = t4 - s3 - t0;
+    s3 = t4 + s3 - t2;
+
+    y[0] = s4 - s7;
+    y[1] = s2 + s1;
+    y[2] = s0 - s3;
+    y[3] = s8 + s5;
+    y[5] = s8 - s5;
+    y[6] = s0 + s3;
+    y[7] = s2 - s1;
+    y[8] = s4 + s7;
+}
+
+fn imdct36(grbuf: &mut [f32], overlap: &mut [f32], window: &[f32], nbands: usize) {
+    for j in 0..nbands {
+        let g = &mut grbuf[18 * j..18 * j + 18];
+        let overlap = &mut overlap[9 * j..9 * j + 9];
+        let mut co = [0.0f32; 9];
+        let mut si = [0.0f32; 9];
+        co[0] = -g[0];
+        si[0] = g[17];
+        for i in 0..4 {
+            si[8 - 2 * i] = g[4 * i + 1] - g[4 * i + 2];
+            co[1 + 2 * i] = g[4 * i + 1] + g[4 * i + 2];
+            si[7 - 2 * i] = g[4 * i + 4] - g[4 * i + 3];
+            co[2 + 2 * i] = -(g[4 * i + 3] + g[4 * i + 4]);
+        }
+        dct3_9(&mut co);
+        dct3_9(&mut si);
+
+        si[1] = -si[1];
+        si[3] = -si[3];
+        si[5] = -si[5];
+        si[7] = -si[7];
+
+        for i in 0..9 {
+            let ovl = overlap[i];
+            let sum = co[i] * TWIDDLE9[9 + i] + si[i] * TWIDDLE9[i];
+            overlap[i] = co[i] * TWIDDLE9[i] - si[i] * TWIDDLE9[9 + i];
+            g[i] = ovl * window[i] - sum * window[9 + i];
+            g[17 - i] = ovl * window[9 + i] + sum * window[i];
+        }
+    }
+}
+
+fn idct3(x0: f32, x1: f32, x2: f32) -> [f32; 3] {
+    let m1 = x1 * 0.86602540;
+    let a1 = x0 - x2 * 0.5;
+    [a1 + m1, x0 + x2, a1 - m1]
+}
+
+fn imdct12(x: &[f32], overlap: &mut [f32; 3]) -> [f32; 6] {
+    let co = idct3(-x[0], x[6] + x[3], x[12] + x[9]);
+    let mut si = idct3(x[15], x[12] - x[9], x[6] - x[3]);
+    si[1] = -si[1];
+
+    let mut dst = [0.0f32; 6];
+    for i in 0..3 {
+        let ovl = overlap[i];
+        let sum = co[i] * TWIDDLE3[3 + i] + si[i] * TWIDDLE3[i];
+        overlap[i] = co[i] * TWIDDLE3[i] - si[i] * TWIDDLE3[3 + i];
+        dst[i] = ovl * TWIDDLE3[2 - i] - sum * TWIDDLE3[5 - i];
+        dst[5 - i] = ovl * TWIDDLE3[5 - i] + sum * TWIDDLE3[2 - i];
+    }
+    dst
+}
+
+fn imdct_short(grbuf: &mut [f32], overlap: &mut [f32], nbands: usize) {
+    for j in 0..nbands {
+        let g = &mut grbuf[18 * j..18 * j + 18];
+        let overlap = &mut overlap[9 * j..9 * j + 9];
+
+        let mut tmp = [0.0f32; 18];
+        for i in 0..18 {
+            tmp[i] = g[i];
+        }
+        for i in 0..6 {
+            g[i] = overlap[i];
+        }
+
+        let mut ovl = [overlap[6], overlap[7], overlap[8]];
+        let a = imdct12(&tmp[0..], &mut ovl);
+        let b = imdct12(&tmp[1..], &mut ovl);
+        let c = imdct12(&tmp[2..], &mut ovl);
+        for i in 0..6 {
+            g[6 + i] = a[i];
+            g[12 + i] = b[i];
+            overlap[i] = c[i];
+        }
+        for i in 0..3 {
+            overlap[6 + i] = ovl[i];
+        }
+    }
+}
+
+fn change_sign(grbuf: &mut [f32]) {
+    let mut offset = 18;
+    for _ in 0..16 {
+        let mut i = 1;
+        while i < 18 {
+            grbuf[offset + i] = -grbuf[offset + i];
+            i += 2;
+        }
+        offset += 36;
+    }
+}
+
+fn imdct_granule(grbuf: &mut [f32], overlap: &mut [f32], block_type: u8, n_long_bands: usize) {
+    if n_long_bands != 0 {
+        imdct36(grbuf, overlap, &MDCT_WINDOW[0], n_long_bands);
+    }
+    let grbuf = &mut grbuf[18 * n_long_bands..];
+    let overlap = &mut overlap[9 * n_long_bands..];
+    if block_type == SHORT_BLOCK_TYPE {
+        imdct_short(grbuf, overlap, 32 - n_long_bands);
+    } else {
+        let window = if block_type == STOP_BLOCK_TYPE { &MDCT_WINDOW[1] } else { &MDCT_WINDOW[0] };
+        imdct36(grbuf, overlap, window, 32 - n_long_bands);
+    }
+}
+
+fn dct_ii(grbuf: &mut [f32], n: usize) {
+    for k in 0..n {
+        let mut t = [[0.0f32; 8]; 4];
+        {
+            let y = &grbuf[k..];
+            for i in 0..8 {
+                let x0 = y[i * 18];
+                let x1 = y[(15 - i) * 18];
+                let x2 = y[(16 + i) * 18];
+                let x3 = y[(31 - i) * 18];
+                let t0 = x0 + x3;
+                let t1 = x1 + x2;
+                let t2 = (x1 - x2) * DCT_SECANT[3 * i];
+                let t3 = (x0 - x3) * DCT_SECANT[3 * i + 1];
+                t[0][i] = t0 + t1;
+                t[1][i] = (t0 - t1) * DCT_SECANT[3 * i + 2];
+                t[2][i] = t3 + t2;
+                t[3][i] = (t3 - t2) * DCT_SECANT[3 * i + 2];
+            }
+        }
+
+        for x in t.iter_mut() {
+            let (mut x0, mut x1, mut x2, mut x3) = (x[0], x[1], x[2], x[3]);
+            let (mut x4, mut x5, mut x6, mut x7) = (x[4], x[5], x[6], x[7]);
+            let mut xt = x0 - x7; x0 += x7;
+            x7 = x1 - x6; x1 += x6;
+            x6 = x2 - x5; x2 += x5;
+            x5 = x3 - x4; x3 += x4;
+            x4 = x0 - x3; x0 += x3;
+            x3 = x1 - x2; x1 += x2;
+            x[0] = x0 + x1;
+            x[4] = (x0 - x1) * 0.70710677;
+            x5 = x5 + x6;
+            x6 = (x6 + x7) * 0.70710677;
+            x7 = x7 + xt;
+            x3 = (x3 + x4) * 0.70710677;
+            x5 -= x7 * 0.198912367; // rotate by PI/8
+            x7 += x5 * 0.382683432;
+            x5 -= x7 * 0.198912367;
+            x0 = xt - x6; xt += x6;
+            x[1] = (xt + x7) * 0.50979561;
+            x[2] = (x4 + x3) * 0.54119611;
+            x[3] = (x0 - x5) * 0.60134488;
+            x[5] = (x0 + x5) * 0.89997619;
+            x[6] = (x4 - x3) * 1.30656302;
+            x[7] = (xt - x7) * 2.56291556;
+        }
+
+        let mut y = k;
+        for i in 0..7 {
+            grbuf[y] = t[0][i];
+            grbuf[y + 18] = t[2][i] + t[3][i] + t[3][i + 1];
+            grbuf[y + 36] = t[1][i] + t[1][i + 1];
+            grbuf[y + 54] = t[2][i + 1] + t[3][i] + t[3][i + 1];
+            y += 72;
+        }
+        grbuf[y] = t[0][7];
+        grbuf[y + 18] = t[2][7] + t[3][7];
+        grbuf[y + 36] = t[1][7];
+        grbuf[y + 54] = t[3][7];
+    }
+}
+
+fn scale_pcm(sample: f32) -> i16 {
+    if sample >= 32766.5 {
+        32767
+    } else if sample <= -32767.5 {
+        -32768
+    } else {
+        let s = (sample + 0.5) as i16;
+        if s < 0 { s - 1 } else { s }
+    }
+}
+
+fn synth_pair(pcm: &mut [i16], offset: usize, nch: usize, z: &[f32], zoffset: usize) {
+    let z = &z[zoffset..];
+    let mut a;
+    a = (z[14 * 64] - z[0]) * 29.0;
+    a += (z[64] + z[13 * 64]) * 213.0;
+    a += (z[12 * 64] - z[2 * 64]) * 459.0;
+    a += (z[3 * 64] + z[11 * 64]) * 2037.0;
+    a += (z[10 * 64] - z[4 * 64]) * 5153.0;
+    a += (z[5 * 64] + z[9 * 64]) * 6574.0;
+    a += (z[8 * 64] - z[6 * 64]) * 37489.0;
+    a += z[7 * 64] * 75038.0;
+    pcm[offset] = scale_pcm(a);
+
+    let z = &z[2..];
+    a = z[14 * 64] * 104.0;
+    a += z[12 * 64] * 1567.0;
+    a += z[10 * 64] * 9727.0;
+    a += z[8 * 64] * 64019.0;
+    a += z[6 * 64] * -9975.0;
+    a += z[4 * 64] * -45.0;
+    a += z[2 * 64] * 146.0;
+    a += z[0] * -5.0;
+    pcm[offset + 16 * nch] = scale_pcm(a);
+}
+
+fn synth(grbuf: &[f32], xl: usize, pcm: &mut [i16], dstl: usize, nch: usize, lins: &mut [f32], lins_offset: usize) {
+    let xr = xl + 576 * (nch - 1);
+    let dstr = dstl + (nch - 1);
+    let zlin = (lins_offset + 15 * 64) as isize;
+    let z = |i: isize| (zlin + i) as usize;
+
+    lins[z(4 * 15)] = grbuf[xl + 18 * 16];
+    lins[z(4 * 15 + 1)] = grbuf[xr + 18 * 16];
+    lins[z(4 * 15 + 2)] = grbuf[xl];
+    lins[z(4 * 15 + 3)] = grbuf[xr];
+    lins[z(4 * 31)] = grbuf[xl + 1 + 18 * 16];
+    lins[z(4 * 31 + 1)] = grbuf[xr + 1 + 18 * 16];
+    lins[z(4 * 31 + 2)] = grbuf[xl + 1];
+    lins[z(4 * 31 + 3)] = grbuf[xr + 1];
+
+    synth_pair(pcm, dstr, nch, lins, lins_offset + 4 * 15 + 1);
+    synth_pair(pcm, dstr + 32 * nch, nch, lins, lins_offset + 4 * 15 + 64 + 1);
+    synth_pair(pcm, dstl, nch, lins, lins_offset + 4 * 15);
+    synth_pair(pcm, dstl + 32 * nch, nch, lins, lins_offset + 4 * 15 + 64);
+
+    let mut w = 0;
+    for i in (0..15).rev() {
+        let ii = i as isize;
+        let band = 18 * (31 - i);
+        lins[z(4 * ii)] = grbuf[xl + band];
+        lins[z(4 * ii + 1)] = grbuf[xr + band];
+        lins[z(4 * ii + 2)] = grbuf[xl + 1 + band];
+        lins[z(4 * ii + 3)] = grbuf[xr + 1 + band];
+        lins[z(4 * (ii + 16))] = grbuf[xl + 1 + 18 * (1 + i)];
+        lins[z(4 * (ii + 16) + 1)] = grbuf[xr + 1 + 18 * (1 + i)];
+        lins[z(4 * (ii - 16) + 2)] = grbuf[xl + 18 * (1 + i)];
+        lins[z(4 * (ii - 16) + 3)] = grbuf[xr + 18 * (1 + i)];
+
+        let mut a = [0.0f32; 4];
+        let mut b = [0.0f32; 4];
+        for k in 0..8 {
+            let (w0, w1) = (SYNTH_WINDOW[w], SYNTH_WINDOW[w + 1]);
+            w += 2;
+            let vz = z(4 * ii - k * 64);
+            let vy = z(4 * ii - (15 - k) * 64);
+            for j in 0..4 {
+                let (zv, yv) = (lins[vz + j], lins[vy + j]);
+                if k == 0 {
+                    b[j] = zv * w1 + yv * w0;
+                    a[j] = zv * w0 - yv * w1;
+                } else if k % 2 == 0 {
+                    b[j] += zv * w1 + yv * w0;
+                    a[j] += zv * w0 - yv * w1;
+                } else {
+                    b[j] += zv * w1 + yv * w0;
+                    a[j] += yv * w1 - zv * w0;
+                }
+            }
+        }
+
+        pcm[dstr + (15 - i) * nch] = scale_pcm(a[1]);
+        pcm[dstr + (17 + i) * nch] = scale_pcm(b[1]);
+        pcm[dstl + (15 - i) * nch] = scale_pcm(a[0]);
+        pcm[dstl + (17 + i) * nch] = scale_pcm(b[0]);
+        pcm[dstr + (47 - i) * nch] = scale_pcm(a[3]);
+        pcm[dstr + (49 + i) * nch] = scale_pcm(b[3]);
+        pcm[dstl + (47 - i) * nch] = scale_pcm(a[2]);
+        pcm[dstl + (49 + i) * nch] = scale_pcm(b[2]);
+    }
+}
+
+fn synth_granule(qmf_state: &mut [f32], grbuf: &mut [f32], nbands: usize, nch: usize, pcm: &mut [i16], offset: usize, lins: &mut [f32]) {
+    for ch in 0..nch {
+        dct_ii(&mut grbuf[576 * ch..], nbands);
+    }
+
+    for i in 0..15 * 64 {
+        lins[i] = qmf_state[i];
+    }
+    let mut i = 0;
+    while i < nbands {
+        synth(grbuf, i, pcm, offset + 32 * nch * i, nch, lins, i * 64);
+        i += 2;
+    }
+
+    if nch == 1 {
+        let mut i = 0;
+        while i < 15 * 64 {
+            qmf_state[i] = lins[nbands * 64 + i];
+            i += 2;
+        }
+    } else {
+        for i in 0..15 * 64 {
+            qmf_state[i] = lins[nbands * 64 + i];
+        }
+    }
+}
+
+static SCF_LONG: [[u8; 23]; 8] = [
+    [6, 6, 6, 6, 6, 6, 8, 10, 12, 14, 16, 20, 24, 28, 32, 38, 46, 52, 60, 68, 58, 54, 0],
+    [12, 12, 12, 12, 12, 12, 16, 20, 24, 28, 32, 40, 48, 56, 64, 76, 90, 2, 2, 2, 2, 2, 0],
+    [6, 6, 6, 6, 6, 6, 8, 10, 12, 14, 16, 20, 24, 28, 32, 38, 46, 52, 60, 68, 58, 54, 0],
+    [6, 6, 6, 6, 6, 6, 8, 10, 12, 14, 16, 18, 22, 26, 32, 38, 46, 54, 62, 70, 76, 36, 0],
+    [6, 6, 6, 6, 6, 6, 8, 10, 12, 14, 16, 20, 24, 28, 32, 38, 46, 52, 60, 68, 58, 54, 0],
+    [4, 4, 4, 4, 4, 4, 6, 6, 8, 8, 10, 12, 16, 20, 24, 28, 34, 42, 50, 54, 76, 158, 0],
+    [4, 4, 4, 4, 4, 4, 6, 6, 6, 8, 10, 12, 16, 18, 22, 28, 34, 40, 46, 54, 54, 192, 0],
+    [4, 4, 4, 4, 4, 4, 6, 6, 8, 10, 12, 16, 20, 24, 30, 38, 46, 56, 68, 84, 102, 26, 0]
+];
+
+static SCF_SHORT: [[u8; 40]; 8] = [
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 24, 24, 24, 30, 30, 30, 40, 40, 40, 18, 18, 18, 0],
+    [8, 8, 8, 8, 8, 8, 8, 8, 8, 12, 12, 12, 16, 16, 16, 20, 20, 20, 24, 24, 24, 28, 28, 28, 36, 36, 36, 2, 2, 2, 2, 2, 2, 2, 2, 2, 26, 26, 26, 0],
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 6, 6, 6, 8, 8, 8, 10, 10, 10, 14, 14, 14, 18, 18, 18, 26, 26, 26, 32, 32, 32, 42, 42, 42, 18, 18, 18, 0],
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 24, 24, 24, 32, 32, 32, 44, 44, 44, 12, 12, 12, 0],
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 24, 24, 24, 30, 30, 30, 40, 40, 40, 18, 18, 18, 0],
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 22, 22, 22, 30, 30, 30, 56, 56, 56, 0],
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 6, 6, 6, 10, 10, 10, 12, 12, 12, 14, 14, 14, 16, 16, 16, 20, 20, 20, 26, 26, 26, 66, 66, 66, 0],
+    [4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 6, 6, 6, 8, 8, 8, 12, 12, 12, 16, 16, 16, 20, 20, 20, 26, 26, 26, 34, 34, 34, 42, 42, 42, 12, 12, 12, 0]
+];
+
+static SCF_MIXED: [[u8; 40]; 8] = [
+    [6, 6, 6, 6, 6, 6, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 24, 24, 24, 30, 30, 30, 40, 40, 40, 18, 18, 18, 0, 0, 0, 0],
+    [12, 12, 12, 4, 4, 4, 8, 8, 8, 12, 12, 12, 16, 16, 16, 20, 20, 20, 24, 24, 24, 28, 28, 28, 36, 36, 36, 2, 2, 2, 2, 2, 2, 2, 2, 2, 26, 26, 26, 0],
+    [6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 8, 8, 8, 10, 10, 10, 14, 14, 14, 18, 18, 18, 26, 26, 26, 32, 32, 32, 42, 42, 42, 18, 18, 18, 0, 0, 0, 0],
+    [6, 6, 6, 6, 6, 6, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 24, 24, 24, 32, 32, 32, 44, 44, 44, 12, 12, 12, 0, 0, 0, 0],
+    [6, 6, 6, 6, 6, 6, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 24, 24, 24, 30, 30, 30, 40, 40, 40, 18, 18, 18, 0, 0, 0, 0],
+    [4, 4, 4, 4, 4, 4, 6, 6, 4, 4, 4, 6, 6, 6, 8, 8, 8, 10, 10, 10, 12, 12, 12, 14, 14, 14, 18, 18, 18, 22, 22, 22, 30, 30, 30, 56, 56, 56, 0, 0],
+    [4, 4, 4, 4, 4, 4, 6, 6, 4, 4, 4, 6, 6, 6, 6, 6, 6, 10, 10, 10, 12, 12, 12, 14, 14, 14, 16, 16, 16, 20, 20, 20, 26, 26, 26, 66, 66, 66, 0, 0],
+    [4, 4, 4, 4, 4, 4, 6, 6, 4, 4, 4, 6, 6, 6, 8, 8, 8, 12, 12, 12, 16, 16, 16, 20, 20, 20, 26, 26, 26, 34, 34, 34, 42, 42, 42, 12, 12, 12, 0, 0]
+];
+
+static SCF_PARTITIONS: [[u8; 28]; 3] = [
+    [6, 5, 5, 5, 6, 5, 5, 5, 6, 5, 7, 3, 11, 10, 0, 0, 7, 7, 7, 0, 6, 6, 6, 3, 8, 8, 5, 0],
+    [8, 9, 6, 12, 6, 9, 9, 9, 6, 9, 12, 6, 15, 18, 0, 0, 6, 15, 12, 0, 6, 12, 9, 6, 6, 18, 9, 0],
+    [9, 9, 6, 12, 9, 9, 9, 9, 9, 9, 12, 6, 18, 18, 0, 0, 12, 12, 12, 0, 12, 9, 9, 6, 15, 12, 9, 0]
+];
+
+static SCFC_DECODE: [u8; 16] = [
+    0, 1, 2, 3, 12, 5, 6, 7, 9, 10, 11, 13, 14, 15, 18, 19
+];
+
+static SCF_MOD: [i32; 24] = [
+    5, 5, 4, 4, 5, 5, 4, 1, 4, 3, 1, 1, 5, 6, 6, 1,
+    4, 4, 4, 1, 4, 3, 1, 1
+];
+
+static PREAMP: [u8; 10] = [
+    1, 1, 1, 1, 2, 2, 3, 3, 3, 2
+];
+
+static EXPFRAC: [f32; 4] = [
+    9.31322575e-10, 7.83145814e-10, 6.58544508e-10, 5.53767716e-10
+];
+
+static POW43: [f32; 145] = [
+    0.0, -1.0, -2.519842, -4.326749, -6.349604, -8.549880, -10.902724, -13.390518, -16.000000, -18.720754, -21.544347, -24.463781, -27.473142, -30.567351, -33.741992, -36.993181,
+    0.0, 1.0, 2.519842, 4.326749, 6.349604, 8.549880, 10.902724, 13.390518, 16.000000, 18.720754, 21.544347, 24.463781, 27.473142, 30.567351, 33.741992, 36.993181,
+    40.317474, 43.711787, 47.173345, 50.699631, 54.288352, 57.937408, 61.644865, 65.408941, 69.227979, 73.100443, 77.024898, 81.000000, 85.024491, 89.097188, 93.216975, 97.382800,
+    101.593667, 105.848633, 110.146801, 114.487321, 118.869381, 123.292209, 127.755065, 132.257246, 136.798076, 141.376907, 145.993119, 150.646117, 155.335327, 160.060199, 164.820202, 169.614826,
+    174.443577, 179.305980, 184.201575, 189.129918, 194.090580, 199.083145, 204.107210, 209.162385, 214.248292, 219.364564, 224.510845, 229.686789, 234.892058, 240.126328, 245.389280, 250.680604,
+    256.000000, 261.347174, 266.721841, 272.123723, 277.552547, 283.008049, 288.489971, 293.998060, 299.532071, 305.091761, 310.676898, 316.287249, 321.922592, 327.582707, 333.267377, 338.976394,
+    344.709550, 350.466646, 356.247482, 362.051866, 367.879608, 373.730522, 379.604427, 385.501143, 391.420496, 397.362314, 403.326427, 409.312672, 415.320884, 421.350905, 427.402579, 433.475750,
+    439.570269, 445.685987, 451.822757, 457.980436, 464.158883, 470.357960, 476.577530, 482.817459, 489.077615, 495.357868, 501.658090, 507.978156, 514.317941, 520.677324, 527.056184, 533.454404,
+    539.871867, 546.308458, 552.764065, 559.238575, 565.731879, 572.243870, 578.774440, 585.323483, 591.890898, 598.476581, 605.080431, 611.702349, 618.342238, 625.000000, 631.675540, 638.368763,
+    645.079578
+];
+
+static HUFFMAN_TABLES: [i16; 2164] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    785, 785, 785, 785, 784, 784, 784, 784, 513, 513, 513, 513, 513, 513, 513, 513,
+    256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256,
+    -255, 1313, 1298, 1282, 785, 785, 785, 785, 784, 784, 784, 784, 769, 769, 769, 769,
+    256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256,
+    290, 288, -255, 1313, 1298, 1282, 769, 769, 769, 769, 529, 529, 529, 529, 529, 529,
+    529, 529, 528, 528, 528, 528, 528, 528, 528, 528, 512, 512, 512, 512, 512, 512,
+    512, 512, 290, 288, -253, -318, -351, -367, 785, 785, 785, 785, 784, 784, 784, 784,
+    769, 769, 769, 769, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256,
+    256, 256, 256, 256, 819, 818, 547, 547, 275, 275, 275, 275, 561, 560, 515, 546,
+    289, 274, 288, 258, -254, -287, 1329, 1299, 1314, 1312, 1057, 1057, 1042, 1042, 1026, 1026,
+    784, 784, 784, 784, 529, 529, 529, 529, 529, 529, 529, 529, 769, 769, 769, 769,
+    768, 768, 768, 768, 563, 560, 306, 306, 291, 259, -252, -413, -477, -542, 1298, -575,
+    1041, 1041, 784, 784, 784, 784, 769, 769, 769, 769, 256, 256, 256, 256, 256, 256,
+    256, 256, 256, 256, 256, 256, 256, 256, 256, 256, -383, -399, 1107, 1092, 1106, 1061,
+    849, 849, 789, 789, 1104, 1091, 773, 773, 1076, 1075, 341, 340, 325, 309, 834, 804,
+    577, 577, 532, 532, 516, 516, 832, 818, 803, 816, 561, 561, 531, 531, 515, 546,
+    289, 289, 288, 258, -252, -429, -493, -559, 1057, 1057, 1042, 1042, 529, 529, 529, 529,
+    529, 529, 529, 529, 784, 784, 784, 784, 769, 769, 769, 769, 512, 512, 512, 512,
+    512, 512, 512, 512, -382, 1077, -415, 1106, 1061, 1104, 849, 849, 789, 789, 1091, 1076,
+    1029, 1075, 834, 834, 597, 581, 340, 340, 339, 324, 804, 833, 532, 532, 832, 772,
+    818, 803, 817, 787, 816, 771, 290, 290, 290, 290, 288, 258, -253, -349, -414, -447,
+    -463, 1329, 1299, -479, 1314, 1312, 1057, 1057, 1042, 1042, 1026, 1026, 785, 785, 785, 785,
+    784, 784, 784, 784, 769, 769, 769, 769, 768, 768, 768, 768, -319, 851, 821, -335,
+    836, 850, 805, 849, 341, 340, 325, 336, 533, 533, 579, 579, 564, 564, 773, 832,
+    578, 548, 563, 516, 321, 276, 306, 291, 304, 259, -251, -572, -733, -830, -863, -879,
+    1041, 1041, 784, 784, 784, 784, 769, 769, 769, 769, 256, 256, 256, 256, 256, 256,
+    256, 256, 256, 256, 256, 256, 256, 256, 256, 256, -511, -527, -543, 1396, 1351, 1381,
+    1366, 1395, 1335, 1380, -559, 1334, 1138, 1138, 1063, 1063, 1350, 1392, 1031, 1031, 1062, 1062,
+    1364, 1363, 1120, 1120, 1333, 1348, 881, 881, 881, 881, 375, 374, 359, 373, 343, 358,
+    341, 325, 791, 791, 1123, 1122, -703, 1105, 1045, -719, 865, 865, 790, 790, 774, 774,
+    1104, 1029, 338, 293, 323, 308, -799, -815, 833, 788, 772, 818, 803, 816, 322, 292,
+    307, 320, 561, 531, 515, 546, 289, 274, 288, 258, -251, -525, -605, -685, -765, -831,
+    -846, 1298, 1057, 1057, 1312, 1282, 785, 785, 785, 785, 784, 784, 784, 784, 769, 769,
+    769, 769, 512, 512, 512, 512, 512, 512, 512, 512, 1399, 1398, 1383, 1367, 1382, 1396,
+    1351, -511, 1381, 1366, 1139, 1139, 1079, 1079, 1124, 1124, 1364, 1349, 1363, 1333, 882, 882,
+    882, 882, 807, 807, 807, 807, 1094, 1094, 1136, 1136, 373, 341, 535, 535, 881, 775,
+    867, 822, 774, -591, 324, 338, -671, 849, 550, 550, 866, 864, 609, 609, 293, 336,
+    534, 534, 789, 835, 773, -751, 834, 804, 308, 307, 833, 788, 832, 772, 562, 562,
+    547, 547, 305, 275, 560, 515, 290, 290, -252, -397, -477, -557, -622, -653, -719, -735,
+    -750, 1329, 1299, 1314, 1057, 1057, 1042, 1042, 1312, 1282, 1024, 1024, 785, 785, 785, 785,
+    784, 784, 784, 784, 769, 769, 769, 769, -383, 1127, 1141, 1111, 1126, 1140, 1095, 1110,
+    869, 869, 883, 883, 1079, 1109, 882, 882, 375, 374, 807, 868, 838, 881, 791, -463,
+    867, 822, 368, 263, 852, 837, 836, -543, 610, 610, 550, 550, 352, 336, 534, 534,
+    865, 774, 851, 821, 850, 805, 593, 533, 579, 564, 773, 832, 578, 578, 548, 548,
+    577, 577, 307, 276, 306, 291, 516, 560, 259, 259, -250, -2107, -2507, -2764, -2909, -2974,
+    -3007, -3023, 1041, 1041, 1040, 1040, 769, 769, 769, 769, 256, 256, 256, 256, 256, 256,
+    256, 256, 256, 256, 256, 256, 256, 256, 256, 256, -767, -1052, -1213, -1277, -1358, -1405,
+    -1469, -1535, -1550, -1582, -1614, -1647, -1662, -1694, -1726, -1759, -1774, -1807, -1822, -1854, -1886, 1565,
+    -1919, -1935, -1951, -1967, 1731, 1730, 1580, 1717, -1983, 1729, 1564, -1999, 1548, -2015, -2031, 1715,
+    1595, -2047, 1714, -2063, 1610, -2079, 1609, -2095, 1323, 1323, 1457, 1457, 1307, 1307, 1712, 1547,
+    1641, 1700, 1699, 1594, 1685, 1625, 1442, 1442, 1322, 1322, -780, -973, -910, 1279, 1278, 1277,
+    1262, 1276, 1261, 1275, 1215, 1260, 1229, -959, 974, 974, 989, 989, -943, 735, 478, 478,
+    495, 463, 506, 414, -1039, 1003, 958, 1017, 927, 942, 987, 957, 431, 476, 1272, 1167,
+    1228, -1183, 1256, -1199, 895, 895, 941, 941, 1242, 1227, 1212, 1135, 1014, 1014, 490, 489,
+    503, 487, 910, 1013, 985, 925, 863, 894, 970, 955, 1012, 847, -1343, 831, 755, 755,
+    984, 909, 428, 366, 754, 559, -1391, 752, 486, 457, 924, 997, 698, 698, 983, 893,
+    740, 740, 908, 877, 739, 739, 667, 667, 953, 938, 497, 287, 271, 271, 683, 606,
+    590, 712, 726, 574, 302, 302, 738, 736, 481, 286, 526, 725, 605, 711, 636, 724,
+    696, 651, 589, 681, 666, 710, 364, 467, 573, 695, 466, 466, 301, 465, 379, 379,
+    709, 604, 665, 679, 316, 316, 634, 633, 436, 436, 464, 269, 424, 394, 452, 332,
+    438, 363, 347, 408, 393, 448, 331, 422, 362, 407, 392, 421, 346, 406, 391, 376,
+    375, 359, 1441, 1306, -2367, 1290, -2383, 1337, -2399, -2415, 1426, 1321, -2431, 1411, 1336, -2447,
+    -2463, -2479, 1169, 1169, 1049, 1049, 1424, 1289, 1412, 1352, 1319, -2495, 1154, 1154, 1064, 1064,
+    1153, 1153, 416, 390, 360, 404, 403, 389, 344, 374, 373, 343, 358, 372, 327, 357,
+    342, 311, 356, 326, 1395, 1394, 1137, 1137, 1047, 1047, 1365, 1392, 1287, 1379, 1334, 1364,
+    1349, 1378, 1318, 1363, 792, 792, 792, 792, 1152, 1152, 1032, 1032, 1121, 1121, 1046, 1046,
+    1120, 1120, 1030, 1030, -2895, 1106, 1061, 1104, 849, 849, 789, 789, 1091, 1076, 1029, 1090,
+    1060, 1075, 833, 833, 309, 324, 532, 532, 832, 772, 818, 803, 561, 561, 531, 560,
+    515, 546, 289, 274, 288, 258, -250, -1179, -1579, -1836, -1996, -2124, -2253, -2333, -2413, -2477,
+    -2542, -2574, -2607, -2622, -2655, 1314, 1313, 1298, 1312, 1282, 785, 785, 785, 785, 1040, 1040,
+    1025, 1025, 768, 768, 768, 768, -766, -798, -830, -862, -895, -911, -927, -943, -959, -975,
+    -991, -1007, -1023, -1039, -1055, -1070, 1724, 1647, -1103, -1119, 1631, 1767, 1662, 1738, 1708, 1723,
+    -1135, 1780, 1615, 1779, 1599, 1677, 1646, 1778, 1583, -1151, 1777, 1567, 1737, 1692, 1765, 1722,
+    1707, 1630, 1751, 1661, 1764, 1614, 1736, 1676, 1763, 1750, 1645, 1598, 1721, 1691, 1762, 1706,
+    1582, 1761, 1566, -1167, 1749, 1629, 767, 766, 751, 765, 494, 494, 735, 764, 719, 749,
+    734, 763, 447, 447, 748, 718, 477, 506, 431, 491, 446, 476, 461, 505, 415, 430,
+    475, 445, 504, 399, 460, 489, 414, 503, 383, 474, 429, 459, 502, 502, 746, 752,
+    488, 398, 501, 473, 413, 472, 486, 271, 480, 270, -1439, -1455, 1357, -1471, -1487, -1503,
+    1341, 1325, -1519, 1489, 1463, 1403, 1309, -1535, 1372, 1448, 1418, 1476, 1356, 1462, 1387, -1551,
+    1475, 1340, 1447, 1402, 1386, -1567, 1068, 1068, 1474, 1461, 455, 380, 468, 440, 395, 425,
+    410, 454, 364, 467, 466, 464, 453, 269, 409, 448, 268, 432, 1371, 1473, 1432, 1417,
+    1308, 1460, 1355, 1446, 1459, 1431, 1083, 1083, 1401, 1416, 1458, 1445, 1067, 1067, 1370, 1457,
+    1051, 1051, 1291, 1430, 1385, 1444, 1354, 1415, 1400, 1443, 1082, 1082, 1173, 1113, 1186, 1066,
+    1185, 1050, -1967, 1158, 1128, 1172, 1097, 1171, 1081, -1983, 1157, 1112, 416, 266, 375, 400,
+    1170, 1142, 1127, 1065, 793, 793, 1169, 1033, 1156, 1096, 1141, 1111, 1155, 1080, 1126, 1140,
+    898, 898, 808, 808, 897, 897, 792, 792, 1095, 1152, 1032, 1125, 1110, 1139, 1079, 1124,
+    882, 807, 838, 881, 853, 791, -2319, 867, 368, 263, 822, 852, 837, 866, 806, 865,
+    -2399, 851, 352, 262, 534, 534, 821, 836, 594, 594, 549, 549, 593, 593, 533, 533,
+    848, 773, 579, 579, 564, 578, 548, 563, 276, 276, 577, 576, 306, 291, 516, 560,
+    305, 305, 275, 259, -251, -892, -2058, -2620, -2828, -2957, -3023, -3039, 1041, 1041, 1040, 1040,
+    769, 769, 769, 769, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256, 256,
+    256, 256, 256, 256, -511, -527, -543, -559, 1530, -575, -591, 1528, 1527, 1407, 1526, 1391,
+    1023, 1023, 1023, 1023, 1525, 1375, 1268, 1268, 1103, 1103, 1087, 1087, 1039, 1039, 1523, -604,
+    815, 815, 815, 815, 510, 495, 509, 479, 508, 463, 507, 447, 431, 505, 415, 399,
+    -734, -782, 1262, -815, 1259, 1244, -831, 1258, 1228, -847, -863, 1196, -879, 1253, 987, 987,
+    748, -767, 493, 493, 462, 477, 414, 414, 686, 669, 478, 446, 461, 445, 474, 429,
+    487, 458, 412, 471, 1266, 1264, 1009, 1009, 799, 799, -1019, -1276, -1452, -1581, -1677, -1757,
+    -1821, -1886, -1933, -1997, 1257, 1257, 1483, 1468, 1512, 1422, 1497, 1406, 1467, 1496, 1421, 1510,
+    1134, 1134, 1225, 1225, 1466, 1451, 1374, 1405, 1252, 1252, 1358, 1480, 1164, 1164, 1251, 1251,
+    1238, 1238, 1389, 1465, -1407, 1054, 1101, -1423, 1207, -1439, 830, 830, 1248, 1038, 1237, 1117,
+    1223, 1148, 1236, 1208, 411, 426, 395, 410, 379, 269, 1193, 1222, 1132, 1235, 1221, 1116,
+    976, 976, 1192, 1162, 1177, 1220, 1131, 1191, 963, 963, -1647, 961, 780, -1663, 558, 558,
+    994, 993, 437, 408, 393, 407, 829, 978, 813, 797, 947, -1743, 721, 721, 377, 392,
+    844, 950, 828, 890, 706, 706, 812, 859, 796, 960, 948, 843, 934, 874, 571, 571,
+    -1919, 690, 555, 689, 421, 346, 539, 539, 944, 779, 918, 873, 932, 842, 903, 888,
+    570, 570, 931, 917, 674, 674, -2575, 1562, -2591, 1609, -2607, 1654, 1322, 1322, 1441, 1441,
+    1696, 1546, 1683, 1593, 1669, 1624, 1426, 1426, 1321, 1321, 1639, 1680, 1425, 1425, 1305, 1305,
+    1545, 1668, 1608, 1623, 1667, 1592, 1638, 1666, 1320, 1320, 1652, 1607, 1409, 1409, 1304, 1304,
+    1288, 1288, 1664, 1637, 1395, 1395, 1335, 1335, 1622, 1636, 1394, 1394, 1319, 1319, 1606, 1621,
+    1392, 1392, 1137, 1137, 1137, 1137, 345, 390, 360, 375, 404, 373, 1047, -2751, -2767, -2783,
+    1062, 1121, 1046, -2799, 1077, -2815, 1106, 1061, 789, 789, 1105, 1104, 263, 355, 310, 340,
+    325, 354, 352, 262, 339, 324, 1091, 1076, 1029, 1090, 1060, 1075, 833, 833, 788, 788,
+    1088, 1028, 818, 818, 803, 803, 561, 561, 531, 531, 816, 771, 546, 546, 289, 274,
+    288, 258, -253, -317, -381, -446, -478, -509, 1279, 1279, -811, -1179, -1451, -1756, -1900, -2028,
+    -2189, -2253, -2333, -2414, -2445, -2511, -2526, 1313, 1298, -2559, 1041, 1041, 1040, 1040, 1025, 1025,
+    1024, 1024, 1022, 1007, 1021, 991, 1020, 975, 1019, 959, 687, 687, 1018, 1017, 671, 671,
+    655, 655, 1016, 1015, 639, 639, 758, 758, 623, 623, 757, 607, 756, 591, 755, 575,
+    754, 559, 543, 543, 1009, 783, -575, -621, -685, -749, 496, -590, 750, 749, 734, 748,
+    974, 989, 1003, 958, 988, 973, 1002, 942, 987, 957, 972, 1001, 926, 986, 941, 971,
+    956, 1000, 910, 985, 925, 999, 894, 970, -1071, -1087, -1102, 1390, -1135, 1436, 1509, 1451,
+    1374, -1151, 1405, 1358, 1480, 1420, -1167, 1507, 1494, 1389, 1342, 1465, 1435, 1450, 1326, 1505,
+    1310, 1493, 1373, 1479, 1404, 1492, 1464, 1419, 428, 443, 472, 397, 736, 526, 464, 464,
+    486, 457, 442, 471, 484, 482, 1357, 1449, 1434, 1478, 1388, 1491, 1341, 1490, 1325, 1489,
+    1463, 1403, 1309, 1477, 1372, 1448, 1418, 1433, 1476, 1356, 1462, 1387, -1439, 1475, 1340, 1447,
+    1402, 1474, 1324, 1461, 1371, 1473, 269, 448, 1432, 1417, 1308, 1460, -1711, 1459, -1727, 1441,
+    1099, 1099, 1446, 1386, 1431, 1401, -1743, 1289, 1083, 1083, 1160, 1160, 1458, 1445, 1067, 1067,
+    1370, 1457, 1307, 1430, 1129, 1129, 1098, 1098, 268, 432, 267, 416, 266, 400, -1887, 1144,
+    1187, 1082, 1173, 1113, 1186, 1066, 1050, 1158, 1128, 1143, 1172, 1097, 1171, 1081, 420, 391,
+    1157, 1112, 1170, 1142, 1127, 1065, 1169, 1049, 1156, 1096, 1141, 1111, 1155, 1080, 1126, 1154,
+    1064, 1153, 1140, 1095, 1048, -2159, 1125, 1110, 1137, -2175, 823, 823, 1139, 1138, 807, 807,
+    384, 264, 368, 263, 868, 838, 853, 791, 867, 822, 852, 837, 866, 806, 865, 790,
+    -2319, 851, 821, 836, 352, 262, 850, 805, 849, -2399, 533, 533, 835, 820, 336, 261,
+    578, 548, 563, 577, 532, 532, 832, 772, 562, 562, 547, 547, 305, 275, 560, 515,
+    290, 290, 288, 258
+];
+
+static COUNT1_TABLE_A: [u8; 28] = [
+    130, 162, 193, 209, 44, 28, 76, 140, 9, 9, 9, 9, 9, 9, 9, 9,
+    190, 254, 222, 238, 126, 94, 157, 157, 109, 61, 173, 205
+];
+
+static COUNT1_TABLE_B: [u8; 16] = [
+    252, 236, 220, 204, 188, 172, 156, 140, 124, 108, 92, 76, 60, 44, 28, 12
+];
+
+static HUFFMAN_INDEX: [u16; 32] = [
+    0, 32, 64, 98, 0, 132, 180, 218, 292, 364, 426, 538, 648, 746, 0, 1126,
+    1460, 1460, 1460, 1460, 1460, 1460, 1460, 1460, 1842, 1842, 1842, 1842, 1842, 1842, 1842, 1842
+];
+
+static LINBITS: [u8; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 2, 3, 4, 6, 8, 10, 13, 4, 5, 6, 7, 8, 9, 11, 13
+];
+
+static PAN: [f32; 14] = [
+    0.0, 1.0, 0.21132487, 0.78867513, 0.36602540, 0.63397460, 0.5, 0.5, 0.63397460, 0.36602540, 0.78867513, 0.21132487, 1.0, 0.0
+];
+
+static ANTIALIAS: [[f32; 8]; 2] = [
+    [0.85749293, 0.88174200, 0.94962865, 0.98331459, 0.99551782, 0.99916056, 0.99989920, 0.99999316],
+    [0.51449576, 0.47173197, 0.31337745, 0.18191320, 0.09457419, 0.04096558, 0.01419856, 0.00369997]
+];
+
+static TWIDDLE9: [f32; 18] = [
+    0.73727734, 0.79335334, 0.84339145, 0.88701083, 0.92387953, 0.95371695, 0.97629601, 0.99144486, 0.99904822, 0.67559021, 0.60876143, 0.53729961, 0.46174861, 0.38268343, 0.30070580, 0.21643961,
+    0.13052619, 0.04361938
+];
+
+static TWIDDLE3: [f32; 6] = [
+    0.79335334, 0.92387953, 0.99144486, 0.60876143, 0.38268343, 0.13052619
+];
+
+static MDCT_WINDOW: [[f32; 18]; 2] = [
+    [0.99904822, 0.99144486, 0.97629601, 0.95371695, 0.92387953, 0.88701083, 0.84339145, 0.79335334, 0.73727734, 0.04361938, 0.13052619, 0.21643961, 0.30070580, 0.38268343, 0.46174861, 0.53729961, 0.60876143, 0.67559021],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.99144486, 0.92387953, 0.79335334, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.13052619, 0.38268343, 0.60876143]
+];
+
+static DCT_SECANT: [f32; 24] = [
+    10.19000816, 0.50060302, 0.50241929, 3.40760851, 0.50547093, 0.52249861, 2.05778098, 0.51544732, 0.56694406, 1.48416460, 0.53104258, 0.64682180, 1.16943991, 0.55310392, 0.78815460, 0.97256821,
+    0.58293498, 1.06067765, 0.83934963, 0.62250412, 1.72244716, 0.74453628, 0.67480832, 5.10114861
+];
+
+static SYNTH_WINDOW: [f32; 240] = [
+    -1.0, 26.0, -31.0, 208.0, 218.0, 401.0, -519.0, 2063.0, 2000.0, 4788.0, -5517.0, 7134.0, 5959.0, 35640.0, -39336.0, 74992.0,
+    -1.0, 24.0, -35.0, 202.0, 222.0, 347.0, -581.0, 2080.0, 1952.0, 4425.0, -5879.0, 7640.0, 5288.0, 33791.0, -41176.0, 74856.0,
+    -1.0, 21.0, -38.0, 196.0, 225.0, 294.0, -645.0, 2087.0, 1893.0, 4063.0, -6237.0, 8092.0, 4561.0, 31947.0, -43006.0, 74630.0,
+    -1.0, 19.0, -41.0, 190.0, 227.0, 244.0, -711.0, 2085.0, 1822.0, 3705.0, -6589.0, 8492.0, 3776.0, 30112.0, -44821.0, 74313.0,
+    -1.0, 17.0, -45.0, 183.0, 228.0, 197.0, -779.0, 2075.0, 1739.0, 3351.0, -6935.0, 8840.0, 2935.0, 28289.0, -46617.0, 73908.0,
+    -1.0, 16.0, -49.0, 176.0, 228.0, 153.0, -848.0, 2057.0, 1644.0, 3004.0, -7271.0, 9139.0, 2037.0, 26482.0, -48390.0, 73415.0,
+    -2.0, 14.0, -53.0, 169.0, 227.0, 111.0, -919.0, 2032.0, 1535.0, 2663.0, -7597.0, 9389.0, 1082.0, 24694.0, -50137.0, 72835.0,
+    -2.0, 13.0, -58.0, 161.0, 224.0, 72.0, -991.0, 2001.0, 1414.0, 2330.0, -7910.0, 9592.0, 70.0, 22929.0, -51853.0, 72169.0,
+    -2.0, 11.0, -63.0, 154.0, 221.0, 36.0, -1064.0, 1962.0, 1280.0, 2006.0, -8209.0, 9750.0, -998.0, 21189.0, -53534.0, 71420.0,
+    -2.0, 10.0, -68.0, 147.0, 215.0, 2.0, -1137.0, 1919.0, 1131.0, 1692.0, -8491.0, 9863.0, -2122.0, 19478.0, -55178.0, 70590.0,
+    -3.0, 9.0, -73.0, 139.0, 208.0, -29.0, -1210.0, 1870.0, 970.0, 1388.0, -8755.0, 9935.0, -3300.0, 17799.0, -56778.0, 69679.0,
+    -3.0, 8.0, -79.0, 132.0, 200.0, -57.0, -1283.0, 1817.0, 794.0, 1095.0, -8998.0, 9966.0, -4533.0, 16155.0, -58333.0, 68692.0,
+    -4.0, 7.0, -85.0, 125.0, 189.0, -83.0, -1356.0, 1759.0, 605.0, 814.0, -9219.0, 9959.0, -5818.0, 14548.0, -59838.0, 67629.0,
+    -4.0, 7.0, -91.0, 117.0, 177.0, -106.0, -1428.0, 1698.0, 402.0, 545.0, -9416.0, 9916.0, -7154.0, 12980.0, -61289.0, 66494.0,
+    -5.0, 6.0, -97.0, 111.0, 163.0, -127.0, -1498.0, 1634.0, 185.0, 288.0, -9585.0, 9838.0, -8540.0, 11455.0, -62684.0, 65290.0
+];
+
+#[cfg(test)]
+mod tests {
+    use util::pcm::decode_pcm;
+    use util::sound_stream::{ SoundFormat, CodecKind };
+
+    // The fixtures are sine mixes and their reference PCM is FFmpeg's decoding, which rounds differently
+    const TOLERANCE: i32 = 2;
+
+    fn check(data: &[u8], reference: &[u8], sample_rate: u32, stereo: bool) {
+        let format = SoundFormat::from_parts(CodecKind::MP3, sample_rate, true, stereo).unwrap();
+        let samples = decode_pcm(&format, data).unwrap();
+        let expected: Vec<i16> = reference.chunks(2).map(|c| (c[0] as u16 | (c[1] as u16) << 8) as i16).collect();
+
+        assert_eq!(samples.len(), expected.len());
+        for (i, (&sample, &expected)) in samples.iter().zip(expected.iter()).enumerate() {
+            assert!((sample as i32 - expected as i32).abs() <= TOLERANCE, "sample {}: {} != {}", i, sample, expected);
+        }
+    }
+
+    #[test]
+    fn decode_mpeg1_mono() {
+        check(include_bytes!("../../tests/fixtures/sine_44100_mono.mp3"),
+              include_bytes!("../../tests/fixtures/sine_44100_mono.pcm"), 44100, false);
+    }
+
+    #[test]
+    fn decode_mpeg2_stereo() {
+        check(include_bytes!("../../tests/fixtures/sine_22050_stereo.mp3"),
+              include_bytes!("../../tests/fixtures/sine_22050_stereo.pcm"), 22050, true);
+    }
+
+    #[test]
+    fn decode_stops_at_garbage() {
+        let mut data = include_bytes!("../../tests/fixtures/sine_44100_mono.mp3").to_vec();
+        let reference = include_bytes!("../../tests/fixtures/sine_44100_mono.pcm");
+        data.push_all(b"TAG not a frame");
+        check(&data, reference, 44100, false);
+    }
+}
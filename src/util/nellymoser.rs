@@ -0,0 +1,367 @@
+use std::old_io::{ IoResult, IoError, InvalidInput };
+use std::f32::consts::PI;
+use std::num::Float;
+use util::sound_stream::{ SoundFormat, CodecKind };
+
+// Nellymoser Asao decoding, ported from FFmpeg's nellymoser.c and nellymoserdec.c. Like them, this file is
+// licensed under the LGPL version 2.1 or later
+
+pub const BLOCK_SIZE: usize = 64;
+pub const BLOCK_SAMPLES: usize = 256;
+
+const BANDS: usize = 23;
+const HEADER_BITS: usize = 116;
+const DETAIL_BITS: i32 = 198;
+const BUF_LEN: usize = 128;
+const FILL_LEN: usize = 124;
+const BIT_CAP: i32 = 6;
+const BASE_OFF: i32 = 4228;
+const BASE_SHIFT: i32 = 19;
+
+const DEQUANTIZATION_TABLE: [f32; 127] = [
+    0.0000000000,
+
+    -0.8472560048, 0.7224709988,
+
+    -1.5247479677, -0.4531480074, 0.3753609955, 1.4717899561,
+
+    -1.9822579622, -1.1929379702, -0.5829370022, -0.0693780035, 0.3909569979, 0.9069200158, 1.4862740040, 2.2215409279,
+
+    -2.3887870312, -1.8067539930, -1.4105420113, -1.0773609877, -0.7995010018, -0.5558109879, -0.3334020078, -0.1324490011,
+    0.0568020009, 0.2548770010, 0.4773550034, 0.7386850119, 1.0443060398, 1.3954459429, 1.8098750114, 2.3918759823,
+
+    -2.3893830776, -1.9884680510, -1.7514040470, -1.5643119812, -1.3922129869, -1.2164649963, -1.0469499826, -0.8905100226,
+    -0.7645580173, -0.6454579830, -0.5259280205, -0.4059549868, -0.3029719889, -0.2096900046, -0.1239869967, -0.0479229987,
+    0.0257730000, 0.1001340002, 0.1737180054, 0.2585540116, 0.3522900045, 0.4569880068, 0.5767750144, 0.7003160119,
+    0.8425520062, 1.0093879700, 1.1821349859, 1.3534560204, 1.5320819616, 1.7332619429, 1.9722349644, 2.3978140354,
+
+    -2.5756309032, -2.0573320389, -1.8984919786, -1.7727810144, -1.6662600040, -1.5742180347, -1.4993319511, -1.4316639900,
+    -1.3652280569, -1.3000990152, -1.2280930281, -1.1588579416, -1.0921250582, -1.0135740042, -0.9202849865, -0.8287050128,
+    -0.7374889851, -0.6447759867, -0.5590940118, -0.4857139885, -0.4110319912, -0.3459700048, -0.2851159871, -0.2341620028,
+    -0.1870580018, -0.1442500055, -0.1107169986, -0.0739680007, -0.0365610011, -0.0073290002, 0.0203610007, 0.0479039997,
+    0.0751969963, 0.0980999991, 0.1220389977, 0.1458999962, 0.1694349945, 0.1970459968, 0.2252430022, 0.2556869984,
+    0.2870100141, 0.3197099864, 0.3525829911, 0.3889069855, 0.4334920049, 0.4769459963, 0.5204820037, 0.5644530058,
+    0.6122040153, 0.6685929894, 0.7341650128, 0.8032159805, 0.8784040213, 0.9566209912, 1.0397069454, 1.1293770075,
+    1.2211159468, 1.3080279827, 1.4024800062, 1.5056819916, 1.6227730513, 1.7724959850, 1.9430880547, 2.2903931141
+];
+
+const BAND_SIZES_TABLE: [usize; BANDS] = [
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 4, 4, 5, 6, 6, 7, 8, 9, 10, 12, 14, 15
+];
+
+const INIT_TABLE: [u16; 64] = [
+    3134, 5342, 6870, 7792, 8569, 9185, 9744, 10191, 10631, 11061, 11434, 11770,
+    12116, 12513, 12925, 13300, 13674, 14027, 14352, 14716, 15117, 15477, 15824,
+    16157, 16513, 16804, 17090, 17401, 17679, 17948, 18238, 18520, 18764, 19078,
+    19381, 19640, 19921, 20205, 20500, 20813, 21162, 21465, 21794, 22137, 22453,
+    22756, 23067, 23350, 23636, 23926, 24227, 24521, 24819, 25107, 25414, 25730,
+    26120, 26497, 26895, 27344, 27877, 28463, 29426, 31355
+];
+
+const DELTA_TABLE: [i16; 32] = [
+    -11725, -9420, -7910, -6801, -5948, -5233, -4599, -4039, -3507, -3030, -2596,
+    -2170, -1774, -1383, -1016, -660, -329, -1, 337, 696, 1085, 1512, 1962, 2433,
+    2968, 3569, 4314, 5279, 6622, 8154, 10076, 12975
+];
+
+// Nellymoser Asao, always mono and coded in 64 byte blocks of two overlapping transforms. The overlap
+// carries from block to block, so one decoder must be used for a whole sound
+#[derive(Debug, Clone)]
+pub struct NellymoserDecoder {
+    sample_rate: u32,
+    window: Vec<f32>,
+    imdct: Vec<f32>,
+    previous: Vec<f32>,
+    seed: u32,
+}
+
+impl NellymoserDecoder {
+    pub fn new(format: &SoundFormat) -> IoResult<Self> {
+        match format.codec() {
+            CodecKind::Nellymoser16kHz | CodecKind::Nellymoser8kHz | CodecKind::Nellymoser => (),
+            _ => return Err(IoError { kind: InvalidInput, desc: "Nellymoser sound expected", detail: None })
+        }
+
+        // The sine window and the cosines of the 256 point inverse MDCT, of which only the middle half is kept
+        let window = (0..BUF_LEN).map(|i| ((i as f32 + 0.5) * PI / (2 * BUF_LEN) as f32).sin()).collect();
+        let mut imdct = Vec::with_capacity(BUF_LEN * BUF_LEN);
+        for n in 0..BUF_LEN {
+            for k in 0..BUF_LEN {
+                let phase = PI / BUF_LEN as f32 * (n as f32 + (BUF_LEN / 2) as f32 + 0.5 + (BUF_LEN / 2) as f32) * (k as f32 + 0.5);
+                imdct.push(-phase.cos());
+            }
+        }
+
+        Ok(NellymoserDecoder {
+            sample_rate: format.sample_rate(),
+            window: window,
+            imdct: imdct,
+            previous: vec![0.0; BUF_LEN],
+            seed: 0
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Trailing partial blocks are ignored by the Flash player
+    pub fn sample_count(&self, data: &[u8]) -> usize {
+        data.len() / BLOCK_SIZE * BLOCK_SAMPLES
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> IoResult<Vec<i16>> {
+        let mut samples = Vec::with_capacity(self.sample_count(data));
+        for block in data.chunks(BLOCK_SIZE).filter(|b| b.len() == BLOCK_SIZE) {
+            samples.extend(try!(self.decode_block(block)).into_iter());
+        }
+        Ok(samples)
+    }
+
+    pub fn decode_block(&mut self, block: &[u8]) -> IoResult<Vec<i16>> {
+        if block.len() != BLOCK_SIZE {
+            return Err(IoError { kind: InvalidInput, desc: "Nellymoser blocks are 64 bytes", detail: None })
+        }
+
+        // The band energies come first, as a start value and deltas in the log domain
+        let mut bits = BlockBits { data: block, position: 0 };
+        let mut energies = Vec::with_capacity(FILL_LEN);
+        let mut powers = Vec::with_capacity(FILL_LEN);
+        let mut energy = INIT_TABLE[bits.read(6) as usize] as f32;
+        for band in 0..BANDS {
+            if band > 0 {
+                energy += DELTA_TABLE[bits.read(5) as usize] as f32;
+            }
+            let power = -(energy / 2048.0).exp2() / (32768.0 * 8.0);
+            for _ in 0..BAND_SIZES_TABLE[band] {
+                energies.push(energy);
+                powers.push(power);
+            }
+        }
+        let allocation = sample_bits(&energies);
+
+        // Both halves share the allocation, coefficients without bits are filled with noise of the band's power
+        let mut samples = Vec::with_capacity(BLOCK_SAMPLES);
+        for half in 0..2 {
+            let mut bits = BlockBits { data: block, position: HEADER_BITS + half * DETAIL_BITS as usize };
+            let mut coefficients = [0.0f32; BUF_LEN];
+            for i in 0..FILL_LEN {
+                coefficients[i] = if allocation[i] <= 0 {
+                    self.seed = ((self.seed as u64 * 1664525 + 1013904223) & 0xffffffff) as u32;
+                    let sign = if (self.seed >> 16) & 1 != 0 { -1.0 } else { 1.0 };
+                    sign * 0.5f32.sqrt() * powers[i]
+                } else {
+                    let value = bits.read(allocation[i] as usize) as usize;
+                    DEQUANTIZATION_TABLE[(1 << allocation[i] as usize) - 1 + value] * powers[i]
+                };
+            }
+
+            let output: Vec<f32> = (0..BUF_LEN).map(|n| {
+                let row = &self.imdct[n * BUF_LEN..(n + 1) * BUF_LEN];
+                row.iter().zip(coefficients.iter()).fold(0.0, |sum, (c, v)| sum + c * v)
+            }).collect();
+
+            // Overlap the second half of the last transform with the first half of this one
+            let mut windowed = [0.0f32; BUF_LEN];
+            for i in 0..BUF_LEN / 2 {
+                let (previous, current) = (self.previous[BUF_LEN / 2 + i], output[BUF_LEN / 2 - 1 - i]);
+                let (wi, wj) = (self.window[i], self.window[BUF_LEN - 1 - i]);
+                windowed[i] = previous * wj - current * wi;
+                windowed[BUF_LEN - 1 - i] = previous * wi + current * wj;
+            }
+            samples.extend(windowed.iter().map(|&v| {
+                let v = (v * 32768.0).round();
+                if v > 32767.0 { 32767 } else if v < -32768.0 { -32768 } else { v as i16 }
+            }));
+            self.previous = output;
+        }
+
+        Ok(samples)
+    }
+}
+
+// Blocks are read from the lowest bit of each byte up
+struct BlockBits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BlockBits<'a> {
+    fn read(&mut self, count: usize) -> u32 {
+        let mut value = 0;
+        for i in 0..count {
+            let bit = self.data[self.position >> 3] >> (self.position & 7) & 1;
+            value |= (bit as u32) << i;
+            self.position += 1;
+        }
+        value
+    }
+}
+
+// The bits given to each coefficient, found by searching for the offset from the band energies that
+// spends exactly the detail bits of a half. Fixed point as in the reference, since encoders rely on it
+fn sample_bits(energies: &[f32]) -> Vec<i32> {
+    let mut max = 0;
+    for &energy in energies.iter() {
+        if energy > max as f32 {
+            max = energy as i32;
+        }
+    }
+    let mut shift = -16 + headroom(&mut max);
+
+    let mut scaled = Vec::with_capacity(FILL_LEN);
+    let mut sum = 0;
+    for &energy in energies.iter() {
+        let value = signed_shift(energy as i32, shift) as i16 as i32;
+        let value = ((3 * value) >> 2) as i16 as i32;
+        scaled.push(value);
+        sum += value;
+    }
+
+    shift += 11;
+    let shift_saved = shift;
+    sum -= DETAIL_BITS << shift as usize;
+    shift += headroom(&mut sum);
+    let mut small_off = (BASE_OFF * (sum >> 16)) >> 15;
+    shift = shift_saved - (BASE_SHIFT + shift - 31);
+    small_off = signed_shift(small_off, shift);
+
+    let mut bitsum = sum_bits(&scaled, shift_saved, small_off);
+    if bitsum != DETAIL_BITS {
+        let mut off = bitsum - DETAIL_BITS;
+        shift = 0;
+        while off.abs() <= 16383 {
+            off *= 2;
+            shift += 1;
+        }
+        off = (off * BASE_OFF) >> 15;
+        shift = shift_saved - (BASE_SHIFT + shift - 15);
+        off = signed_shift(off, shift);
+
+        let (mut last_off, mut last_bitsum) = (small_off, bitsum);
+        let mut j = 1;
+        while j < 20 {
+            last_off = small_off;
+            small_off += off;
+            last_bitsum = bitsum;
+            bitsum = sum_bits(&scaled, shift_saved, small_off);
+            if (bitsum - DETAIL_BITS) * (last_bitsum - DETAIL_BITS) <= 0 {
+                break
+            }
+            j += 1;
+        }
+
+        let (mut big_off, mut big_bitsum, mut small_bitsum) = if bitsum > DETAIL_BITS {
+            let big_off = small_off;
+            small_off = last_off;
+            (big_off, bitsum, last_bitsum)
+        } else {
+            (last_off, last_bitsum, bitsum)
+        };
+
+        while bitsum != DETAIL_BITS && j <= 19 {
+            off = (big_off + small_off) >> 1;
+            bitsum = sum_bits(&scaled, shift_saved, off);
+            if bitsum > DETAIL_BITS {
+                big_off = off;
+                big_bitsum = bitsum;
+            } else {
+                small_off = off;
+                small_bitsum = bitsum;
+            }
+            j += 1;
+        }
+
+        if (big_bitsum - DETAIL_BITS).abs() >= (small_bitsum - DETAIL_BITS).abs() {
+            bitsum = small_bitsum;
+        } else {
+            small_off = big_off;
+            bitsum = big_bitsum;
+        }
+    }
+
+    let mut bits: Vec<i32> = scaled.iter().map(|&value| coefficient_bits(value, shift_saved, small_off)).collect();
+    if bitsum > DETAIL_BITS {
+        let (mut total, mut i) = (0, 0);
+        while total < DETAIL_BITS {
+            total += bits[i];
+            i += 1;
+        }
+        bits[i - 1] -= total - DETAIL_BITS;
+        for bits in bits[i..].iter_mut() {
+            *bits = 0;
+        }
+    }
+    bits
+}
+
+fn coefficient_bits(value: i32, shift: i32, off: i32) -> i32 {
+    let bits = (((value - off) >> (shift - 1) as usize) + 1) >> 1;
+    if bits < 0 { 0 } else if bits > BIT_CAP { BIT_CAP } else { bits }
+}
+
+fn sum_bits(scaled: &[i32], shift: i32, off: i32) -> i32 {
+    scaled.iter().fold(0, |sum, &value| sum + coefficient_bits(value, shift, off))
+}
+
+fn signed_shift(value: i32, shift: i32) -> i32 {
+    if shift > 0 { value << shift as usize } else { value >> (-shift) as usize }
+}
+
+// Normalises the value so its top bit is bit 30, returning the shift used
+fn headroom(value: &mut i32) -> i32 {
+    if *value == 0 {
+        return 31
+    }
+    let mut log2 = 0;
+    while (value.abs() as u32) >> (log2 + 1) != 0 {
+        log2 += 1;
+    }
+    let shift = 30 - log2;
+    *value <<= shift as usize;
+    shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ NellymoserDecoder, BLOCK_SIZE };
+    use util::sound_stream::{ SoundFormat, CodecKind };
+
+    // FFmpeg's encoding of a sine mix, with its decoding as the reference. Its FFT based transform and
+    // conversion from float round differently
+    const TOLERANCE: i32 = 1;
+
+    #[test]
+    fn decode_matches_reference() {
+        let flv = include_bytes!("../../tests/fixtures/nellymoser_22050.flv");
+        let reference = include_bytes!("../../tests/fixtures/nellymoser_22050.pcm");
+        let format = SoundFormat::from_parts(CodecKind::Nellymoser, 22050, true, false).unwrap();
+
+        // The FLV audio tags each hold one block after their sound format byte
+        let mut data = Vec::new();
+        let mut pos = 13;
+        while pos < flv.len() {
+            let size = (flv[pos + 1] as usize) << 16 | (flv[pos + 2] as usize) << 8 | flv[pos + 3] as usize;
+            if flv[pos] == 8 {
+                data.push_all(&flv[pos + 12..pos + 11 + size]);
+            }
+            pos += 11 + size + 4;
+        }
+
+        let mut decoder = NellymoserDecoder::new(&format).unwrap();
+        let samples = decoder.decode(&data).unwrap();
+        let expected: Vec<i16> = reference.chunks(2).map(|c| (c[0] as u16 | (c[1] as u16) << 8) as i16).collect();
+
+        assert_eq!(samples.len(), expected.len());
+        assert_eq!(decoder.sample_count(&data[..data.len() - 1]), expected.len() - 256);
+        for (i, (&sample, &expected)) in samples.iter().zip(expected.iter()).enumerate() {
+            assert!((sample as i32 - expected as i32).abs() <= TOLERANCE, "sample {}: {} != {}", i, sample, expected);
+        }
+
+        // The overlap and noise seed carry over, so decoding block by block gives the same samples
+        let mut decoder = NellymoserDecoder::new(&format).unwrap();
+        let blocks: Vec<i16> = data.chunks(BLOCK_SIZE).flat_map(|b| decoder.decode_block(b).unwrap().into_iter()).collect();
+        assert!(blocks == samples);
+        assert!(decoder.decode_block(&data[..10]).is_err());
+    }
+}
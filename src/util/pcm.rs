@@ -0,0 +1,33 @@
+use std::old_io::{ IoResult, IoError, InvalidInput };
+use util::sound_stream::{ SoundFormat, CodecKind };
+use util::adpcm::AdpcmDecoder;
+use util::nellymoser::NellymoserDecoder;
+#[cfg(feature = "mp3")]
+use util::mp3_decoder::Mp3Decoder;
+
+// Decodes sound data in any codec to interleaved 16-bit samples
+// Streaming ADPCM must be decoded one SoundStreamBlock at a time, see `SoundStream::read_block`,
+// while streaming MP3 and Nellymoser must be decoded in one go as MP3 frames use the bit reservoir
+// of earlier blocks and Nellymoser blocks overlap
+pub fn decode_pcm(format: &SoundFormat, data: &[u8]) -> IoResult<Vec<i16>> {
+    match format.codec() {
+        // Native endian data was written on little-endian machines in practice
+        CodecKind::UncompressedNative | CodecKind::Uncompressed => Ok(decode_uncompressed(format, data)),
+        CodecKind::ADPCM => try!(AdpcmDecoder::new(format)).decode(data),
+        CodecKind::Nellymoser16kHz | CodecKind::Nellymoser8kHz | CodecKind::Nellymoser =>
+            try!(NellymoserDecoder::new(format)).decode(data),
+
+        #[cfg(feature = "mp3")]
+        CodecKind::MP3 => try!(Mp3Decoder::new(format)).decode(data),
+
+        _ => Err(IoError { kind: InvalidInput, desc: "unsupported audio codec", detail: None })
+    }
+}
+
+fn decode_uncompressed(format: &SoundFormat, data: &[u8]) -> Vec<i16> {
+    if format.is_16bit() {
+        data.chunks(2).filter(|c| c.len() == 2).map(|c| (c[0] as u16 | (c[1] as u16) << 8) as i16).collect()
+    } else {
+        data.iter().map(|&v| ((v as i16) - 0x80) << 8).collect()
+    }
+}
@@ -0,0 +1,456 @@
+use std::old_io::{ Reader, Writer, BufReader, IoResult, IoError, InvalidInput };
+use std::cmp::min;
+use std::iter::repeat;
+use bits::BitReader;
+use zlib;
+use util::video::{ VideoStream, VideoFrame, VideoCodec };
+
+// Screen Video and Screen Video V2, video codecs 3 and 6. Frames are a grid of zlib compressed blocks stored
+// from the bottom left, and blocks that did not change since the previous frame are left out
+pub struct ScreenVideoDecoder {
+    codec: VideoCodec,
+    width: usize,
+    height: usize,
+    image: Vec<u8>,
+
+    // V2 blocks may restore rows from the last keyframe, or continue the deflate stream of a block of the
+    // keyframe or of the current frame
+    keyframe: Option<Vec<u8>>,
+    primes: Vec<Vec<u8>>,
+    palette: Vec<u32>,
+}
+
+impl ScreenVideoDecoder {
+    pub fn new(stream: &VideoStream) -> IoResult<Self> {
+        if stream.codec != VideoCodec::ScreenVideo && stream.codec != VideoCodec::ScreenVideo2 {
+            return Err(IoError { kind: InvalidInput, desc: "Screen Video stream expected", detail: None })
+        }
+
+        let mut decoder = ScreenVideoDecoder {
+            codec: stream.codec,
+            width: 0,
+            height: 0,
+            image: Vec::new(),
+            keyframe: None,
+            primes: Vec::new(),
+            palette: DEFAULT_PALETTE.to_vec()
+        };
+        decoder.resize(stream.width as usize, stream.height as usize);
+        Ok(decoder)
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width as u16
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height as u16
+    }
+
+    // Applies a frame to the image and returns it as RGBA rows from the top, `width()` by `height()` pixels
+    pub fn decode(&mut self, frame: &VideoFrame) -> IoResult<Vec<u8>> {
+        let v2 = self.codec == VideoCodec::ScreenVideo2;
+        let keyframe = v2 && frame.is_keyframe(self.codec);
+
+        let mut bits = BitReader::new(BufReader::new(&frame.data[..]));
+        let block_width = (try!(bits.read_ubits(4)) as usize + 1) * 16;
+        let width = try!(bits.read_ubits(12)) as usize;
+        let block_height = (try!(bits.read_ubits(4)) as usize + 1) * 16;
+        let height = try!(bits.read_ubits(12)) as usize;
+        let mut reader = bits.into_inner();
+
+        let (has_iframe, has_palette) = if v2 {
+            let flags = try!(reader.read_u8());
+            (flags & 0x2 != 0, flags & 0x1 != 0)
+        } else {
+            (false, false)
+        };
+
+        // FFmpeg does not decode I-frame images either, and no stream using them was found to check against
+        if has_iframe {
+            return Err(IoError { kind: InvalidInput, desc: "Screen Video V2 I-frame images are unsupported", detail: None })
+        }
+
+        if width != self.width || height != self.height {
+            self.resize(width, height);
+        }
+
+        // A custom palette is sent as a block of up to 128 BGR colors and replaces the default one
+        if has_palette {
+            if let Some((header, data)) = try!(read_block(&mut reader, true)) {
+                let colors = try!(self.inflate(&header, 0, 0, &data, &[]));
+                for (entry, bgr) in self.palette.iter_mut().zip(colors.chunks(3).filter(|c| c.len() == 3)) {
+                    *entry = (bgr[2] as u32) << 16 | (bgr[1] as u32) << 8 | bgr[0] as u32;
+                }
+            }
+        }
+
+        let columns = (width + block_width - 1) / block_width;
+        let rows = (height + block_height - 1) / block_height;
+        let mut current = repeat(Vec::new()).take(columns * rows).collect::<Vec<_>>();
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let block = Block {
+                    x: column * block_width,
+                    bottom: row * block_height,
+                    width: min(block_width, width - column * block_width),
+                    height: min(block_height, height - row * block_height)
+                };
+                let index = row * columns + column;
+                let (header, data) = match try!(read_block(&mut reader, v2)) {
+                    Some(block) => block,
+                    None => continue
+                };
+
+                // Only some rows changed, the others are those of the last keyframe
+                let (start, lines) = header.diff.unwrap_or((0, block.height));
+                if header.diff.is_some() {
+                    if start + lines > block.height {
+                        return Err(block_error())
+                    }
+                    let image = match self.keyframe {
+                        Some(ref image) => image,
+                        None => return Err(IoError { kind: InvalidInput, desc: "Screen Video V2 block without a keyframe", detail: None })
+                    };
+                    for line in 0..block.height {
+                        let offset = ((height - block.bottom - line - 1) * width + block.x) * 4;
+                        self.image[offset..offset + block.width * 4].clone_from_slice(&image[offset..offset + block.width * 4]);
+                    }
+                }
+
+                let pixels = try!(self.inflate(&header, index, columns, &data, &current));
+                let block = Block { bottom: block.bottom + start, height: lines, ..block };
+                if header.hybrid {
+                    try!(self.draw_hybrid(&block, &pixels));
+                } else {
+                    try!(self.draw_bgr(&block, &pixels));
+                }
+                current[index] = pixels;
+            }
+        }
+
+        if keyframe {
+            self.keyframe = Some(self.image.clone());
+            self.primes = current;
+        }
+        Ok(self.image.clone())
+    }
+
+    // Decompresses a block, continuing the deflate stream of its prime when it has one
+    fn inflate(&self, header: &BlockHeader, index: usize, columns: usize, data: &[u8], current: &[Vec<u8>]) -> IoResult<Vec<u8>> {
+        let prime = match header.prime {
+            Prime::None => return zlib::decompress(data),
+            Prime::Previous => self.primes.get(index),
+            Prime::Current(column, row) if column < columns => current.get(row * columns + column),
+            _ => None
+        };
+        match prime {
+            Some(prime) if !prime.is_empty() => decompress_primed(prime, data),
+            _ => Err(IoError { kind: InvalidInput, desc: "Screen Video V2 block primed from a block that was not sent", detail: None })
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.image = Vec::with_capacity(width * height * 4);
+        for _ in 0..width * height {
+            self.image.push_all(&[0, 0, 0, 255]);
+        }
+        self.keyframe = None;
+        self.primes.clear();
+    }
+
+    // Rows of 24-bit BGR pixels from the bottom of the block
+    fn draw_bgr(&mut self, block: &Block, pixels: &[u8]) -> IoResult<()> {
+        if pixels.len() < block.width * block.height * 3 {
+            return Err(block_error())
+        }
+
+        let mut source = pixels.chunks(3);
+        for line in 0..block.height {
+            let offset = ((self.height - block.bottom - line - 1) * self.width + block.x) * 4;
+            for pixel in self.image[offset..offset + block.width * 4].chunks_mut(4) {
+                let bgr = source.next().unwrap();
+                pixel[0] = bgr[2];
+                pixel[1] = bgr[1];
+                pixel[2] = bgr[0];
+            }
+        }
+        Ok(())
+    }
+
+    // Pixels with the top bit set are 15-bit RGB in two bytes, the others index the palette
+    fn draw_hybrid(&mut self, block: &Block, pixels: &[u8]) -> IoResult<()> {
+        let scale = |c: u16| ((c << 3) | (c >> 2)) as u8;
+
+        let mut position = 0;
+        for line in 0..block.height {
+            let offset = ((self.height - block.bottom - line - 1) * self.width + block.x) * 4;
+            for pixel in self.image[offset..offset + block.width * 4].chunks_mut(4) {
+                let first = match pixels.get(position) {
+                    Some(&byte) => byte,
+                    None => return Err(block_error())
+                };
+                if first & 0x80 != 0 {
+                    let second = match pixels.get(position + 1) {
+                        Some(&byte) => byte,
+                        None => return Err(block_error())
+                    };
+                    let color = (first as u16 & 0x7f) << 8 | second as u16;
+                    pixel[0] = scale(color >> 10);
+                    pixel[1] = scale((color >> 5) & 0x1f);
+                    pixel[2] = scale(color & 0x1f);
+                    position += 2;
+                } else {
+                    let color = self.palette[first as usize];
+                    pixel[0] = (color >> 16) as u8;
+                    pixel[1] = (color >> 8) as u8;
+                    pixel[2] = color as u8;
+                    position += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// A block in pixels, `bottom` counts up from the last row of the image
+#[derive(Copy, Clone)]
+struct Block {
+    x: usize,
+    bottom: usize,
+    width: usize,
+    height: usize,
+}
+
+struct BlockHeader {
+    hybrid: bool,
+    diff: Option<(usize, usize)>,
+    prime: Prime,
+}
+
+#[derive(Copy, Clone)]
+enum Prime {
+    None,
+    Previous,
+    Current(usize, usize),
+}
+
+// Reads the size, V2 format and compressed data of a block, `None` for a block that did not change.
+// A block primed from the current frame names the column and row of its prime. The layout follows FFmpeg's
+// flashsv decoder, which names the SWF 10 specification as its source
+fn read_block<R : Reader>(reader: &mut R, v2: bool) -> IoResult<Option<(BlockHeader, Vec<u8>)>> {
+    let mut size = try!(reader.read_be_u16()) as usize;
+    if size == 0 {
+        return Ok(None)
+    }
+
+    let mut header = BlockHeader { hybrid: false, diff: None, prime: Prime::None };
+    if v2 {
+        let format = try!(reader.read_u8());
+        size -= 1;
+        header.hybrid = match (format >> 3) & 0x3 {
+            0 => false,
+            2 => true,
+            _ => return Err(IoError { kind: InvalidInput, desc: "invalid Screen Video V2 color depth", detail: None })
+        };
+        if format & 0x4 != 0 {
+            if size < 2 {
+                return Err(block_error())
+            }
+            let start = try!(reader.read_u8()) as usize;
+            header.diff = Some((start, try!(reader.read_u8()) as usize));
+            size -= 2;
+        }
+        if format & 0x3 == 0x3 {
+            return Err(IoError { kind: InvalidInput, desc: "Screen Video V2 block primed twice", detail: None })
+        }
+        if format & 0x2 != 0 {
+            if size < 2 {
+                return Err(block_error())
+            }
+            let (column, row) = (try!(reader.read_u8()) as usize, try!(reader.read_u8()) as usize);
+            size -= 2;
+            header.prime = Prime::Current(column, row);
+        } else if format & 0x1 != 0 {
+            header.prime = Prime::Previous;
+        }
+    }
+
+    Ok(Some((header, try!(reader.read_exact(size)))))
+}
+
+fn block_error() -> IoError {
+    IoError { kind: InvalidInput, desc: "invalid Screen Video block", detail: None }
+}
+
+// A primed block continues a deflate stream that first held the block's pixels from the last keyframe,
+// so those pixels are stored ahead of it and dropped from the output
+fn decompress_primed(prime: &[u8], data: &[u8]) -> IoResult<Vec<u8>> {
+    let mut stream = Vec::with_capacity(prime.len() + data.len() + 16);
+    for chunk in prime.chunks(0xffff) {
+        stream.push(0); // stored block, not final
+        try!(stream.write_le_u16(chunk.len() as u16));
+        try!(stream.write_le_u16(!(chunk.len() as u16)));
+        stream.push_all(chunk);
+    }
+    stream.push_all(data);
+
+    let pixels = try!(zlib::decompress_raw(&stream));
+    if pixels.len() < prime.len() {
+        return Err(block_error())
+    }
+    Ok(pixels[prime.len()..].to_vec())
+}
+
+static DEFAULT_PALETTE: [u32; 128] = [
+    0x000000, 0x333333, 0x666666, 0x999999, 0xCCCCCC, 0xFFFFFF, 0x330000, 0x660000,
+    0x990000, 0xCC0000, 0xFF0000, 0x003300, 0x006600, 0x009900, 0x00CC00, 0x00FF00,
+    0x000033, 0x000066, 0x000099, 0x0000CC, 0x0000FF, 0x333300, 0x666600, 0x999900,
+    0xCCCC00, 0xFFFF00, 0x003333, 0x006666, 0x009999, 0x00CCCC, 0x00FFFF, 0x330033,
+    0x660066, 0x990099, 0xCC00CC, 0xFF00FF, 0xFFFF33, 0xFFFF66, 0xFFFF99, 0xFFFFCC,
+    0xFF33FF, 0xFF66FF, 0xFF99FF, 0xFFCCFF, 0x33FFFF, 0x66FFFF, 0x99FFFF, 0xCCFFFF,
+    0xCCCC33, 0xCCCC66, 0xCCCC99, 0xCCCCFF, 0xCC33CC, 0xCC66CC, 0xCC99CC, 0xCCFFCC,
+    0x33CCCC, 0x66CCCC, 0x99CCCC, 0xFFCCCC, 0x999933, 0x999966, 0x9999CC, 0x9999FF,
+    0x993399, 0x996699, 0x99CC99, 0x99FF99, 0x339999, 0x669999, 0xCC9999, 0xFF9999,
+    0x666633, 0x666699, 0x6666CC, 0x6666FF, 0x663366, 0x669966, 0x66CC66, 0x66FF66,
+    0x336666, 0x996666, 0xCC6666, 0xFF6666, 0x333366, 0x333399, 0x3333CC, 0x3333FF,
+    0x336633, 0x339933, 0x33CC33, 0x33FF33, 0x663333, 0x993333, 0xCC3333, 0xFF3333,
+    0x003366, 0x336600, 0x660033, 0x006633, 0x330066, 0x663300, 0x336699, 0x669933,
+    0x993366, 0x339966, 0x663399, 0x996633, 0x6699CC, 0x99CC66, 0xCC6699, 0x66CC99,
+    0x9966CC, 0xCC9966, 0x99CCFF, 0xCCFF99, 0xFF99CC, 0x99FFCC, 0xCC99FF, 0xFFCC99,
+    0x111111, 0x222222, 0x444444, 0x555555, 0xAAAAAA, 0xBBBBBB, 0xDDDDDD, 0xEEEEEE
+];
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "zlib")]
+    use zlib;
+    use util::video::{ VideoStream, VideoFrame, VideoCodec };
+    use super::ScreenVideoDecoder;
+
+    fn stream(codec: VideoCodec, width: u16, height: u16) -> VideoStream {
+        VideoStream { id: 1, frame_count: 6, width: width, height: height, deblocking: 0, smoothing: false, codec: codec }
+    }
+
+    // FLV video tags hold the codec byte before the frame
+    #[cfg(feature = "zlib")]
+    fn frames(flv: &[u8]) -> Vec<VideoFrame> {
+        let mut frames = Vec::new();
+        let mut pos = 13;
+        while pos < flv.len() {
+            let size = (flv[pos + 1] as usize) << 16 | (flv[pos + 2] as usize) << 8 | flv[pos + 3] as usize;
+            if flv[pos] == 9 {
+                let data = flv[pos + 12..pos + 11 + size].to_vec();
+                frames.push(VideoFrame { stream_id: 1, frame_num: frames.len() as u16, data: data });
+            }
+            pos += 11 + size + 4;
+        }
+        frames
+    }
+
+    // Decodes every frame to RGB rows from the top, the layout of the references
+    #[cfg(feature = "zlib")]
+    fn decode(codec: VideoCodec, flv: &[u8]) -> Vec<u8> {
+        let mut decoder = ScreenVideoDecoder::new(&stream(codec, 40, 36)).unwrap();
+        let mut rgb = Vec::new();
+        for frame in frames(flv).iter() {
+            let image = decoder.decode(frame).unwrap();
+            assert_eq!((decoder.width(), decoder.height(), image.len()), (40, 36, 40 * 36 * 4));
+            for pixel in image.chunks(4) {
+                assert_eq!(pixel[3], 0xff);
+                rgb.push_all(&pixel[..3]);
+            }
+        }
+        rgb
+    }
+
+    // FFmpeg's encodings of six frames of a moving rectangle with keyframes every four frames. Both
+    // versions are lossless in BGR, so the source is their reference
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn decode_bgr() {
+        let reference = &include_bytes!("../../tests/fixtures/screen_video_40x36.rgb")[..];
+        assert!(decode(VideoCodec::ScreenVideo, include_bytes!("../../tests/fixtures/screen_video_40x36.flv")) == reference);
+
+        // Inter frames of the second version code the changed rows of their block primed from the keyframe
+        let flv = include_bytes!("../../tests/fixtures/screen_video2_40x36.flv");
+        let formats = frames(flv).iter().map(|frame| frame.data[7]).collect::<Vec<_>>();
+        assert_eq!(formats, vec![0x00, 0x05, 0x05, 0x05, 0x00, 0x05]);
+        assert!(decode(VideoCodec::ScreenVideo2, flv) == reference);
+    }
+
+    // The same frames in the hybrid palette and 15-bit format, FFmpeg's decoding is the reference
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn decode_hybrid() {
+        let flv = include_bytes!("../../tests/fixtures/screen_video2_hybrid_40x36.flv");
+        let formats = frames(flv).iter().map(|frame| frame.data[7]).collect::<Vec<_>>();
+        assert_eq!(formats, vec![0x10, 0x15, 0x15, 0x15, 0x10, 0x15]);
+        let keyframes = frames(flv).iter().map(|frame| frame.is_keyframe(VideoCodec::ScreenVideo2)).collect::<Vec<_>>();
+        assert_eq!(keyframes, vec![true, false, false, false, true, false]);
+        let reference = &include_bytes!("../../tests/fixtures/screen_video2_hybrid_40x36.rgb")[..];
+        assert!(decode(VideoCodec::ScreenVideo2, flv) == reference);
+    }
+
+    #[cfg(feature = "zlib")]
+    fn write_block(data: &mut Vec<u8>, format: u8, prime: &[u8], bytes: &[u8]) {
+        let size = 1 + prime.len() + bytes.len();
+        data.push_all(&[(size >> 8) as u8, size as u8, format]);
+        data.push_all(prime);
+        data.push_all(bytes);
+    }
+
+    // A 32x16 keyframe with a custom palette of blue and orange and two hybrid blocks. The second block is
+    // the first with a red 15-bit pixel in its bottom left, deflated with the first as the prime
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn custom_palette_and_current_prime() {
+        let first = (0..256).map(|i| ((i % 16 + i / 16) & 1) as u8).collect::<Vec<_>>();
+        let palette = zlib::compress(&[0xff, 0x00, 0x00, 0x00, 0x80, 0xff]).unwrap();
+        let block = zlib::compress(&first).unwrap();
+        let primed = [0xfb, 0xc3, 0x30, 0xc2, 0x03, 0x00, 0x00];
+
+        let mut data = vec![0x00, 0x20, 0x00, 0x10, 0x01];
+        for &(format, ref prime, ref bytes) in [(0x00, vec![], palette), (0x10, vec![], block),
+                                                (0x12, vec![0, 0], primed.to_vec())].iter() {
+            write_block(&mut data, format, prime, bytes);
+        }
+        let frame = VideoFrame { stream_id: 1, frame_num: 0, data: data };
+
+        let mut decoder = ScreenVideoDecoder::new(&stream(VideoCodec::ScreenVideo2, 32, 16)).unwrap();
+        let image = decoder.decode(&frame).unwrap();
+        for y in 0..16 {
+            for x in 0..32 {
+                let expected = if x == 16 && y == 15 {
+                    [0xff, 0x00, 0x00, 0xff]
+                } else if (x % 16 + 15 - y) & 1 == 0 {
+                    [0x00, 0x00, 0xff, 0xff]
+                } else {
+                    [0xff, 0x80, 0x00, 0xff]
+                };
+                let offset = (y * 32 + x) * 4;
+                assert!(image[offset..offset + 4] == expected, "pixel {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_frames() {
+        let mut decoder = ScreenVideoDecoder::new(&stream(VideoCodec::ScreenVideo2, 16, 16)).unwrap();
+
+        // I-frame images and blocks with both prime flags are left out, as FFmpeg does
+        let frame = VideoFrame { stream_id: 1, frame_num: 0, data: vec![0x00, 0x10, 0x00, 0x10, 0x02, 0x00, 0x00] };
+        assert!(decoder.decode(&frame).is_err());
+        let frame = VideoFrame { stream_id: 1, frame_num: 0, data: vec![0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x04, 0x13,
+            0x00, 0x00, 0x00] };
+        assert!(decoder.decode(&frame).is_err());
+
+        // A diff block needs a keyframe to restore its other rows from
+        let frame = VideoFrame { stream_id: 1, frame_num: 0, data: vec![0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x04, 0x04,
+            0x00, 0x01, 0x00] };
+        assert!(decoder.decode(&frame).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::old_io::{ Reader, Writer, ByRefReader, BufReader, IoResult, IoError, InvalidInput, EndOfFile };
+use std::collections::{ HashMap, HashSet };
+use swf::{ Magic, Header, Compression, Tag, TagKind };
+use util::sound_stream::{ SoundFormat, CodecKind };
+use util::adpcm::AdpcmEncoder;
+use util::mp3::{ Mp3Frame, split_frames };
+use zlib;
+
+// New audio for a DefineSound character or a streaming soundtrack
+#[derive(Debug, Clone)]
+pub struct SoundImport {
+    pub format: SoundFormat,
+    source: Source,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    Pcm(Vec<i16>),
+    Mp3(Vec<u8>, Vec<Mp3Frame>),
+}
+
+// Swaps DefineSound characters and streaming soundtracks while copying a SWF file
+pub struct SoundReplacer {
+    sounds: HashMap<u16, SoundImport>,
+    streams: HashMap<Option<u16>, SoundImport>,
+}
+
+impl SoundImport {
+    // Interleaved PCM, stored as 16-bit uncompressed or as ADPCM sound
+    pub fn from_pcm(samples: Vec<i16>, sample_rate: u32, channels: u16, codec: CodecKind) -> IoResult<Self> {
+        match codec {
+            CodecKind::Uncompressed | CodecKind::ADPCM => (),
+            _ => return Err(IoError { kind: InvalidInput, desc: "PCM can only be stored uncompressed or as ADPCM", detail: None })
+        }
+        if channels != 1 && channels != 2 {
+            return Err(IoError { kind: InvalidInput, desc: "sound must be mono or stereo", detail: None })
+        }
+
+        let format = try!(SoundFormat::from_parts(codec, sample_rate, true, channels == 2).ok_or(
+            IoError { kind: InvalidInput, desc: "sample rate not supported by SWF", detail: None }
+        ));
+
+        Ok(SoundImport {
+            format: format,
+            source: Source::Pcm(samples)
+        })
+    }
+
+    // The contents of an MP3 file, ID3 tags are dropped
+    pub fn from_mp3(data: Vec<u8>) -> IoResult<Self> {
+        let mut data = data;
+        let length = data.len();
+        if length >= 128 && &data[length - 128..length - 125] == &b"TAG"[..] {
+            data.truncate(length - 128);
+        }
+
+        let frames = split_frames(&data);
+        let first = *try!(frames.first().ok_or(
+            IoError { kind: InvalidInput, desc: "no MP3 frames found", detail: None }
+        ));
+        if frames.iter().any(|f| f.sample_rate != first.sample_rate || f.channels != first.channels) {
+            return Err(IoError { kind: InvalidInput, desc: "MP3 frames change sample rate or channel count", detail: None })
+        }
+
+        let format = try!(SoundFormat::from_parts(CodecKind::MP3, first.sample_rate, true, first.channels == 2).ok_or(
+            IoError { kind: InvalidInput, desc: "MP3 sample rate not supported by SWF", detail: None }
+        ));
+
+        Ok(SoundImport {
+            format: format,
+            source: Source::Mp3(data, frames)
+        })
+    }
+
+    // Samples per channel
+    pub fn sample_count(&self) -> u32 {
+        match self.source {
+            Source::Pcm(ref samples) => (samples.len() / self.format.channels() as usize) as u32,
+            Source::Mp3(_, ref frames) => frames.iter().fold(0, |count, f| count + f.samples())
+        }
+    }
+
+    pub fn write_define_sound<W : Writer>(&self, id: u16, writer: &mut W) -> IoResult<()> {
+        let mut body = Vec::new();
+        try!(body.write_le_u16(id));
+        try!(body.write_u8(self.format.to_u8()));
+        try!(body.write_le_u32(self.sample_count()));
+        match self.source {
+            Source::Pcm(ref samples) => body.push_all(&try!(self.encode(samples))),
+            Source::Mp3(ref data, ref frames) => {
+                try!(body.write_le_u16(0)); // seek samples, the encoder delay is not known
+                body.push_all(frame_data(data, frames));
+            }
+        }
+
+        write_tag(writer, TagKind::DefineSound, &body)
+    }
+
+    // The SoundStreamHead2 body and one SoundStreamBlock body per frame, `frame_rate` in frames per second
+    pub fn stream(&self, frame_rate: f32) -> IoResult<(Vec<u8>, Vec<Vec<u8>>)> {
+        if !(frame_rate > 0.0) {
+            return Err(IoError { kind: InvalidInput, desc: "frame rate must be positive", detail: None })
+        }
+
+        let rate = self.format.sample_rate();
+        let frame_start = |frame: usize| (frame as f64 * rate as f64 / frame_rate as f64 + 0.5) as u32;
+        let total = self.sample_count();
+
+        let mut head = Vec::new();
+        try!(head.write_u8(self.format.to_u8() & 0x0f)); // playback format
+        try!(head.write_u8(self.format.to_u8()));
+        try!(head.write_le_u16(frame_start(1) as u16));
+
+        let mut blocks = Vec::new();
+        match self.source {
+            Source::Pcm(ref samples) => {
+                let channels = self.format.channels() as usize;
+                let mut frame = 0;
+                while frame_start(frame) < total {
+                    let end = if frame_start(frame + 1) < total { frame_start(frame + 1) } else { total };
+                    let block = &samples[frame_start(frame) as usize * channels..end as usize * channels];
+                    blocks.push(try!(self.encode(block)));
+                    frame += 1;
+                }
+            },
+            Source::Mp3(ref data, ref frames) => {
+                try!(head.write_le_i16(0)); // latency seek
+
+                // Each block starts with the MP3 frame that holds the first sample of its SWF frame,
+                // the seek count skips to that sample
+                let mut position = 0;
+                let mut next = 0;
+                let mut frame = 0;
+                while next < frames.len() {
+                    let (start, first) = (position, next);
+                    while next < frames.len() && position + frames[next].samples() <= frame_start(frame + 1) {
+                        position += frames[next].samples();
+                        next += 1;
+                    }
+
+                    let mut block = Vec::new();
+                    try!(block.write_le_u16((position - start) as u16));
+                    try!(block.write_le_i16((frame_start(frame) as i64 - start as i64) as i16));
+                    block.push_all(frame_data(data, &frames[first..next]));
+                    blocks.push(block);
+                    frame += 1;
+                }
+            }
+        }
+
+        Ok((head, blocks))
+    }
+
+    fn encode(&self, samples: &[i16]) -> IoResult<Vec<u8>> {
+        match self.format.codec() {
+            CodecKind::ADPCM => try!(AdpcmEncoder::new(self.format.channels())).encode(samples),
+            _ => {
+                let mut data = Vec::with_capacity(samples.len() * 2);
+                for &sample in samples.iter() {
+                    try!(data.write_le_i16(sample));
+                }
+                Ok(data)
+            }
+        }
+    }
+}
+
+impl SoundReplacer {
+    pub fn new() -> Self {
+        SoundReplacer {
+            sounds: HashMap::new(),
+            streams: HashMap::new()
+        }
+    }
+
+    // Sounds whose id the file does not define are added to the first frame
+    pub fn replace_sound(&mut self, id: u16, sound: SoundImport) {
+        self.sounds.insert(id, sound);
+    }
+
+    // Replaces the soundtrack of the main timeline or of a DefineSprite, starting at the frame of the
+    // old stream head, or adds one from the first frame; blocks past the end of the timeline are dropped
+    pub fn replace_stream(&mut self, sprite_id: Option<u16>, sound: SoundImport) {
+        self.streams.insert(sprite_id, sound);
+    }
+
+    // Copies a SWF file from its first byte, keeping its compression
+    pub fn rewrite<R : Reader, W : Writer>(&self, reader: &mut R, writer: &mut W) -> IoResult<()> {
+        let magic = try!(Magic::read(reader));
+        match magic.compression {
+            Compression::None => (),
+            #[cfg(feature = "zlib")]
+            Compression::Zlib => (),
+            _ => return Err(IoError { kind: InvalidInput, desc: "unsupported SWF compression", detail: None })
+        }
+        let data = try!(magic.reader(reader.by_ref()).read_to_end());
+        let header = try!(Header::read(&mut BufReader::new(&data[..])));
+        let tags = &data[header.len()..];
+        let frame_rate = header.frame_rate as f32 / 256.0;
+
+        let mut output = Vec::new();
+        try!(header.write(&mut output));
+        let defined = try!(defined_sounds(tags));
+        try!(self.rewrite_timeline(tags, &mut output, None, frame_rate, &defined));
+
+        let magic = Magic { size: (magic.len() + output.len()) as u32, ..magic };
+        let output = match magic.compression {
+            Compression::None => output,
+            Compression::Zlib => try!(zlib::compress(&output)),
+            _ => return Err(IoError { kind: InvalidInput, desc: "unsupported SWF compression", detail: None })
+        };
+        try!(magic.write(writer));
+        writer.write_all(&output)
+    }
+
+    fn rewrite_timeline<W : Writer>(&self, tags: &[u8], writer: &mut W, sprite_id: Option<u16>, frame_rate: f32,
+        defined: &HashSet<u16>) -> IoResult<()>
+    {
+        let stream = match self.streams.get(&sprite_id) {
+            Some(sound) => Some((try!(stream_head_frame(tags)).unwrap_or(0), try!(sound.stream(frame_rate)))),
+            None => None
+        };
+        let mut head_written = false;
+        let mut block = 0;
+        let mut frame = 0;
+
+        let mut reader = BufReader::new(tags);
+        loop {
+            let tag = match Tag::read(&mut reader) {
+                Ok(tag) => tag,
+                Err(ref err) if err.kind == EndOfFile && sprite_id.is_none() => break,
+                Err(err) => return Err(err)
+            };
+            let body = try!(tag.reader(&mut reader).read_to_end());
+
+            match tag.kind {
+                TagKind::DefineSound if body.len() >= 2 => {
+                    let id = try!(BufReader::new(&body[..]).read_le_u16());
+                    if let Some(sound) = self.sounds.get(&id) {
+                        try!(sound.write_define_sound(id, writer));
+                        continue
+                    }
+                },
+                TagKind::SoundStreamHead | TagKind::SoundStreamHead2 | TagKind::SoundStreamBlock if stream.is_some() => continue,
+                TagKind::ShowFrame => {
+                    if sprite_id.is_none() && frame == 0 {
+                        let mut added: Vec<(&u16, &SoundImport)> = self.sounds.iter().filter(|&(id, _)| !defined.contains(id)).collect();
+                        added.sort_by(|a, b| a.0.cmp(b.0));
+                        for &(&id, sound) in added.iter() {
+                            try!(sound.write_define_sound(id, writer));
+                        }
+                    }
+
+                    if let Some((start, (ref head, ref blocks))) = stream {
+                        if frame >= start && !head_written {
+                            try!(write_tag(writer, TagKind::SoundStreamHead2, head));
+                            head_written = true;
+                        }
+                        if head_written && block < blocks.len() {
+                            try!(write_tag(writer, TagKind::SoundStreamBlock, &blocks[block]));
+                            block += 1;
+                        }
+                    }
+                    frame += 1;
+                },
+                TagKind::DefineSprite if body.len() >= 4 => {
+                    let id = try!(BufReader::new(&body[..]).read_le_u16());
+                    let mut sprite = body[..4].to_vec();
+                    try!(self.rewrite_timeline(&body[4..], &mut sprite, Some(id), frame_rate, defined));
+                    try!(write_tag(writer, TagKind::DefineSprite, &sprite));
+                    continue
+                },
+                _ => ()
+            }
+
+            try!(write_tag(writer, tag.kind, &body));
+            if tag.kind == TagKind::End {
+                break
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_tag<W : Writer>(writer: &mut W, kind: TagKind, body: &[u8]) -> IoResult<()> {
+    try!(Tag { kind: kind, length: body.len() as u32 }.write(writer));
+    writer.write_all(body)
+}
+
+fn frame_data<'a>(data: &'a [u8], frames: &[Mp3Frame]) -> &'a [u8] {
+    match (frames.first(), frames.last()) {
+        (Some(first), Some(last)) => &data[first.offset..last.offset + last.length],
+        _ => &data[..0]
+    }
+}
+
+// Ids of the DefineSound tags in the main timeline
+fn defined_sounds(tags: &[u8]) -> IoResult<HashSet<u16>> {
+    let mut ids = HashSet::new();
+    let mut reader = BufReader::new(tags);
+    loop {
+        let tag = match Tag::read(&mut reader) {
+            Ok(tag) => tag,
+            Err(ref err) if err.kind == EndOfFile => break,
+            Err(err) => return Err(err)
+        };
+        let body = try!(tag.reader(&mut reader).read_to_end());
+        match tag.kind {
+            TagKind::DefineSound if body.len() >= 2 => { ids.insert(try!(BufReader::new(&body[..]).read_le_u16())); },
+            TagKind::End => break,
+            _ => ()
+        }
+    }
+    Ok(ids)
+}
+
+// Frame of the first stream head in a timeline, nested sprites are not searched
+fn stream_head_frame(tags: &[u8]) -> IoResult<Option<usize>> {
+    let mut reader = BufReader::new(tags);
+    let mut frame = 0;
+    loop {
+        let tag = match Tag::read(&mut reader) {
+            Ok(tag) => tag,
+            Err(ref err) if err.kind == EndOfFile => return Ok(None),
+            Err(err) => return Err(err)
+        };
+        try!(tag.reader(&mut reader).read_to_end());
+        match tag.kind {
+            TagKind::SoundStreamHead | TagKind::SoundStreamHead2 => return Ok(Some(frame)),
+            TagKind::ShowFrame => frame += 1,
+            TagKind::End => return Ok(None),
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SoundReplacer;
+
+    #[test]
+    fn rewrite_rejects_lzma() {
+        let data = b"ZWS\x0a\x20\x00\x00\x00";
+        let mut output = Vec::new();
+        assert!(SoundReplacer::new().rewrite(&mut &data[..], &mut output).is_err());
+        assert!(output.is_empty());
+    }
+
+    #[cfg(not(feature = "zlib"))]
+    #[test]
+    fn rewrite_needs_zlib() {
+        let data = b"CWS\x0a\x20\x00\x00\x00";
+        let mut output = Vec::new();
+        assert!(SoundReplacer::new().rewrite(&mut &data[..], &mut output).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use std::old_io::{ Reader, IoResult, IoError, InvalidInput };
+use std::old_io::util::{ NullWriter, copy };
+use swf::{ Tag, TagKind };
+use types::{ read_string, decode_string };
+
+// Positions are in samples at 44.1 kHz regardless of the sound's rate, levels range from 0 to 32768
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EnvelopePoint {
+    pub position: u32,
+    pub left: u16,
+    pub right: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundInfo {
+    pub sync_stop: bool,
+    pub sync_no_multiple: bool,
+    pub in_point: Option<u32>,
+    pub out_point: Option<u32>,
+    pub loops: Option<u16>,
+    pub envelope: Option<Vec<EnvelopePoint>>,
+}
+
+// StartSound refers to a DefineSound by id, StartSound2 by its class name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartSound {
+    pub id: Option<u16>,
+    pub class_name: Option<String>,
+    pub info: SoundInfo,
+}
+
+// Sounds played on the OverUpToIdle, IdleToOverUp, OverUpToOverDown and OverDownToOverUp transitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ButtonSound {
+    pub button_id: u16,
+    pub sounds: Vec<Option<(u16, SoundInfo)>>,
+}
+
+impl SoundInfo {
+    pub fn read<R : Reader>(reader: &mut R) -> IoResult<Self> {
+        let flags = try!(reader.read_u8());
+        let in_point = if flags & 0x01 != 0 { Some(try!(reader.read_le_u32())) } else { None };
+        let out_point = if flags & 0x02 != 0 { Some(try!(reader.read_le_u32())) } else { None };
+        let loops = if flags & 0x04 != 0 { Some(try!(reader.read_le_u16())) } else { None };
+        let envelope = if flags & 0x08 != 0 {
+            let count = try!(reader.read_u8());
+            let mut points = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                points.push(EnvelopePoint {
+                    position: try!(reader.read_le_u32()),
+                    left: try!(reader.read_le_u16()),
+                    right: try!(reader.read_le_u16())
+                });
+            }
+            Some(points)
+        } else {
+            None
+        };
+
+        Ok(SoundInfo {
+            sync_stop: flags & 0x20 != 0,
+            sync_no_multiple: flags & 0x10 != 0,
+            in_point: in_point,
+            out_point: out_point,
+            loops: loops,
+            envelope: envelope
+        })
+    }
+
+    pub fn loop_count(&self) -> u32 {
+        match self.loops {
+            Some(0) | None => 1,
+            Some(loops) => loops as u32
+        }
+    }
+
+    // Left and right levels at a 44.1 kHz position, interpolated between envelope points
+    pub fn envelope_level(&self, position: u32) -> (f32, f32) {
+        let points = match self.envelope {
+            Some(ref points) if !points.is_empty() => points,
+            _ => return (1.0, 1.0)
+        };
+
+        let next = match points.iter().position(|p| p.position > position) {
+            Some(0) => return (points[0].left as f32 / 32768.0, points[0].right as f32 / 32768.0),
+            Some(next) => next,
+            None => {
+                let last = points[points.len() - 1];
+                return (last.left as f32 / 32768.0, last.right as f32 / 32768.0)
+            }
+        };
+
+        let (a, b) = (points[next - 1], points[next]);
+        let t = (position - a.position) as f32 / (b.position - a.position) as f32;
+        (
+            (a.left as f32 + (b.left as f32 - a.left as f32) * t) / 32768.0,
+            (a.right as f32 + (b.right as f32 - a.right as f32) * t) / 32768.0
+        )
+    }
+}
+
+impl StartSound {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        let mut reader = tag.reader(reader);
+        let (id, class_name) = match tag.kind {
+            TagKind::StartSound => (Some(try!(reader.read_le_u16())), None),
+            TagKind::StartSound2 => (None, Some(decode_string(try!(read_string(&mut reader)), version))),
+            _ => return Err(IoError { kind: InvalidInput, desc: "StartSound tag expected", detail: None })
+        };
+        let info = try!(SoundInfo::read(&mut reader));
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(StartSound {
+            id: id,
+            class_name: class_name,
+            info: info
+        })
+    }
+}
+
+impl ButtonSound {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineButtonSound {
+            return Err(IoError { kind: InvalidInput, desc: "DefineButtonSound tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let button_id = try!(reader.read_le_u16());
+        let mut sounds = Vec::with_capacity(4);
+        for _ in 0..4 {
+            sounds.push(match try!(reader.read_le_u16()) {
+                0 => None,
+                id => Some((id, try!(SoundInfo::read(&mut reader))))
+            });
+        }
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(ButtonSound {
+            button_id: button_id,
+            sounds: sounds
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ SoundInfo, EnvelopePoint };
+
+    #[test]
+    fn read_envelope() {
+        // Loops and an envelope fading from the left to the right channel over 100 samples
+        let data = [
+            0x1c, 0x03, 0x00, 0x02,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00,
+            0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80
+        ];
+        let info = SoundInfo::read(&mut &data[..]).unwrap();
+        assert!(info.sync_no_multiple && !info.sync_stop);
+        assert_eq!((info.in_point, info.out_point, info.loops, info.loop_count()), (None, None, Some(3), 3));
+        assert_eq!(info.envelope, Some(vec![
+            EnvelopePoint { position: 0, left: 32768, right: 0 },
+            EnvelopePoint { position: 100, left: 0, right: 32768 }
+        ]));
+
+        assert_eq!(info.envelope_level(0), (1.0, 0.0));
+        assert_eq!(info.envelope_level(25), (0.75, 0.25));
+        assert_eq!(info.envelope_level(1000), (0.0, 1.0));
+    }
+
+    #[test]
+    fn envelope_before_first_point() {
+        let info = SoundInfo { sync_stop: false, sync_no_multiple: false, in_point: Some(10), out_point: None, loops: Some(0),
+            envelope: Some(vec![EnvelopePoint { position: 50, left: 16384, right: 8192 }]) };
+        assert_eq!(info.envelope_level(0), (0.5, 0.25));
+        assert_eq!(info.envelope_level(60), (0.5, 0.25));
+        assert_eq!(info.loop_count(), 1);
+
+        let info = SoundInfo { envelope: None, .. info };
+        assert_eq!(info.envelope_level(0), (1.0, 1.0));
+    }
+}
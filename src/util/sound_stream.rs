@@ -1,7 +1,6 @@
-use std::old_io::{ Reader, ByRefReader, IoResult, IoError, InvalidInput, EndOfFile };
+use std::old_io::{ Reader, Seek, SeekSet, MemReader, IoResult, IoError, InvalidInput, EndOfFile };
 use std::old_io::util::{ LimitReader, NullWriter, copy };
 use swf::{ Tag, TagKind };
-use std::mem::replace;
 use std::num;
 
 pub struct SoundStream<R> {
@@ -10,14 +9,46 @@ pub struct SoundStream<R> {
     pub seek: u16,
     pub id: u16,
     pub start_frame: usize,
+    pub blocks: Vec<SoundBlock>,
     started: bool,
+    streaming: bool,
+    block_samples: u16,
+    frame: usize,
+    block: usize,
+    offsets: Vec<(u64, u32)>,
 
-    reader: IoResult<LimitReader<R>>,
+    reader: Option<LimitReader<R>>,
+    error: Option<IoError>,
+}
+
+// A streaming sound found anywhere in the file, `frame` is the frame of its head in the owning timeline
+#[derive(Debug, Clone)]
+pub struct StreamSound {
+    pub sprite_id: Option<u16>,
+    pub frame: usize,
+    tag: Tag,
+    data: Vec<u8>,
+}
+
+// A SoundStreamBlock seen so far, `position` counts the samples coded in the blocks before it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SoundBlock {
+    pub frame: usize,
+    pub samples: u16,
+    pub seek: i16,
+    pub position: u32,
 }
 
 #[derive(Debug, FromPrimitive, Copy, Clone, PartialEq, Eq)]
 pub enum CodecKind {
-    MP3 = 2
+    UncompressedNative = 0,
+    ADPCM              = 1,
+    MP3                = 2,
+    Uncompressed       = 3,
+    Nellymoser16kHz    = 4,
+    Nellymoser8kHz     = 5,
+    Nellymoser         = 6,
+    Speex              = 11
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -29,18 +60,66 @@ pub struct SoundFormat {
 
 impl SoundFormat {
     pub fn new(format: u8) -> Option<Self> {
+        let codec = if let Some(c) = num::from_u8((format >> 4) & 0x0f) { c } else { return None };
         Some(SoundFormat {
             format: format,
-            sample_rate: match (format >> 2) & 0x3 {
-                0 => 5500,
-                1 => 11000,
-                2 => 22050,
-                3 => 44100,
-                _ => unreachable!()
+            sample_rate: match codec {
+                // These codecs ignore the rate field
+                CodecKind::Nellymoser16kHz | CodecKind::Speex => 16000,
+                CodecKind::Nellymoser8kHz => 8000,
+                _ => match (format >> 2) & 0x3 {
+                    0 => 5512,
+                    1 => 11025,
+                    2 => 22050,
+                    3 => 44100,
+                    _ => unreachable!()
+                }
             },
-            codec: if let Some(c) = num::from_u8((format >> 4) & 0x0f) { c } else { return None }
+            codec: codec
         })
     }
+
+    // Only the four SWF rates can be stored, the Nellymoser 8/16 kHz and Speex codecs imply their own
+    pub fn from_parts(codec: CodecKind, sample_rate: u32, is_16bit: bool, is_stereo: bool) -> Option<Self> {
+        let rate = match (codec, sample_rate) {
+            (CodecKind::Nellymoser16kHz, _) | (CodecKind::Nellymoser8kHz, _) | (CodecKind::Speex, _) => 0,
+            (_, 5512) => 0,
+            (_, 11025) => 1,
+            (_, 22050) => 2,
+            (_, 44100) => 3,
+            _ => return None
+        };
+        let format = (codec as u8) << 4 | rate << 2 | if is_16bit { 0x02 } else { 0 } | if is_stereo { 0x01 } else { 0 };
+        SoundFormat::new(format)
+    }
+
+    pub fn codec(&self) -> CodecKind {
+        self.codec
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    pub fn is_16bit(&self) -> bool {
+        self.format & 0x02 != 0
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        self.format & 0x01 != 0
+    }
+
+    pub fn channels(&self) -> u16 {
+        if self.is_stereo() { 2 } else { 1 }
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        if self.is_16bit() { 16 } else { 8 }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        self.format
+    }
 }
 
 impl<R : Reader> SoundStream<R> {
@@ -49,6 +128,8 @@ impl<R : Reader> SoundStream<R> {
         let samples;
         let seek;
         let id;
+        let block_samples;
+        let streaming;
 
         let codec_error = IoError { kind: InvalidInput, desc: "unsupported audio codec", detail: None };
 
@@ -57,41 +138,120 @@ impl<R : Reader> SoundStream<R> {
             TagKind::SoundStreamHead | TagKind::SoundStreamHead2 => {
                 try!(reader.read_u8());
                 format = try!(SoundFormat::new(try!(reader.read_u8())).ok_or(codec_error));
-                try!(reader.read_le_u16()); // average frame samples
-                seek = try!(reader.read_le_u16());
+                block_samples = try!(reader.read_le_u16());
+                seek = if format.codec == CodecKind::MP3 { try!(reader.read_le_u16()) } else { 0 };
                 try!(SoundStream::drain(&mut reader));
                 samples = 0;
                 id = 0;
+                streaming = true;
             },
             TagKind::DefineSound => {
                 id = try!(reader.read_le_u16());
                 format = try!(SoundFormat::new(try!(reader.read_u8())).ok_or(codec_error));
                 samples = try!(reader.read_le_u32());
-                seek = try!(reader.read_le_u16());
+                seek = if format.codec == CodecKind::MP3 { try!(reader.read_le_u16()) } else { 0 };
+                block_samples = 0;
+                streaming = false;
             },
             _ => return Err(IoError { kind: InvalidInput, desc: "audio tag expected", detail: None })
         }
 
 
         Ok(SoundStream {
-            reader: Ok(reader),
+            reader: Some(reader),
+            error: None,
             format: format,
             samples: samples,
             seek: seek,
             id: id,
             start_frame: 0,
-            started: false
+            blocks: Vec::new(),
+            started: false,
+            streaming: streaming,
+            block_samples: block_samples,
+            frame: 0,
+            block: 0,
+            offsets: Vec::new()
         })
     }
 
+    // The MP3 SeekSamples of the DefineSound or SoundStreamHead, which is signed
+    pub fn seek_samples(&self) -> i16 {
+        self.seek as i16
+    }
+
+    // Samples of encoder delay to drop from the start of the decoded sound, a negative seek drops nothing
+    pub fn latency(&self) -> u32 {
+        let seek = if self.streaming {
+            self.blocks.first().map(|b| b.seek).unwrap_or(self.seek_samples())
+        } else {
+            self.seek_samples()
+        };
+        if seek > 0 { seek as u32 } else { 0 }
+    }
+
+    // Index into the decoded stream of the sample that plays when the timeline reaches `frame`,
+    // frames are counted from the stream head and only blocks read so far are known unless the stream is `indexed`
+    pub fn frame_sample(&self, frame: usize) -> Option<u32> {
+        if !self.streaming {
+            return None
+        }
+
+        let block = match self.blocks.iter().rev().find(|b| b.frame <= frame) {
+            Some(block) => block,
+            None => return None
+        };
+        let sample = block.position as i64 + block.seek as i64 + (frame - block.frame) as i64 * self.block_samples as i64;
+        Some(if sample > 0 { sample as u32 } else { 0 })
+    }
+
+    // Index into the decoded stream of the first sample of the current block
+    pub fn sample_position(&self) -> u32 {
+        if self.block > 0 { self.blocks[self.block - 1].position } else { 0 }
+    }
+
+    // The SoundStreamBlock that `read_block` last read from
+    pub fn current_block(&self) -> Option<SoundBlock> {
+        if self.block > 0 { Some(self.blocks[self.block - 1]) } else { None }
+    }
+
     pub fn into_inner(self) -> R {
         self.reader.unwrap().into_inner()
     }
 
     fn reader(&mut self) -> IoResult<&mut LimitReader<R>> {
-        match self.reader.as_mut() {
-            Ok(r) => Ok(r),
-            Err(err) => Err(err.clone())
+        match self.error {
+            Some(ref err) => Err(err.clone()),
+            None => Ok(self.reader.as_mut().unwrap())
+        }
+    }
+
+    // Reads what remains of the current SoundStreamBlock, or of a DefineSound body
+    pub fn read_block(&mut self) -> IoResult<Vec<u8>> {
+        loop {
+            let data = try!(try!(self.reader()).read_to_end());
+            if !data.is_empty() {
+                return Ok(data)
+            }
+            try!(self.next_block());
+        }
+    }
+
+    fn next_block(&mut self) -> IoResult<()> {
+        if !self.streaming {
+            return Err(IoError { kind: EndOfFile, desc: "end of sound data", detail: None })
+        }
+
+        // The inner reader is kept after the end of the stream so it can still seek
+        let mut reader = self.reader.take().unwrap().into_inner();
+        let result = self.process(&mut reader);
+        self.reader = Some(LimitReader::new(reader, *result.as_ref().unwrap_or(&0)));
+        match result {
+            Err(err) => {
+                self.error = Some(err.clone());
+                Err(err)
+            },
+            Ok(_) => Ok(())
         }
     }
 
@@ -99,27 +259,131 @@ impl<R : Reader> SoundStream<R> {
         copy(reader, &mut NullWriter)
     }
 
-    fn process(&mut self, mut reader: R) -> IoResult<LimitReader<R>> {
-        let mut limit;
+    fn process(&mut self, reader: &mut R) -> IoResult<usize> {
+        let limit;
         loop {
-            let tag = try!(Tag::read(&mut reader));
-            let mut tagreader = LimitReader::new(reader.by_ref(), tag.length as usize);
+            let tag = try!(Tag::read(reader));
+            let mut tagreader = tag.reader(reader);
             match tag.kind {
                 TagKind::SoundStreamBlock => {
-                    self.started = true;
-                    self.samples += try!(tagreader.read_le_u16()) as u32;
-                    try!(tagreader.read_le_u16()); // seek samples
+                    if !self.started {
+                        self.start_frame = self.frame;
+                        self.started = true;
+                    }
+
+                    // Only MP3 blocks carry a sample count, other codecs use the count from the stream head
+                    let (samples, seek) = if self.format.codec == CodecKind::MP3 {
+                        (try!(tagreader.read_le_u16()), try!(tagreader.read_le_i16()))
+                    } else {
+                        (self.block_samples, 0)
+                    };
+                    if self.block == self.blocks.len() {
+                        self.blocks.push(SoundBlock {
+                            frame: self.frame,
+                            samples: samples,
+                            seek: seek,
+                            position: self.samples
+                        });
+                    }
+                    self.block += 1;
+                    self.samples += samples as u32;
                     limit = tagreader.limit();
                     break;
                 },
                 TagKind::End => return Err(IoError { kind: EndOfFile, desc: "end swf tag", detail: None }),
-                TagKind::ShowFrame => self.start_frame += 1,
+                TagKind::ShowFrame => self.frame += 1,
                 _ => ()
             }
             try!(SoundStream::drain(&mut tagreader));
         }
 
-        return Ok(LimitReader::new(reader, limit));
+        Ok(limit)
+    }
+}
+
+impl<R : Reader + Seek> SoundStream<R> {
+    // Opens a sound like `new`, indexing the SoundStreamBlocks of a streaming sound so it can seek by frame
+    pub fn indexed(tag: Tag, reader: R) -> IoResult<Self> {
+        let mut stream = try!(SoundStream::new(tag, reader));
+        if stream.streaming {
+            let mut reader = stream.reader.take().unwrap().into_inner();
+            let result = stream.index(&mut reader);
+            stream.reader = Some(LimitReader::new(reader, 0));
+            try!(result);
+        }
+        Ok(stream)
+    }
+
+    // Moves to the block that holds `frame`, counted from the stream head like `frame_sample`,
+    // decoding from there and dropping `frame_sample(frame) - sample_position()` samples lands on the frame
+    pub fn seek_to_frame(&mut self, frame: usize) -> IoResult<()> {
+        if self.offsets.is_empty() {
+            return Err(IoError { kind: InvalidInput, desc: "sound stream has no block index", detail: None })
+        }
+
+        let index = self.blocks.iter().rposition(|b| b.frame <= frame).unwrap_or(0);
+        let block = self.blocks[index];
+        let (offset, length) = self.offsets[index];
+
+        let mut reader = self.reader.take().unwrap().into_inner();
+        let result = reader.seek(offset as i64, SeekSet);
+        self.reader = Some(LimitReader::new(reader, length as usize));
+        try!(result);
+
+        self.error = None;
+        self.frame = block.frame;
+        self.block = index + 1;
+        self.samples = block.position + block.samples as u32;
+        Ok(())
+    }
+
+    // Reads the tags once, recording the body offset of every block, then returns to the stream head
+    fn index(&mut self, reader: &mut R) -> IoResult<()> {
+        let origin = try!(reader.tell());
+        let mut frame = 0;
+        let mut position = 0;
+
+        loop {
+            let tag = match Tag::read(reader) {
+                Ok(tag) => tag,
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            };
+            let start = try!(reader.tell());
+
+            match tag.kind {
+                TagKind::SoundStreamBlock => {
+                    // MP3 blocks start with their sample count and seek, the rest is frame data
+                    let (samples, seek, header) = if self.format.codec == CodecKind::MP3 {
+                        if tag.length < 4 {
+                            return Err(IoError { kind: InvalidInput, desc: "MP3 sound stream block too short", detail: None })
+                        }
+                        let mut body = tag.reader(reader);
+                        (try!(body.read_le_u16()), try!(body.read_le_i16()), 4)
+                    } else {
+                        (self.block_samples, 0, 0)
+                    };
+                    self.blocks.push(SoundBlock {
+                        frame: frame,
+                        samples: samples,
+                        seek: seek,
+                        position: position
+                    });
+                    self.offsets.push((start + header, tag.length - header as u32));
+                    position += samples as u32;
+                },
+                TagKind::ShowFrame => frame += 1,
+                TagKind::End => break,
+                _ => ()
+            }
+            try!(reader.seek((start + tag.length as u64) as i64, SeekSet));
+        }
+
+        if let Some(block) = self.blocks.first() {
+            self.start_frame = block.frame;
+            self.started = true;
+        }
+        reader.seek(origin as i64, SeekSet)
     }
 }
 
@@ -128,14 +392,8 @@ impl<R : Reader> Reader for SoundStream<R> {
         loop {
             return match try!(self.reader()).read(buf) {
                 Err(ref err) if err.kind == EndOfFile => {
-                    let err = err.clone();
-                    let reader = replace(&mut self.reader, Err(err)).unwrap().into_inner();
-                    let reader = self.process(reader);
-                    let _ = replace(&mut self.reader, reader);
-                    match self.reader.as_ref() {
-                        Err(err) => Err(err.clone()),
-                        _ => continue
-                    }
+                    try!(self.next_block());
+                    continue
                 },
                 Err(err) => Err(err),
                 Ok(sz) => Ok(sz)
@@ -143,3 +401,159 @@ impl<R : Reader> Reader for SoundStream<R> {
         }
     }
 }
+
+impl StreamSound {
+    // Lists the streaming sounds of the main timeline and of every DefineSprite, `reader` starts at the first tag
+    pub fn find<R : Reader>(reader: &mut R) -> IoResult<Vec<Self>> {
+        let mut streams = Vec::new();
+        try!(StreamSound::scan(reader, None, &mut streams));
+        Ok(streams)
+    }
+
+    pub fn stream(&self) -> IoResult<SoundStream<MemReader>> {
+        SoundStream::indexed(self.tag, MemReader::new(self.data.clone()))
+    }
+
+    // Copies each stream head with the blocks and frames that follow it in the same timeline
+    fn scan<R : Reader>(reader: &mut R, sprite_id: Option<u16>, streams: &mut Vec<StreamSound>) -> IoResult<()> {
+        let mut owned = Vec::new();
+        let mut current = None;
+        let mut frame = 0;
+
+        loop {
+            let tag = match Tag::read(reader) {
+                Ok(tag) => tag,
+                Err(ref err) if err.kind == EndOfFile && sprite_id.is_none() => break,
+                Err(err) => return Err(err)
+            };
+            let body = try!(tag.reader(reader).read_to_end());
+
+            match tag.kind {
+                TagKind::End => break,
+                TagKind::ShowFrame => frame += 1,
+                TagKind::SoundStreamHead | TagKind::SoundStreamHead2 => {
+                    current = Some(streams.len());
+                    owned.push(streams.len());
+                    streams.push(StreamSound {
+                        sprite_id: sprite_id,
+                        frame: frame,
+                        tag: tag,
+                        data: body
+                    });
+                    continue
+                },
+                TagKind::SoundStreamBlock => (),
+                TagKind::DefineSprite => {
+                    let mut sprite = MemReader::new(body);
+                    let id = try!(sprite.read_le_u16());
+                    try!(sprite.read_le_u16()); // frame count
+                    try!(StreamSound::scan(&mut sprite, Some(id), streams));
+                    continue
+                },
+                _ => continue
+            }
+
+            if let Some(index) = current {
+                let data = &mut streams[index].data;
+                try!(tag.write(data));
+                data.push_all(&body);
+            }
+        }
+
+        for &index in owned.iter() {
+            try!(Tag { kind: TagKind::End, length: 0 }.write(&mut streams[index].data));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemReader;
+    use swf::{ Tag, TagKind };
+    use super::{ SoundStream, StreamSound, SoundBlock };
+
+    fn tag(data: &mut Vec<u8>, kind: TagKind, body: &[u8]) {
+        Tag { kind: kind, length: body.len() as u32 }.write(data).unwrap();
+        data.push_all(body);
+    }
+
+    // 8-bit mono uncompressed at 5512 Hz with 4 samples per block
+    const HEAD: [u8; 4] = [0x30, 0x30, 0x04, 0x00];
+
+    #[test]
+    fn find_streams() {
+        let mut sprite = vec![0x05, 0x00, 0x02, 0x00];
+        tag(&mut sprite, TagKind::ShowFrame, &[]);
+        tag(&mut sprite, TagKind::SoundStreamHead2, &HEAD);
+        tag(&mut sprite, TagKind::SoundStreamBlock, &[9, 9, 9, 9]);
+        tag(&mut sprite, TagKind::ShowFrame, &[]);
+        tag(&mut sprite, TagKind::End, &[]);
+
+        let mut data = Vec::new();
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::SoundStreamHead, &HEAD);
+        tag(&mut data, TagKind::SoundStreamBlock, &[1, 2, 3, 4]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::DefineSprite, &sprite);
+        tag(&mut data, TagKind::SoundStreamBlock, &[5, 6, 7, 8]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::End, &[]);
+
+        let streams = StreamSound::find(&mut &data[..]).unwrap();
+        assert_eq!(streams.iter().map(|s| (s.sprite_id, s.frame)).collect::<Vec<_>>(), vec![(None, 1), (Some(5), 1)]);
+
+        // Blocks of the sprite's stream stay out of the main timeline's stream
+        let mut stream = streams[0].stream().unwrap();
+        assert_eq!(stream.blocks, vec![
+            SoundBlock { frame: 0, samples: 4, seek: 0, position: 0 },
+            SoundBlock { frame: 1, samples: 4, seek: 0, position: 4 }
+        ]);
+        assert_eq!(stream.read_block().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(stream.read_block().unwrap(), vec![5, 6, 7, 8]);
+        assert!(stream.read_block().is_err());
+
+        let mut stream = streams[1].stream().unwrap();
+        assert_eq!(stream.read_block().unwrap(), vec![9, 9, 9, 9]);
+        assert!(stream.read_block().is_err());
+    }
+
+    #[test]
+    fn seek_to_frame() {
+        // A 44.1 kHz MP3 stream head with a seek of -1, then blocks with their own sample counts and seeks
+        let head = [0x2e, 0x2e, 0x80, 0x04, 0xff, 0xff];
+        let mut data = head.to_vec();
+        tag(&mut data, TagKind::SoundStreamBlock, &[0x80, 0x04, 0x40, 0x02, 0xaa, 0xaa, 0xaa]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::SoundStreamBlock, &[0x00, 0x09, 0x64, 0x00, 0xbb, 0xbb]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::SoundStreamBlock, &[0x80, 0x04, 0x32, 0x00, 0xcc]);
+        tag(&mut data, TagKind::ShowFrame, &[]);
+        tag(&mut data, TagKind::End, &[]);
+
+        let head_tag = Tag { kind: TagKind::SoundStreamHead2, length: head.len() as u32 };
+        let mut stream = SoundStream::indexed(head_tag, MemReader::new(data.clone())).unwrap();
+        assert_eq!((stream.seek, stream.seek_samples(), stream.latency()), (0xffff, -1, 576));
+        assert_eq!(stream.frame_sample(1), Some(1152 + 100));
+        assert_eq!(stream.frame_sample(2), Some(1152 + 100 + 1152));
+        assert_eq!(stream.frame_sample(3), Some(3456 + 50));
+
+        // Frames without a block of their own belong to the block before them
+        stream.seek_to_frame(2).unwrap();
+        assert_eq!(stream.read_block().unwrap(), vec![0xbb, 0xbb]);
+        assert_eq!((stream.sample_position(), stream.current_block().map(|b| b.frame)), (1152, Some(1)));
+        assert_eq!(stream.read_block().unwrap(), vec![0xcc]);
+        assert_eq!(stream.sample_position(), 3456);
+        assert!(stream.read_block().is_err());
+
+        // Seeking recovers from the end of the stream
+        stream.seek_to_frame(0).unwrap();
+        assert_eq!(stream.read_block().unwrap(), vec![0xaa, 0xaa, 0xaa]);
+        assert_eq!(stream.read_block().unwrap(), vec![0xbb, 0xbb]);
+
+        let mut stream = SoundStream::new(head_tag, MemReader::new(data)).unwrap();
+        assert!(stream.seek_to_frame(1).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::old_io::{ Reader, ByRefReader, IoResult, IoError, InvalidInput };
+use std::old_io::util::{ NullWriter, copy };
+use std::collections::HashMap;
+use swf::{ Tag, TagKind };
+use types::{ Rect, Rgba, Matrix };
+use bits::BitReader;
+use util::font::Font;
+use util::csm::CSMTextSettings;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GlyphEntry {
+    pub index: u32,
+    pub advance: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextRecord {
+    pub font_id: Option<u16>,
+    pub color: Option<Rgba>,
+    pub x_offset: Option<i16>,
+    pub y_offset: Option<i16>,
+    pub height: Option<u16>,
+    pub glyphs: Vec<GlyphEntry>,
+}
+
+// DefineText and DefineText2
+#[derive(Debug, Clone)]
+pub struct Text {
+    pub kind: TagKind,
+    pub id: u16,
+    pub bounds: Rect,
+    pub matrix: Matrix,
+    pub records: Vec<TextRecord>,
+    pub csm_settings: Option<CSMTextSettings>,
+}
+
+// A text record resolved against its font, positions are in the text's coordinate space
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub font_id: u16,
+    pub color: Rgba,
+    pub x: i32,
+    pub y: i32,
+    pub height: u16,
+    pub text: String,
+    pub advances: Vec<i32>,
+}
+
+impl Text {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        let alpha = match tag.kind {
+            TagKind::DefineText => false,
+            TagKind::DefineText2 => true,
+            _ => return Err(IoError { kind: InvalidInput, desc: "text tag expected", detail: None })
+        };
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let bounds = try!(Rect::read(&mut reader));
+        let matrix = try!(Matrix::read(&mut reader));
+        let glyph_bits = try!(reader.read_u8());
+        let advance_bits = try!(reader.read_u8());
+
+        let mut records = Vec::new();
+        loop {
+            let flags = try!(reader.read_u8());
+            if flags == 0 {
+                break
+            }
+
+            let font_id = if flags & 0x08 != 0 { Some(try!(reader.read_le_u16())) } else { None };
+            let color = if flags & 0x04 != 0 {
+                Some(if alpha { try!(Rgba::read_rgba(&mut reader)) } else { try!(Rgba::read_rgb(&mut reader)) })
+            } else {
+                None
+            };
+            let x_offset = if flags & 0x01 != 0 { Some(try!(reader.read_le_i16())) } else { None };
+            let y_offset = if flags & 0x02 != 0 { Some(try!(reader.read_le_i16())) } else { None };
+            let height = if flags & 0x08 != 0 { Some(try!(reader.read_le_u16())) } else { None };
+
+            let count = try!(reader.read_u8()) as usize;
+            let mut glyphs = Vec::with_capacity(count);
+            let mut bits = BitReader::new(reader.by_ref());
+            for _ in 0..count {
+                glyphs.push(GlyphEntry {
+                    index: try!(bits.read_ubits(glyph_bits)),
+                    advance: try!(bits.read_sbits(advance_bits))
+                });
+            }
+
+            records.push(TextRecord {
+                font_id: font_id,
+                color: color,
+                x_offset: x_offset,
+                y_offset: y_offset,
+                height: height,
+                glyphs: glyphs
+            });
+        }
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(Text {
+            kind: tag.kind,
+            id: id,
+            bounds: bounds,
+            matrix: matrix,
+            records: records,
+            csm_settings: None
+        })
+    }
+
+    pub fn runs(&self, fonts: &HashMap<u16, Font>) -> IoResult<Vec<TextRun>> {
+        let mut runs = Vec::with_capacity(self.records.len());
+        let mut font_id = None;
+        let mut color = Rgba { r: 0, g: 0, b: 0, a: 0xff };
+        let mut height = 0;
+        let (mut x, mut y) = (0, 0);
+
+        for record in self.records.iter() {
+            if record.font_id.is_some() {
+                font_id = record.font_id;
+            }
+            color = record.color.unwrap_or(color);
+            height = record.height.unwrap_or(height);
+            x = record.x_offset.map(|v| v as i32).unwrap_or(x);
+            y = record.y_offset.map(|v| v as i32).unwrap_or(y);
+
+            let id = try!(font_id.ok_or(IoError { kind: InvalidInput, desc: "text record without font", detail: None }));
+            let font = try!(fonts.get(&id).ok_or(IoError { kind: InvalidInput, desc: "text refers to unknown font", detail: None }));
+
+            let mut text = String::with_capacity(record.glyphs.len());
+            let mut advances = Vec::with_capacity(record.glyphs.len());
+            let start = x;
+            for glyph in record.glyphs.iter() {
+                text.push(font.char_for_glyph(glyph.index as usize).unwrap_or('\u{fffd}'));
+                advances.push(glyph.advance);
+                x += glyph.advance;
+            }
+
+            runs.push(TextRun {
+                font_id: id,
+                color: color,
+                x: start,
+                y: y,
+                height: height,
+                text: text,
+                advances: advances
+            });
+        }
+
+        Ok(runs)
+    }
+
+    // Visible text with runs on different baselines separated by newlines
+    pub fn text(&self, fonts: &HashMap<u16, Font>) -> IoResult<String> {
+        let mut text = String::new();
+        let mut baseline = None;
+        for run in try!(self.runs(fonts)).into_iter() {
+            if baseline.is_some() && baseline != Some(run.y) {
+                text.push('\n');
+            }
+            baseline = Some(run.y);
+            text.push_str(&run.text);
+        }
+
+        Ok(text)
+    }
+
+    pub fn apply_csm_settings(&mut self, settings: &CSMTextSettings) {
+        self.csm_settings = Some(*settings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use swf::{ Tag, TagKind };
+    use types::Rgba;
+    use util::font::{ Font, FontFlags, LanguageCode };
+    use super::Text;
+
+    // A DefineText2 with an empty bounds and matrix, and two records on different baselines
+    const DEFINE_TEXT2: [u8; 34] = [
+        0x01, 0x00, 0x00, 0x00, 0x08, 0x08,
+        0x8f, 0x02, 0x00, 0xff, 0x00, 0x00, 0x80, 0x10, 0x00, 0x20, 0x00, 0x40, 0x01, 0x02, 0x00, 0x64, 0x01, 0x50,
+        0x82, 0x40, 0x00, 0x01, 0x00, 0x0a,
+        0x00,
+        0xaa, 0xbb, 0xcc
+    ];
+
+    fn font(id: u16, codes: &str) -> Font {
+        let flags = FontFlags { small_text: false, shift_jis: false, ansi: false, italic: false, bold: false, wide_codes: true,
+            utf8: true };
+        Font { kind: TagKind::DefineFont2, id: id, name: String::new(), copyright: String::new(), flags: flags,
+            language: LanguageCode::Latin, glyphs: Vec::new(), codes: codes.chars().map(|c| c as u16).collect(), layout: None,
+            align_zones: None }
+    }
+
+    #[test]
+    fn read_records() {
+        let tag = Tag { kind: TagKind::DefineText2, length: DEFINE_TEXT2.len() as u32 };
+        let text = Text::read(tag, &mut &DEFINE_TEXT2[..]).unwrap();
+        assert_eq!(text.id, 1);
+        assert_eq!(text.records.len(), 2);
+
+        let record = &text.records[0];
+        assert_eq!((record.font_id, record.x_offset, record.y_offset, record.height), (Some(2), Some(16), Some(32), Some(320)));
+        assert_eq!(record.color, Some(Rgba { r: 0xff, g: 0, b: 0, a: 0x80 }));
+        assert_eq!(record.glyphs.iter().map(|g| (g.index, g.advance)).collect::<Vec<_>>(), vec![(0, 100), (1, 80)]);
+
+        let record = &text.records[1];
+        assert_eq!((record.font_id, record.color, record.x_offset, record.y_offset), (None, None, None, Some(64)));
+        assert_eq!(record.glyphs.len(), 1);
+    }
+
+    #[test]
+    fn runs_inherit_styles() {
+        let tag = Tag { kind: TagKind::DefineText2, length: DEFINE_TEXT2.len() as u32 };
+        let text = Text::read(tag, &mut &DEFINE_TEXT2[..]).unwrap();
+        let mut fonts = HashMap::new();
+        fonts.insert(2, font(2, "Hi"));
+
+        let runs = text.runs(&fonts).unwrap();
+        assert_eq!((runs[0].x, runs[0].y, runs[0].height, &runs[0].text[..]), (16, 32, 320, "Hi"));
+        assert_eq!((runs[1].x, runs[1].y, runs[1].height, &runs[1].text[..]), (196, 64, 320, "H"));
+        assert_eq!(runs[1].color, Rgba { r: 0xff, g: 0, b: 0, a: 0x80 });
+        assert_eq!(text.text(&fonts).unwrap(), "Hi\nH");
+
+        assert!(text.runs(&HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use std::old_io::{ Reader, MemReader, IoResult, IoError, InvalidInput, EndOfFile };
+use std::old_io::util::{ NullWriter, copy };
+use std::collections::{ BTreeMap, HashMap };
+use std::mem;
+use std::ascii::AsciiExt;
+use std::rc::Rc;
+use swf::{ Tag, TagKind };
+use util::display_list::{ DisplayList, PlaceObject, RemoveObject };
+use util::frame_label::{ LabelTable, FrameLabel, SceneAndFrameLabelData };
+
+// A tag that runs when its frame is entered. Sound and other frame tags are kept whole for the caller
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlTag {
+    Place(PlaceObject),
+    Remove(RemoveObject),
+    DoAction(Vec<u8>),
+    Other(TagKind, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub tags: Vec<ControlTag>,
+}
+
+// The frames of the main timeline or a DefineSprite, frames and labels count from 0.
+// `frame_count` is the one declared, tags after the last ShowFrame are dropped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    pub id: Option<u16>,
+    pub frame_count: u16,
+    pub frames: Vec<Frame>,
+    pub labels: LabelTable,
+}
+
+// A placed instance of a timeline with its own playhead. Entered frames queue their actions until taken
+#[derive(Debug, Clone)]
+pub struct MovieClip {
+    pub timeline: Rc<Timeline>,
+    pub current_frame: usize,
+    pub playing: bool,
+    pub display_list: DisplayList,
+    children: BTreeMap<u16, MovieClip>,
+    actions: Vec<Vec<u8>>,
+
+    // The sprites this clip is nested in and its own, a sprite placed inside itself is not instantiated again
+    ancestors: Vec<u16>,
+}
+
+impl Frame {
+    pub fn actions(&self) -> Vec<Vec<u8>> {
+        self.tags.iter().filter_map(|tag| match *tag {
+            ControlTag::DoAction(ref data) => Some(data.clone()),
+            _ => None
+        }).collect()
+    }
+}
+
+impl Timeline {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R, version: u8) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineSprite {
+            return Err(IoError { kind: InvalidInput, desc: "DefineSprite tag expected", detail: None })
+        }
+
+        let mut sprite = MemReader::new(try!(tag.reader(reader).read_to_end()));
+        let id = try!(sprite.read_le_u16());
+        let frame_count = try!(sprite.read_le_u16());
+        let mut timeline = Timeline { id: Some(id), frame_count: frame_count, frames: Vec::new(), labels: LabelTable::new() };
+        try!(timeline.read_tags(&mut sprite, version, None));
+        Ok(timeline)
+    }
+
+    // The main timeline from the first tag after the header, with the sprites defined along the way
+    pub fn read_main<R : Reader>(reader: &mut R, version: u8, frame_count: u16) -> IoResult<(Self, HashMap<u16, Rc<Timeline>>)> {
+        let mut timeline = Timeline { id: None, frame_count: frame_count, frames: Vec::new(), labels: LabelTable::new() };
+        let mut sprites = HashMap::new();
+        try!(timeline.read_tags(reader, version, Some(&mut sprites)));
+        Ok((timeline, sprites))
+    }
+
+    fn read_tags<R : Reader>(&mut self, reader: &mut R, version: u8, mut sprites: Option<&mut HashMap<u16, Rc<Timeline>>>) -> IoResult<()> {
+        let mut tags = Vec::new();
+        loop {
+            let tag = match Tag::read(reader) {
+                Ok(tag) => tag,
+                Err(ref err) if err.kind == EndOfFile && self.id.is_none() => break,
+                Err(err) => return Err(err)
+            };
+
+            match tag.kind {
+                TagKind::End => break,
+                TagKind::ShowFrame => {
+                    self.frames.push(Frame { tags: mem::replace(&mut tags, Vec::new()) });
+                    continue
+                },
+                TagKind::PlaceObject | TagKind::PlaceObject2 | TagKind::PlaceObject3 => {
+                    tags.push(ControlTag::Place(try!(PlaceObject::read(tag, reader, version))));
+                    continue
+                },
+                TagKind::RemoveObject | TagKind::RemoveObject2 => {
+                    tags.push(ControlTag::Remove(try!(RemoveObject::read(tag, reader))));
+                    continue
+                },
+                TagKind::DoAction => {
+                    tags.push(ControlTag::DoAction(try!(tag.reader(reader).read_to_end())));
+                    continue
+                },
+                TagKind::FrameLabel => {
+                    let label = try!(FrameLabel::read(tag, reader, version));
+                    self.labels.add_label(self.frames.len(), label);
+                    continue
+                },
+                TagKind::DefineSceneAndFrameLabelData => {
+                    let data = try!(SceneAndFrameLabelData::read(tag, reader));
+                    self.labels.add_scene_data(data);
+                    continue
+                },
+                TagKind::StartSound | TagKind::StartSound2 | TagKind::SoundStreamHead | TagKind::SoundStreamHead2 |
+                TagKind::SoundStreamBlock | TagKind::SetBackgroundColor | TagKind::VideoFrame => {
+                    tags.push(ControlTag::Other(tag.kind, try!(tag.reader(reader).read_to_end())));
+                    continue
+                },
+                TagKind::DefineSprite => if let Some(ref mut sprites) = sprites {
+                    let sprite = try!(Timeline::read(tag, reader, version));
+                    sprites.insert(sprite.id.unwrap(), Rc::new(sprite));
+                    continue
+                },
+                _ => ()
+            }
+            try!(copy(&mut tag.reader(reader), &mut NullWriter));
+        }
+
+        Ok(())
+    }
+
+    pub fn label_frame(&self, label: &str) -> Option<usize> {
+        self.labels.label_frame(label)
+    }
+}
+
+impl MovieClip {
+    // Places the clip on its first frame, which is entered straight away
+    pub fn new(timeline: Rc<Timeline>, sprites: &HashMap<u16, Rc<Timeline>>) -> Self {
+        MovieClip::nested(timeline, sprites, &[])
+    }
+
+    fn nested(timeline: Rc<Timeline>, sprites: &HashMap<u16, Rc<Timeline>>, ancestors: &[u16]) -> Self {
+        let mut ancestors = ancestors.to_vec();
+        ancestors.extend(timeline.id.into_iter());
+        let mut clip = MovieClip {
+            timeline: timeline,
+            current_frame: 0,
+            playing: true,
+            display_list: DisplayList::new(),
+            children: BTreeMap::new(),
+            actions: Vec::new(),
+            ancestors: ancestors
+        };
+        clip.enter(0, sprites);
+        clip
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.timeline.frames.len()
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    // Moves the playhead to a frame, clamped to the last. Going back rebuilds the display list from the
+    // first frame and keeps the clips still placed with the same character
+    pub fn goto_frame(&mut self, frame: usize, sprites: &HashMap<u16, Rc<Timeline>>) {
+        let last = match self.frame_count() {
+            0 => return,
+            count => count - 1
+        };
+        let frame = if frame > last { last } else { frame };
+        if frame == self.current_frame {
+            return
+        }
+
+        if frame > self.current_frame {
+            for skipped in self.current_frame + 1..frame {
+                self.apply_frame(skipped, sprites, false);
+            }
+            self.enter(frame, sprites);
+        } else {
+            let mut previous = mem::replace(&mut self.children, BTreeMap::new());
+            self.display_list.clear();
+            for skipped in 0..frame {
+                self.apply_frame(skipped, sprites, false);
+            }
+            self.enter(frame, sprites);
+
+            for (depth, child) in self.children.iter_mut() {
+                if let Some(old) = previous.remove(depth) {
+                    if old.timeline.id == child.timeline.id {
+                        *child = old;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn next_frame(&mut self, sprites: &HashMap<u16, Rc<Timeline>>) {
+        let frame = self.current_frame + 1;
+        self.goto_frame(frame, sprites);
+        self.stop();
+    }
+
+    pub fn previous_frame(&mut self, sprites: &HashMap<u16, Rc<Timeline>>) {
+        if self.current_frame > 0 {
+            let frame = self.current_frame - 1;
+            self.goto_frame(frame, sprites);
+        }
+        self.stop();
+    }
+
+    // One tick of the movie: the clip moves on a frame if it is playing, looping at the end, and then the
+    // clips already placed on it do the same
+    pub fn advance(&mut self, sprites: &HashMap<u16, Rc<Timeline>>) {
+        let placed: Vec<u16> = self.children.keys().map(|&depth| depth).collect();
+        if self.playing && self.frame_count() > 1 {
+            if self.current_frame + 1 < self.frame_count() {
+                let frame = self.current_frame + 1;
+                self.enter(frame, sprites);
+            } else {
+                self.goto_frame(0, sprites);
+            }
+        }
+
+        for depth in placed.iter() {
+            if let Some(child) = self.children.get_mut(depth) {
+                child.advance(sprites);
+            }
+        }
+    }
+
+    pub fn child(&self, depth: u16) -> Option<&MovieClip> {
+        self.children.get(&depth)
+    }
+
+    pub fn child_mut(&mut self, depth: u16) -> Option<&mut MovieClip> {
+        self.children.get_mut(&depth)
+    }
+
+    // The depth of the sprite instance placed with the name, compared without case as in AVM1
+    pub fn child_depth(&self, name: &str) -> Option<u16> {
+        let name = name.to_ascii_lowercase();
+        self.children.keys().map(|&depth| depth).find(|&depth| match self.display_list.get(depth) {
+            Some(object) => object.name.as_ref().map(|n| n.to_ascii_lowercase() == name).unwrap_or(false),
+            None => false
+        })
+    }
+
+    pub fn children(&self) -> Vec<(u16, &MovieClip)> {
+        self.children.iter().map(|(&depth, child)| (depth, child)).collect()
+    }
+
+    // Places an instance at a depth outside of the timeline's tags, replacing whatever was there
+    pub fn insert_child(&mut self, depth: u16, child: MovieClip) {
+        self.children.insert(depth, child);
+    }
+
+    pub fn remove_child(&mut self, depth: u16) -> Option<MovieClip> {
+        self.children.remove(&depth)
+    }
+
+    // The actions of the frames entered since the last call, in order
+    pub fn take_actions(&mut self) -> Vec<Vec<u8>> {
+        mem::replace(&mut self.actions, Vec::new())
+    }
+
+    fn enter(&mut self, frame: usize, sprites: &HashMap<u16, Rc<Timeline>>) {
+        self.current_frame = frame;
+        self.apply_frame(frame, sprites, true);
+    }
+
+    fn apply_frame(&mut self, frame: usize, sprites: &HashMap<u16, Rc<Timeline>>, actions: bool) {
+        let timeline = self.timeline.clone();
+        let tags = match timeline.frames.get(frame) {
+            Some(frame) => &frame.tags,
+            None => return
+        };
+
+        for tag in tags.iter() {
+            match *tag {
+                ControlTag::Place(ref place) => {
+                    let replaced = match self.display_list.get(place.depth) {
+                        Some(object) => !place.is_move || place.character_id.map(|id| id != object.character_id).unwrap_or(false),
+                        None => !place.is_move
+                    };
+                    self.display_list.place(place);
+                    if replaced {
+                        self.children.remove(&place.depth);
+                        match place.character_id.and_then(|id| sprites.get(&id).map(|sprite| (id, sprite))) {
+                            Some((id, sprite)) if !self.ancestors.contains(&id) => {
+                                let child = MovieClip::nested(sprite.clone(), sprites, &self.ancestors);
+                                self.children.insert(place.depth, child);
+                            },
+                            _ => ()
+                        }
+                    }
+                },
+                ControlTag::Remove(ref remove) => {
+                    self.display_list.remove(remove);
+                    self.children.remove(&remove.depth);
+                },
+                ControlTag::DoAction(ref data) => if actions {
+                    self.actions.push(data.clone());
+                },
+                ControlTag::Other(..) => ()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use util::display_list::{ PlaceObject, RemoveObject };
+    use util::frame_label::LabelTable;
+    use super::{ Timeline, Frame, ControlTag, MovieClip };
+
+    fn place(depth: u16, id: u16) -> ControlTag {
+        ControlTag::Place(PlaceObject { depth: depth, is_move: false, character_id: Some(id), class_name: None,
+            matrix: None, color_transform: None, ratio: None, name: None, clip_depth: None, filters: None,
+            blend_mode: None, cache_as_bitmap: None, visible: None, background_color: None, clip_actions: None })
+    }
+
+    fn timeline(id: Option<u16>, frames: Vec<Vec<ControlTag>>) -> Rc<Timeline> {
+        Rc::new(Timeline { id: id, frame_count: frames.len() as u16, labels: LabelTable::new(),
+            frames: frames.into_iter().map(|tags| Frame { tags: tags }).collect() })
+    }
+
+    // A two frame sprite 1, and a main timeline placing it at depth 1 and a shape at depth 2 on the second
+    // frame, which the third removes
+    fn movie() -> (Rc<Timeline>, HashMap<u16, Rc<Timeline>>) {
+        let mut sprites = HashMap::new();
+        sprites.insert(1, timeline(Some(1), vec![vec![], vec![]]));
+        let main = timeline(None, vec![
+            vec![place(1, 1), ControlTag::DoAction(vec![0x01])],
+            vec![place(2, 5), ControlTag::DoAction(vec![0x02])],
+            vec![ControlTag::Remove(RemoveObject { depth: 2, character_id: None }), ControlTag::DoAction(vec![0x03])]
+        ]);
+        (main, sprites)
+    }
+
+    #[test]
+    fn goto_forward_and_back() {
+        let (main, sprites) = movie();
+        let mut clip = MovieClip::new(main, &sprites);
+        assert_eq!(clip.take_actions(), vec![vec![0x01]]);
+
+        // Skipped frames change the display list without running their actions
+        clip.goto_frame(1, &sprites);
+        assert_eq!(clip.display_list.iter().map(|o| o.depth).collect::<Vec<_>>(), vec![1, 2]);
+        clip.goto_frame(7, &sprites);
+        assert_eq!((clip.current_frame, clip.display_list.len()), (2, 1));
+        assert_eq!(clip.take_actions(), vec![vec![0x02], vec![0x03]]);
+
+        // Going back rebuilds the list, the sprite instance is kept with its playhead
+        clip.child_mut(1).unwrap().goto_frame(1, &sprites);
+        clip.goto_frame(1, &sprites);
+        assert_eq!(clip.display_list.iter().map(|o| o.depth).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(clip.child(1).unwrap().current_frame, 1);
+        assert_eq!(clip.take_actions(), vec![vec![0x02]]);
+
+        clip.previous_frame(&sprites);
+        assert_eq!((clip.current_frame, clip.playing, clip.display_list.len()), (0, false, 1));
+        clip.next_frame(&sprites);
+        assert_eq!((clip.current_frame, clip.playing), (1, false));
+    }
+
+    #[test]
+    fn advance_loops() {
+        let (main, sprites) = movie();
+        let mut clip = MovieClip::new(main, &sprites);
+        clip.advance(&sprites);
+        clip.advance(&sprites);
+        assert_eq!((clip.current_frame, clip.child(1).unwrap().current_frame), (2, 0));
+
+        // Both loop at their ends, and a stopped clip stays on its frame while its children play
+        clip.advance(&sprites);
+        assert_eq!((clip.current_frame, clip.child(1).unwrap().current_frame), (0, 1));
+        assert_eq!(clip.take_actions(), vec![vec![0x01], vec![0x02], vec![0x03], vec![0x01]]);
+        clip.stop();
+        clip.advance(&sprites);
+        assert_eq!((clip.current_frame, clip.child(1).unwrap().current_frame), (0, 0));
+    }
+
+    #[test]
+    fn recursive_sprites() {
+        // Sprite 1 places itself, sprite 2 places sprite 3 which places sprite 2 again
+        let mut sprites = HashMap::new();
+        sprites.insert(1, timeline(Some(1), vec![vec![place(1, 1)]]));
+        sprites.insert(2, timeline(Some(2), vec![vec![place(1, 3)]]));
+        sprites.insert(3, timeline(Some(3), vec![vec![place(1, 2)]]));
+        let main = timeline(None, vec![vec![place(1, 1), place(2, 2)]]);
+
+        let clip = MovieClip::new(main, &sprites);
+        let inner = clip.child(1).unwrap();
+        assert!(inner.child(1).is_none() && inner.display_list.get(1).is_some());
+        let inner = clip.child(2).unwrap().child(1).unwrap();
+        assert_eq!(inner.timeline.id, Some(3));
+        assert!(inner.child(1).is_none() && inner.display_list.get(1).is_some());
+    }
+}
@@ -0,0 +1,607 @@
+use std::old_io::{ Writer, IoResult, IoError, InvalidInput };
+use std::cmp::{ min, max };
+use std::i32;
+use std::collections::BTreeMap;
+use std::iter::repeat;
+use swf::TagKind;
+use shape::PathCommand;
+use util::font::Font;
+
+const UNITS_PER_EM: i32 = 2048;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrueTypeWarning {
+    UnmappedGlyph(usize),
+    DuplicateCode(u16),
+}
+
+struct Glyph {
+    contours: Vec<Vec<(i32, i32, bool)>>,
+    x_min: i32,
+    y_min: i32,
+    x_max: i32,
+    y_max: i32,
+    advance: i32,
+}
+
+// Converts a DefineFont2/DefineFont3 font into a TrueType font file
+pub fn write_truetype<W : Writer>(font: &Font, writer: &mut W) -> IoResult<Vec<TrueTypeWarning>> {
+    match font.kind {
+        TagKind::DefineFont2 | TagKind::DefineFont3 => (),
+        _ => return Err(IoError { kind: InvalidInput, desc: "DefineFont2 or DefineFont3 expected", detail: None })
+    }
+
+    if font.codes.len() != font.glyphs.len() {
+        return Err(IoError { kind: InvalidInput, desc: "font code table does not match glyph count", detail: None })
+    }
+
+    let em = font.em_square() as i32;
+    let scale = |v: i32| -> i32 {
+        let v = v * UNITS_PER_EM;
+        if v < 0 { (v - em / 2) / em } else { (v + em / 2) / em }
+    };
+
+    let mut warnings = Vec::new();
+
+    // Glyph 0 is reserved for .notdef
+    let mut glyphs = vec![Glyph { contours: Vec::new(), x_min: 0, y_min: 0, x_max: 0, y_max: 0, advance: UNITS_PER_EM / 2 }];
+    for (i, shape) in font.glyphs.iter().enumerate() {
+        let mut contours = Vec::new();
+        let mut contour: Vec<(i32, i32, bool)> = Vec::new();
+        let outline = shape.outline();
+        for &command in outline.iter() {
+            match command {
+                PathCommand::MoveTo(x, y) => {
+                    close_contour(&mut contours, contour);
+                    contour = vec![(scale(x), -scale(y), true)];
+                },
+                PathCommand::LineTo(x, y) => contour.push((scale(x), -scale(y), true)),
+                PathCommand::CurveTo(cx, cy, x, y) => {
+                    contour.push((scale(cx), -scale(cy), false));
+                    contour.push((scale(x), -scale(y), true));
+                }
+            }
+        }
+        close_contour(&mut contours, contour);
+
+        let points = contours.iter().flat_map(|c| c.iter());
+        let (x_min, y_min, x_max, y_max) = if contours.is_empty() {
+            (0, 0, 0, 0)
+        } else {
+            points.fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), |(x0, y0, x1, y1), &(x, y, _)| {
+                (min(x0, x), min(y0, y), max(x1, x), max(y1, y))
+            })
+        };
+
+        let advance = match font.advance(i) {
+            Some(advance) => scale(advance as i32),
+            None => x_max
+        };
+
+        glyphs.push(Glyph {
+            contours: contours,
+            x_min: x_min,
+            y_min: y_min,
+            x_max: x_max,
+            y_max: y_max,
+            advance: max(0, advance)
+        });
+    }
+
+    let mut cmap = BTreeMap::new();
+    for (i, &code) in font.codes.iter().enumerate() {
+        if code == 0 || code == 0xffff {
+            warnings.push(TrueTypeWarning::UnmappedGlyph(i));
+        } else if cmap.contains_key(&code) {
+            warnings.push(TrueTypeWarning::DuplicateCode(code));
+        } else {
+            cmap.insert(code, i as u16 + 1);
+        }
+    }
+
+    let (ascent, descent, leading) = match font.layout {
+        Some(ref layout) => (scale(layout.ascent as i32), scale(layout.descent as i32), scale(layout.leading as i32)),
+        None => (
+            glyphs.iter().fold(0, |v, g| max(v, g.y_max)),
+            glyphs.iter().fold(0, |v, g| max(v, -g.y_min)),
+            0
+        )
+    };
+
+    let mut tables = Vec::new();
+    tables.push((*b"OS/2", try!(os2_table(font, &glyphs, &cmap, ascent, descent, leading))));
+    tables.push((*b"cmap", try!(cmap_table(&cmap))));
+    let (glyf, loca) = try!(glyf_table(&glyphs));
+    tables.push((*b"glyf", glyf));
+    tables.push((*b"head", try!(head_table(font, &glyphs))));
+    tables.push((*b"hhea", try!(hhea_table(&glyphs, ascent, descent, leading))));
+    tables.push((*b"hmtx", try!(hmtx_table(&glyphs))));
+    if let Some(kern) = try!(kern_table(font, &cmap)) {
+        tables.push((*b"kern", kern));
+    }
+    tables.push((*b"loca", loca));
+    tables.push((*b"maxp", try!(maxp_table(&glyphs))));
+    tables.push((*b"name", try!(name_table(font))));
+    tables.push((*b"post", try!(post_table(font))));
+
+    try!(writer.write_all(&try!(sfnt(tables))));
+
+    Ok(warnings)
+}
+
+fn close_contour(contours: &mut Vec<Vec<(i32, i32, bool)>>, mut contour: Vec<(i32, i32, bool)>) {
+    if contour.is_empty() {
+        return
+    }
+
+    let (x, y, _) = contour[0];
+    let closed = contour.len() > 1 && match contour.last() {
+        Some(&(lx, ly, true)) => lx == x && ly == y,
+        _ => false
+    };
+    if closed {
+        contour.pop();
+    }
+
+    if contour.len() > 1 {
+        contours.push(contour);
+    }
+}
+
+fn sfnt(tables: Vec<([u8; 4], Vec<u8>)>) -> IoResult<Vec<u8>> {
+    let count = tables.len();
+    let mut search_range = 1;
+    let mut entry_selector = 0;
+    while search_range * 2 <= count {
+        search_range *= 2;
+        entry_selector += 1;
+    }
+
+    let mut font = Vec::new();
+    try!(font.write_be_u32(0x00010000));
+    try!(font.write_be_u16(count as u16));
+    try!(font.write_be_u16(search_range as u16 * 16));
+    try!(font.write_be_u16(entry_selector));
+    try!(font.write_be_u16((count - search_range) as u16 * 16));
+
+    let mut offset = 12 + 16 * count;
+    let mut head_offset = None;
+    for &(ref tag, ref data) in tables.iter() {
+        if tag == b"head" {
+            head_offset = Some(offset);
+        }
+        try!(font.write_all(&tag[..]));
+        try!(font.write_be_u32(checksum(data)));
+        try!(font.write_be_u32(offset as u32));
+        try!(font.write_be_u32(data.len() as u32));
+        offset += (data.len() + 3) & !3;
+    }
+
+    for &(_, ref data) in tables.iter() {
+        font.push_all(data);
+        font.extend(repeat(0).take(((data.len() + 3) & !3) - data.len()));
+    }
+
+    if let Some(offset) = head_offset {
+        let adjustment = (0x1b1b0afbau64 - checksum(&font) as u64) as u32;
+        for i in 0..4 {
+            font[offset + 8 + i] = (adjustment >> (24 - i * 8)) as u8;
+        }
+    }
+
+    Ok(font)
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut sum = 0u64;
+    for chunk in data.chunks(4) {
+        let mut word = 0u32;
+        for i in 0..4 {
+            word = word << 8 | *chunk.get(i).unwrap_or(&0) as u32;
+        }
+        sum += word as u64;
+    }
+    sum as u32
+}
+
+fn glyf_table(glyphs: &[Glyph]) -> IoResult<(Vec<u8>, Vec<u8>)> {
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+
+    for glyph in glyphs.iter() {
+        try!(loca.write_be_u32(glyf.len() as u32));
+        if glyph.contours.is_empty() {
+            continue
+        }
+
+        try!(glyf.write_be_i16(glyph.contours.len() as i16));
+        try!(glyf.write_be_i16(glyph.x_min as i16));
+        try!(glyf.write_be_i16(glyph.y_min as i16));
+        try!(glyf.write_be_i16(glyph.x_max as i16));
+        try!(glyf.write_be_i16(glyph.y_max as i16));
+
+        let mut end = 0;
+        for contour in glyph.contours.iter() {
+            end += contour.len();
+            try!(glyf.write_be_u16(end as u16 - 1));
+        }
+        try!(glyf.write_be_u16(0)); // instructions
+
+        let mut flags = Vec::new();
+        let mut xs = Vec::new();
+        let mut ys = Vec::new();
+        let (mut px, mut py) = (0, 0);
+        for &(x, y, on_curve) in glyph.contours.iter().flat_map(|c| c.iter()) {
+            let mut flag = if on_curve { 0x01 } else { 0x00 };
+            flag |= try!(encode_delta(&mut xs, x - px, 0x02, 0x10));
+            flag |= try!(encode_delta(&mut ys, y - py, 0x04, 0x20));
+            flags.push(flag);
+            px = x;
+            py = y;
+        }
+        glyf.push_all(&flags);
+        glyf.push_all(&xs);
+        glyf.push_all(&ys);
+
+        while glyf.len() % 4 != 0 {
+            glyf.push(0);
+        }
+    }
+    try!(loca.write_be_u32(glyf.len() as u32));
+
+    Ok((glyf, loca))
+}
+
+fn encode_delta(data: &mut Vec<u8>, delta: i32, short: u8, same: u8) -> IoResult<u8> {
+    if delta == 0 {
+        Ok(same)
+    } else if delta > -256 && delta < 256 {
+        let magnitude = if delta < 0 { -delta } else { delta };
+        data.push(magnitude as u8);
+        Ok(if delta > 0 { short | same } else { short })
+    } else {
+        try!(data.write_be_i16(delta as i16));
+        Ok(0)
+    }
+}
+
+fn head_table(font: &Font, glyphs: &[Glyph]) -> IoResult<Vec<u8>> {
+    let outlined = glyphs.iter().filter(|g| !g.contours.is_empty());
+    let (x_min, y_min, x_max, y_max) = outlined.fold((0, 0, 0, 0), |(x0, y0, x1, y1), g| {
+        (min(x0, g.x_min), min(y0, g.y_min), max(x1, g.x_max), max(y1, g.y_max))
+    });
+
+    let mut head = Vec::new();
+    try!(head.write_be_u32(0x00010000)); // version
+    try!(head.write_be_u32(0x00010000)); // font revision
+    try!(head.write_be_u32(0)); // checksum adjustment
+    try!(head.write_be_u32(0x5f0f3cf5)); // magic
+    try!(head.write_be_u16(0x000b)); // baseline at y=0, lsb at x=0, integer ppem
+    try!(head.write_be_u16(UNITS_PER_EM as u16));
+    try!(head.write_be_u64(0)); // created
+    try!(head.write_be_u64(0)); // modified
+    try!(head.write_be_i16(x_min as i16));
+    try!(head.write_be_i16(y_min as i16));
+    try!(head.write_be_i16(x_max as i16));
+    try!(head.write_be_i16(y_max as i16));
+    try!(head.write_be_u16(mac_style(font)));
+    try!(head.write_be_u16(8)); // lowest recommended ppem
+    try!(head.write_be_i16(2)); // font direction hint
+    try!(head.write_be_i16(1)); // long loca offsets
+    try!(head.write_be_i16(0)); // glyph data format
+    Ok(head)
+}
+
+fn hhea_table(glyphs: &[Glyph], ascent: i32, descent: i32, leading: i32) -> IoResult<Vec<u8>> {
+    let advance_max = glyphs.iter().fold(0, |v, g| max(v, g.advance));
+    let outlined = || glyphs.iter().filter(|g| !g.contours.is_empty());
+    let lsb_min = outlined().fold(0, |v, g| min(v, g.x_min));
+    let rsb_min = outlined().fold(0, |v, g| min(v, g.advance - g.x_max));
+    let extent_max = outlined().fold(0, |v, g| max(v, g.x_max));
+
+    let mut hhea = Vec::new();
+    try!(hhea.write_be_u32(0x00010000));
+    try!(hhea.write_be_i16(ascent as i16));
+    try!(hhea.write_be_i16(-descent as i16));
+    try!(hhea.write_be_i16(leading as i16));
+    try!(hhea.write_be_u16(advance_max as u16));
+    try!(hhea.write_be_i16(lsb_min as i16));
+    try!(hhea.write_be_i16(rsb_min as i16));
+    try!(hhea.write_be_i16(extent_max as i16));
+    try!(hhea.write_be_i16(1)); // caret slope rise
+    try!(hhea.write_be_i16(0)); // caret slope run
+    try!(hhea.write_be_i16(0)); // caret offset
+    for _ in 0..4 {
+        try!(hhea.write_be_i16(0));
+    }
+    try!(hhea.write_be_i16(0)); // metric data format
+    try!(hhea.write_be_u16(glyphs.len() as u16));
+    Ok(hhea)
+}
+
+fn hmtx_table(glyphs: &[Glyph]) -> IoResult<Vec<u8>> {
+    let mut hmtx = Vec::new();
+    for glyph in glyphs.iter() {
+        try!(hmtx.write_be_u16(glyph.advance as u16));
+        try!(hmtx.write_be_i16(glyph.x_min as i16));
+    }
+    Ok(hmtx)
+}
+
+fn maxp_table(glyphs: &[Glyph]) -> IoResult<Vec<u8>> {
+    let max_points = glyphs.iter().fold(0, |v, g| max(v, g.contours.iter().fold(0, |n, c| n + c.len())));
+    let max_contours = glyphs.iter().fold(0, |v, g| max(v, g.contours.len()));
+
+    let mut maxp = Vec::new();
+    try!(maxp.write_be_u32(0x00010000));
+    try!(maxp.write_be_u16(glyphs.len() as u16));
+    try!(maxp.write_be_u16(max_points as u16));
+    try!(maxp.write_be_u16(max_contours as u16));
+    try!(maxp.write_be_u16(0)); // composite points
+    try!(maxp.write_be_u16(0)); // composite contours
+    try!(maxp.write_be_u16(2)); // zones
+    for _ in 0..8 {
+        try!(maxp.write_be_u16(0)); // twilight points, storage, instructions and components
+    }
+    Ok(maxp)
+}
+
+fn cmap_table(cmap: &BTreeMap<u16, u16>) -> IoResult<Vec<u8>> {
+    // Segments of consecutive codes that map to consecutive glyphs
+    let mut segments: Vec<(u16, u16, u16)> = Vec::new();
+    for (&code, &glyph) in cmap.iter() {
+        if let Some(last) = segments.last_mut() {
+            let (start, end, first) = *last;
+            if end + 1 == code && first + (code - start) == glyph {
+                *last = (start, code, first);
+                continue
+            }
+        }
+        segments.push((code, code, glyph));
+    }
+    segments.push((0xffff, 0xffff, 0));
+
+    let count = segments.len();
+    let mut search_range = 1;
+    let mut entry_selector = 0;
+    while search_range * 2 <= count {
+        search_range *= 2;
+        entry_selector += 1;
+    }
+
+    let mut subtable = Vec::new();
+    try!(subtable.write_be_u16(4)); // format
+    try!(subtable.write_be_u16((16 + count * 8) as u16));
+    try!(subtable.write_be_u16(0)); // language
+    try!(subtable.write_be_u16(count as u16 * 2));
+    try!(subtable.write_be_u16(search_range as u16 * 2));
+    try!(subtable.write_be_u16(entry_selector));
+    try!(subtable.write_be_u16((count - search_range) as u16 * 2));
+    for &(_, end, _) in segments.iter() {
+        try!(subtable.write_be_u16(end));
+    }
+    try!(subtable.write_be_u16(0)); // reserved
+    for &(start, _, _) in segments.iter() {
+        try!(subtable.write_be_u16(start));
+    }
+    for &(start, _, glyph) in segments.iter() {
+        let delta = if start == 0xffff { 1 } else { (glyph as u32 + 0x10000 - start as u32) as u16 };
+        try!(subtable.write_be_u16(delta));
+    }
+    for _ in segments.iter() {
+        try!(subtable.write_be_u16(0)); // range offset
+    }
+
+    let mut table = Vec::new();
+    try!(table.write_be_u16(0)); // version
+    try!(table.write_be_u16(2));
+    try!(table.write_be_u16(0)); // unicode
+    try!(table.write_be_u16(3)); // BMP
+    try!(table.write_be_u32(20));
+    try!(table.write_be_u16(3)); // windows
+    try!(table.write_be_u16(1)); // unicode BMP
+    try!(table.write_be_u32(20));
+    table.push_all(&subtable);
+    Ok(table)
+}
+
+fn kern_table(font: &Font, cmap: &BTreeMap<u16, u16>) -> IoResult<Option<Vec<u8>>> {
+    let kerning = match font.layout {
+        Some(ref layout) if !layout.kerning.is_empty() => &layout.kerning,
+        _ => return Ok(None)
+    };
+
+    let em = font.em_square() as i32;
+    let mut pairs = BTreeMap::new();
+    for record in kerning.iter() {
+        if let (Some(&left), Some(&right)) = (cmap.get(&record.left), cmap.get(&record.right)) {
+            pairs.insert((left, right), (record.adjustment as i32 * UNITS_PER_EM / em) as i16);
+        }
+    }
+
+    if pairs.is_empty() {
+        return Ok(None)
+    }
+
+    let count = pairs.len();
+    let mut search_range = 1;
+    let mut entry_selector = 0;
+    while search_range * 2 <= count {
+        search_range *= 2;
+        entry_selector += 1;
+    }
+
+    let mut kern = Vec::new();
+    try!(kern.write_be_u16(0)); // version
+    try!(kern.write_be_u16(1)); // subtables
+    try!(kern.write_be_u16(0)); // subtable version
+    try!(kern.write_be_u16((14 + count * 6) as u16));
+    try!(kern.write_be_u16(0x0001)); // horizontal, format 0
+    try!(kern.write_be_u16(count as u16));
+    try!(kern.write_be_u16(search_range as u16 * 6));
+    try!(kern.write_be_u16(entry_selector));
+    try!(kern.write_be_u16((count - search_range) as u16 * 6));
+    for (&(left, right), &value) in pairs.iter() {
+        try!(kern.write_be_u16(left));
+        try!(kern.write_be_u16(right));
+        try!(kern.write_be_i16(value));
+    }
+    Ok(Some(kern))
+}
+
+fn name_table(font: &Font) -> IoResult<Vec<u8>> {
+    let family = if font.name.is_empty() { format!("SWF Font {}", font.id) } else { font.name.clone() };
+    let subfamily = match (font.flags.bold, font.flags.italic) {
+        (true, true) => "Bold Italic",
+        (true, false) => "Bold",
+        (false, true) => "Italic",
+        (false, false) => "Regular"
+    };
+    let full_name = if subfamily == "Regular" { family.clone() } else { format!("{} {}", family, subfamily) };
+    let postscript: String = full_name.chars().filter(|&c| c > ' ' && c < '\x7f' && !"[](){}<>/%".contains_char(c)).collect();
+
+    let mut names = Vec::new();
+    if !font.copyright.is_empty() {
+        names.push((0, font.copyright.clone()));
+    }
+    names.push((1, family.clone()));
+    names.push((2, String::from_str(subfamily)));
+    names.push((3, format!("{};{}", family, subfamily)));
+    names.push((4, full_name));
+    names.push((6, postscript));
+
+    let mut strings = Vec::new();
+    let mut records = Vec::new();
+    for &(id, ref value) in names.iter() {
+        let offset = strings.len();
+        for unit in value.utf16_units() {
+            try!(strings.write_be_u16(unit));
+        }
+        try!(records.write_be_u16(3)); // windows
+        try!(records.write_be_u16(1)); // unicode BMP
+        try!(records.write_be_u16(0x0409)); // en-US
+        try!(records.write_be_u16(id));
+        try!(records.write_be_u16((strings.len() - offset) as u16));
+        try!(records.write_be_u16(offset as u16));
+    }
+
+    let mut name = Vec::new();
+    try!(name.write_be_u16(0)); // format
+    try!(name.write_be_u16(names.len() as u16));
+    try!(name.write_be_u16((6 + records.len()) as u16));
+    name.push_all(&records);
+    name.push_all(&strings);
+    Ok(name)
+}
+
+fn os2_table(font: &Font, glyphs: &[Glyph], cmap: &BTreeMap<u16, u16>, ascent: i32, descent: i32, leading: i32) -> IoResult<Vec<u8>> {
+    let advances: Vec<i32> = glyphs.iter().skip(1).map(|g| g.advance).filter(|&a| a > 0).collect();
+    let average = if advances.is_empty() { 0 } else { advances.iter().fold(0, |v, &a| v + a) / advances.len() as i32 };
+    let first = cmap.keys().next().map(|&c| c).unwrap_or(0);
+    let last = cmap.keys().next_back().map(|&c| c).unwrap_or(0);
+    let selection = match (font.flags.bold, font.flags.italic) {
+        (false, false) => 0x0040,
+        (bold, italic) => (if bold { 0x0020 } else { 0 }) | (if italic { 0x0001 } else { 0 })
+    };
+
+    let mut os2 = Vec::new();
+    try!(os2.write_be_u16(1)); // version
+    try!(os2.write_be_i16(average as i16));
+    try!(os2.write_be_u16(if font.flags.bold { 700 } else { 400 }));
+    try!(os2.write_be_u16(5)); // medium width
+    try!(os2.write_be_u16(0)); // installable embedding
+    let em = UNITS_PER_EM;
+    for &v in [em * 13 / 20, em * 6 / 10, 0, em * 15 / 100, em * 13 / 20, em * 6 / 10, 0, em * 45 / 100].iter() {
+        try!(os2.write_be_i16(v as i16)); // sub and superscript sizes and offsets
+    }
+    try!(os2.write_be_i16((em / 20) as i16)); // strikeout size
+    try!(os2.write_be_i16((em * 26 / 100) as i16)); // strikeout position
+    try!(os2.write_be_i16(0)); // family class
+    os2.push_all(&[0; 10]); // panose
+    for _ in 0..4 {
+        try!(os2.write_be_u32(0)); // unicode ranges
+    }
+    os2.push_all(b"SWF ");
+    try!(os2.write_be_u16(selection));
+    try!(os2.write_be_u16(first));
+    try!(os2.write_be_u16(last));
+    try!(os2.write_be_i16(ascent as i16));
+    try!(os2.write_be_i16(-descent as i16));
+    try!(os2.write_be_i16(leading as i16));
+    try!(os2.write_be_u16(ascent as u16));
+    try!(os2.write_be_u16(descent as u16));
+    try!(os2.write_be_u32(1)); // latin 1 code page
+    try!(os2.write_be_u32(0));
+    Ok(os2)
+}
+
+fn post_table(font: &Font) -> IoResult<Vec<u8>> {
+    let mut post = Vec::new();
+    try!(post.write_be_u32(0x00030000)); // no glyph names
+    try!(post.write_be_i32(if font.flags.italic { -12 << 16 } else { 0 }));
+    try!(post.write_be_i16(-(UNITS_PER_EM / 10) as i16)); // underline position
+    try!(post.write_be_i16((UNITS_PER_EM / 20) as i16)); // underline thickness
+    for _ in 0..5 {
+        try!(post.write_be_u32(0)); // fixed pitch and memory usage
+    }
+    Ok(post)
+}
+
+fn mac_style(font: &Font) -> u16 {
+    (if font.flags.bold { 0x01 } else { 0 }) | (if font.flags.italic { 0x02 } else { 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ write_truetype, checksum };
+    use swf::TagKind;
+    use shape::{ Shape, ShapeRecord };
+    use util::font::{ Font, FontFlags, LanguageCode };
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        (data[offset] as u32) << 24 | (data[offset + 1] as u32) << 16 | (data[offset + 2] as u32) << 8 | data[offset + 3] as u32
+    }
+
+    #[test]
+    fn tables_and_checksums() {
+        let mut square = Shape::new();
+        square.records.push(ShapeRecord::StyleChange { move_to: Some((0, -1024)), fill_style0: None, fill_style1: Some(1),
+            line_style: None });
+        square.records.push(ShapeRecord::StraightEdge { dx: 1024, dy: 0 });
+        square.records.push(ShapeRecord::StraightEdge { dx: 0, dy: 1024 });
+        square.records.push(ShapeRecord::StraightEdge { dx: -1024, dy: 0 });
+        square.records.push(ShapeRecord::StraightEdge { dx: 0, dy: -1024 });
+        let flags = FontFlags { small_text: false, shift_jis: false, ansi: false, italic: false, bold: true, wide_codes: true,
+            utf8: true };
+        let font = Font { kind: TagKind::DefineFont3, id: 1, name: "Test".to_string(), copyright: String::new(), flags: flags,
+            language: LanguageCode::Latin, glyphs: vec![square], codes: vec![65], layout: None, align_zones: None };
+
+        let mut data = Vec::new();
+        assert!(write_truetype(&font, &mut data).unwrap().is_empty());
+
+        // Fixed size tables have the lengths of their versions, and every table's checksum matches
+        let count = (data[4] as usize) << 8 | data[5] as usize;
+        for i in 0..count {
+            let entry = 12 + i * 16;
+            let (offset, length) = (read_u32(&data, entry + 8) as usize, read_u32(&data, entry + 12) as usize);
+            let mut table = data[offset..offset + length].to_vec();
+            match &String::from_utf8(data[entry..entry + 4].to_vec()).unwrap()[..] {
+                "head" => {
+                    assert_eq!(length, 54);
+                    for i in 8..12 {
+                        table[i] = 0;
+                    }
+                },
+                "hhea" => assert_eq!(length, 36),
+                "maxp" => assert_eq!(length, 32),
+                "OS/2" => assert_eq!(length, 86),
+                "post" => assert_eq!(length, 32),
+                _ => ()
+            }
+            assert_eq!(checksum(&table), read_u32(&data, entry + 4));
+        }
+
+        assert_eq!(data.len() % 4, 0);
+        assert_eq!(checksum(&data), 0xb1b0afba);
+    }
+}
@@ -0,0 +1,466 @@
+use std::old_io::{ Reader, Writer, BufReader, MemReader, IoResult, IoError, InvalidInput, EndOfFile };
+use std::old_io::util::{ NullWriter, copy };
+use std::num;
+use std::cmp::min;
+use swf::{ Tag, TagKind, Header };
+use bits::BitReader;
+use util::sound_stream::StreamSound;
+use util::vp6::{ Vp6Header, split_vp6_alpha };
+
+#[derive(Debug, FromPrimitive, Copy, Clone, PartialEq, Eq)]
+pub enum VideoCodec {
+    SorensonH263 = 2,
+    ScreenVideo  = 3,
+    VP6          = 4,
+    VP6Alpha     = 5,
+    ScreenVideo2 = 6
+}
+
+// DefineVideoStream, `deblocking` is 0 to use the setting of each frame, 1 for off, or 2 to 6 for a filter level
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VideoStream {
+    pub id: u16,
+    pub frame_count: u16,
+    pub width: u16,
+    pub height: u16,
+    pub deblocking: u8,
+    pub smoothing: bool,
+    pub codec: VideoCodec,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoFrame {
+    pub stream_id: u16,
+    pub frame_num: u16,
+    pub data: Vec<u8>,
+}
+
+// A decoded picture in 4:2:0 YCbCr, the chroma planes are (width + 1) / 2 by (height + 1) / 2. VP6 with alpha
+// adds a full size alpha plane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YuvFrame {
+    pub width: u16,
+    pub height: u16,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+    pub alpha: Option<Vec<u8>>,
+}
+
+impl YuvFrame {
+    // Converts from BT.601 studio range to RGBA, opaque unless there is an alpha plane
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let chroma_width = (width + 1) / 2;
+        let clamp = |v: i32| if v < 0 { 0 } else if v > 255 { 255 } else { v as u8 };
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for row in 0..height {
+            for col in 0..width {
+                let c = (row / 2) * chroma_width + col / 2;
+                let y = (self.y[row * width + col] as i32 - 16) * 298;
+                let u = self.u[c] as i32 - 128;
+                let v = self.v[c] as i32 - 128;
+                rgba.push(clamp((y + 409 * v + 128) >> 8));
+                rgba.push(clamp((y - 100 * u - 208 * v + 128) >> 8));
+                rgba.push(clamp((y + 516 * u + 128) >> 8));
+                rgba.push(match self.alpha {
+                    Some(ref alpha) => alpha[row * width + col],
+                    None => 255
+                });
+            }
+        }
+        rgba
+    }
+}
+
+impl VideoStream {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::DefineVideoStream {
+            return Err(IoError { kind: InvalidInput, desc: "DefineVideoStream tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let id = try!(reader.read_le_u16());
+        let frame_count = try!(reader.read_le_u16());
+        let width = try!(reader.read_le_u16());
+        let height = try!(reader.read_le_u16());
+        let flags = try!(reader.read_u8());
+        let codec = match num::from_u8(try!(reader.read_u8())) {
+            Some(codec) => codec,
+            None => return Err(IoError { kind: InvalidInput, desc: "unsupported video codec", detail: None })
+        };
+        try!(copy(&mut reader, &mut NullWriter));
+
+        Ok(VideoStream {
+            id: id,
+            frame_count: frame_count,
+            width: width,
+            height: height,
+            deblocking: (flags >> 1) & 0x7,
+            smoothing: flags & 0x1 != 0,
+            codec: codec
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(Tag { kind: TagKind::DefineVideoStream, length: 10 }.write(writer));
+        try!(writer.write_le_u16(self.id));
+        try!(writer.write_le_u16(self.frame_count));
+        try!(writer.write_le_u16(self.width));
+        try!(writer.write_le_u16(self.height));
+        try!(writer.write_u8((self.deblocking & 0x7) << 1 | if self.smoothing { 1 } else { 0 }));
+        writer.write_u8(self.codec as u8)
+    }
+}
+
+impl VideoFrame {
+    pub fn read<R : Reader>(tag: Tag, reader: &mut R) -> IoResult<Self> {
+        if tag.kind != TagKind::VideoFrame {
+            return Err(IoError { kind: InvalidInput, desc: "VideoFrame tag expected", detail: None })
+        }
+
+        let mut reader = tag.reader(reader);
+        let stream_id = try!(reader.read_le_u16());
+        let frame_num = try!(reader.read_le_u16());
+        let data = try!(reader.read_to_end());
+
+        Ok(VideoFrame {
+            stream_id: stream_id,
+            frame_num: frame_num,
+            data: data
+        })
+    }
+
+    pub fn write<W : Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(Tag { kind: TagKind::VideoFrame, length: 4 + self.data.len() as u32 }.write(writer));
+        try!(writer.write_le_u16(self.stream_id));
+        try!(writer.write_le_u16(self.frame_num));
+        writer.write_all(&self.data)
+    }
+
+    // Whether the frame decodes without the frames before it, truncated or unparsable payloads count as inter frames
+    pub fn is_keyframe(&self, codec: VideoCodec) -> bool {
+        match codec {
+            VideoCodec::SorensonH263 => h263_keyframe(&self.data).unwrap_or(false),
+            VideoCodec::ScreenVideo | VideoCodec::ScreenVideo2 => screen_keyframe(&self.data, codec).unwrap_or(false),
+            // The alpha variant starts with the 24-bit offset of its alpha data
+            VideoCodec::VP6 => self.data.first().map(|&b| b & 0x80 == 0).unwrap_or(false),
+            VideoCodec::VP6Alpha => self.data.get(3).map(|&b| b & 0x80 == 0).unwrap_or(false)
+        }
+    }
+}
+
+fn h263_keyframe(data: &[u8]) -> IoResult<bool> {
+    let mut bits = BitReader::new(BufReader::new(data));
+    if try!(bits.read_ubits(17)) != 1 {
+        return Ok(false)
+    }
+    try!(bits.read_ubits(5)); // version
+    try!(bits.read_ubits(8)); // temporal reference
+    match try!(bits.read_ubits(3)) {
+        0 => try!(bits.read_ubits(16)),
+        1 => try!(bits.read_ubits(32)),
+        _ => 0
+    };
+    Ok(try!(bits.read_ubits(2)) == 0)
+}
+
+// A screen video frame is a keyframe when every block is coded. In the second version a custom palette
+// comes as a block ahead of them, and blocks must not restore rows from or be primed by the last keyframe
+fn screen_keyframe(data: &[u8], codec: VideoCodec) -> IoResult<bool> {
+    let mut bits = BitReader::new(BufReader::new(data));
+    let block_width = (try!(bits.read_ubits(4)) + 1) * 16;
+    let width = try!(bits.read_ubits(12));
+    let block_height = (try!(bits.read_ubits(4)) + 1) * 16;
+    let height = try!(bits.read_ubits(12));
+
+    let mut reader = bits.into_inner();
+    if codec == VideoCodec::ScreenVideo2 && try!(reader.read_u8()) & 0x1 != 0 {
+        let size = try!(reader.read_be_u16());
+        try!(reader.read_exact(size as usize));
+    }
+
+    let blocks = ((width + block_width - 1) / block_width) * ((height + block_height - 1) / block_height);
+    for _ in 0..blocks {
+        let size = try!(reader.read_be_u16());
+        if size == 0 {
+            return Ok(false)
+        }
+        let block = try!(reader.read_exact(size as usize));
+        if codec == VideoCodec::ScreenVideo2 && block[0] & 0x5 != 0 {
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+// Writes video stream `id` as an FLV file, muxing in the streaming sound of the timeline that holds its frames.
+// `reader` starts at the first tag, frame numbers are timed by the movie's frame rate
+pub fn write_flv<R : Reader, W : Writer>(reader: &mut R, writer: &mut W, header: &Header, id: u16) -> IoResult<()> {
+    let frame_rate = header.frame_rate as f64 / 256.0;
+    if !(frame_rate > 0.0) {
+        return Err(IoError { kind: InvalidInput, desc: "frame rate must be positive", detail: None })
+    }
+
+    let tags = try!(reader.read_to_end());
+    let mut stream = None;
+    let mut frames = Vec::new();
+    try!(scan(&mut BufReader::new(&tags[..]), None, id, &mut stream, &mut frames));
+    let stream = match stream {
+        Some(stream) => stream,
+        None => return Err(IoError { kind: InvalidInput, desc: "video stream not found", detail: None })
+    };
+
+    // Frames after the first are only taken from the same timeline, the first one anchors the soundtrack
+    let (sprite_id, base) = match frames.first() {
+        Some(&(sprite_id, frame, ref video)) => (sprite_id, frame as i64 - video.frame_num as i64),
+        None => (None, 0)
+    };
+    let timestamp = |frame: i64| (frame as f64 * 1000.0 / frame_rate + 0.5) as u32;
+
+    // Tags are kept in timestamp order, audio before video within the same frame
+    let mut packets: Vec<(u32, u8, Vec<u8>)> = Vec::new();
+    let mut duration = 0;
+    let mut keyframe_header = None;
+    for (index, &(_, _, ref video)) in frames.iter().filter(|f| f.0 == sprite_id).enumerate() {
+        let keyframe = index == 0 || video.is_keyframe(stream.codec);
+        let mut data = vec![(if keyframe { 1 } else { 2 }) << 4 | stream.codec as u8];
+        if stream.codec == VideoCodec::VP6 || stream.codec == VideoCodec::VP6Alpha {
+            // FLV carries the crop from the coded size of the last keyframe to the display size
+            let color = if stream.codec == VideoCodec::VP6Alpha {
+                split_vp6_alpha(&video.data).map(|(color, _)| color)
+            } else {
+                Ok(&video.data[..])
+            };
+            if let Ok(header) = color.and_then(|color| Vp6Header::read(color, None)) {
+                keyframe_header = Some(header);
+            }
+            let (width, height) = match keyframe_header {
+                Some(header) => (header.coded_width(), header.coded_height()),
+                None => ((stream.width + 15) / 16 * 16, (stream.height + 15) / 16 * 16)
+            };
+            let adjust = |coded: u16, size: u16| if coded > size { min(15, coded - size) as u8 } else { 0 };
+            data.push(adjust(width, stream.width) << 4 | adjust(height, stream.height));
+        }
+        data.push_all(&video.data);
+
+        let time = timestamp(video.frame_num as i64);
+        duration = timestamp(video.frame_num as i64 + 1);
+        packets.push((time, TAG_VIDEO, data));
+    }
+
+    let sounds = try!(StreamSound::find(&mut BufReader::new(&tags[..])));
+    let mut audio_codec = None;
+    if let Some(head) = sounds.iter().find(|s| s.sprite_id == sprite_id) {
+        let mut sound = try!(head.stream());
+        let flags = sound.format.to_u8();
+        audio_codec = Some(sound.format.codec());
+        loop {
+            let block = match sound.read_block() {
+                Ok(block) => block,
+                Err(ref err) if err.kind == EndOfFile => break,
+                Err(err) => return Err(err)
+            };
+            let frame = (head.frame + sound.current_block().unwrap().frame) as i64 - base;
+            if frame < 0 {
+                continue
+            }
+
+            let mut data = vec![flags];
+            data.push_all(&block);
+            packets.push((timestamp(frame), TAG_AUDIO, data));
+        }
+    }
+    packets.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    try!(writer.write_all(b"FLV"));
+    try!(writer.write_u8(1));
+    try!(writer.write_u8(if audio_codec.is_some() { 0x05 } else { 0x01 }));
+    try!(writer.write_be_u32(9));
+    try!(writer.write_be_u32(0));
+
+    let mut metadata = vec![
+        ("duration", duration as f64 / 1000.0),
+        ("width", stream.width as f64),
+        ("height", stream.height as f64),
+        ("framerate", frame_rate),
+        ("videocodecid", stream.codec as u8 as f64)
+    ];
+    if let Some(codec) = audio_codec {
+        metadata.push(("audiocodecid", codec as u8 as f64));
+    }
+    try!(write_flv_tag(writer, TAG_SCRIPT, 0, &try!(on_metadata(&metadata))));
+
+    for &(time, kind, ref data) in packets.iter() {
+        try!(write_flv_tag(writer, kind, time, data));
+    }
+    Ok(())
+}
+
+const TAG_AUDIO: u8 = 8;
+const TAG_VIDEO: u8 = 9;
+const TAG_SCRIPT: u8 = 18;
+
+// Collects the DefineVideoStream and the VideoFrames of stream `id` with their owning sprite and timeline frame
+fn scan<R : Reader>(reader: &mut R, sprite_id: Option<u16>, id: u16, stream: &mut Option<VideoStream>,
+    frames: &mut Vec<(Option<u16>, usize, VideoFrame)>) -> IoResult<()>
+{
+    let mut frame = 0;
+    loop {
+        let tag = match Tag::read(reader) {
+            Ok(tag) => tag,
+            Err(ref err) if err.kind == EndOfFile && sprite_id.is_none() => break,
+            Err(err) => return Err(err)
+        };
+
+        match tag.kind {
+            TagKind::End => break,
+            TagKind::ShowFrame => frame += 1,
+            TagKind::DefineVideoStream => {
+                let video = try!(VideoStream::read(tag, reader));
+                if video.id == id {
+                    *stream = Some(video);
+                }
+                continue
+            },
+            TagKind::VideoFrame => {
+                let video = try!(VideoFrame::read(tag, reader));
+                if video.stream_id == id {
+                    frames.push((sprite_id, frame, video));
+                }
+                continue
+            },
+            TagKind::DefineSprite => {
+                let mut sprite = MemReader::new(try!(tag.reader(reader).read_to_end()));
+                let sprite_id = try!(sprite.read_le_u16());
+                try!(sprite.read_le_u16()); // frame count
+                try!(scan(&mut sprite, Some(sprite_id), id, stream, frames));
+                continue
+            },
+            _ => ()
+        }
+        try!(copy(&mut tag.reader(reader), &mut NullWriter));
+    }
+
+    Ok(())
+}
+
+fn write_flv_tag<W : Writer>(writer: &mut W, kind: u8, time: u32, data: &[u8]) -> IoResult<()> {
+    try!(writer.write_u8(kind));
+    try!(writer.write_be_uint_n(data.len() as u64, 3));
+    try!(writer.write_be_uint_n((time & 0xffffff) as u64, 3));
+    try!(writer.write_u8((time >> 24) as u8));
+    try!(writer.write_be_uint_n(0, 3)); // stream id
+    try!(writer.write_all(data));
+    writer.write_be_u32(11 + data.len() as u32)
+}
+
+// The AMF0 onMetaData script data, an ECMA array of numbers
+fn on_metadata(values: &[(&str, f64)]) -> IoResult<Vec<u8>> {
+    let mut data = Vec::new();
+    try!(data.write_u8(2));
+    try!(data.write_be_u16(10));
+    try!(data.write_all(b"onMetaData"));
+    try!(data.write_u8(8));
+    try!(data.write_be_u32(values.len() as u32));
+    for &(name, value) in values.iter() {
+        try!(data.write_be_u16(name.len() as u16));
+        try!(data.write_all(name.as_bytes()));
+        try!(data.write_u8(0));
+        try!(data.write_be_f64(value));
+    }
+    try!(data.write_be_uint_n(9, 3)); // object end marker
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::BufReader;
+    use swf::{ Tag, TagKind, Header };
+    use super::{ VideoStream, VideoFrame, VideoCodec, write_flv };
+
+    fn tag(data: &mut Vec<u8>, kind: TagKind, body: &[u8]) {
+        Tag { kind: kind, length: body.len() as u32 }.write(data).unwrap();
+        data.push_all(body);
+    }
+
+    // A 16x16 screen video frame of one block, which is a keyframe when the block is coded
+    fn frame(frame_num: u16, keyframe: bool) -> VideoFrame {
+        let mut data = vec![0x00, 0x10, 0x00, 0x10];
+        data.push_all(if keyframe { &[0x00, 0x02, 0x78, 0x9c][..] } else { &[0x00, 0x00][..] });
+        VideoFrame { stream_id: 1, frame_num: frame_num, data: data }
+    }
+
+    #[test]
+    fn write_flv_round_trip() {
+        // Video on frames 0, 1 and 3 and a frame of another stream, with sound blocks on frames 0 to 2
+        let mut data = Vec::new();
+        VideoStream { id: 1, frame_count: 4, width: 16, height: 16, deblocking: 0, smoothing: false,
+            codec: VideoCodec::ScreenVideo }.write(&mut data).unwrap();
+        tag(&mut data, TagKind::SoundStreamHead, &[0x30, 0x30, 0x02, 0x00]);
+        for i in 0..4 {
+            if i < 3 {
+                tag(&mut data, TagKind::SoundStreamBlock, &[i as u8, 0x80]);
+            }
+            if i != 2 {
+                frame(i, i != 1).write(&mut data).unwrap();
+            }
+            VideoFrame { stream_id: 2, .. frame(i, true) }.write(&mut data).unwrap();
+            tag(&mut data, TagKind::ShowFrame, &[]);
+        }
+        tag(&mut data, TagKind::End, &[]);
+
+        let header = Header { rectangle: vec![0], frame_rate: 10 << 8, frame_count: 4 };
+        let mut flv = Vec::new();
+        write_flv(&mut &data[..], &mut flv, &header, 1).unwrap();
+        assert!(write_flv(&mut &data[..], &mut Vec::new(), &header, 3).is_err());
+
+        // Audio and video flags, the header size and the first previous tag size
+        assert_eq!(&flv[..13], &[b'F', b'L', b'V', 1, 0x05, 0, 0, 0, 9, 0, 0, 0, 0][..]);
+
+        let mut tags = Vec::new();
+        let mut pos = 13;
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |v, &b| v << 8 | b as usize);
+        while pos < flv.len() {
+            let size = be(&flv[pos + 1..pos + 4]);
+            let time = (flv[pos + 7] as usize) << 24 | be(&flv[pos + 4..pos + 7]);
+            let end = pos + 11 + size;
+            assert_eq!(be(&flv[end..end + 4]), 11 + size);
+            tags.push((flv[pos], time, flv[pos + 11..end].to_vec()));
+            pos = end + 4;
+        }
+        assert_eq!(pos, flv.len());
+
+        let mut key = vec![0x13];
+        key.push_all(&frame(0, true).data);
+        let mut inter = vec![0x23];
+        inter.push_all(&frame(1, false).data);
+        assert_eq!(tags[1..].iter().map(|t| (t.0, t.1, &t.2[..])).collect::<Vec<_>>(), vec![
+            (8, 0, &[0x30, 0, 0x80][..]), (9, 0, &key[..]),
+            (8, 100, &[0x30, 1, 0x80][..]), (9, 100, &inter[..]),
+            (8, 200, &[0x30, 2, 0x80][..]), (9, 300, &key[..])
+        ]);
+
+        // The script tag is an onMetaData ECMA array of numbers
+        let (kind, time, ref script) = tags[0];
+        assert_eq!((kind, time), (18, 0));
+        let mut reader = BufReader::new(&script[..]);
+        assert_eq!(reader.read_u8().unwrap(), 2);
+        let length = reader.read_be_u16().unwrap() as usize;
+        assert_eq!(reader.read_exact(length).unwrap(), b"onMetaData".to_vec());
+        assert_eq!(reader.read_u8().unwrap(), 8);
+        let mut metadata = Vec::new();
+        for _ in 0..reader.read_be_u32().unwrap() {
+            let length = reader.read_be_u16().unwrap() as usize;
+            let name = String::from_utf8(reader.read_exact(length).unwrap()).unwrap();
+            assert_eq!(reader.read_u8().unwrap(), 0);
+            metadata.push((name, reader.read_be_f64().unwrap()));
+        }
+        assert_eq!(reader.read_to_end().unwrap(), vec![0, 0, 9]);
+        assert_eq!(metadata.iter().map(|&(ref name, value)| (&name[..], value)).collect::<Vec<_>>(), vec![
+            ("duration", 0.4), ("width", 16.0), ("height", 16.0), ("framerate", 10.0), ("videocodecid", 3.0),
+            ("audiocodecid", 3.0)
+        ]);
+    }
+}
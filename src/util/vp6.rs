@@ -0,0 +1,2180 @@
+use std::old_io::{ IoResult, IoError, InvalidInput };
+use std::iter::repeat;
+use std::cmp::{ min, max, Ordering };
+use util::video::{ VideoStream, VideoFrame, VideoCodec, YuvFrame };
+
+// VP6 decoding, ported from FFmpeg's vp56.c, vp56data.c, vp6.c, vp3dsp.c and vp6dsp.c. Like them, this file is
+// licensed under the LGPL version 2.1 or later
+
+// The uncoded start of a VP6 frame. Frames are coded at whole macroblocks and the displayed size may be
+// smaller, inter frames take the version, profile and sizes of the keyframe before them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Vp6Header {
+    pub keyframe: bool,
+    pub quantizer: u8,
+    pub separated_coefficients: bool,
+    pub version: u8,
+    pub profile: u8,
+    pub interlaced: bool,
+    pub mb_rows: u8,
+    pub mb_columns: u8,
+    pub display_mb_rows: u8,
+    pub display_mb_columns: u8,
+    pub golden: bool,
+}
+
+impl Vp6Header {
+    pub fn read(data: &[u8], keyframe: Option<&Vp6Header>) -> IoResult<Self> {
+        let truncated = IoError { kind: InvalidInput, desc: "truncated VP6 frame header", detail: None };
+        let first = match data.first() {
+            Some(&byte) => byte,
+            None => return Err(truncated)
+        };
+
+        let mut header = Vp6Header {
+            keyframe: first & 0x80 == 0,
+            quantizer: (first >> 1) & 0x3f,
+            separated_coefficients: first & 0x1 != 0,
+            version: 0,
+            profile: 0,
+            interlaced: false,
+            mb_rows: 0,
+            mb_columns: 0,
+            display_mb_rows: 0,
+            display_mb_columns: 0,
+            golden: false
+        };
+
+        if header.keyframe {
+            if data.len() < 2 {
+                return Err(truncated)
+            }
+            header.version = data[1] >> 3;
+            header.profile = (data[1] >> 1) & 0x3;
+            header.interlaced = data[1] & 0x1 != 0;
+
+            // The offset to the second coefficient partition comes before the sizes when there is one
+            let sizes = if header.has_partition_offset() { 4 } else { 2 };
+            if data.len() < sizes + 4 {
+                return Err(truncated)
+            }
+            header.mb_rows = data[sizes];
+            header.mb_columns = data[sizes + 1];
+            header.display_mb_rows = data[sizes + 2];
+            header.display_mb_columns = data[sizes + 3];
+            if header.mb_rows == 0 || header.mb_columns == 0 {
+                return Err(IoError { kind: InvalidInput, desc: "invalid VP6 frame size", detail: None })
+            }
+        } else {
+            match keyframe {
+                Some(keyframe) => {
+                    header.version = keyframe.version;
+                    header.profile = keyframe.profile;
+                    header.interlaced = keyframe.interlaced;
+                    header.mb_rows = keyframe.mb_rows;
+                    header.mb_columns = keyframe.mb_columns;
+                    header.display_mb_rows = keyframe.display_mb_rows;
+                    header.display_mb_columns = keyframe.display_mb_columns;
+                },
+                None => return Err(IoError { kind: InvalidInput, desc: "VP6 inter frame without a keyframe", detail: None })
+            }
+
+            // The first boolean coded flag has even odds, which leaves it as the top bit
+            let start = if header.has_partition_offset() { 3 } else { 1 };
+            match data.get(start) {
+                Some(&byte) => header.golden = byte & 0x80 != 0,
+                None => return Err(truncated)
+            }
+        }
+
+        Ok(header)
+    }
+
+    // Frames with separated coefficients, and all frames of the simple profile, start with the offset of the
+    // coefficient partition
+    pub fn has_partition_offset(&self) -> bool {
+        self.separated_coefficients || self.profile == 0
+    }
+
+    pub fn coded_width(&self) -> u16 {
+        self.mb_columns as u16 * 16
+    }
+
+    pub fn coded_height(&self) -> u16 {
+        self.mb_rows as u16 * 16
+    }
+}
+
+// Splits a VP6 alpha frame into its color and alpha frames, each coded as a VP6 frame of its own
+pub fn split_vp6_alpha(data: &[u8]) -> IoResult<(&[u8], &[u8])> {
+    if data.len() < 3 {
+        return Err(IoError { kind: InvalidInput, desc: "truncated VP6 alpha frame", detail: None })
+    }
+
+    let offset = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    if offset > data.len() - 3 {
+        return Err(IoError { kind: InvalidInput, desc: "invalid VP6 alpha offset", detail: None })
+    }
+    Ok((&data[3..3 + offset], &data[3 + offset..]))
+}
+
+// Macroblock types, REFERENCE_FRAME gives the frame each of them predicts from
+const MB_INTER_NOVEC_PF: u8 = 0;
+const MB_INTRA: u8 = 1;
+const MB_INTER_DELTA_PF: u8 = 2;
+const MB_INTER_V1_PF: u8 = 3;
+const MB_INTER_V2_PF: u8 = 4;
+const MB_INTER_NOVEC_GF: u8 = 5;
+const MB_INTER_DELTA_GF: u8 = 6;
+const MB_INTER_4V: u8 = 7;
+const MB_INTER_V1_GF: u8 = 8;
+const MB_INTER_V2_GF: u8 = 9;
+
+const FRAME_NONE: i8 = -1;
+const FRAME_CURRENT: i8 = 0;
+const FRAME_PREVIOUS: i8 = 1;
+const FRAME_GOLDEN: i8 = 2;
+
+// VP6 and VP6 with alpha, video codecs 4 and 5. The alpha plane of codec 5 is a second VP6 stream with its own
+// models and reference frames, decoded for luma only. Frames must be decoded in order from a keyframe
+pub struct Vp6Decoder {
+    width: u16,
+    height: u16,
+    color: Context,
+    alpha: Option<Context>,
+}
+
+impl Vp6Decoder {
+    pub fn new(stream: &VideoStream) -> IoResult<Self> {
+        let alpha = match stream.codec {
+            VideoCodec::VP6 => None,
+            VideoCodec::VP6Alpha => Some(Context::new(true)),
+            _ => return Err(IoError { kind: InvalidInput, desc: "VP6 stream expected", detail: None })
+        };
+
+        Ok(Vp6Decoder {
+            width: stream.width,
+            height: stream.height,
+            color: Context::new(false),
+            alpha: alpha
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.color = Context::new(false);
+        if self.alpha.is_some() {
+            self.alpha = Some(Context::new(true));
+        }
+    }
+
+    // The coded size is cropped to the size of the stream, the alpha plane is kept apart for `YuvFrame::to_rgba`
+    pub fn decode(&mut self, frame: &VideoFrame) -> IoResult<YuvFrame> {
+        match self.alpha {
+            Some(ref mut alpha) => {
+                let (color_data, alpha_data) = try!(split_vp6_alpha(&frame.data));
+                try!(self.color.decode(color_data));
+                try!(alpha.decode(alpha_data));
+                if alpha.mb_width != self.color.mb_width || alpha.mb_height != self.color.mb_height {
+                    return Err(IoError { kind: InvalidInput, desc: "VP6 alpha frame size differs from the color frame", detail: None })
+                }
+            },
+            None => try!(self.color.decode(&frame.data))
+        }
+
+        let visible = |size: u16, coded: usize| if size > 0 && (size as usize) < coded { size as usize } else { coded };
+        let width = visible(self.width, self.color.mb_width * 16);
+        let height = visible(self.height, self.color.mb_height * 16);
+        let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+        let copy = |plane: &[u8], stride: usize, width: usize, height: usize| {
+            let mut data = Vec::with_capacity(width * height);
+            for row in 0..height {
+                data.push_all(&plane[row * stride..row * stride + width]);
+            }
+            data
+        };
+
+        let stride = self.color.mb_width * 16;
+        let alpha = match self.alpha {
+            Some(ref alpha) => alpha.previous.as_ref().map(|picture| copy(&picture.planes[0], stride, width, height)),
+            None => None
+        };
+        match self.color.previous {
+            Some(ref picture) => Ok(YuvFrame {
+                width: width as u16,
+                height: height as u16,
+                y: copy(&picture.planes[0], stride, width, height),
+                u: copy(&picture.planes[1], stride / 2, chroma_width, chroma_height),
+                v: copy(&picture.planes[2], stride / 2, chroma_width, chroma_height),
+                alpha: alpha
+            }),
+            None => Err(IoError { kind: InvalidInput, desc: "VP6 frame was not decoded", detail: None })
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Vector {
+    x: i16,
+    y: i16,
+}
+
+const ZERO_VECTOR: Vector = Vector { x: 0, y: 0 };
+
+#[derive(Debug, Copy, Clone)]
+struct Macroblock {
+    kind: u8,
+    vector: Vector,
+}
+
+// The DC of the block above or to the left, for the prediction of the next DC and the context of its coding
+#[derive(Debug, Copy, Clone)]
+struct RefDc {
+    not_null: bool,
+    frame: i8,
+    dc: i16,
+}
+
+const EMPTY_DC: RefDc = RefDc { not_null: false, frame: FRAME_NONE, dc: 0 };
+
+// Planes padded to whole macroblocks, an alpha stream only has the first
+#[derive(Clone)]
+struct Picture {
+    planes: Vec<Vec<u8>>,
+}
+
+// Probabilities carried from frame to frame, keyframes reset them to the defaults
+struct Model {
+    coeff_reorder: [u8; 64],
+    coeff_index_to_pos: [u8; 64],
+    coeff_index_to_idct_selector: [u8; 64],
+    vector_sig: [u8; 2],
+    vector_dct: [u8; 2],
+    vector_pdv: [[u8; 7]; 2],
+    vector_fdv: [[u8; 8]; 2],
+    coeff_dccv: [[u8; 11]; 2],
+    coeff_ract: [[[[u8; 11]; 6]; 3]; 2],
+    coeff_dcct: [[[u8; 5]; 3]; 2],
+    coeff_runv: [[u8; 14]; 2],
+    mb_type: [[[u8; 10]; 10]; 3],
+    mb_types_stats: [[[u8; 2]; 10]; 3],
+}
+
+// The coefficients are coded after the modes with the same boolean decoder, or in a partition of their own with
+// a second boolean decoder or Huffman codes
+enum Partition<'a> {
+    Shared,
+    Bool(BoolDecoder<'a>),
+    Huffman(BitStream<'a>),
+}
+
+// The decoding state of one VP6 stream
+struct Context {
+    luma_only: bool,
+    keyframe_header: Option<Vp6Header>,
+    model: Model,
+    mb_width: usize,
+    mb_height: usize,
+    dequant_dc: i32,
+    dequant_ac: i32,
+    filter_limit: i32,
+
+    // Settings of the motion compensation filters, they carry over when a frame does not code them
+    deblock_filtering: bool,
+    filter_mode: u8,
+    filter_selection: usize,
+    max_vector_length: i32,
+    sample_variance_threshold: i32,
+
+    use_huffman: bool,
+    dccv_trees: Vec<HuffTree>,
+    runv_trees: Vec<HuffTree>,
+    ract_trees: Vec<HuffTree>,
+    nb_null: [[u32; 2]; 2],
+
+    macroblocks: Vec<Macroblock>,
+    above: Vec<RefDc>,
+    left: [RefDc; 4],
+    above_index: [usize; 6],
+    prev_dc: [[i16; 3]; 3],
+    mb_type: u8,
+    vectors: [Vector; 6],
+    candidates: [Vector; 2],
+    candidate_pos: usize,
+    coeffs: [[i16; 64]; 6],
+    idct_selector: [u8; 6],
+
+    previous: Option<Picture>,
+    golden: Option<Picture>,
+}
+
+impl Context {
+    fn new(luma_only: bool) -> Self {
+        Context {
+            luma_only: luma_only,
+            keyframe_header: None,
+            model: Model {
+                coeff_reorder: [0; 64],
+                coeff_index_to_pos: [0; 64],
+                coeff_index_to_idct_selector: [0; 64],
+                vector_sig: [0; 2],
+                vector_dct: [0; 2],
+                vector_pdv: [[0; 7]; 2],
+                vector_fdv: [[0; 8]; 2],
+                coeff_dccv: [[0; 11]; 2],
+                coeff_ract: [[[[0; 11]; 6]; 3]; 2],
+                coeff_dcct: [[[0; 5]; 3]; 2],
+                coeff_runv: [[0; 14]; 2],
+                mb_type: [[[0; 10]; 10]; 3],
+                mb_types_stats: [[[0; 2]; 10]; 3]
+            },
+            mb_width: 0,
+            mb_height: 0,
+            dequant_dc: 0,
+            dequant_ac: 0,
+            filter_limit: 0,
+            deblock_filtering: false,
+            filter_mode: 0,
+            filter_selection: 0,
+            max_vector_length: 0,
+            sample_variance_threshold: 0,
+            use_huffman: false,
+            dccv_trees: Vec::new(),
+            runv_trees: Vec::new(),
+            ract_trees: Vec::new(),
+            nb_null: [[0; 2]; 2],
+            macroblocks: Vec::new(),
+            above: Vec::new(),
+            left: [EMPTY_DC; 4],
+            above_index: [0; 6],
+            prev_dc: [[0; 3]; 3],
+            mb_type: MB_INTER_NOVEC_PF,
+            vectors: [ZERO_VECTOR; 6],
+            candidates: [ZERO_VECTOR; 2],
+            candidate_pos: 0,
+            coeffs: [[0; 64]; 6],
+            idct_selector: [0; 6],
+            previous: None,
+            golden: None
+        }
+    }
+
+    fn decode(&mut self, data: &[u8]) -> IoResult<()> {
+        let header = try!(Vp6Header::read(data, self.keyframe_header.as_ref()));
+        if header.keyframe {
+            if header.version > 8 {
+                return Err(IoError { kind: InvalidInput, desc: "unsupported VP6 version", detail: None })
+            }
+            if header.interlaced {
+                return Err(IoError { kind: InvalidInput, desc: "interlaced VP6 frames are not supported", detail: None })
+            }
+            if header.mb_columns as usize != self.mb_width || header.mb_rows as usize != self.mb_height {
+                self.mb_width = header.mb_columns as usize;
+                self.mb_height = header.mb_rows as usize;
+                self.macroblocks = repeat(Macroblock { kind: MB_INTRA, vector: ZERO_VECTOR }).take(self.mb_width * self.mb_height).collect();
+                self.above = repeat(EMPTY_DC).take(4 * self.mb_width + 6).collect();
+                self.previous = None;
+                self.golden = None;
+            }
+        }
+
+        let quantizer = header.quantizer as usize;
+        self.dequant_dc = (DC_DEQUANT[quantizer] as i32) << 2;
+        self.dequant_ac = (AC_DEQUANT[quantizer] as i32) << 2;
+        self.filter_limit = FILTER_THRESHOLD[quantizer] as i32;
+
+        // The partition offset counts from the start of the frame, an offset of 2 means there is no partition
+        let truncated = IoError { kind: InvalidInput, desc: "truncated VP6 frame", detail: None };
+        let mut start = if header.keyframe { 6 } else { 1 };
+        let mut offset = None;
+        if header.has_partition_offset() {
+            let at = if header.keyframe { 2 } else { 1 };
+            if data.len() < at + 2 {
+                return Err(truncated)
+            }
+            let value = (data[at] as usize) << 8 | data[at + 1] as usize;
+            if value != 2 {
+                offset = Some(value);
+            }
+            start += 2;
+        }
+        if start >= data.len() {
+            return Err(truncated)
+        }
+
+        let mut c = try!(BoolDecoder::new(&data[start..]));
+        let mut parse_filter_info = false;
+        let mut variance_shift = 0;
+        let mut golden = false;
+        if header.keyframe {
+            c.read_bits(2);
+            parse_filter_info = header.profile != 0;
+            if header.version < 8 {
+                variance_shift = 5;
+            }
+            self.keyframe_header = Some(header);
+        } else {
+            golden = c.read_bit();
+            if header.profile != 0 {
+                self.deblock_filtering = c.read_bit();
+                if self.deblock_filtering {
+                    c.read_bit();
+                }
+                if header.version > 7 {
+                    parse_filter_info = c.read_bit();
+                }
+            }
+        }
+
+        if parse_filter_info {
+            if c.read_bit() {
+                self.filter_mode = 2;
+                self.sample_variance_threshold = (c.read_bits(5) as i32) << variance_shift;
+                self.max_vector_length = 2 << c.read_bits(3) as usize;
+            } else if c.read_bit() {
+                self.filter_mode = 1;
+            } else {
+                self.filter_mode = 0;
+            }
+            self.filter_selection = if header.version > 7 { c.read_bits(4) as usize } else { 16 };
+        }
+        self.use_huffman = c.read_bit();
+
+        let mut partition = match offset {
+            Some(offset) if offset > data.len() => return Err(IoError { kind: InvalidInput, desc: "invalid VP6 partition offset", detail: None }),
+            Some(offset) if self.use_huffman => Partition::Huffman(BitStream { data: &data[offset..], pos: 0 }),
+            Some(offset) => Partition::Bool(try!(BoolDecoder::new(&data[offset..]))),
+            None => Partition::Shared
+        };
+
+        let size = self.mb_width * self.mb_height * 256;
+        let mut picture = Picture { planes: vec![repeat(0).take(size).collect()] };
+        if !self.luma_only {
+            picture.planes.push(repeat(0).take(size / 4).collect());
+            picture.planes.push(repeat(0).take(size / 4).collect());
+        }
+
+        let previous = self.previous.take();
+        let golden_picture = self.golden.take();
+        let result = self.decode_macroblocks(header.keyframe, &mut c, &mut partition, &mut picture,
+            previous.as_ref(), golden_picture.as_ref());
+        match result {
+            Ok(()) => {
+                self.golden = if header.keyframe || golden { Some(picture.clone()) } else { golden_picture };
+                self.previous = Some(picture);
+                Ok(())
+            },
+            Err(err) => {
+                self.previous = previous;
+                self.golden = golden_picture;
+                Err(err)
+            }
+        }
+    }
+
+    fn decode_macroblocks(&mut self, keyframe: bool, c: &mut BoolDecoder, partition: &mut Partition, picture: &mut Picture,
+        previous: Option<&Picture>, golden: Option<&Picture>) -> IoResult<()>
+    {
+        if keyframe {
+            self.default_models();
+            for mb in self.macroblocks.iter_mut() {
+                mb.kind = MB_INTRA;
+            }
+        } else {
+            self.parse_mb_type_models(c);
+            self.parse_vector_models(c);
+            self.mb_type = MB_INTER_NOVEC_PF;
+        }
+        self.parse_coeff_models(c, keyframe);
+
+        self.prev_dc = [[0; 3]; 3];
+        self.prev_dc[1][FRAME_CURRENT as usize] = 128;
+        self.prev_dc[2][FRAME_CURRENT as usize] = 128;
+        for above in self.above.iter_mut() {
+            *above = EMPTY_DC;
+        }
+        let width = self.mb_width;
+        self.above[2 * width + 2].frame = FRAME_CURRENT;
+        self.above[3 * width + 4].frame = FRAME_CURRENT;
+
+        for row in 0..self.mb_height {
+            self.left = [EMPTY_DC; 4];
+            self.above_index = [1, 2, 1, 2, 2 * width + 3, 3 * width + 5];
+
+            for col in 0..width {
+                let mb_type = if keyframe { MB_INTRA } else { self.decode_vectors(c, row, col) };
+                try!(match *partition {
+                    Partition::Shared => self.parse_coeffs(c),
+                    Partition::Bool(ref mut cc) => self.parse_coeffs(cc),
+                    Partition::Huffman(ref mut bits) => self.parse_coeffs_huffman(bits)
+                });
+                try!(self.render_macroblock(picture, previous, golden, row, col, mb_type));
+
+                for b in 0..4 {
+                    self.above_index[b] += 2;
+                }
+                self.above_index[4] += 1;
+                self.above_index[5] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn coeff_order_table_init(&mut self) {
+        let version = self.keyframe_header.map_or(0, |header| header.version);
+        let model = &mut self.model;
+        let mut idx = 1;
+        model.coeff_index_to_pos[0] = 0;
+        for i in 0..16 {
+            for pos in 1..64 {
+                if model.coeff_reorder[pos] == i {
+                    model.coeff_index_to_pos[idx] = pos as u8;
+                    idx += 1;
+                }
+            }
+        }
+
+        for idx in 0..64 {
+            let mut max = 0;
+            for i in 0..idx + 1 {
+                if model.coeff_index_to_pos[i] > max {
+                    max = model.coeff_index_to_pos[i];
+                }
+            }
+            if version > 6 {
+                max += 1;
+            }
+            model.coeff_index_to_idct_selector[idx] = max;
+        }
+    }
+
+    fn default_models(&mut self) {
+        {
+            let model = &mut self.model;
+            model.vector_dct = [0xa2, 0xa4];
+            model.vector_sig = [0x80, 0x80];
+            model.mb_types_stats = DEF_MB_TYPES_STATS;
+            model.vector_fdv = DEF_FDV_VECTOR_MODEL;
+            model.vector_pdv = DEF_PDV_VECTOR_MODEL;
+            model.coeff_runv = DEF_RUNV_COEFF_MODEL;
+            model.coeff_reorder = DEF_COEFF_REORDER;
+        }
+        self.coeff_order_table_init();
+    }
+
+    fn parse_mb_type_models(&mut self, c: &mut BoolDecoder) {
+        let model = &mut self.model;
+        for ctx in 0..3 {
+            if c.read_prob(174) {
+                let idx = c.read_bits(4) as usize;
+                model.mb_types_stats[ctx] = PRE_DEF_MB_TYPE_STATS[idx][ctx];
+            }
+            if c.read_prob(254) {
+                for kind in 0..10 {
+                    for i in 0..2 {
+                        if c.read_prob(205) {
+                            let sign = c.read_bit();
+                            let mut delta = c.read_tree(&PMBTM_TREE, &MB_TYPE_MODEL_MODEL) as i32;
+                            if delta == 0 {
+                                delta = 4 * c.read_bits(7) as i32;
+                            }
+                            let stat = model.mb_types_stats[ctx][kind][i] as i32 + if sign { -delta } else { delta };
+                            model.mb_types_stats[ctx][kind][i] = (stat & 0xff) as u8;
+                        }
+                    }
+                }
+            }
+        }
+
+        // The probabilities of each type depend on the type of the previous macroblock
+        for ctx in 0..3 {
+            let mut p = [0i32; 10];
+            for kind in 0..10 {
+                p[kind] = 100 * model.mb_types_stats[ctx][kind][1] as i32;
+            }
+
+            for kind in 0..10 {
+                let stats = model.mb_types_stats[ctx][kind];
+                let probs = &mut model.mb_type[ctx][kind];
+                probs[0] = (255 - (255 * stats[0] as i32) / (1 + stats[0] as i32 + stats[1] as i32)) as u8;
+
+                p[kind] = 0;
+                let p02 = p[0] + p[2];
+                let p34 = p[3] + p[4];
+                let p0234 = p02 + p34;
+                let p17 = p[1] + p[7];
+                let p56 = p[5] + p[6];
+                let p89 = p[8] + p[9];
+                let p5689 = p56 + p89;
+                let p156789 = p17 + p5689;
+
+                probs[1] = (1 + 255 * p0234 / (1 + p0234 + p156789)) as u8;
+                probs[2] = (1 + 255 * p02 / (1 + p0234)) as u8;
+                probs[3] = (1 + 255 * p17 / (1 + p156789)) as u8;
+                probs[4] = (1 + 255 * p[0] / (1 + p02)) as u8;
+                probs[5] = (1 + 255 * p[3] / (1 + p34)) as u8;
+                probs[6] = (1 + 255 * p[1] / (1 + p17)) as u8;
+                probs[7] = (1 + 255 * p56 / (1 + p5689)) as u8;
+                probs[8] = (1 + 255 * p[5] / (1 + p56)) as u8;
+                probs[9] = (1 + 255 * p[8] / (1 + p89)) as u8;
+
+                p[kind] = 100 * stats[1] as i32;
+            }
+        }
+    }
+
+    fn parse_vector_models(&mut self, c: &mut BoolDecoder) {
+        let model = &mut self.model;
+        for comp in 0..2 {
+            if c.read_prob(SIG_DCT_PCT[comp][0]) {
+                model.vector_dct[comp] = c.read_nn();
+            }
+            if c.read_prob(SIG_DCT_PCT[comp][1]) {
+                model.vector_sig[comp] = c.read_nn();
+            }
+        }
+        for comp in 0..2 {
+            for node in 0..7 {
+                if c.read_prob(PDV_PCT[comp][node]) {
+                    model.vector_pdv[comp][node] = c.read_nn();
+                }
+            }
+        }
+        for comp in 0..2 {
+            for node in 0..8 {
+                if c.read_prob(FDV_PCT[comp][node]) {
+                    model.vector_fdv[comp][node] = c.read_nn();
+                }
+            }
+        }
+    }
+
+    fn parse_coeff_models(&mut self, c: &mut BoolDecoder, keyframe: bool) {
+        // Nodes that are not coded take the last coded value, which starts at even odds
+        let mut def_prob = [0x80u8; 11];
+        for pt in 0..2 {
+            for node in 0..11 {
+                if c.read_prob(DCCV_PCT[pt][node]) {
+                    def_prob[node] = c.read_nn();
+                    self.model.coeff_dccv[pt][node] = def_prob[node];
+                } else if keyframe {
+                    self.model.coeff_dccv[pt][node] = def_prob[node];
+                }
+            }
+        }
+
+        if c.read_bit() {
+            for pos in 1..64 {
+                if c.read_prob(COEFF_REORDER_PCT[pos]) {
+                    self.model.coeff_reorder[pos] = c.read_bits(4) as u8;
+                }
+            }
+            self.coeff_order_table_init();
+        }
+
+        let model = &mut self.model;
+        for cg in 0..2 {
+            for node in 0..14 {
+                if c.read_prob(RUNV_PCT[cg][node]) {
+                    model.coeff_runv[cg][node] = c.read_nn();
+                }
+            }
+        }
+
+        for ct in 0..3 {
+            for pt in 0..2 {
+                for cg in 0..6 {
+                    for node in 0..11 {
+                        if c.read_prob(RACT_PCT[ct][pt][cg][node]) {
+                            def_prob[node] = c.read_nn();
+                            model.coeff_ract[pt][ct][cg][node] = def_prob[node];
+                        } else if keyframe {
+                            model.coeff_ract[pt][ct][cg][node] = def_prob[node];
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.use_huffman {
+            self.dccv_trees.clear();
+            self.runv_trees.clear();
+            self.ract_trees.clear();
+            for pt in 0..2 {
+                self.dccv_trees.push(HuffTree::new(&model.coeff_dccv[pt], &HUFF_COEFF_MAP, 12));
+                self.runv_trees.push(HuffTree::new(&model.coeff_runv[pt], &HUFF_RUN_MAP, 9));
+                for ct in 0..3 {
+                    for cg in 0..6 {
+                        self.ract_trees.push(HuffTree::new(&model.coeff_ract[pt][ct][cg], &HUFF_COEFF_MAP, 12));
+                    }
+                }
+            }
+            self.nb_null = [[0; 2]; 2];
+        } else {
+            // The DC coding types are a linear combination of the DC values
+            for pt in 0..2 {
+                for ctx in 0..3 {
+                    for node in 0..5 {
+                        let lc = DCCV_LC[ctx][node];
+                        let prob = ((model.coeff_dccv[pt][node] as i32 * lc[0] + 128) >> 8) + lc[1];
+                        model.coeff_dcct[pt][ctx][node] = min(max(prob, 1), 255) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    // Finds up to two distinct nonzero vectors of neighbouring macroblocks that use the same reference frame,
+    // returns the context for the macroblock type
+    fn vector_predictors(&mut self, row: usize, col: usize, frame: i8) -> usize {
+        let mut count = 0i32;
+        let mut vectors = [ZERO_VECTOR; 2];
+        for (pos, &(dx, dy)) in CANDIDATE_PREDICTOR_POS.iter().enumerate() {
+            let x = col as i32 + dx;
+            let y = row as i32 + dy;
+            if x < 0 || x >= self.mb_width as i32 || y < 0 || y >= self.mb_height as i32 {
+                continue
+            }
+            let mb = self.macroblocks[y as usize * self.mb_width + x as usize];
+            if REFERENCE_FRAME[mb.kind as usize] != frame {
+                continue
+            }
+            if mb.vector == vectors[0] || mb.vector == ZERO_VECTOR {
+                continue
+            }
+
+            vectors[count as usize] = mb.vector;
+            count += 1;
+            if count > 1 {
+                count = -1;
+                break
+            }
+            self.candidate_pos = pos;
+        }
+
+        self.candidates = vectors;
+        (count + 1) as usize
+    }
+
+    fn parse_vector_adjustment(&mut self, c: &mut BoolDecoder) -> Vector {
+        let mut vector = if self.candidate_pos < 2 { self.candidates[0] } else { ZERO_VECTOR };
+        for comp in 0..2 {
+            let mut delta;
+            if c.read_prob(self.model.vector_dct[comp]) {
+                delta = 0;
+                for &j in [0, 1, 2, 7, 6, 5, 4].iter() {
+                    delta |= (c.read_prob(self.model.vector_fdv[comp][j]) as i32) << j;
+                }
+                if delta & 0xf0 != 0 {
+                    delta |= (c.read_prob(self.model.vector_fdv[comp][3]) as i32) << 3;
+                } else {
+                    delta |= 8;
+                }
+            } else {
+                delta = c.read_tree(&PVA_TREE, &self.model.vector_pdv[comp]) as i32;
+            }
+
+            if delta != 0 && c.read_prob(self.model.vector_sig[comp]) {
+                delta = -delta;
+            }
+            if comp == 0 {
+                vector.x = (vector.x as i32 + delta) as i16;
+            } else {
+                vector.y = (vector.y as i32 + delta) as i16;
+            }
+        }
+        vector
+    }
+
+    fn decode_4mv(&mut self, c: &mut BoolDecoder, row: usize, col: usize) {
+        let mut kinds = [0u8; 4];
+        for b in 0..4 {
+            kinds[b] = c.read_bits(2) as u8;
+            if kinds[b] != 0 {
+                kinds[b] += 1;
+            }
+        }
+
+        let (mut x, mut y) = (0i32, 0i32);
+        for b in 0..4 {
+            match kinds[b] {
+                MB_INTER_NOVEC_PF => self.vectors[b] = ZERO_VECTOR,
+                MB_INTER_DELTA_PF => self.vectors[b] = self.parse_vector_adjustment(c),
+                MB_INTER_V1_PF => self.vectors[b] = self.candidates[0],
+                _ => self.vectors[b] = self.candidates[1]
+            }
+            x += self.vectors[b].x as i32;
+            y += self.vectors[b].y as i32;
+        }
+
+        // The last vector stands for the macroblock, chroma uses the rounded average
+        self.macroblocks[row * self.mb_width + col].vector = self.vectors[3];
+        let average = |sum: i32| (if sum > 0 { (sum + 2) >> 2 } else { (sum + 1) >> 2 }) as i16;
+        self.vectors[4] = Vector { x: average(x), y: average(y) };
+        self.vectors[5] = self.vectors[4];
+    }
+
+    fn decode_vectors(&mut self, c: &mut BoolDecoder, row: usize, col: usize) -> u8 {
+        let ctx = self.vector_predictors(row, col, FRAME_PREVIOUS);
+        let prev_type = self.mb_type as usize;
+        self.mb_type = if c.read_prob(self.model.mb_type[ctx][prev_type][0]) {
+            prev_type as u8
+        } else {
+            c.read_tree(&PMBT_TREE, &self.model.mb_type[ctx][prev_type])
+        };
+        let index = row * self.mb_width + col;
+        self.macroblocks[index].kind = self.mb_type;
+
+        let vector = match self.mb_type {
+            MB_INTER_V1_PF => self.candidates[0],
+            MB_INTER_V2_PF => self.candidates[1],
+            MB_INTER_V1_GF => {
+                self.vector_predictors(row, col, FRAME_GOLDEN);
+                self.candidates[0]
+            },
+            MB_INTER_V2_GF => {
+                self.vector_predictors(row, col, FRAME_GOLDEN);
+                self.candidates[1]
+            },
+            MB_INTER_DELTA_PF => self.parse_vector_adjustment(c),
+            MB_INTER_DELTA_GF => {
+                self.vector_predictors(row, col, FRAME_GOLDEN);
+                self.parse_vector_adjustment(c)
+            },
+            MB_INTER_4V => {
+                self.decode_4mv(c, row, col);
+                return self.mb_type
+            },
+            _ => ZERO_VECTOR
+        };
+
+        self.macroblocks[index].vector = vector;
+        self.vectors = [vector; 6];
+        self.mb_type
+    }
+
+    fn parse_coeffs(&mut self, c: &mut BoolDecoder) -> IoResult<()> {
+        if c.is_end() {
+            return Err(IoError { kind: InvalidInput, desc: "truncated VP6 coefficients", detail: None })
+        }
+
+        for b in 0..6 {
+            let pt = if b > 3 { 1 } else { 0 };
+            let mut ct = 1;
+            let ctx = self.left[B6TO4[b]].not_null as usize + self.above[self.above_index[b]].not_null as usize;
+
+            let mut model1 = self.model.coeff_dccv[pt];
+            let mut model2 = [0u8; 11];
+            for node in 0..5 {
+                model2[node] = self.model.coeff_dcct[pt][ctx][node];
+            }
+
+            let mut coeff_idx = 0;
+            loop {
+                let mut run = 1;
+                if (coeff_idx > 1 && ct == 0) || c.read_prob(model2[0]) {
+                    let mut coeff;
+                    if c.read_prob(model2[2]) {
+                        if c.read_prob(model2[3]) {
+                            let idx = c.read_tree(&PC_TREE, &model1) as usize;
+                            coeff = COEFF_BIAS[idx + 5] as i32;
+                            let mut i = COEFF_BIT_LENGTH[idx] as i32;
+                            while i >= 0 {
+                                coeff += (c.read_prob(COEFF_PARSE_TABLE[idx][i as usize]) as i32) << i as usize;
+                                i -= 1;
+                            }
+                        } else if c.read_prob(model2[4]) {
+                            coeff = 3 + c.read_prob(model1[5]) as i32;
+                        } else {
+                            coeff = 2;
+                        }
+                        ct = 2;
+                    } else {
+                        ct = 1;
+                        coeff = 1;
+                    }
+
+                    if c.read_bit() {
+                        coeff = -coeff;
+                    }
+                    if coeff_idx > 0 {
+                        coeff *= self.dequant_ac;
+                    }
+                    let pos = self.model.coeff_index_to_pos[coeff_idx] as usize;
+                    self.coeffs[b][IDCT_SCANTABLE[pos] as usize] = coeff as i16;
+                } else {
+                    ct = 0;
+                    if coeff_idx > 0 {
+                        if !c.read_prob(model2[1]) {
+                            break
+                        }
+
+                        let model3 = self.model.coeff_runv[if coeff_idx >= 6 { 1 } else { 0 }];
+                        run = c.read_tree(&PCR_TREE, &model3) as usize;
+                        if run == 0 {
+                            run = 9;
+                            for i in 0..6 {
+                                run += (c.read_prob(model3[i + 8]) as usize) << i;
+                            }
+                        }
+                    }
+                }
+
+                coeff_idx += run;
+                if coeff_idx >= 64 {
+                    break
+                }
+                model1 = self.model.coeff_ract[pt][ct][COEFF_GROUPS[coeff_idx] as usize];
+                model2 = model1;
+            }
+
+            let not_null = self.coeffs[b][0] != 0;
+            self.left[B6TO4[b]].not_null = not_null;
+            self.above[self.above_index[b]].not_null = not_null;
+            self.idct_selector[b] = self.model.coeff_index_to_idct_selector[min(coeff_idx, 63)];
+        }
+        Ok(())
+    }
+
+    // Counts of blocks in a row with no DC or no AC coefficients are coded once for the whole run
+    fn read_nb_null(bits: &mut BitStream) -> u32 {
+        let mut value = bits.read(2);
+        if value == 2 {
+            value += bits.read(2);
+        } else if value == 3 {
+            value = bits.read(1) << 2;
+            value = 6 + value + bits.read(2 + value as usize);
+        }
+        value
+    }
+
+    fn parse_coeffs_huffman(&mut self, bits: &mut BitStream) -> IoResult<()> {
+        for b in 0..6 {
+            let pt = if b > 3 { 1 } else { 0 };
+            let mut ct = 0;
+            let mut tree = &self.dccv_trees[pt];
+            let mut coeff_idx = 0;
+            loop {
+                let mut run = 1;
+                if coeff_idx < 2 && self.nb_null[coeff_idx][pt] > 0 {
+                    self.nb_null[coeff_idx][pt] -= 1;
+                    if coeff_idx > 0 {
+                        break
+                    }
+                } else {
+                    if bits.left() <= 0 {
+                        return Err(IoError { kind: InvalidInput, desc: "truncated VP6 coefficients", detail: None })
+                    }
+                    let coeff = tree.read(bits);
+                    if coeff == 0 {
+                        if coeff_idx > 0 {
+                            run += self.runv_trees[if coeff_idx >= 6 { 1 } else { 0 }].read(bits) as usize;
+                            if run >= 9 {
+                                run += bits.read(6) as usize;
+                            }
+                        } else {
+                            self.nb_null[0][pt] = Context::read_nb_null(bits);
+                        }
+                        ct = 0;
+                    } else if coeff == 11 {
+                        // End of block, after the DC it starts a run of blocks without AC coefficients
+                        if coeff_idx == 1 {
+                            self.nb_null[1][pt] = Context::read_nb_null(bits);
+                        }
+                        break
+                    } else {
+                        let mut value = COEFF_BIAS[coeff as usize] as i32;
+                        if coeff > 4 {
+                            value += bits.read(if coeff <= 9 { coeff as usize - 4 } else { 11 }) as i32;
+                        }
+                        ct = if value > 1 { 2 } else { 1 };
+                        if bits.read(1) != 0 {
+                            value = -value;
+                        }
+                        if coeff_idx > 0 {
+                            value *= self.dequant_ac;
+                        }
+                        let pos = self.model.coeff_index_to_pos[coeff_idx] as usize;
+                        self.coeffs[b][IDCT_SCANTABLE[pos] as usize] = value as i16;
+                    }
+                }
+
+                coeff_idx += run;
+                if coeff_idx >= 64 {
+                    break
+                }
+                let cg = min(COEFF_GROUPS[coeff_idx] as usize, 3);
+                tree = &self.ract_trees[(pt * 3 + ct) * 6 + cg];
+            }
+            self.idct_selector[b] = self.model.coeff_index_to_idct_selector[min(coeff_idx, 63)];
+        }
+        Ok(())
+    }
+
+    // Adds the DC predicted from the blocks above and to the left that use the same reference frame, or the last
+    // DC of the plane for that frame
+    fn add_predictors_dc(&mut self, frame: i8) {
+        for b in 0..6 {
+            let above = self.above[self.above_index[b]];
+            let left = self.left[B6TO4[b]];
+            let plane = B2P[b] as usize;
+            let mut count = 0;
+            let mut dc = 0i32;
+            if left.frame == frame {
+                dc += left.dc as i32;
+                count += 1;
+            }
+            if above.frame == frame {
+                dc += above.dc as i32;
+                count += 1;
+            }
+            if count == 0 {
+                dc = self.prev_dc[plane][frame as usize] as i32;
+            } else if count == 2 {
+                dc /= 2;
+            }
+
+            let value = (self.coeffs[b][0] as i32 + dc) as i16;
+            self.prev_dc[plane][frame as usize] = value;
+            let stored = RefDc { not_null: above.not_null, frame: frame, dc: value };
+            self.above[self.above_index[b]] = stored;
+            self.left[B6TO4[b]] = RefDc { not_null: left.not_null, frame: frame, dc: value };
+            self.coeffs[b][0] = (value as i32 * self.dequant_dc) as i16;
+        }
+    }
+
+    fn render_macroblock(&mut self, picture: &mut Picture, previous: Option<&Picture>, golden: Option<&Picture>,
+        row: usize, col: usize, mb_type: u8) -> IoResult<()>
+    {
+        let frame = REFERENCE_FRAME[mb_type as usize];
+        self.add_predictors_dc(frame);
+
+        let reference = match frame {
+            FRAME_PREVIOUS => previous,
+            FRAME_GOLDEN => golden,
+            _ => None
+        };
+        if mb_type != MB_INTRA && reference.is_none() {
+            return Err(IoError { kind: InvalidInput, desc: "VP6 inter frame without a reference frame", detail: None })
+        }
+
+        let blocks = if self.luma_only { 4 } else { 6 };
+        for b in 0..blocks {
+            let plane = B2P[b] as usize;
+            let (x, y, stride, height) = if b < 4 {
+                (col * 16 + (b & 1) * 8, row * 16 + (b >> 1) * 8, self.mb_width * 16, self.mb_height * 16)
+            } else {
+                (col * 8, row * 8, self.mb_width * 8, self.mb_height * 8)
+            };
+            let offset = y * stride + x;
+            let selector = self.idct_selector[b];
+
+            match (mb_type, reference) {
+                (MB_INTRA, _) | (_, None) => idct_put(&mut picture.planes[plane], offset, stride, &mut self.coeffs[b], selector),
+                (MB_INTER_NOVEC_PF, Some(reference)) | (MB_INTER_NOVEC_GF, Some(reference)) => {
+                    for i in 0..8 {
+                        let start = offset + i * stride;
+                        for j in 0..8 {
+                            picture.planes[plane][start + j] = reference.planes[plane][start + j];
+                        }
+                    }
+                    idct_add(&mut picture.planes[plane], offset, stride, &mut self.coeffs[b], selector);
+                },
+                (_, Some(reference)) => {
+                    self.predict(&mut picture.planes[plane], &reference.planes[plane], b, x, y, stride, height);
+                    idct_add(&mut picture.planes[plane], offset, stride, &mut self.coeffs[b], selector);
+                }
+            }
+        }
+
+        // The chroma blocks of an alpha stream are coded but never shown
+        if self.luma_only {
+            self.coeffs[4] = [0; 64];
+            self.coeffs[5] = [0; 64];
+        }
+        Ok(())
+    }
+
+    // Motion compensation of one block from a 12x12 window of the reference frame, with edge pixels repeated
+    // outside of it
+    fn predict(&self, dst: &mut [u8], src: &[u8], b: usize, x: usize, y: usize, stride: usize, height: usize) {
+        let vector = self.vectors[b];
+        let div = COORD_DIV[b] as i32;
+        let mask = (div - 1) as i16;
+        let dx = vector.x as i32 / div;
+        let dy = vector.y as i32 / div;
+
+        let left = x as i32 + dx - 2;
+        let top = y as i32 + dy - 2;
+        let mut window = [0u8; 144];
+        for i in 0..12 {
+            let row = min(max(top + i, 0), height as i32 - 1) as usize;
+            for j in 0..12 {
+                let col = min(max(left + j, 0), stride as i32 - 1) as usize;
+                window[(i * 12 + j) as usize] = src[row * stride + col];
+            }
+        }
+
+        if self.deblock_filtering {
+            deblock_window(&mut window, dx & 7, dy & 7, self.filter_limit);
+        }
+
+        let mut overlap = 0i32;
+        if vector.x & mask != 0 {
+            overlap += if vector.x > 0 { 1 } else { -1 };
+        }
+        if vector.y & mask != 0 {
+            overlap += if vector.y > 0 { 12 } else { -12 };
+        }
+
+        let base = 2 * 12 + 2;
+        let offset = y * stride + x;
+        if overlap != 0 {
+            self.filter(dst, offset, stride, &window, base, base + overlap, vector, mask, b < 4);
+        } else {
+            for i in 0..8 {
+                for j in 0..8 {
+                    dst[offset + i * stride + j] = window[(base as usize) + i * 12 + j];
+                }
+            }
+        }
+    }
+
+    // Subpixel interpolation, bilinear or with the four tap filters for luma
+    fn filter(&self, dst: &mut [u8], dst_offset: usize, stride: usize, window: &[u8], offset1: i32, offset2: i32,
+        vector: Vector, mask: i16, luma: bool)
+    {
+        let mut x8 = (vector.x & mask) as usize;
+        let mut y8 = (vector.y & mask) as usize;
+        let mut filter4 = 0;
+        if luma {
+            x8 *= 2;
+            y8 *= 2;
+            filter4 = self.filter_mode;
+            if filter4 == 2 {
+                let (vx, vy) = (vector.x as i32, vector.y as i32);
+                if self.max_vector_length != 0 && (vx.abs() > self.max_vector_length || vy.abs() > self.max_vector_length) {
+                    filter4 = 0;
+                } else if self.sample_variance_threshold != 0 && block_variance(window, offset1 as usize) < self.sample_variance_threshold {
+                    filter4 = 0;
+                }
+            }
+        }
+
+        // Start from the pixel above or to the left for negative vectors
+        let offset = if offset2 < offset1 { offset2 } else { offset1 };
+        let diagonal = if (vector.x as i32 ^ vector.y as i32) < 0 { offset - 1 } else { offset };
+        if filter4 != 0 {
+            let weights = &BLOCK_COPY_FILTER[self.filter_selection];
+            if y8 == 0 {
+                filter_hv4(dst, dst_offset, stride, window, offset as usize, 1, &weights[x8]);
+            } else if x8 == 0 {
+                filter_hv4(dst, dst_offset, stride, window, offset as usize, 12, &weights[y8]);
+            } else {
+                filter_diag4(dst, dst_offset, stride, window, diagonal as usize, &weights[x8], &weights[y8]);
+            }
+        } else if x8 == 0 || y8 == 0 {
+            bilinear(dst, dst_offset, stride, window, offset as usize, 12, 8, x8 as i32, y8 as i32);
+        } else {
+            let mut tmp = [0u8; 12 * 9];
+            bilinear(&mut tmp, 0, 12, window, diagonal as usize, 12, 9, x8 as i32, 0);
+            bilinear(dst, dst_offset, stride, &tmp, 0, 12, 8, 0, y8 as i32);
+        }
+    }
+}
+
+fn clip(value: i32) -> u8 {
+    if value < 0 { 0 } else if value > 255 { 255 } else { value as u8 }
+}
+
+fn block_variance(src: &[u8], offset: usize) -> i32 {
+    let mut sum = 0;
+    let mut square_sum = 0;
+    for y in 0..4 {
+        for x in 0..4 {
+            let value = src[offset + y * 2 * 12 + x * 2] as i32;
+            sum += value;
+            square_sum += value * value;
+        }
+    }
+    (16 * square_sum - sum * sum) >> 8
+}
+
+fn filter_hv4(dst: &mut [u8], dst_offset: usize, stride: usize, src: &[u8], offset: usize, delta: usize, weights: &[i32; 4]) {
+    for y in 0..8 {
+        for x in 0..8 {
+            let s = offset + y * 12 + x;
+            let value = src[s - delta] as i32 * weights[0] + src[s] as i32 * weights[1]
+                + src[s + delta] as i32 * weights[2] + src[s + 2 * delta] as i32 * weights[3];
+            dst[dst_offset + y * stride + x] = clip((value + 64) >> 7);
+        }
+    }
+}
+
+fn filter_diag4(dst: &mut [u8], dst_offset: usize, stride: usize, src: &[u8], offset: usize, h_weights: &[i32; 4],
+    v_weights: &[i32; 4])
+{
+    let mut tmp = [0i32; 8 * 11];
+    for y in 0..11 {
+        for x in 0..8 {
+            let s = offset + y * 12 + x - 12;
+            let value = src[s - 1] as i32 * h_weights[0] + src[s] as i32 * h_weights[1]
+                + src[s + 1] as i32 * h_weights[2] + src[s + 2] as i32 * h_weights[3];
+            tmp[y * 8 + x] = clip((value + 64) >> 7) as i32;
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let t = (y + 1) * 8 + x;
+            let value = tmp[t - 8] * v_weights[0] + tmp[t] * v_weights[1] + tmp[t + 8] * v_weights[2] + tmp[t + 16] * v_weights[3];
+            dst[dst_offset + y * stride + x] = clip((value + 64) >> 7);
+        }
+    }
+}
+
+// Bilinear interpolation in eighths of a pixel, only reading the pixels that have a weight
+fn bilinear(dst: &mut [u8], dst_offset: usize, dst_stride: usize, src: &[u8], offset: usize, src_stride: usize, rows: usize,
+    x: i32, y: i32)
+{
+    let a = (8 - x) * (8 - y);
+    let b = x * (8 - y);
+    let c = (8 - x) * y;
+    let d = x * y;
+    for i in 0..rows {
+        for j in 0..8 {
+            let s = offset + i * src_stride + j;
+            let value = if d != 0 {
+                a * src[s] as i32 + b * src[s + 1] as i32 + c * src[s + src_stride] as i32 + d * src[s + src_stride + 1] as i32
+            } else if b + c != 0 {
+                let step = if c != 0 { src_stride } else { 1 };
+                a * src[s] as i32 + (b + c) * src[s + step] as i32
+            } else {
+                a * src[s] as i32
+            };
+            dst[dst_offset + i * dst_stride + j] = ((value + 32) >> 6) as u8;
+        }
+    }
+}
+
+// The VP3 loop filter across the block edges that fall inside the motion compensation window
+fn deblock_window(window: &mut [u8; 144], dx: i32, dy: i32, limit: i32) {
+    let bound = |value: i32| {
+        let magnitude = value.abs();
+        let bounded = if magnitude < limit { magnitude } else if magnitude < 2 * limit { 2 * limit - magnitude } else { 0 };
+        if value < 0 { -bounded } else { bounded }
+    };
+
+    if dx != 0 {
+        let col = (10 - dx) as usize;
+        for row in 0..12 {
+            let p = row * 12 + col;
+            let value = (window[p - 2] as i32 - window[p + 1] as i32) + (window[p] as i32 - window[p - 1] as i32) * 3;
+            let value = bound((value + 4) >> 3);
+            window[p - 1] = clip(window[p - 1] as i32 + value);
+            window[p] = clip(window[p] as i32 - value);
+        }
+    }
+    if dy != 0 {
+        let row = (10 - dy) as usize;
+        for col in 0..12 {
+            let p = row * 12 + col;
+            let value = (window[p - 24] as i32 - window[p + 12] as i32) + (window[p] as i32 - window[p - 12] as i32) * 3;
+            let value = bound((value + 4) >> 3);
+            window[p - 12] = clip(window[p - 12] as i32 + value);
+            window[p] = clip(window[p] as i32 - value);
+        }
+    }
+}
+
+// The VP3 integer inverse DCT on transposed coefficients, `selector` tells how far into the block they reach
+fn idct_put(dst: &mut [u8], offset: usize, stride: usize, block: &mut [i16; 64], selector: u8) {
+    if selector > 10 || selector == 1 {
+        idct(dst, offset, stride, block, true);
+    } else {
+        idct10(dst, offset, stride, block, true);
+    }
+    *block = [0; 64];
+}
+
+fn idct_add(dst: &mut [u8], offset: usize, stride: usize, block: &mut [i16; 64], selector: u8) {
+    if selector > 10 {
+        idct(dst, offset, stride, block, false);
+        *block = [0; 64];
+    } else if selector > 1 {
+        idct10(dst, offset, stride, block, false);
+        *block = [0; 64];
+    } else {
+        let dc = (block[0] as i32 + 15) >> 5;
+        for i in 0..8 {
+            for j in 0..8 {
+                let p = offset + i * stride + j;
+                dst[p] = clip(dst[p] as i32 + dc);
+            }
+        }
+        block[0] = 0;
+    }
+}
+
+const C1S7: i32 = 64277;
+const C2S6: i32 = 60547;
+const C3S5: i32 = 54491;
+const C4S4: i32 = 46341;
+const C5S3: i32 = 36410;
+const C6S2: i32 = 25080;
+const C7S1: i32 = 12785;
+
+fn mul(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64) as i32) >> 16
+}
+
+// Writes or adds the eight outputs of the second pass down one column of the block
+fn store(dst: &mut [u8], offset: usize, stride: usize, values: [i32; 8], put: bool) {
+    for k in 0..8 {
+        let p = offset + k * stride;
+        dst[p] = if put { clip(values[k] >> 4) } else { clip(dst[p] as i32 + (values[k] >> 4)) };
+    }
+}
+
+fn idct(dst: &mut [u8], offset: usize, stride: usize, ip: &mut [i16; 64], put: bool) {
+    for i in 0..8 {
+        let v = |k: usize| ip[i + k * 8] as i32;
+        if v(0) | v(1) | v(2) | v(3) | v(4) | v(5) | v(6) | v(7) != 0 {
+            let a = mul(C1S7, v(1)) + mul(C7S1, v(7));
+            let b = mul(C7S1, v(1)) - mul(C1S7, v(7));
+            let c = mul(C3S5, v(3)) + mul(C5S3, v(5));
+            let d = mul(C3S5, v(5)) - mul(C5S3, v(3));
+            let ad = mul(C4S4, a - c);
+            let bd = mul(C4S4, b - d);
+            let cd = a + c;
+            let dd = b + d;
+            let e = mul(C4S4, v(0) + v(4));
+            let f = mul(C4S4, v(0) - v(4));
+            let g = mul(C2S6, v(2)) + mul(C6S2, v(6));
+            let h = mul(C6S2, v(2)) - mul(C2S6, v(6));
+            let ed = e - g;
+            let gd = e + g;
+            let add = f + ad;
+            let bdd = bd - h;
+            let fd = f - ad;
+            let hd = bd + h;
+
+            let out = [gd + cd, add + hd, add - hd, ed + dd, ed - dd, fd + bdd, fd - bdd, gd - cd];
+            for k in 0..8 {
+                ip[i + k * 8] = out[k] as i16;
+            }
+        }
+    }
+
+    for i in 0..8 {
+        let v = |k: usize| ip[i * 8 + k] as i32;
+        let column = offset + i;
+        if v(1) | v(2) | v(3) | v(4) | v(5) | v(6) | v(7) != 0 {
+            let a = mul(C1S7, v(1)) + mul(C7S1, v(7));
+            let b = mul(C7S1, v(1)) - mul(C1S7, v(7));
+            let c = mul(C3S5, v(3)) + mul(C5S3, v(5));
+            let d = mul(C3S5, v(5)) - mul(C5S3, v(3));
+            let ad = mul(C4S4, a - c);
+            let bd = mul(C4S4, b - d);
+            let cd = a + c;
+            let dd = b + d;
+            let bias = if put { 8 + 16 * 128 } else { 8 };
+            let e = mul(C4S4, v(0) + v(4)) + bias;
+            let f = mul(C4S4, v(0) - v(4)) + bias;
+            let g = mul(C2S6, v(2)) + mul(C6S2, v(6));
+            let h = mul(C6S2, v(2)) - mul(C2S6, v(6));
+            let ed = e - g;
+            let gd = e + g;
+            let add = f + ad;
+            let bdd = bd - h;
+            let fd = f - ad;
+            let hd = bd + h;
+            store(dst, column, stride, [gd + cd, add + hd, add - hd, ed + dd, ed - dd, fd + bdd, fd - bdd, gd - cd], put);
+        } else if put {
+            let value = clip(128 + ((C4S4 * v(0) + (8 << 16)) >> 20));
+            for k in 0..8 {
+                dst[column + k * stride] = value;
+            }
+        } else if v(0) != 0 {
+            let value = (C4S4 * v(0) + (8 << 16)) >> 20;
+            for k in 0..8 {
+                let p = column + k * stride;
+                dst[p] = clip(dst[p] as i32 + value);
+            }
+        }
+    }
+}
+
+// The inverse DCT when only the top left 4x4 coefficients can be set
+fn idct10(dst: &mut [u8], offset: usize, stride: usize, ip: &mut [i16; 64], put: bool) {
+    for i in 0..4 {
+        let v = |k: usize| ip[i + k * 8] as i32;
+        if v(0) | v(1) | v(2) | v(3) != 0 {
+            let a = mul(C1S7, v(1));
+            let b = mul(C7S1, v(1));
+            let c = mul(C3S5, v(3));
+            let d = -mul(C5S3, v(3));
+            let ad = mul(C4S4, a - c);
+            let bd = mul(C4S4, b - d);
+            let cd = a + c;
+            let dd = b + d;
+            let e = mul(C4S4, v(0));
+            let f = e;
+            let g = mul(C2S6, v(2));
+            let h = mul(C6S2, v(2));
+            let ed = e - g;
+            let gd = e + g;
+            let add = f + ad;
+            let bdd = bd - h;
+            let fd = f - ad;
+            let hd = bd + h;
+
+            let out = [gd + cd, add + hd, add - hd, ed + dd, ed - dd, fd + bdd, fd - bdd, gd - cd];
+            for k in 0..8 {
+                ip[i + k * 8] = out[k] as i16;
+            }
+        }
+    }
+
+    for i in 0..8 {
+        let v = |k: usize| ip[i * 8 + k] as i32;
+        let column = offset + i;
+        if v(0) | v(1) | v(2) | v(3) != 0 {
+            let a = mul(C1S7, v(1));
+            let b = mul(C7S1, v(1));
+            let c = mul(C3S5, v(3));
+            let d = -mul(C5S3, v(3));
+            let ad = mul(C4S4, a - c);
+            let bd = mul(C4S4, b - d);
+            let cd = a + c;
+            let dd = b + d;
+            let e = mul(C4S4, v(0)) + if put { 16 * 128 } else { 0 };
+            let f = e;
+            let g = mul(C2S6, v(2));
+            let h = mul(C6S2, v(2));
+            let ed = e - g + 8;
+            let gd = e + g + 8;
+            let add = f + ad + 8;
+            let bdd = bd - h;
+            let fd = f - ad + 8;
+            let hd = bd + h;
+            store(dst, column, stride, [gd + cd, add + hd, add - hd, ed + dd, ed - dd, fd + bdd, fd - bdd, gd - cd], put);
+        } else if put {
+            for k in 0..8 {
+                dst[column + k * stride] = 128;
+            }
+        }
+    }
+}
+
+// The boolean entropy decoder shared by VP5, VP6 and VP8
+struct BoolDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    high: u32,
+    bits: i32,
+    code_word: u32,
+}
+
+impl<'a> BoolDecoder<'a> {
+    fn new(data: &'a [u8]) -> IoResult<BoolDecoder<'a>> {
+        if data.is_empty() {
+            return Err(IoError { kind: InvalidInput, desc: "empty VP6 partition", detail: None })
+        }
+        let mut c = BoolDecoder { data: data, pos: 0, high: 255, bits: -16, code_word: 0 };
+        c.code_word = c.byte(0) << 16 | c.byte(1) << 8 | c.byte(2);
+        c.pos = 3;
+        Ok(c)
+    }
+
+    // Reads past the end give zeros
+    fn byte(&self, pos: usize) -> u32 {
+        self.data.get(pos).map_or(0, |&byte| byte as u32)
+    }
+
+    fn is_end(&self) -> bool {
+        self.pos >= self.data.len() && self.bits >= 0
+    }
+
+    fn renorm(&mut self) {
+        let mut shift = 0;
+        while shift < 8 && self.high << shift < 128 {
+            shift += 1;
+        }
+        self.high <<= shift;
+        self.code_word <<= shift;
+        self.bits += shift as i32;
+        if self.bits >= 0 && self.pos < self.data.len() {
+            self.code_word |= (self.byte(self.pos) << 8 | self.byte(self.pos + 1)) << self.bits as usize;
+            self.pos += 2;
+            self.bits -= 16;
+        }
+    }
+
+    // `prob` is the probability of a zero in 256ths
+    fn read_prob(&mut self, prob: u8) -> bool {
+        self.renorm();
+        let low = 1 + (((self.high - 1) * prob as u32) >> 8);
+        let low_shift = low << 16;
+        if self.code_word >= low_shift {
+            self.high -= low;
+            self.code_word -= low_shift;
+            true
+        } else {
+            self.high = low;
+            false
+        }
+    }
+
+    fn read_bit(&mut self) -> bool {
+        self.renorm();
+        let low = (self.high + 1) >> 1;
+        let low_shift = low << 16;
+        if self.code_word >= low_shift {
+            self.high -= low;
+            self.code_word -= low_shift;
+            true
+        } else {
+            self.high = low;
+            false
+        }
+    }
+
+    fn read_bits(&mut self, count: usize) -> u32 {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | self.read_bit() as u32;
+        }
+        value
+    }
+
+    // A 7 bit probability that cannot be zero
+    fn read_nn(&mut self) -> u8 {
+        let value = (self.read_bits(7) << 1) as u8;
+        if value == 0 { 1 } else { value }
+    }
+
+    // Trees are (jump, probability index) pairs, leaves hold the negated value
+    fn read_tree(&mut self, tree: &[(i8, u8)], probs: &[u8]) -> u8 {
+        let mut i = 0;
+        while tree[i].0 > 0 {
+            if self.read_prob(probs[tree[i].1 as usize]) {
+                i += tree[i].0 as usize;
+            } else {
+                i += 1;
+            }
+        }
+        (-tree[i].0) as u8
+    }
+}
+
+// Most significant bit first, reads past the end give zeros
+struct BitStream<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitStream<'a> {
+    fn read(&mut self, count: usize) -> u32 {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.data.get(self.pos >> 3).map_or(0, |&byte| byte);
+            value = value << 1 | ((byte >> (7 - (self.pos & 7))) & 1) as u32;
+            self.pos += 1;
+        }
+        value
+    }
+
+    fn left(&self) -> i64 {
+        self.data.len() as i64 * 8 - self.pos as i64
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct HuffNode {
+    count: u32,
+    sym: i16,
+    n0: i16,
+}
+
+const HNODE: i16 = -1;
+
+// Huffman codes built from the probabilities of a boolean coding tree, nodes ascend by count with ties broken by
+// descending symbol, and a new parent goes before nodes of the same count
+struct HuffTree {
+    nodes: Vec<HuffNode>,
+    head: usize,
+}
+
+impl HuffTree {
+    fn new(probs: &[u8], map: &[u8], size: usize) -> Self {
+        let mut nodes: Vec<HuffNode> = repeat(HuffNode { count: 0, sym: 0, n0: 0 }).take(2 * size).collect();
+        nodes[size].count = 256;
+        for i in 0..size - 1 {
+            let a = nodes[size + i].count * probs[i] as u32 >> 8;
+            let b = nodes[size + i].count * (255 - probs[i] as u32) >> 8;
+            nodes[map[2 * i] as usize].count = if a == 0 { 1 } else { a };
+            nodes[map[2 * i + 1] as usize].count = if b == 0 { 1 } else { b };
+        }
+
+        for i in 0..size {
+            nodes[i].sym = i as i16;
+            nodes[i].n0 = -2;
+        }
+        nodes[..size].sort_by(|a, b| match a.count.cmp(&b.count) {
+            Ordering::Equal => b.sym.cmp(&a.sym),
+            order => order
+        });
+
+        let mut cur_node = size;
+        nodes[size * 2 - 1].count = 0;
+        let mut i = 0;
+        while i < size * 2 - 1 {
+            let count = nodes[i].count + nodes[i + 1].count;
+            let mut j = cur_node;
+            while j > i + 2 {
+                if count > nodes[j - 1].count {
+                    break
+                }
+                nodes[j] = nodes[j - 1];
+                j -= 1;
+            }
+            nodes[j] = HuffNode { count: count, sym: HNODE, n0: i as i16 };
+            cur_node += 1;
+            i += 2;
+        }
+
+        HuffTree { nodes: nodes, head: size * 2 - 2 }
+    }
+
+    fn read(&self, bits: &mut BitStream) -> u8 {
+        let mut node = self.head;
+        while self.nodes[node].sym == HNODE && self.nodes[node].count != 0 {
+            node = self.nodes[node].n0 as usize + bits.read(1) as usize;
+        }
+        self.nodes[node].sym as u8
+    }
+}
+
+// The zigzag order transposed, the inverse DCT works on transposed blocks
+const IDCT_SCANTABLE: [u8; 64] = [
+    0, 8, 1, 2, 9, 16, 24, 17, 10, 3, 4, 11, 18, 25, 32, 40, 33, 26, 19, 12, 5, 6, 13, 20, 27, 34, 41, 48, 56, 49,
+    42, 35, 28, 21, 14, 7, 15, 22, 29, 36, 43, 50, 57, 58, 51, 44, 37, 30, 23, 31, 38, 45, 52, 59, 60, 53, 46, 39,
+    47, 54, 61, 62, 55, 63
+];
+
+const DEF_FDV_VECTOR_MODEL: [[u8; 8]; 2] = [[247, 210, 135, 68, 138, 220, 239, 246], [244, 184, 201, 44, 173, 221, 239, 253]];
+
+const DEF_PDV_VECTOR_MODEL: [[u8; 7]; 2] = [[225, 146, 172, 147, 214, 39, 156], [204, 170, 119, 235, 140, 230, 228]];
+
+const DEF_COEFF_REORDER: [u8; 64] = [
+    0, 0, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 4, 4, 4, 5, 5, 5, 5, 6, 6, 7, 7, 7, 7, 7, 8, 8, 9, 9, 9, 9, 9, 9, 10,
+    10, 11, 11, 11, 11, 11, 11, 12, 12, 12, 12, 12, 12, 13, 13, 13, 13, 13, 14, 14, 14, 14, 15, 15, 15, 15, 15, 15
+];
+
+const DEF_RUNV_COEFF_MODEL: [[u8; 14]; 2] = [
+    [198, 197, 196, 146, 198, 204, 169, 142, 130, 136, 149, 149, 191, 249],
+    [135, 201, 181, 154, 98, 117, 132, 126, 146, 169, 184, 240, 246, 254]
+];
+
+const DEF_MB_TYPES_STATS: [[[u8; 2]; 10]; 3] = [
+    [[69, 42], [1, 2], [1, 7], [44, 42], [6, 22], [1, 3], [0, 2], [1, 5], [0, 1], [0, 0]],
+    [[229, 8], [1, 1], [0, 8], [0, 0], [0, 0], [1, 2], [0, 1], [0, 0], [1, 1], [0, 0]],
+    [[122, 35], [1, 1], [1, 6], [46, 34], [0, 0], [1, 2], [0, 1], [0, 1], [1, 1], [0, 0]]
+];
+
+// Probabilities that a model value is updated
+const SIG_DCT_PCT: [[u8; 2]; 2] = [[237, 246], [231, 243]];
+
+const PDV_PCT: [[u8; 7]; 2] = [[253, 253, 254, 254, 254, 254, 254], [245, 253, 254, 254, 254, 254, 254]];
+
+const FDV_PCT: [[u8; 8]; 2] = [[254, 254, 254, 254, 254, 250, 250, 252], [254, 254, 254, 254, 254, 251, 251, 254]];
+
+const DCCV_PCT: [[u8; 11]; 2] = [
+    [146, 255, 181, 207, 232, 243, 238, 251, 244, 250, 249],
+    [179, 255, 214, 240, 250, 255, 244, 255, 255, 255, 255]
+];
+
+const COEFF_REORDER_PCT: [u8; 64] = [
+    255, 132, 132, 159, 153, 151, 161, 170, 164, 162, 136, 110, 103, 114, 129, 118, 124, 125, 132, 136, 114, 110,
+    142, 135, 134, 123, 143, 126, 153, 183, 166, 161, 171, 180, 179, 164, 203, 218, 225, 217, 215, 206, 203, 217,
+    229, 241, 248, 243, 253, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255
+];
+
+const RUNV_PCT: [[u8; 14]; 2] = [
+    [219, 246, 238, 249, 232, 239, 249, 255, 248, 253, 239, 244, 241, 248],
+    [198, 232, 251, 253, 219, 241, 253, 255, 248, 249, 244, 238, 251, 255]
+];
+
+const RACT_PCT: [[[[u8; 11]; 6]; 2]; 3] = [
+    [
+        [
+            [227, 246, 230, 247, 244, 255, 255, 255, 255, 255, 255],
+            [255, 255, 209, 231, 231, 249, 249, 253, 255, 255, 255],
+            [255, 255, 225, 242, 241, 251, 253, 255, 255, 255, 255],
+            [255, 255, 241, 253, 252, 255, 255, 255, 255, 255, 255],
+            [255, 255, 248, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+        ],
+        [
+            [240, 255, 248, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 240, 253, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+        ]
+    ],
+    [
+        [
+            [206, 203, 227, 239, 247, 255, 253, 255, 255, 255, 255],
+            [207, 199, 220, 236, 243, 252, 252, 255, 255, 255, 255],
+            [212, 219, 230, 243, 244, 253, 252, 255, 255, 255, 255],
+            [236, 237, 247, 252, 253, 255, 255, 255, 255, 255, 255],
+            [240, 240, 248, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+        ],
+        [
+            [230, 233, 249, 255, 255, 255, 255, 255, 255, 255, 255],
+            [238, 238, 250, 255, 255, 255, 255, 255, 255, 255, 255],
+            [248, 251, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+        ]
+    ],
+    [
+        [
+            [225, 239, 227, 231, 244, 253, 243, 255, 255, 253, 255],
+            [232, 234, 224, 228, 242, 249, 242, 252, 251, 251, 255],
+            [235, 249, 238, 240, 251, 255, 249, 255, 253, 253, 255],
+            [249, 253, 251, 250, 255, 255, 255, 255, 255, 255, 255],
+            [251, 250, 249, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+        ],
+        [
+            [243, 244, 250, 250, 255, 255, 255, 255, 255, 255, 255],
+            [249, 248, 250, 253, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+        ]
+    ]
+];
+
+// Scale and bias of the DC coding types for each context
+const DCCV_LC: [[[i32; 2]; 5]; 3] = [
+    [[122, 133], [0, 1], [78, 171], [139, 117], [168, 79]],
+    [[133, 51], [0, 1], [169, 71], [214, 44], [210, 38]],
+    [[142, -16], [0, 1], [221, -30], [246, -3], [203, 17]]
+];
+
+const COEFF_GROUPS: [u8; 64] = [
+    0, 0, 1, 1, 1, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5
+];
+
+// Four tap filters for each filter selection and eighth of a pixel
+const BLOCK_COPY_FILTER: [[[i32; 4]; 8]; 17] = [
+    [
+        [0, 128, 0, 0],
+        [-3, 122, 9, 0],
+        [-4, 109, 24, -1],
+        [-5, 91, 45, -3],
+        [-4, 68, 68, -4],
+        [-3, 45, 91, -5],
+        [-1, 24, 109, -4],
+        [0, 9, 122, -3]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-4, 124, 9, -1],
+        [-5, 110, 25, -2],
+        [-6, 91, 46, -3],
+        [-5, 69, 69, -5],
+        [-3, 46, 91, -6],
+        [-2, 25, 110, -5],
+        [-1, 9, 124, -4]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-4, 123, 10, -1],
+        [-6, 110, 26, -2],
+        [-7, 92, 47, -4],
+        [-6, 70, 70, -6],
+        [-4, 47, 92, -7],
+        [-2, 26, 110, -6],
+        [-1, 10, 123, -4]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-5, 124, 10, -1],
+        [-7, 110, 27, -2],
+        [-7, 91, 48, -4],
+        [-6, 70, 70, -6],
+        [-4, 48, 92, -8],
+        [-2, 27, 110, -7],
+        [-1, 10, 124, -5]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-6, 124, 11, -1],
+        [-8, 111, 28, -3],
+        [-8, 92, 49, -5],
+        [-7, 71, 71, -7],
+        [-5, 49, 92, -8],
+        [-3, 28, 111, -8],
+        [-1, 11, 124, -6]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-6, 123, 12, -1],
+        [-9, 111, 29, -3],
+        [-9, 93, 50, -6],
+        [-8, 72, 72, -8],
+        [-6, 50, 93, -9],
+        [-3, 29, 111, -9],
+        [-1, 12, 123, -6]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-7, 124, 12, -1],
+        [-10, 111, 30, -3],
+        [-10, 93, 51, -6],
+        [-9, 73, 73, -9],
+        [-6, 51, 93, -10],
+        [-3, 30, 111, -10],
+        [-1, 12, 124, -7]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-7, 123, 13, -1],
+        [-11, 112, 31, -4],
+        [-11, 94, 52, -7],
+        [-10, 74, 74, -10],
+        [-7, 52, 94, -11],
+        [-4, 31, 112, -11],
+        [-1, 13, 123, -7]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-8, 124, 13, -1],
+        [-12, 112, 32, -4],
+        [-12, 94, 53, -7],
+        [-10, 74, 74, -10],
+        [-7, 53, 94, -12],
+        [-4, 32, 112, -12],
+        [-1, 13, 124, -8]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-9, 124, 14, -1],
+        [-13, 112, 33, -4],
+        [-13, 95, 54, -8],
+        [-11, 75, 75, -11],
+        [-8, 54, 95, -13],
+        [-4, 33, 112, -13],
+        [-1, 14, 124, -9]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-9, 123, 15, -1],
+        [-14, 113, 34, -5],
+        [-14, 95, 55, -8],
+        [-12, 76, 76, -12],
+        [-8, 55, 95, -14],
+        [-5, 34, 112, -13],
+        [-1, 15, 123, -9]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-10, 124, 15, -1],
+        [-14, 113, 34, -5],
+        [-15, 96, 56, -9],
+        [-13, 77, 77, -13],
+        [-9, 56, 96, -15],
+        [-5, 34, 113, -14],
+        [-1, 15, 124, -10]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-10, 123, 16, -1],
+        [-15, 113, 35, -5],
+        [-16, 98, 56, -10],
+        [-14, 78, 78, -14],
+        [-10, 56, 98, -16],
+        [-5, 35, 113, -15],
+        [-1, 16, 123, -10]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-11, 124, 17, -2],
+        [-16, 113, 36, -5],
+        [-17, 98, 57, -10],
+        [-14, 78, 78, -14],
+        [-10, 57, 98, -17],
+        [-5, 36, 113, -16],
+        [-2, 17, 124, -11]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-12, 125, 17, -2],
+        [-17, 114, 37, -6],
+        [-18, 99, 58, -11],
+        [-15, 79, 79, -15],
+        [-11, 58, 99, -18],
+        [-6, 37, 114, -17],
+        [-2, 17, 125, -12]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-12, 124, 18, -2],
+        [-18, 114, 38, -6],
+        [-19, 99, 59, -11],
+        [-16, 80, 80, -16],
+        [-11, 59, 99, -19],
+        [-6, 38, 114, -18],
+        [-2, 18, 124, -12]
+    ],
+    [
+        [0, 128, 0, 0],
+        [-4, 118, 16, -2],
+        [-7, 106, 34, -5],
+        [-8, 90, 53, -7],
+        [-8, 72, 72, -8],
+        [-7, 53, 90, -8],
+        [-5, 34, 106, -7],
+        [-2, 16, 118, -4]
+    ]
+];
+
+const COORD_DIV: [u8; 6] = [4, 4, 4, 4, 8, 8];
+
+// Where the nodes of the boolean coding trees go in the Huffman trees
+const HUFF_COEFF_MAP: [u8; 22] = [13, 14, 11, 0, 1, 15, 16, 18, 2, 17, 3, 4, 19, 20, 5, 6, 21, 22, 7, 8, 9, 10];
+
+const HUFF_RUN_MAP: [u8; 16] = [10, 13, 11, 12, 0, 1, 2, 3, 14, 8, 15, 16, 4, 5, 6, 7];
+
+// The plane of each block and its index in the left blocks
+const B2P: [u8; 6] = [0, 0, 0, 0, 1, 2];
+
+const B6TO4: [usize; 6] = [0, 0, 1, 1, 2, 3];
+
+const COEFF_PARSE_TABLE: [[u8; 11]; 6] = [
+    [159, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [145, 165, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [140, 148, 173, 0, 0, 0, 0, 0, 0, 0, 0],
+    [135, 140, 155, 176, 0, 0, 0, 0, 0, 0, 0],
+    [130, 134, 141, 157, 180, 0, 0, 0, 0, 0, 0],
+    [129, 130, 133, 140, 153, 177, 196, 230, 243, 254, 254]
+];
+
+const COEFF_BIAS: [u8; 11] = [0, 1, 2, 3, 4, 5, 7, 11, 19, 35, 67];
+
+const COEFF_BIT_LENGTH: [u8; 6] = [0, 1, 2, 3, 4, 10];
+
+const REFERENCE_FRAME: [i8; 10] = [
+    FRAME_PREVIOUS, FRAME_CURRENT, FRAME_PREVIOUS, FRAME_PREVIOUS, FRAME_PREVIOUS, FRAME_GOLDEN, FRAME_GOLDEN,
+    FRAME_PREVIOUS, FRAME_GOLDEN, FRAME_GOLDEN
+];
+
+const AC_DEQUANT: [u8; 64] = [
+    94, 92, 90, 88, 86, 82, 78, 74, 70, 66, 62, 58, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43, 42, 40, 39, 37,
+    36, 35, 34, 33, 32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11, 10, 9,
+    8, 7, 6, 5, 4, 3, 2, 1
+];
+
+const DC_DEQUANT: [u8; 64] = [
+    47, 47, 47, 47, 45, 43, 43, 43, 43, 43, 42, 41, 41, 40, 40, 40, 40, 35, 35, 35, 35, 33, 33, 33, 33, 32, 32, 32,
+    27, 27, 26, 26, 25, 25, 24, 24, 23, 23, 19, 19, 19, 19, 18, 18, 17, 16, 16, 16, 16, 16, 15, 11, 11, 11, 10, 10,
+    9, 8, 7, 5, 3, 3, 2, 2
+];
+
+const FILTER_THRESHOLD: [u8; 64] = [
+    14, 14, 13, 13, 12, 12, 10, 10, 10, 10, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8,
+    8, 8, 7, 7, 7, 7, 7, 7, 6, 6, 6, 6, 6, 6, 5, 5, 5, 5, 4, 4, 4, 4, 4, 4, 4, 3, 3, 3, 3, 2
+];
+
+// Sets of macroblock type statistics a frame can switch to
+const PRE_DEF_MB_TYPE_STATS: [[[[u8; 2]; 10]; 3]; 16] = [
+    [
+        [[9, 15], [32, 25], [7, 19], [9, 21], [1, 12], [14, 12], [3, 18], [14, 23], [3, 10], [0, 4]],
+        [[41, 22], [1, 0], [1, 31], [0, 0], [0, 0], [0, 1], [1, 7], [0, 1], [98, 25], [4, 10]],
+        [[2, 3], [2, 3], [0, 2], [0, 2], [0, 0], [11, 4], [1, 4], [0, 2], [3, 2], [0, 4]]
+    ],
+    [
+        [[48, 39], [1, 2], [11, 27], [29, 44], [7, 27], [1, 4], [0, 3], [1, 6], [1, 2], [0, 0]],
+        [[123, 37], [6, 4], [1, 27], [0, 0], [0, 0], [5, 8], [1, 7], [0, 1], [12, 10], [0, 2]],
+        [[49, 46], [3, 4], [7, 31], [42, 41], [0, 0], [2, 6], [1, 7], [1, 4], [2, 4], [0, 1]]
+    ],
+    [
+        [[21, 32], [1, 2], [4, 10], [32, 43], [6, 23], [2, 3], [1, 19], [1, 6], [12, 21], [0, 7]],
+        [[26, 14], [14, 12], [0, 24], [0, 0], [0, 0], [55, 17], [1, 9], [0, 36], [5, 7], [1, 3]],
+        [[26, 25], [1, 1], [2, 10], [67, 39], [0, 0], [1, 1], [0, 14], [0, 2], [31, 26], [1, 6]]
+    ],
+    [
+        [[69, 83], [0, 0], [0, 2], [10, 29], [3, 12], [0, 1], [0, 3], [0, 3], [2, 2], [0, 0]],
+        [[209, 5], [0, 0], [0, 27], [0, 0], [0, 0], [0, 1], [0, 1], [0, 1], [0, 0], [0, 0]],
+        [[103, 46], [1, 2], [2, 10], [33, 42], [0, 0], [1, 4], [0, 3], [0, 1], [1, 3], [0, 0]]
+    ],
+    [
+        [[11, 20], [1, 4], [18, 36], [43, 48], [13, 35], [0, 2], [0, 5], [3, 12], [1, 2], [0, 0]],
+        [[2, 5], [4, 5], [0, 121], [0, 0], [0, 0], [0, 3], [2, 4], [1, 4], [2, 2], [0, 1]],
+        [[14, 31], [9, 13], [14, 54], [22, 29], [0, 0], [2, 6], [4, 18], [6, 13], [1, 5], [0, 1]]
+    ],
+    [
+        [[70, 44], [0, 1], [2, 10], [37, 46], [8, 26], [0, 2], [0, 2], [0, 2], [0, 1], [0, 0]],
+        [[175, 5], [0, 1], [0, 48], [0, 0], [0, 0], [0, 2], [0, 1], [0, 2], [0, 1], [0, 0]],
+        [[85, 39], [0, 0], [1, 9], [69, 40], [0, 0], [0, 1], [0, 3], [0, 1], [2, 3], [0, 0]]
+    ],
+    [
+        [[8, 15], [0, 1], [8, 21], [74, 53], [22, 42], [0, 1], [0, 2], [0, 3], [1, 2], [0, 0]],
+        [[83, 5], [2, 3], [0, 102], [0, 0], [0, 0], [1, 3], [0, 2], [0, 1], [0, 0], [0, 0]],
+        [[31, 28], [0, 0], [3, 14], [130, 34], [0, 0], [0, 1], [0, 3], [0, 1], [3, 3], [0, 1]]
+    ],
+    [
+        [[141, 42], [0, 0], [1, 4], [11, 24], [1, 11], [0, 1], [0, 1], [0, 2], [0, 0], [0, 0]],
+        [[233, 6], [0, 0], [0, 8], [0, 0], [0, 0], [0, 1], [0, 1], [0, 0], [0, 1], [0, 0]],
+        [[171, 25], [0, 0], [1, 5], [25, 21], [0, 0], [0, 1], [0, 1], [0, 0], [0, 0], [0, 0]]
+    ],
+    [
+        [[8, 19], [4, 10], [24, 45], [21, 37], [9, 29], [0, 3], [1, 7], [11, 25], [0, 2], [0, 1]],
+        [[34, 16], [112, 21], [1, 28], [0, 0], [0, 0], [6, 8], [1, 7], [0, 3], [2, 5], [0, 2]],
+        [[17, 21], [68, 29], [6, 15], [13, 22], [0, 0], [6, 12], [3, 14], [4, 10], [1, 7], [0, 3]]
+    ],
+    [
+        [[46, 42], [0, 1], [2, 10], [54, 51], [10, 30], [0, 2], [0, 2], [0, 1], [0, 1], [0, 0]],
+        [[159, 35], [2, 2], [0, 25], [0, 0], [0, 0], [3, 6], [0, 5], [0, 1], [4, 4], [0, 1]],
+        [[51, 39], [0, 1], [2, 12], [91, 44], [0, 0], [0, 2], [0, 3], [0, 1], [2, 3], [0, 1]]
+    ],
+    [
+        [[28, 32], [0, 0], [3, 10], [75, 51], [14, 33], [0, 1], [0, 2], [0, 1], [1, 2], [0, 0]],
+        [[75, 39], [5, 7], [2, 48], [0, 0], [0, 0], [3, 11], [2, 16], [1, 4], [7, 10], [0, 2]],
+        [[81, 25], [0, 0], [2, 9], [106, 26], [0, 0], [0, 1], [0, 1], [0, 1], [1, 1], [0, 0]]
+    ],
+    [
+        [[100, 46], [0, 1], [3, 9], [21, 37], [5, 20], [0, 1], [0, 2], [1, 2], [0, 1], [0, 0]],
+        [[212, 21], [0, 1], [0, 9], [0, 0], [0, 0], [1, 2], [0, 2], [0, 0], [2, 2], [0, 0]],
+        [[140, 37], [0, 1], [1, 8], [24, 33], [0, 0], [1, 2], [0, 2], [0, 1], [1, 2], [0, 0]]
+    ],
+    [
+        [[27, 29], [0, 1], [9, 25], [53, 51], [12, 34], [0, 1], [0, 3], [1, 5], [0, 2], [0, 0]],
+        [[4, 2], [0, 0], [0, 172], [0, 0], [0, 0], [0, 1], [0, 2], [0, 0], [2, 0], [0, 0]],
+        [[14, 23], [1, 3], [11, 53], [90, 31], [0, 0], [0, 3], [1, 5], [2, 6], [1, 2], [0, 0]]
+    ],
+    [
+        [[80, 38], [0, 0], [1, 4], [69, 33], [5, 16], [0, 1], [0, 1], [0, 0], [0, 1], [0, 0]],
+        [[187, 22], [1, 1], [0, 17], [0, 0], [0, 0], [3, 6], [0, 4], [0, 1], [4, 4], [0, 1]],
+        [[123, 29], [0, 0], [1, 7], [57, 30], [0, 0], [0, 1], [0, 1], [0, 1], [0, 1], [0, 0]]
+    ],
+    [
+        [[16, 20], [0, 0], [2, 8], [104, 49], [15, 33], [0, 1], [0, 1], [0, 1], [1, 1], [0, 0]],
+        [[133, 6], [1, 2], [1, 70], [0, 0], [0, 0], [0, 2], [0, 4], [0, 3], [1, 1], [0, 0]],
+        [[13, 14], [0, 0], [4, 20], [175, 20], [0, 0], [0, 1], [0, 1], [0, 1], [1, 1], [0, 0]]
+    ],
+    [
+        [[194, 16], [0, 0], [1, 1], [1, 9], [1, 3], [0, 0], [0, 1], [0, 1], [0, 0], [0, 0]],
+        [[251, 1], [0, 0], [0, 2], [0, 0], [0, 0], [0, 0], [0, 0], [0, 0], [0, 0], [0, 0]],
+        [[202, 23], [0, 0], [1, 3], [2, 9], [0, 0], [0, 1], [0, 1], [0, 1], [0, 0], [0, 0]]
+    ]
+];
+
+const MB_TYPE_MODEL_MODEL: [u8; 6] = [171, 83, 199, 140, 125, 104];
+
+// Neighbours that give the vector predictors, from the closest
+const CANDIDATE_PREDICTOR_POS: [(i32, i32); 12] = [
+    (0, -1), (-1, 0), (-1, -1), (1, -1), (0, -2), (-2, 0), (-2, -1), (-1, -2), (1, -2), (2, -1), (-2, -2), (2, -2)
+];
+
+// Boolean coding trees
+const PVA_TREE: [(i8, u8); 15] = [
+    (8, 0), (4, 1), (2, 2), (0, 0), (-1, 0), (2, 3), (-2, 0), (-3, 0), (4, 4), (2, 5), (-4, 0), (-5, 0), (2, 6),
+    (-6, 0), (-7, 0)
+];
+
+const PC_TREE: [(i8, u8); 11] = [
+    (4, 6), (2, 7), (0, 0), (-1, 0), (4, 8), (2, 9), (-2, 0), (-3, 0), (2, 10), (-4, 0), (-5, 0)
+];
+
+const PCR_TREE: [(i8, u8); 17] = [
+    (8, 0), (4, 1), (2, 2), (-1, 0), (-2, 0), (2, 3), (-3, 0), (-4, 0), (8, 4), (4, 5), (2, 6), (-5, 0), (-6, 0),
+    (2, 7), (-7, 0), (-8, 0), (0, 0)
+];
+
+const PMBTM_TREE: [(i8, u8); 13] = [
+    (4, 0), (2, 1), (-8, 0), (-4, 0), (8, 2), (6, 3), (4, 4), (2, 5), (-24, 0), (-20, 0), (-16, 0), (-12, 0),
+    (0, 0)
+];
+
+const PMBT_TREE: [(i8, u8); 19] = [
+    (8, 1), (4, 2), (2, 4), (0, 0), (-2, 0), (2, 5), (-3, 0), (-4, 0), (4, 3), (2, 6), (-1, 0), (-7, 0), (4, 7),
+    (2, 8), (-5, 0), (-6, 0), (2, 9), (-8, 0), (-9, 0)
+];
+
+#[cfg(test)]
+mod tests {
+    use super::Vp6Decoder;
+    use util::video::{ VideoStream, VideoFrame, VideoCodec };
+
+    // The fixtures cover the header options, filters, macroblock types, both coefficient codings and golden
+    // frames. Their reference pictures are FFmpeg's decoding, which matches to the bit
+    fn check(flv: &[u8], reference: &[u8], codec: VideoCodec, width: u16, height: u16) {
+        let stream = VideoStream { id: 1, frame_count: 0, width: width, height: height, deblocking: 0, smoothing: false,
+            codec: codec };
+        let mut decoder = Vp6Decoder::new(&stream).unwrap();
+
+        // FLV video tags hold the codec byte and the crop adjustment byte before the frame
+        let mut decoded = Vec::new();
+        let mut pos = 13;
+        let mut frame_num = 0;
+        while pos < flv.len() {
+            let size = (flv[pos + 1] as usize) << 16 | (flv[pos + 2] as usize) << 8 | flv[pos + 3] as usize;
+            let frame = VideoFrame { stream_id: 1, frame_num: frame_num, data: flv[pos + 13..pos + 11 + size].to_vec() };
+            let picture = decoder.decode(&frame).unwrap();
+            assert_eq!((picture.width, picture.height), (width, height));
+            decoded.push_all(&picture.y);
+            decoded.push_all(&picture.u);
+            decoded.push_all(&picture.v);
+            if let Some(ref alpha) = picture.alpha {
+                decoded.push_all(alpha);
+            }
+            pos += 11 + size + 4;
+            frame_num += 1;
+        }
+
+        assert_eq!(decoded.len(), reference.len());
+        assert!(decoded == reference);
+    }
+
+    #[test]
+    fn decode_cropped() {
+        check(include_bytes!("../../tests/fixtures/vp6_45x30.flv"), include_bytes!("../../tests/fixtures/vp6_45x30.yuv"),
+            VideoCodec::VP6, 45, 30);
+    }
+
+    #[test]
+    fn decode_alpha() {
+        check(include_bytes!("../../tests/fixtures/vp6_alpha_32x32.flv"),
+            include_bytes!("../../tests/fixtures/vp6_alpha_32x32.yuv"), VideoCodec::VP6Alpha, 32, 32);
+    }
+
+    #[test]
+    fn alpha_goes_to_rgba() {
+        let stream = VideoStream { id: 1, frame_count: 0, width: 32, height: 32, deblocking: 0, smoothing: false,
+            codec: VideoCodec::VP6Alpha };
+        let flv = include_bytes!("../../tests/fixtures/vp6_alpha_32x32.flv");
+        let size = (flv[14] as usize) << 16 | (flv[15] as usize) << 8 | flv[16] as usize;
+        let frame = VideoFrame { stream_id: 1, frame_num: 0, data: flv[26..24 + size].to_vec() };
+
+        let picture = Vp6Decoder::new(&stream).unwrap().decode(&frame).unwrap();
+        let alpha: Vec<u8> = picture.to_rgba().chunks(4).map(|pixel| pixel[3]).collect();
+        assert_eq!(Some(alpha), picture.alpha);
+    }
+
+    #[test]
+    fn inter_frame_needs_keyframe() {
+        let stream = VideoStream { id: 1, frame_count: 0, width: 45, height: 30, deblocking: 0, smoothing: false,
+            codec: VideoCodec::VP6 };
+        let frame = VideoFrame { stream_id: 1, frame_num: 0, data: vec![0x80 | 20 << 1, 0, 0, 0] };
+        assert!(Vp6Decoder::new(&stream).unwrap().decode(&frame).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::old_io::{ Reader, Writer, IoResult, IoError, InvalidInput, EndOfFile };
+use util::sound_stream::{ SoundStream, SoundFormat, CodecKind };
+use util::pcm::decode_pcm;
+use util::mp3::split_frames;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_MPEGLAYER3: u16 = 0x0055;
+
+// Writes a DefineSound or the remainder of a streaming sound as a RIFF WAV file
+// Uncompressed sound keeps its sample size, everything else except MP3 is decoded to 16-bit PCM
+pub fn write_wav<R : Reader, W : Writer>(sound: &mut SoundStream<R>, writer: &mut W) -> IoResult<()> {
+    let format = sound.format;
+    match format.codec() {
+        CodecKind::MP3 => {
+            let data = try!(read_blocks(sound, |data: &mut Vec<u8>, block: Vec<u8>| { data.push_all(&block); Ok(()) }));
+            write_mp3_wav(&format, sound.samples, sound.latency(), &data, writer)
+        },
+        CodecKind::UncompressedNative | CodecKind::Uncompressed if !format.is_16bit() => {
+            let data = try!(read_blocks(sound, |data: &mut Vec<u8>, block: Vec<u8>| { data.push_all(&block); Ok(()) }));
+            write_pcm_wav(&format, 8, &data, writer)
+        },
+        CodecKind::ADPCM => {
+            let samples = try!(read_blocks(sound, |samples: &mut Vec<i16>, block: Vec<u8>| {
+                samples.extend(try!(decode_pcm(&format, &block)).into_iter());
+                Ok(())
+            }));
+            write_samples_wav(&format, &samples, writer)
+        },
+        _ => {
+            // Nellymoser's overlap and noise seed carry from block to block
+            let data = try!(read_blocks(sound, |data: &mut Vec<u8>, block: Vec<u8>| { data.push_all(&block); Ok(()) }));
+            let samples = try!(decode_pcm(&format, &data));
+            write_samples_wav(&format, &samples, writer)
+        }
+    }
+}
+
+// Writes MP3 sound data as-is, suitable for a `.mp3` file
+pub fn write_mp3<R : Reader, W : Writer>(sound: &mut SoundStream<R>, writer: &mut W) -> IoResult<()> {
+    if sound.format.codec() != CodecKind::MP3 {
+        return Err(IoError { kind: InvalidInput, desc: "MP3 sound expected", detail: None })
+    }
+
+    try!(read_blocks(sound, |_: &mut Vec<()>, block: Vec<u8>| writer.write_all(&block)));
+    Ok(())
+}
+
+// ADPCM blocks are decoded separately since the codec restarts with every SoundStreamBlock
+fn read_blocks<R : Reader, T, F>(sound: &mut SoundStream<R>, mut f: F) -> IoResult<Vec<T>>
+    where F : FnMut(&mut Vec<T>, Vec<u8>) -> IoResult<()>
+{
+    let mut values = Vec::new();
+    loop {
+        match sound.read_block() {
+            Ok(block) => try!(f(&mut values, block)),
+            Err(ref err) if err.kind == EndOfFile => return Ok(values),
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+fn write_pcm_wav<W : Writer>(format: &SoundFormat, bits: u16, data: &[u8], writer: &mut W) -> IoResult<()> {
+    let channels = format.channels();
+    let block_align = channels * bits / 8;
+
+    try!(write_riff_header(writer, 4 + 8 + 16 + 8 + data.len() as u32 + (data.len() as u32 & 1)));
+    try!(writer.write_all(b"fmt "));
+    try!(writer.write_le_u32(16));
+    try!(writer.write_le_u16(WAVE_FORMAT_PCM));
+    try!(writer.write_le_u16(channels));
+    try!(writer.write_le_u32(format.sample_rate()));
+    try!(writer.write_le_u32(format.sample_rate() * block_align as u32));
+    try!(writer.write_le_u16(block_align));
+    try!(writer.write_le_u16(bits));
+
+    write_data_chunk(writer, data)
+}
+
+fn write_samples_wav<W : Writer>(format: &SoundFormat, samples: &[i16], writer: &mut W) -> IoResult<()> {
+    let mut data = Vec::with_capacity(samples.len() * 2);
+    for &sample in samples.iter() {
+        try!(data.write_le_i16(sample));
+    }
+    write_pcm_wav(format, 16, &data, writer)
+}
+
+fn write_mp3_wav<W : Writer>(format: &SoundFormat, samples: u32, latency: u32, data: &[u8], writer: &mut W) -> IoResult<()> {
+    let rate = format.sample_rate();
+    let frames = split_frames(data);
+    let block_size = if frames.is_empty() { 0 } else { data.len() / frames.len() };
+    let byte_rate = if samples > 0 { (data.len() as u64 * rate as u64 / samples as u64) as u32 } else { 0 };
+
+    try!(write_riff_header(writer, 4 + 8 + 30 + 12 + 8 + data.len() as u32 + (data.len() as u32 & 1)));
+    try!(writer.write_all(b"fmt "));
+    try!(writer.write_le_u32(30));
+    try!(writer.write_le_u16(WAVE_FORMAT_MPEGLAYER3));
+    try!(writer.write_le_u16(format.channels()));
+    try!(writer.write_le_u32(rate));
+    try!(writer.write_le_u32(byte_rate));
+    try!(writer.write_le_u16(1)); // block align
+    try!(writer.write_le_u16(0)); // bits per sample
+    try!(writer.write_le_u16(12)); // extra size
+    try!(writer.write_le_u16(1)); // MPEGLAYER3_ID_MPEG
+    try!(writer.write_le_u32(2)); // MPEGLAYER3_FLAG_PADDING_OFF
+    try!(writer.write_le_u16(block_size as u16));
+    try!(writer.write_le_u16(1)); // frames per block
+    try!(writer.write_le_u16(latency as u16)); // codec delay
+
+    try!(writer.write_all(b"fact"));
+    try!(writer.write_le_u32(4));
+    try!(writer.write_le_u32(samples));
+
+    write_data_chunk(writer, data)
+}
+
+fn write_riff_header<W : Writer>(writer: &mut W, length: u32) -> IoResult<()> {
+    try!(writer.write_all(b"RIFF"));
+    try!(writer.write_le_u32(length));
+    writer.write_all(b"WAVE")
+}
+
+fn write_data_chunk<W : Writer>(writer: &mut W, data: &[u8]) -> IoResult<()> {
+    try!(writer.write_all(b"data"));
+    try!(writer.write_le_u32(data.len() as u32));
+    try!(writer.write_all(data));
+    if data.len() & 1 != 0 {
+        try!(writer.write_u8(0));
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::old_io::IoResult;
+#[cfg(not(feature = "zlib"))]
+use std::old_io::{ IoError, InvalidInput };
+
+#[cfg(feature = "zlib")]
+use std::old_io::{ Reader, BufReader };
+#[cfg(feature = "zlib")]
+use flate2::CompressionLevel;
+#[cfg(feature = "zlib")]
+use flate2::reader::{ ZlibDecoder, DeflateDecoder };
+#[cfg(feature = "zlib")]
+use flate2::writer::ZlibEncoder;
+
+#[cfg(feature = "zlib")]
+pub fn compress(data: &[u8]) -> IoResult<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), CompressionLevel::Default);
+    try!(encoder.write_all(data));
+    encoder.finish()
+}
+
+#[cfg(feature = "zlib")]
+pub fn decompress(data: &[u8]) -> IoResult<Vec<u8>> {
+    ZlibDecoder::new(BufReader::new(data)).read_to_end()
+}
+
+// Decompresses raw deflate data, without a zlib header or checksum
+#[cfg(feature = "zlib")]
+pub fn decompress_raw(data: &[u8]) -> IoResult<Vec<u8>> {
+    DeflateDecoder::new(BufReader::new(data)).read_to_end()
+}
+
+#[cfg(not(feature = "zlib"))]
+#[allow(unused_variables)]
+pub fn compress(data: &[u8]) -> IoResult<Vec<u8>> {
+    Err(IoError { kind: InvalidInput, desc: "use `zlib' feature to support compressed SWF data", detail: None })
+}
+
+#[cfg(not(feature = "zlib"))]
+#[allow(unused_variables)]
+pub fn decompress(data: &[u8]) -> IoResult<Vec<u8>> {
+    Err(IoError { kind: InvalidInput, desc: "use `zlib' feature to support compressed SWF data", detail: None })
+}
+
+#[cfg(not(feature = "zlib"))]
+#[allow(unused_variables)]
+pub fn decompress_raw(data: &[u8]) -> IoResult<Vec<u8>> {
+    Err(IoError { kind: InvalidInput, desc: "use `zlib' feature to support compressed SWF data", detail: None })
+}